@@ -1,6 +1,7 @@
 //! Defining all native types (and functions?)
 #![allow(unused_assignments)]
 use internment::LocalIntern;
+use serde::{Deserialize, Serialize};
 use shared::SpwnSource;
 use shared::StoredValue;
 
@@ -8,6 +9,7 @@ use crate::compiler_types::*;
 use crate::context::*;
 use crate::globals::Globals;
 use crate::leveldata::*;
+use crate::output_cache;
 use errors::{create_error, RuntimeError};
 use ahash::AHashMap;
 use parser::ast::ObjectMode;
@@ -91,7 +93,11 @@ macro_rules! arg_length {
     };
 }
 
-pub fn context_trigger(context: &Context, uid_counter: &mut usize) -> GdObj {
+pub fn context_trigger(
+    context: &Context,
+    uid_counter: &mut usize,
+    source: CodeArea,
+) -> GdObj {
     let mut params = AHashMap::default();
     params.insert(57, ObjParam::Group(context.start_group));
     (*uid_counter) += 1;
@@ -100,12 +106,13 @@ pub fn context_trigger(context: &Context, uid_counter: &mut usize) -> GdObj {
         func_id: context.func_id,
         mode: ObjectMode::Trigger,
         unique_id: *uid_counter,
+        source,
     }
 }
 
 pub type ArbitraryId = u16;
 pub type SpecificId = u16;
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Id {
     Specific(SpecificId),
     Arbitrary(ArbitraryId), // will be given specific ids at the end of compilation
@@ -141,22 +148,22 @@ macro_rules! id_default_methods {
     };
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Group {
     pub id: Id,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Color {
     pub id: Id,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Block {
     pub id: Id,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Item {
     pub id: Id,
 }
@@ -454,7 +461,7 @@ macro_rules! builtins {
         )*
     } => {
 
-        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
         pub enum Builtin {
             $(
                 $variant,
@@ -478,6 +485,12 @@ macro_rules! builtins {
             )*
         ];
 
+        pub const BUILTIN_DESCRIPTIONS: &[(&str, &str)] = &[
+            $(
+                (stringify!($name), $desc),
+            )*
+        ];
+
         #[derive(Debug, Clone)]
         pub struct BuiltinPermissions (AHashMap<Builtin, bool>);
 
@@ -534,6 +547,10 @@ macro_rules! builtins {
                     })
                 }
             }
+            // the built-in functions that aren't safe use the file system or the network
+            if !$globals.permissions.is_safe(func) {
+                output_cache::impure($globals);
+            }
             for full_context in contexts.iter() {
                 let $full_context: *mut FullContext = full_context;
                 let $context = full_context.inner();
@@ -691,6 +708,30 @@ macro_rules! builtins {
             out
         }
 
+        /// The same information as `builtin_docs`, as a JSON array with one object per built-in function
+        pub fn builtin_docs_json() -> serde_json::Value {
+            let mut all = vec![
+                $(
+                    serde_json::json!({
+                        "name": stringify!($name),
+                        "description": $desc,
+                        "example": $example,
+                        "safe": $safe,
+                        "operator": stringify!($name).starts_with('_') && stringify!($name).ends_with('_'),
+                        "arguments_description": (None::<&str>$(.or(Some($argdesc)))?),
+                        "arguments": [$($(
+                            {
+                                "names": [$(stringify!($arg_name)),*],
+                                "mutable": (false $(|| stringify!($mut) == "mut")?),
+                                "type": (None::<&str>$(.or(Some(stringify!($arg_type))))?),
+                            }
+                        ),+)?],
+                    }),
+                )*
+            ];
+            all.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+            serde_json::Value::Array(all)
+        }
 
     };
 }
@@ -713,6 +754,8 @@ builtins! {
 
     [Print] #[safe = true, desc = "Prints value(s) to the console", example = "$.print(\"Hello world!\")"]
     fn print(#["any"]) {
+        // a cached output wouldn't print again
+        output_cache::impure(globals);
         let mut out = String::new();
         for val in arguments.iter() {
             match &globals.stored_values[*val] {
@@ -737,6 +780,7 @@ builtins! {
 
     [Time] #[safe = true, desc = "Gets the current system time in seconds", example = "now = $.time()"]
     fn time(#["none"]) {
+        output_cache::impure(globals);
         #[cfg(not(target_arch = "wasm32"))]
         {
             arg_length!(info, 0, arguments, "Expected no arguments".to_string(), builtin);
@@ -766,8 +810,16 @@ builtins! {
         Value::Str(env!("CARGO_PKG_VERSION").to_string())
     }
 
+    [Target] #[safe = true, desc = "Gets the Geometry Dash version the level is built for (\"gd2.1\" or \"gd2.2\")", example = "target = $.target()"]
+    fn target(#["none"]) {
+        arg_length!(info, 0, arguments, "Expected no arguments".to_string(), builtin);
+
+        Value::Str(globals.target.name().to_string())
+    }
+
     [GetInput] #[safe = true, desc = "Gets some input from the user", example = "// inp = $.get_input('What is your name?')"]
     fn get_input((prompt): Str) {
+        output_cache::impure(globals);
         print!("{}", prompt);
         stdout()
             .flush()
@@ -1011,7 +1063,7 @@ $.add(obj {
                     func_id: context.func_id,
                     mode: ObjectMode::Object,
                     unique_id: globals.uid_counter,
-
+                    source: info.position,
                 };
                 (*globals).objects.push(obj)
             }
@@ -1020,7 +1072,7 @@ $.add(obj {
                 let obj = GdObj {
                     params: obj_map,
                     mode: ObjectMode::Trigger,
-                    ..context_trigger(context, &mut globals.uid_counter)
+                    ..context_trigger(context, &mut globals.uid_counter, info.position)
                 }
                 .context_parameters(context);
                 (*globals).trigger_order += 1.0;
@@ -1182,24 +1234,7 @@ $.edit_obj(object, X, 600)
 
                 Value::Bool(b) => ObjParam::Bool(*b),
 
-                Value::Array(a) => {
-                    ObjParam::GroupList({
-                        let mut out = Vec::new();
-                        for s in a {
-                            out.push(match globals.stored_values[*s] {
-                            Value::Group(g) => g,
-                            _ => return Err(RuntimeError::CustomError(create_error(
-                                info,
-                                "Arrays in object parameters can only contain groups",
-                                &[],
-                                None,
-                            )))
-                        })
-                        }
-
-                        out
-                    })
-                }
+                Value::Array(a) => array_obj_param(a, globals, &info)?,
                 obj @ Value::Dict(_) => {
                     let typ = obj.member(globals.TYPE_MEMBER_NAME, context, globals, info.clone()).unwrap();
                     if globals.stored_values[typ] == Value::TypeIndicator(20) {
@@ -1283,7 +1318,8 @@ $.extend_trigger_func(10g, () {
                     tag: Attribute { tags: Vec::new() }
                 }.to_expression()),
                 arrow: false,
-                pos: info.position.pos
+                pos: info.position.pos,
+                comments: Comments::default(),
             }
         ]};
 
@@ -1305,6 +1341,7 @@ $.random([1, 2, 3, 6]) // returns either 1, 2, 3, or 6
 $.random(1..11) // returns a random integer between 1 and 10
     "]
     fn random(#["see example"]) {
+        output_cache::impure(globals);
         #[cfg(not(target_arch = "wasm32"))]
         {
             use rand::seq::SliceRandom;
@@ -1393,6 +1430,8 @@ $.random(1..11) // returns a random integer between 1 and 10
 
     [ReadLevel] #[safe = true, desc = "Returns the level string of the level being written to, or nothing if there is no output level", example = "level_string = $.level_string()"]
     fn level_string() {
+        // the level can change without the module changing
+        output_cache::impure(globals);
         if !arguments.is_empty() {
             return Err(RuntimeError::BuiltinError {
                 builtin,
@@ -1404,6 +1443,7 @@ $.random(1..11) // returns a random integer between 1 and 10
     }
     [ParseLevel] #[safe = true, desc = "Returns a array of the objects in the level being written to, or an empty array if there is no output level", example = "level = $.level_objects()"]
     [[RAW]] fn level_objects() {
+        output_cache::impure(globals);
         if !arguments.is_empty() {
             return Err(RuntimeError::BuiltinError {
                 builtin,
@@ -1432,6 +1472,7 @@ $.random(1..11) // returns a random integer between 1 and 10
     }
 
     [CWD] #[safe = true, desc = "Returns the current working directory", example = "$.cwd() // \"C:/spwn/\""] fn cwd() {
+        output_cache::impure(globals);
         Value::Str(env::current_dir().unwrap().to_str().unwrap().to_string())
     }
 
@@ -5,7 +5,9 @@ use internment::LocalIntern;
 
 use shared::BreakType;
 use shared::ImportType;
+use shared::PACKAGE_DIR;
 use shared::SpwnSource;
+use shared::StoredValue;
 
 use crate::builtins::*;
 use crate::context::*;
@@ -14,14 +16,19 @@ use errors::compiler_info::CompilerInfo;
 use parser::ast;
 
 use crate::globals::Globals;
+use crate::globals::ImportCache;
 use crate::leveldata::*;
+use crate::output_cache;
+use crate::libraries::{find_library, find_project, InstalledLibrary};
+use crate::target::Target;
 use crate::value::*;
 use crate::value_storage::*;
 use crate::STD_PATH;
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 
 use std::io::Write;
 use std::mem;
+use std::rc::Rc;
 
 use errors::RuntimeError;
 
@@ -34,44 +41,20 @@ use crate::compiler_types::*;
 use ariadne::Color as TColor;
 use ariadne::Fmt;
 
-pub fn compile_spwn(
+#[allow(clippy::too_many_arguments)]
+pub fn compile_spwn<'a>(
     statements: Vec<ast::Statement>,
     source: SpwnSource,
     included_paths: Vec<PathBuf>,
     notes: ParseNotes,
     permissions: BuiltinPermissions,
+    target: Target,
     initial_level: String,
-    std_out: &mut impl Write,
-) -> Result<Globals, RuntimeError> {
-    //variables that get changed throughout the compiling
-
-    let mut globals = Globals::new(source.clone(), permissions, initial_level, std_out);
-    globals.includes = included_paths;
-
+    std_out: &'a mut impl Write,
+    import_cache: &mut ImportCache,
+) -> Result<Globals<'a>, RuntimeError> {
     let print_with_color = |a: &str, color| println!("{}", a.fg(color));
 
-    // if statements.is_empty() {
-    //     return Err(RuntimeError::CustomError(create_error(
-    //         CompilerInfo::from_area(crate::compiler_info::CodeArea {
-    //             file: LocalIntern::new(path),
-    //             pos: (0, 0),
-    //         }),
-    //         "this script is empty",
-    //         &[],
-    //         None,
-    //     )));
-    // }
-    let mut start_context = FullContext::new(&globals);
-    //store at pos 0
-    // store_value(Value::Builtins, 1, &mut globals, &start_context);
-    // store_value(Value::Null, 1, &mut globals, &start_context);
-
-    let start_info = CompilerInfo {
-        ..CompilerInfo::from_area(errors::compiler_info::CodeArea {
-            file: LocalIntern::new(source.clone()),
-            pos: (0, 0),
-        })
-    };
     use std::time::Instant;
 
     //println!("Importing standard library...");
@@ -80,57 +63,17 @@ pub fn compile_spwn(
     #[cfg(not(target_arch = "wasm32"))]
     let start_time = Instant::now();
 
-    if !notes.tag.tags.iter().any(|x| x.0 == "no_std") {
-        import_module(
-            &ImportType::Lib(STD_PATH.to_string()),
-            &mut start_context,
-            &mut globals,
-            start_info.clone(),
-            false,
-        )?;
-
-        if let FullContext::Split(_, _) = start_context {
-            return Err(RuntimeError::CustomError(create_error(
-                start_info,
-                "The standard library can not split the context",
-                &[],
-                None,
-            )));
-        }
-
-        if let Value::Dict(d) = &globals.stored_values[start_context.inner().return_value] {
-            for (a, b, c) in d.iter().map(|(k, v)| (*k, *v, -1)) {
-                start_context.inner().new_redefinable_variable(a, b, c)
-            }
-        } else {
-            return Err(RuntimeError::CustomError(create_error(
-                start_info,
-                "The standard library must return a dictionary",
-                &[],
-                None,
-            )));
-        }
-    }
-
-    compile_scope(&statements, &mut start_context, &mut globals, start_info)?;
-    if !statements.is_empty() {
-        for fc in start_context.with_breaks() {
-            let c = fc.inner();
-            let end_pos = statements.last().unwrap().pos.1;
-            if let Some((r, i)) = c.broken {
-                return Err(RuntimeError::BreakNeverUsedError {
-                    breaktype: r,
-                    info: CompilerInfo::from_area(i),
-                    broke: i,
-                    dropped: CodeArea {
-                        pos: (end_pos, end_pos),
-                        file: LocalIntern::new(source),
-                    },
-                    reason: "the program ended".to_string(),
-                });
-            }
-        }
-    }
+    let globals = compile_spwn_quiet(
+        statements,
+        source,
+        included_paths,
+        notes,
+        permissions,
+        target,
+        initial_level,
+        std_out,
+        import_cache,
+    )?;
 
     print_with_color("———————————————————————————\n", TColor::White);
 
@@ -197,6 +140,139 @@ pub fn compile_spwn(
     Ok(globals)
 }
 
+/// Compiles a script without printing any build progress to stdout.
+/// Used by tools that own stdout themselves, like the language server.
+/// Modules in `import_cache` are reused if their source did not change.
+#[allow(clippy::too_many_arguments)]
+pub fn compile_spwn_quiet<'a>(
+    statements: Vec<ast::Statement>,
+    source: SpwnSource,
+    included_paths: Vec<PathBuf>,
+    notes: ParseNotes,
+    permissions: BuiltinPermissions,
+    target: Target,
+    initial_level: String,
+    std_out: &'a mut impl Write,
+    import_cache: &mut ImportCache,
+) -> Result<Globals<'a>, RuntimeError> {
+    //variables that get changed throughout the compiling
+
+    let mut globals = Globals::new(source.clone(), permissions, initial_level, std_out);
+    globals.includes = included_paths;
+    globals.target = target;
+
+    mem::swap(&mut globals.import_cache, import_cache);
+    globals.import_cache.files.clear();
+
+    let result = compile_main(statements, source, notes, &mut globals);
+
+    mem::swap(&mut globals.import_cache, import_cache);
+    result.map(|_| globals)
+}
+
+/// Creates the context a script starts in, with the standard library imported
+/// unless the script is tagged with `#[no_std]`
+pub fn start_context(
+    source: &SpwnSource,
+    notes: &ParseNotes,
+    globals: &mut Globals,
+) -> Result<FullContext, RuntimeError> {
+    let mut start_context = FullContext::new(globals);
+    //store at pos 0
+    // store_value(Value::Builtins, 1, globals, &start_context);
+    // store_value(Value::Null, 1, globals, &start_context);
+
+    let start_info = CompilerInfo {
+        ..CompilerInfo::from_area(errors::compiler_info::CodeArea {
+            file: LocalIntern::new(source.clone()),
+            pos: (0, 0),
+        })
+    };
+
+    if !notes.tag.tags.iter().any(|x| x.0 == "no_std") {
+        import_module(
+            &ImportType::Lib(STD_PATH.to_string()),
+            &mut start_context,
+            globals,
+            start_info.clone(),
+            false,
+        )?;
+
+        if let FullContext::Split(_, _) = start_context {
+            return Err(RuntimeError::CustomError(create_error(
+                start_info,
+                "The standard library can not split the context",
+                &[],
+                None,
+            )));
+        }
+
+        if let Value::Dict(d) = &globals.stored_values[start_context.inner().return_value] {
+            for (a, b, c) in d.iter().map(|(k, v)| (*k, *v, -1)) {
+                start_context.inner().new_redefinable_variable(a, b, c)
+            }
+        } else {
+            return Err(RuntimeError::CustomError(create_error(
+                start_info,
+                "The standard library must return a dictionary",
+                &[],
+                None,
+            )));
+        }
+    }
+
+    Ok(start_context)
+}
+
+fn compile_main(
+    statements: Vec<ast::Statement>,
+    source: SpwnSource,
+    notes: ParseNotes,
+    globals: &mut Globals,
+) -> Result<(), RuntimeError> {
+    // if statements.is_empty() {
+    //     return Err(RuntimeError::CustomError(create_error(
+    //         CompilerInfo::from_area(crate::compiler_info::CodeArea {
+    //             file: LocalIntern::new(path),
+    //             pos: (0, 0),
+    //         }),
+    //         "this script is empty",
+    //         &[],
+    //         None,
+    //     )));
+    // }
+    let mut start_context = start_context(&source, &notes, globals)?;
+    // `new` points the root context at its own local, which is gone once the context is moved
+    start_context.inner().root_context_ptr = &mut start_context;
+
+    let start_info = CompilerInfo::from_area(errors::compiler_info::CodeArea {
+        file: LocalIntern::new(source.clone()),
+        pos: (0, 0),
+    });
+
+    compile_scope(&statements, &mut start_context, globals, start_info)?;
+    if !statements.is_empty() {
+        for fc in start_context.with_breaks() {
+            let c = fc.inner();
+            let end_pos = statements.last().unwrap().pos.1;
+            if let Some((r, i)) = c.broken {
+                return Err(RuntimeError::BreakNeverUsedError {
+                    breaktype: r,
+                    info: CompilerInfo::from_area(i),
+                    broke: i,
+                    dropped: CodeArea {
+                        pos: (end_pos, end_pos),
+                        file: LocalIntern::new(source),
+                    },
+                    reason: "the program ended".to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
 use crate::compiler_types::EvalExpression;
 
 pub fn compile_scope(
@@ -522,7 +598,7 @@ pub fn compile_scope(
                         GdObj {
                             params,
 
-                            ..context_trigger(context, &mut globals.uid_counter)
+                            ..context_trigger(context, &mut globals.uid_counter, info.position)
                         }
                         .context_parameters(context),
                         TriggerOrder(globals.trigger_order),
@@ -985,7 +1061,7 @@ pub fn compile_scope(
         }
 
         //try to merge contexts
-        merge_all_contexts(contexts, globals, false);
+        merge_all_contexts(contexts, globals, false, info.position);
 
         if contexts.iter().next().is_none() {
             break;
@@ -1081,7 +1157,7 @@ pub fn do_assignment(
             concat,
         )?;
         Ok(())
-    } else if let ast::ValueBody::Dictionary(kvs) = &dest.value.body {
+    } else if let ast::ValueBody::Dictionary(kvs, _) = &dest.value.body {
         destructure_sanitize(src)?;
         dict_destructure_define(kvs, info, src, contexts, globals, mutable, concat)?;
         Ok(())
@@ -1420,7 +1496,7 @@ fn dict_destructure_define(
             do_assignment(
                 ranges[0],
                 &Some(
-                    ast::ValueBody::Dictionary(ast_src)
+                    ast::ValueBody::Dictionary(ast_src, Vec::new())
                         .to_variable((0, 0))
                         .to_expression(),
                 ),
@@ -1585,6 +1661,7 @@ pub fn merge_all_contexts(
     contexts: &mut FullContext,
     globals: &mut Globals,
     check_return_vals: bool,
+    area: CodeArea,
 ) {
     if let FullContext::Split(_, _) = contexts {
         let mut broken = Vec::new();
@@ -1599,7 +1676,7 @@ pub fn merge_all_contexts(
 
         if not_broken.len() > 1 {
             loop {
-                if !merge_contexts(&mut not_broken, globals, check_return_vals) {
+                if !merge_contexts(&mut not_broken, globals, check_return_vals, area) {
                     break;
                 }
             }
@@ -1655,7 +1732,57 @@ pub fn get_import_path(
             p
         }
 
-        ImportType::Lib(name) => {
+        ImportType::Lib(lib) => {
+            let (name, version) = match lib.split_once('@') {
+                Some((name, version)) => (name, Some(version)),
+                None => (lib.as_str(), None),
+            };
+
+            // libraries installed by pckp, in the project of the importing file
+            let importing_dir = match globals.path.as_ref() {
+                SpwnSource::File(f) => f.parent().map(|p| p.to_path_buf()),
+                _ => None,
+            };
+            let project = importing_dir
+                .iter()
+                .chain(globals.includes.iter())
+                .find_map(|dir| find_project(dir));
+            let installed = match &project {
+                Some(project) => find_library(project, name, version),
+                None => InstalledLibrary::NotInstalled,
+            };
+            match installed {
+                InstalledLibrary::Found(path) => return Ok(path),
+                InstalledLibrary::NoMatch(versions) => {
+                    return Err(RuntimeError::CustomError(create_error(
+                        info.clone(),
+                        &format!(
+                            "No installed version of library {} matches {}",
+                            name,
+                            version.unwrap_or_default()
+                        ),
+                        &[(
+                            info.position,
+                            &format!("Installed versions: {}", versions.join(", ")),
+                        )],
+                        Some(&format!(
+                            "Install it with `spwn pckp add {}@{}`",
+                            name,
+                            version.unwrap_or("latest")
+                        )),
+                    )))
+                }
+                InstalledLibrary::NotInstalled if version.is_some() => {
+                    return Err(RuntimeError::CustomError(create_error(
+                        info.clone(),
+                        &format!("Library {} isn't installed in {}", name, PACKAGE_DIR),
+                        &[(info.position, "Versions can only be imported from pckp packages")],
+                        Some(&format!("Install it with `spwn pckp add {}`", lib)),
+                    )))
+                }
+                InstalledLibrary::NotInstalled => (),
+            }
+
             let mut outpath = globals.includes[0].clone();
             let mut found = false;
 
@@ -1696,7 +1823,7 @@ pub fn get_import_path(
         // .parent()
         // .unwrap()
         .join("libraries")
-        .join(name),
+        .join(lib),
     })
 }
 
@@ -1707,13 +1834,26 @@ pub fn import_module(
     info: CompilerInfo,
     forced: bool,
 ) -> Result<(), RuntimeError> {
+    import_module_returns(path, contexts, globals, info, forced).map(|_| ())
+}
+
+/// Imports a module like `import_module`, and gives the values it returned in each of the
+/// contexts it ended in. When the module splits the context, `contexts` only gets the last one.
+pub fn import_module_returns(
+    path: &ImportType,
+    contexts: &mut FullContext,
+    globals: &mut Globals,
+    info: CompilerInfo,
+    forced: bool,
+) -> Result<Vec<StoredValue>, RuntimeError> {
     if !forced {
         if let Some(ret) = globals.prev_imports.get(path).cloned() {
             merge_impl(&mut globals.implementations, &ret.1);
             for c in contexts.iter() {
                 c.inner().return_value = ret.0;
             }
-            return Ok(());
+            output_cache::reuse_module(globals, path);
+            return Ok(vec![ret.0]);
         }
     }
     let built_in_path = match path {
@@ -1804,13 +1944,55 @@ pub fn import_module(
         }
     };
 
-    let (parsed, notes) =
-        match parser::parser::parse_spwn(unparsed, module_path.clone(), BUILTIN_NAMES) {
-            Ok(p) => p,
-            Err(err) => return Err(RuntimeError::PackageSyntaxError { err, info }),
-        };
+    if let SpwnSource::File(file) = &module_path {
+        if !globals.import_cache.files.contains(file) {
+            globals.import_cache.files.push(file.clone());
+        }
+    }
+
+    let cache_file = globals
+        .import_cache
+        .output_dir
+        .clone()
+        .map(|dir| output_cache::cache_path(&dir, &module_path, &unparsed, globals));
+    if let (Some(file), false) = (&cache_file, forced) {
+        if let Some((output, implementations, deps)) = output_cache::load(file, globals) {
+            merge_impl(&mut globals.implementations, &implementations);
+            for c in contexts.iter() {
+                c.inner().return_value = output;
+            }
+            output_cache::loaded_module(globals, path, deps);
+            globals.prev_imports.insert(path.clone(), (output, implementations));
+            (*globals).built_in_path = stored_built_in_path;
+            return Ok(vec![output]);
+        }
+    }
+    let types_before = globals.type_ids.keys().cloned().collect::<AHashSet<_>>();
+    output_cache::start_module(globals, &module_path, &unparsed);
+
+    let (parsed, tag) = match globals.import_cache.parsed.get(&module_path) {
+        Some((cached, parsed, tag)) if *cached == unparsed => (parsed.clone(), tag.clone()),
+        _ => {
+            let (parsed, notes) = match parser::parser::parse_spwn(
+                unparsed.clone(),
+                module_path.clone(),
+                BUILTIN_NAMES,
+            ) {
+                Ok(p) => p,
+                Err(err) => return Err(RuntimeError::PackageSyntaxError { err, info }),
+            };
+            let parsed = Rc::new(parsed);
+            globals.import_cache.parsed.insert(
+                module_path.clone(),
+                (unparsed, parsed.clone(), notes.tag.clone()),
+            );
+            (parsed, notes.tag)
+        }
+    };
 
     let mut start_context = FullContext::new(globals);
+    // the garbage collector finds the module's variables through this
+    start_context.inner().root_context_ptr = &mut start_context;
 
     globals.push_new_preserved();
     for c in contexts.with_breaks() {
@@ -1838,7 +2020,7 @@ pub fn import_module(
         stored_impl = Some(stored);
     }
 
-    if !notes.tag.tags.iter().any(|x| x.0 == "no_std") {
+    if !tag.tags.iter().any(|x| x.0 == "no_std") {
         import_module(
             &ImportType::Lib(STD_PATH.to_string()),
             &mut start_context,
@@ -1895,15 +2077,17 @@ pub fn import_module(
 
     globals.pop_preserved();
 
-    let save_value = notes.tag.tags.iter().any(|x| x.0 == "cache_output");
+    let save_value = tag.tags.iter().any(|x| x.0 == "cache_output");
     let mut out_values = 0;
     let mut output_saved = None;
     let mut impl_saved = None;
 
+    let mut returned = Vec::new();
     for fc in start_context.with_breaks() {
         let c = fc.inner();
         if let Some((r, i)) = c.broken {
             if let BreakType::Macro(v, _) = r {
+                returned.push(v.unwrap_or(globals.NULL_STORAGE));
                 for full_context in contexts.iter() {
                     let fn_context = full_context.inner().start_group;
                     (*full_context).inner().return_value = match v {
@@ -1967,17 +2151,17 @@ pub fn import_module(
         impl_saved = Some(globals.implementations.clone());
     }
 
+    let deps = output_cache::finish_module(globals, path, save_value);
     if save_value {
-        globals.prev_imports.insert(
-            path.clone(),
-            (
-                output_saved.unwrap_or(globals.NULL_STORAGE),
-                impl_saved.unwrap_or_default(),
-            ),
-        );
+        let output = output_saved.unwrap_or(globals.NULL_STORAGE);
+        let implementations = impl_saved.unwrap_or_default();
+        if let Some(file) = &cache_file {
+            output_cache::save(file, deps, output, &implementations, &types_before, globals);
+        }
+        globals.prev_imports.insert(path.clone(), (output, implementations));
     }
 
-    Ok(())
+    Ok(returned)
 }
 
 // const ID_MAX: u16 = 999;
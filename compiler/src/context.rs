@@ -382,10 +382,12 @@ impl Context {
 }
 
 //will merge one set of context, returning false if no mergable contexts were found
+// `area` is the code where the contexts are merged, for the spawn triggers this adds
 pub fn merge_contexts(
     contexts: &mut Vec<Context>,
     globals: &mut Globals,
     check_return_vals: bool,
+    area: CodeArea,
 ) -> bool {
     let mut mergable_ind = Vec::<usize>::new();
     let mut ref_c = 0;
@@ -458,7 +460,7 @@ pub fn merge_contexts(
             GdObj {
                 params,
 
-                ..context_trigger(context, &mut globals.uid_counter)
+                ..context_trigger(context, &mut globals.uid_counter, area)
             }
             .context_parameters(context),
             TriggerOrder(globals.trigger_order),
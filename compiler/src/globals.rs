@@ -13,6 +13,8 @@ use errors::compiler_info::CodeArea;
 
 use crate::context::FullContext;
 use crate::leveldata::GdObj;
+use crate::output_cache::DepTracker;
+use crate::target::Target;
 
 use crate::compiler_types::*;
 use crate::value::*;
@@ -23,8 +25,23 @@ use ahash::AHashMap;
 use crate::value_storage::*;
 use errors::compiler_info::CompilerInfo;
 
+use parser::ast;
+
 use std::io::Write;
 use std::path::PathBuf;
+use std::rc::Rc;
+
+/// Parsed modules that can be reused between builds (like in `spwn build --watch`),
+/// so only the files that changed have to be parsed again
+#[derive(Default)]
+pub struct ImportCache {
+    // source code, statements and file tag of every parsed module
+    pub parsed: AHashMap<SpwnSource, (String, Rc<Vec<ast::Statement>>, ast::Attribute)>,
+    // the files that were imported in the last build
+    pub files: Vec<PathBuf>,
+    // where the outputs of `#[cache_output]` modules are cached between builds, if they are
+    pub output_dir: Option<PathBuf>,
+}
 
 #[allow(non_snake_case)]
 pub struct Globals<'a> {
@@ -50,6 +67,8 @@ pub struct Globals<'a> {
     pub initial_objects: Option<StoredValue>,
 
     pub prev_imports: AHashMap<ImportType, (StoredValue, Implementations)>,
+    pub import_cache: ImportCache,
+    pub module_deps: DepTracker,
 
     pub trigger_order: f64,
 
@@ -60,6 +79,7 @@ pub struct Globals<'a> {
     pub includes: Vec<PathBuf>,
 
     pub permissions: BuiltinPermissions,
+    pub target: Target,
 
     pub TYPE_MEMBER_NAME: LocalIntern<String>,
     pub SELF_MEMBER_NAME: LocalIntern<String>,
@@ -242,6 +262,8 @@ Arrays are used to store lists of values at compile time.
             type_ids: AHashMap::default(),
 
             prev_imports: AHashMap::default(),
+            import_cache: ImportCache::default(),
+            module_deps: DepTracker::default(),
             type_id_count: 0,
             trigger_order: 0.0,
             uid_counter: 0,
@@ -263,6 +285,7 @@ Arrays are used to store lists of values at compile time.
             includes: Vec::new(),
 
             permissions,
+            target: Target::default(),
             TYPE_MEMBER_NAME: LocalIntern::new(String::from("type")),
             SELF_MEMBER_NAME: LocalIntern::new(String::from("self")),
             BUILTIN_STORAGE: builtin_storage,
@@ -428,6 +451,9 @@ Arrays are used to store lists of values at compile time.
 
             self.stored_values.mark(*v);
         }
+        for v in self.module_deps.roots() {
+            self.stored_values.mark(v);
+        }
         //dbg!(&self.stored_values.map);
 
         //sweep
@@ -3,13 +3,16 @@
 use crate::builtins::*;
 use crate::compiler_types::FunctionId;
 use crate::context::Context;
+use crate::target::Target;
 use ahash::{AHashMap, AHashSet};
+use errors::compiler_info::CodeArea;
 use parser::ast::ObjectMode;
+use serde::{Deserialize, Serialize};
 use std::hash::Hash;
 
 pub struct TriggerOrder(f32);
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum ObjParam {
     Group(Group),
     Color(Color),
@@ -19,6 +22,9 @@ pub enum ObjParam {
     Bool(bool),
     Text(String),
     GroupList(Vec<Group>),
+    // groups that each come with a number, like the chances of an advanced random trigger
+    // or the counts of a sequence trigger
+    WeightedGroups(Vec<(Group, f64)>),
     Epsilon,
 }
 // this is so bruh
@@ -34,6 +40,12 @@ impl Hash for ObjParam {
             ObjParam::Bool(v) => v.hash(state),
             ObjParam::Text(v) => v.hash(state),
             ObjParam::GroupList(v) => v.hash(state),
+            ObjParam::WeightedGroups(v) => {
+                for (g, n) in v {
+                    g.hash(state);
+                    ((*n * 100000.0) as usize).hash(state);
+                }
+            }
             ObjParam::Epsilon => "epsilon".hash(state),
         }
     }
@@ -104,6 +116,13 @@ impl fmt::Display for ObjParam {
                 out.pop();
                 write!(f, "{}", out)
             }
+            ObjParam::WeightedGroups(list) => {
+                let list = list
+                    .iter()
+                    .map(|(g, n)| format!("{}.{}", ObjParam::Group(*g), ObjParam::Number(*n)))
+                    .collect::<Vec<_>>();
+                write!(f, "{}", list.join("."))
+            }
             ObjParam::Epsilon => write!(f, "0.05"),
         }
     }
@@ -118,6 +137,8 @@ pub struct GdObj {
     pub params: AHashMap<u16, ObjParam>,
     pub mode: ObjectMode,
     pub unique_id: usize,
+    // the code that created this object
+    pub source: CodeArea,
 }
 
 impl GdObj {
@@ -159,6 +180,8 @@ pub fn get_used_ids(ls: &str) -> [AHashSet<u16>; 4] {
                     match (map.get("1"), map.get("52")) {
                         (Some(&"1006"), Some(&"1")) => out[0].insert(value.parse().unwrap()),
                         (Some(&"1006"), _) => out[1].insert(value.parse().unwrap()),
+                        // item edit trigger's target item
+                        (Some(&"3619"), _) => out[3].insert(value.parse().unwrap()),
                         _ => out[0].insert(value.parse().unwrap()),
                     };
                 }
@@ -188,7 +211,23 @@ pub fn get_used_ids(ls: &str) -> [AHashSet<u16>; 4] {
                 }
 
                 "95" => {
-                    out[2].insert(value.parse().unwrap());
+                    match map.get("1") {
+                        // item edit and item compare triggers use a second item
+                        Some(&"3619") | Some(&"3620") => out[3].insert(value.parse().unwrap()),
+                        _ => out[2].insert(value.parse().unwrap()),
+                    };
+                }
+                "442" => {
+                    //spawn trigger remaps
+                    for g in value.split('.').filter(|g| !g.is_empty()) {
+                        out[0].insert(g.parse().unwrap());
+                    }
+                }
+                "152" | "435" => {
+                    //advanced random chances, sequence counts
+                    for g in value.split('.').step_by(2).filter(|g| !g.is_empty()) {
+                        out[0].insert(g.parse().unwrap());
+                    }
                 }
                 //some of these depends on what object it is
                 //pulse target depends on group mode/color mode
@@ -239,11 +278,43 @@ pub fn remove_spwn_objects(file_content: &mut String) {
 pub fn append_objects(
     mut objects: Vec<GdObj>,
     old_ls: &str,
+    target: Target,
 ) -> Result<(String, [usize; 4]), String> {
+    let used_ids = resolve_ids(&mut objects, old_ls, target)?;
+    Ok((serialize_objects(objects), used_ids))
+}
+
+/// Replaces all arbitrary IDs with free specific ones,
+/// and returns how many groups, colors, block IDs and item IDs the level uses.
+/// Fails if the level needs more IDs than the target allows
+pub fn resolve_ids(
+    objects: &mut [GdObj],
+    old_ls: &str,
+    target: Target,
+) -> Result<[usize; 4], String> {
     let mut closed_ids = get_used_ids(old_ls);
 
+    let signature = match SPWN_SIGNATURE_GROUP.id {
+        Id::Specific(n) => n,
+        _ => unreachable!(),
+    };
+    // the objects in the signature group are removed from the level the next time it's built
+    if let Some(obj) = objects.iter().find(|obj| {
+        obj.params
+            .values()
+            .any(|p| class_ids(0, p).contains(&SPWN_SIGNATURE_GROUP.id))
+    }) {
+        return Err(format!(
+            "Group {} can't be used, since it marks the objects SPWN adds to the level (used in {})",
+            signature,
+            module_name(&obj.source.file)
+        ));
+    }
+    // 2.2 has groups above it, and arbitrary groups mustn't get it
+    closed_ids[0].insert(signature);
+
     //collect all specific ids mentioned into closed_[id] lists
-    for obj in &objects {
+    for obj in objects.iter() {
         for prop in obj.params.values() {
             let class_index;
             let id;
@@ -258,6 +329,11 @@ pub fn append_objects(
 
                     id = l.iter().map(|g| g.id).collect();
                 }
+                ObjParam::WeightedGroups(l) => {
+                    class_index = 0;
+
+                    id = l.iter().map(|(g, _)| g.id).collect();
+                }
                 ObjParam::Color(g) => {
                     class_index = 1;
                     id = vec![g.id];
@@ -291,9 +367,10 @@ pub fn append_objects(
         AHashMap::default(),
     ];
 
-    const ID_MAX: u16 = 999;
+    let limits = target.id_limits();
+    let mut overflow = None;
 
-    for obj in &mut objects {
+    'objects: for obj in objects.iter_mut() {
         for prop in obj.params.values_mut() {
             let class_index;
             let ids: Vec<&mut Id>;
@@ -306,6 +383,10 @@ pub fn append_objects(
                     class_index = 0;
                     ids = g.iter_mut().map(|x| &mut x.id).collect();
                 }
+                ObjParam::WeightedGroups(g) => {
+                    class_index = 0;
+                    ids = g.iter_mut().map(|(x, _)| &mut x.id).collect();
+                }
                 ObjParam::Color(g) => {
                     class_index = 1;
                     ids = vec![&mut g.id];
@@ -326,22 +407,15 @@ pub fn append_objects(
                         *id = Id::Specific(match id_maps[class_index].get(i) {
                             Some(a) => *a,
                             None => {
-                                let mut out = None;
-                                for i in 1..10000 {
-                                    if !closed_ids[class_index].contains(&i) {
-                                        out = Some(i);
-                                        closed_ids[class_index].insert(i);
-                                        break;
-                                    }
-                                }
-                                if let Some(id) = out {
+                                let free = (1..=limits[class_index])
+                                    .find(|i| !closed_ids[class_index].contains(i));
+                                if let Some(id) = free {
+                                    closed_ids[class_index].insert(id);
                                     id_maps[class_index].insert(*i, id);
                                     id
                                 } else {
-                                    return Err(format!(
-                                        "This level exceeds the {} limit!",
-                                        ["group", "color", "block ID", "item ID"][class_index]
-                                    ));
+                                    overflow = Some(class_index);
+                                    break 'objects;
                                 }
                             }
                         })
@@ -351,20 +425,138 @@ pub fn append_objects(
             }
         }
     }
-    for (i, list) in closed_ids.iter_mut().enumerate() {
+    for list in closed_ids.iter_mut() {
         list.remove(&0);
-        if list.len() > ID_MAX as usize {
-            return Err(format!(
-                "This level exceeds the {} limit! ({}/{})",
-                ["group", "color", "block ID", "item ID"][i],
-                list.len(),
-                ID_MAX
-            ));
+    }
+    closed_ids[0].remove(&signature);
+    if let Some(i) = overflow {
+        return Err(limit_error(objects, i, &closed_ids[i], &id_maps[i], target));
+    }
+    for (i, list) in closed_ids.iter().enumerate() {
+        if list.len() > limits[i] as usize {
+            return Err(limit_error(objects, i, list, &id_maps[i], target));
         }
     }
 
     //println!("group_map: {:?}", id_maps[0]);
 
+    Ok([
+        closed_ids[0].len(),
+        closed_ids[1].len(),
+        closed_ids[2].len(),
+        closed_ids[3].len(),
+    ])
+}
+
+// the IDs the level would need are the ones already used, and the arbitrary ones that couldn't get one
+fn limit_error(
+    objects: &[GdObj],
+    class_index: usize,
+    used: &AHashSet<SpecificId>,
+    id_map: &AHashMap<ArbitraryId, SpecificId>,
+    target: Target,
+) -> String {
+    let class_name = ["group", "color", "block ID", "item ID"][class_index];
+    let limit = target.id_limits()[class_index];
+
+    let mut unresolved = AHashSet::<ArbitraryId>::default();
+    for obj in objects {
+        for param in obj.params.values() {
+            for id in class_ids(class_index, param) {
+                if let Id::Arbitrary(id) = id {
+                    if !id_map.contains_key(&id) {
+                        unresolved.insert(id);
+                    }
+                }
+            }
+        }
+    }
+
+    let modules = usage_by_module(objects, class_index, id_map)
+        .into_iter()
+        .take(5)
+        .map(|(module, count)| format!("\n    {}: {}", module, count))
+        .collect::<String>();
+
+    let raised = Target::ALL
+        .into_iter()
+        .find(|t| t.id_limits()[class_index] > limit)
+        .map(|t| {
+            format!(
+                "\n({} allows up to {} {}s)",
+                t,
+                t.id_limits()[class_index],
+                class_name
+            )
+        })
+        .unwrap_or_default();
+
+    format!(
+        "This level exceeds the {} {} limit! ({}/{})\nThe modules that use the most {}s:{}{}",
+        target,
+        class_name,
+        used.len() + unresolved.len(),
+        limit,
+        class_name,
+        modules,
+        raised
+    )
+}
+
+fn class_ids(class_index: usize, param: &ObjParam) -> Vec<Id> {
+    match (class_index, param) {
+        (0, ObjParam::Group(g)) => vec![g.id],
+        (0, ObjParam::GroupList(l)) => l.iter().map(|g| g.id).collect(),
+        (0, ObjParam::WeightedGroups(l)) => l.iter().map(|(g, _)| g.id).collect(),
+        (1, ObjParam::Color(c)) => vec![c.id],
+        (2, ObjParam::Block(b)) => vec![b.id],
+        (3, ObjParam::Item(i)) => vec![i.id],
+        _ => Vec::new(),
+    }
+}
+
+// how many arbitrary IDs the objects of each module use, from most to least
+fn usage_by_module(
+    objects: &[GdObj],
+    class_index: usize,
+    id_map: &AHashMap<ArbitraryId, SpecificId>,
+) -> Vec<(String, usize)> {
+    use shared::SpwnSource;
+
+    let allocated = id_map.values().copied().collect::<AHashSet<_>>();
+    let mut used = AHashMap::<&SpwnSource, AHashSet<Id>>::default();
+    for obj in objects {
+        for param in obj.params.values() {
+            for id in class_ids(class_index, param) {
+                // the objects after the limit was hit still have their arbitrary IDs
+                let id = match id {
+                    Id::Specific(id) if allocated.contains(&id) => Id::Specific(id),
+                    Id::Arbitrary(a) => id_map.get(&a).map_or(id, |s| Id::Specific(*s)),
+                    Id::Specific(_) => continue,
+                };
+                used.entry(&*obj.source.file).or_default().insert(id);
+            }
+        }
+    }
+
+    let mut modules = used
+        .into_iter()
+        .map(|(source, ids)| (module_name(source), ids.len()))
+        .collect::<Vec<_>>();
+    modules.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    modules
+}
+
+fn module_name(source: &shared::SpwnSource) -> String {
+    use shared::SpwnSource;
+
+    match source {
+        SpwnSource::File(path) | SpwnSource::BuiltIn(path) => path.display().to_string(),
+        SpwnSource::String(_) => String::from("source"),
+    }
+}
+
+pub fn serialize_objects(objects: Vec<GdObj>) -> String {
     fn serialize_obj(mut trigger: GdObj) -> String {
         let mut obj_string = String::new();
         match trigger.mode {
@@ -440,15 +632,85 @@ pub fn append_objects(
     for obj in objects {
         full_obj_string += &serialize_obj(obj)
     }
-    Ok((
-        full_obj_string,
-        [
-            closed_ids[0].len(),
-            closed_ids[1].len(),
-            closed_ids[2].len(),
-            closed_ids[3].len(),
-        ],
-    ))
+    full_obj_string
+}
+
+/// A JSON map from the added objects (by index) and their groups to the code that created them.
+/// The IDs should already be resolved with `resolve_ids`.
+pub fn source_map(objects: &[GdObj]) -> String {
+    use serde_json::{json, Map, Value};
+    use shared::SpwnSource;
+
+    let mut sources = AHashMap::<SpwnSource, Option<String>>::default();
+    let mut location = |area: &CodeArea| {
+        let file = &*area.file;
+        let text = sources.entry(file.clone()).or_insert_with(|| {
+            let text = match file {
+                SpwnSource::File(path) => std::fs::read_to_string(path).ok(),
+                SpwnSource::BuiltIn(path) => {
+                    get_lib_file(path).and_then(|f| f.contents_utf8().map(str::to_string))
+                }
+                SpwnSource::String(s) => Some(s.as_ref().clone()),
+            };
+            // the positions are in the text the parser reads, which has `\n` line endings
+            text.map(|t| t.replace("\r\n", "\n"))
+        });
+
+        let (line, column) = match text {
+            Some(text) => {
+                let before = &text[..area.pos.0.min(text.len())];
+                let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+                (
+                    before.matches('\n').count() + 1,
+                    before[line_start..].chars().count() + 1,
+                )
+            }
+            None => (0, 0),
+        };
+        let file = match file {
+            SpwnSource::File(path) | SpwnSource::BuiltIn(path) => path.display().to_string(),
+            SpwnSource::String(_) => String::from("source"),
+        };
+        json!({ "file": file, "line": line, "column": column })
+    };
+
+    let mut object_list = Vec::new();
+    let mut groups = Map::new();
+
+    for (index, obj) in objects.iter().enumerate() {
+        let source = location(&obj.source);
+
+        let obj_groups = match obj.params.get(&57) {
+            Some(ObjParam::Group(g)) => vec![*g],
+            Some(ObjParam::GroupList(l)) => l.clone(),
+            _ => Vec::new(),
+        };
+        let obj_groups = obj_groups
+            .iter()
+            .filter_map(|g| match g.id {
+                Id::Specific(id) if id != 0 => Some(id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        for id in &obj_groups {
+            let list = groups
+                .entry(id.to_string())
+                .or_insert_with(|| Value::Array(Vec::new()));
+            if let Value::Array(list) = list {
+                if !list.contains(&source) {
+                    list.push(source.clone());
+                }
+            }
+        }
+
+        let mut entry = source;
+        entry["index"] = json!(index);
+        entry["groups"] = json!(obj_groups);
+        object_list.push(entry);
+    }
+
+    serde_json::to_string_pretty(&json!({ "objects": object_list, "groups": groups })).unwrap()
 }
 
 pub fn apply_fn_ids(func_ids: &[FunctionId]) -> Vec<GdObj> {
@@ -542,3 +804,170 @@ pub fn apply_fn_ids(func_ids: &[FunctionId]) -> Vec<GdObj> {
 
     full_obj_list
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile_spwn_quiet;
+    use crate::parse_levelstring::parse_levelstring;
+    use internment::LocalIntern;
+    use serde_json::{json, Value};
+    use shared::SpwnSource;
+
+    fn compile(code: &str) -> Vec<GdObj> {
+        let source = SpwnSource::String(LocalIntern::new(code.to_string()));
+        let (statements, notes) =
+            parser::parser::parse_spwn(code.to_string(), source.clone(), BUILTIN_NAMES).unwrap();
+        let mut std_out = Vec::new();
+        let compiled = compile_spwn_quiet(
+            statements,
+            source,
+            Vec::new(),
+            notes,
+            Default::default(),
+            Default::default(),
+            String::new(),
+            &mut std_out,
+            &mut Default::default(),
+        )
+        .unwrap_or_else(|_| panic!("compile error"));
+
+        let mut objects = apply_fn_ids(&compiled.func_ids);
+        objects.extend(compiled.objects);
+        resolve_ids(&mut objects, "", Default::default()).unwrap();
+        objects
+    }
+
+    #[test]
+    fn source_map_locations() {
+        let objects = compile(
+            "#[no_std]
+$.add(obj { 1: 1, 57: 5g })
+f = !{
+    $.add(trigger { 1: 1268, 51: 7g })
+}
+f!
+",
+        );
+        let map: Value = serde_json::from_str(&source_map(&objects)).unwrap();
+        let at = |line, column| json!({ "file": "source", "line": line, "column": column });
+
+        // the triggers of the function contexts come first
+        assert_eq!(
+            map["objects"],
+            json!([
+                { "file": "source", "line": 4, "column": 5, "index": 0, "groups": [1] },
+                { "file": "source", "line": 6, "column": 1, "index": 1, "groups": [] },
+                { "file": "source", "line": 2, "column": 1, "index": 2, "groups": [5] },
+            ])
+        );
+        assert_eq!(map["groups"], json!({ "1": [at(4, 5)], "5": [at(2, 1)] }));
+
+        // lines are counted the same with `\r\n` line endings, and columns in characters
+        let objects = compile("#[no_std]\r\n// ünïcödé\r\n\r\nx = 'é'; $.add(obj { 1: 1 })\r\n");
+        let map: Value = serde_json::from_str(&source_map(&objects)).unwrap();
+        assert_eq!(
+            map["objects"],
+            json!([{ "file": "source", "line": 4, "column": 10, "index": 0, "groups": [] }])
+        );
+    }
+
+    fn block(group: Id) -> GdObj {
+        GdObj {
+            func_id: 0,
+            params: [
+                (1, ObjParam::Number(1.0)),
+                (57, ObjParam::Group(Group { id: group })),
+            ]
+            .into_iter()
+            .collect(),
+            mode: ObjectMode::Object,
+            unique_id: 0,
+            source: Default::default(),
+        }
+    }
+
+    fn group_of(obj: &GdObj) -> Id {
+        match obj.params.get(&57) {
+            Some(ObjParam::Group(g)) => g.id,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn skips_signature_group() {
+        let mut objects = (1..=1000)
+            .map(|id| block(Id::Specific(id)))
+            .chain([block(Id::Arbitrary(1))])
+            .collect::<Vec<_>>();
+        let used = resolve_ids(&mut objects, "", Target::Gd22).unwrap();
+        assert_eq!(group_of(&objects[1000]), Id::Specific(1002));
+        assert_eq!(used[0], 1001);
+    }
+
+    #[test]
+    fn rejects_signature_group() {
+        let mut objects = vec![block(SPWN_SIGNATURE_GROUP.id)];
+        for target in Target::ALL {
+            let err = resolve_ids(&mut objects, "", target).unwrap_err();
+            assert!(err.starts_with("Group 1001 can't be used"));
+        }
+    }
+
+    fn parse(ls: &str) -> Vec<Vec<(u16, ObjParam)>> {
+        parse_levelstring(ls)
+            .unwrap()
+            .into_iter()
+            .map(|obj| match obj {
+                crate::value::Value::Obj(params, _) => params,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    fn specific(id: u16) -> Group {
+        Group {
+            id: Id::Specific(id),
+        }
+    }
+
+    #[test]
+    fn classifies_item_trigger_ids() {
+        // an item edit trigger, an item compare trigger and a collision block
+        let ls = "kS38,1_40_2_125_3_255;1,3619,51,1,80,2,95,3;1,3620,51,4,80,5,95,6;1,1816,80,7;";
+        let item = |id| {
+            ObjParam::Item(Item {
+                id: Id::Specific(id),
+            })
+        };
+
+        let objects = parse(ls);
+        assert_eq!(
+            objects[0][1..],
+            [(51, item(1)), (80, item(2)), (95, item(3))]
+        );
+        // the item compare trigger's target is the group it activates
+        assert_eq!(
+            objects[1][1..],
+            [
+                (51, ObjParam::Group(specific(4))),
+                (80, item(5)),
+                (95, item(6))
+            ]
+        );
+
+        let [groups, _, blocks, items] = get_used_ids(ls);
+        assert_eq!(groups, [4].into_iter().collect());
+        assert_eq!(blocks, [7].into_iter().collect());
+        assert_eq!(items, [1, 2, 3, 5, 6].into_iter().collect());
+    }
+
+    #[test]
+    fn weighted_groups_round_trip() {
+        let chances = ObjParam::WeightedGroups(vec![(specific(3), 1.0), (specific(4), 25.0)]);
+        assert_eq!(chances.to_string(), "3.1.4.25");
+
+        let objects = parse(&format!(";1,3607,435,{};", chances));
+        assert_eq!(objects[0][1], (435, chances));
+    }
+}
@@ -8,7 +8,10 @@ pub mod compiler_types;
 pub mod context;
 pub mod globals;
 pub mod leveldata;
+pub mod libraries;
+pub mod output_cache;
 pub mod parse_levelstring;
+pub mod target;
 pub mod value;
 pub mod value_storage;
 
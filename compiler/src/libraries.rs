@@ -0,0 +1,241 @@
+// finds the libraries that pckp installed in the `pckp_libraries` folder of a project,
+// which are in `<name>@<version>`, or in `<name>` for packages installed from their latest commit
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use semver::{Version, VersionReq};
+use shared::{LOCK_FILE_NAME, PACKAGE_DIR};
+
+pub enum InstalledLibrary {
+    Found(PathBuf),
+    /// There is no package with this name in `pckp_libraries`
+    NotInstalled,
+    /// The package is installed, but not in a version that matches. Has the installed versions
+    NoMatch(Vec<String>),
+}
+
+struct Installed {
+    dir: PathBuf,
+    // `None` for a package installed from its latest commit that isn't in the lock file
+    version: Option<String>,
+}
+
+/// The project folder with a `pckp_libraries` folder that a file in `dir` belongs to
+pub fn find_project(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|d| d.join(PACKAGE_DIR).is_dir())
+        .map(Path::to_path_buf)
+}
+
+/// Finds the installed version of a library that matches `version` (`latest`, a semver
+/// requirement like `^1.2`, or a tag). Without a version, the version in `pckp.lock` is used
+pub fn find_library(project: &Path, name: &str, version: Option<&str>) -> InstalledLibrary {
+    let locked = locked_versions(project, name);
+    let installed = match fs::read_dir(project.join(PACKAGE_DIR)) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| {
+                let file_name = e.file_name().to_string_lossy().to_string();
+                let version = if file_name == name {
+                    // the lock file has the version of packages installed without one
+                    locked.first().cloned()
+                } else {
+                    Some(file_name.strip_prefix(name)?.strip_prefix('@')?.to_string())
+                };
+                Some(Installed {
+                    dir: e.path(),
+                    version,
+                })
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    if installed.is_empty() {
+        return InstalledLibrary::NotInstalled;
+    }
+    let unversioned = || installed.iter().find(|i| i.dir.ends_with(name));
+
+    let found = match version {
+        None => installed
+            .iter()
+            .find(|i| i.version.is_some() && locked.contains(i.version.as_ref().unwrap()))
+            .or_else(unversioned)
+            .or_else(|| newest(installed.iter())),
+        Some("latest") => unversioned().or_else(|| newest(installed.iter())),
+        Some(requirement) => {
+            // a full version only matches that version, like in pckp.yaml
+            let req = match Version::parse(requirement) {
+                Ok(v) => VersionReq::parse(&format!("={}", v)).ok(),
+                Err(_) => VersionReq::parse(requirement).ok(),
+            };
+            newest(installed.iter().filter(|i| match &i.version {
+                Some(v) if v == requirement => true,
+                Some(v) => match (&req, parse_version(v)) {
+                    (Some(req), Some(v)) => req.matches(&v),
+                    _ => false,
+                },
+                None => false,
+            }))
+        }
+    };
+    match found {
+        Some(i) => InstalledLibrary::Found(i.dir.clone()),
+        None => {
+            let mut versions = installed
+                .iter()
+                .map(|i| i.version.clone().unwrap_or_else(|| "latest".to_string()))
+                .collect::<Vec<_>>();
+            versions.sort();
+            InstalledLibrary::NoMatch(versions)
+        }
+    }
+}
+
+// the highest semver version, or any of them if none are semver
+fn newest<'a>(installed: impl Iterator<Item = &'a Installed>) -> Option<&'a Installed> {
+    installed.max_by_key(|i| i.version.as_deref().and_then(parse_version))
+}
+
+fn parse_version(version: &str) -> Option<Version> {
+    Version::parse(version.strip_prefix('v').unwrap_or(version)).ok()
+}
+
+// the versions of the packages with this name in the project's lock file
+fn locked_versions(project: &Path, name: &str) -> Vec<String> {
+    let lock = fs::read_to_string(project.join(LOCK_FILE_NAME))
+        .ok()
+        .and_then(|text| serde_yaml::from_str::<serde_yaml::Value>(&text).ok());
+    let packages = match lock.as_ref().and_then(|l| l["packages"].as_sequence()) {
+        Some(packages) => packages,
+        None => return Vec::new(),
+    };
+    packages
+        .iter()
+        .filter(|p| p["name"].as_str() == Some(name))
+        .filter_map(|p| match &p["version"] {
+            serde_yaml::Value::String(s) => Some(s.clone()),
+            serde_yaml::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a project with these folders in `pckp_libraries`, and these `(name, version)`s in its lock file
+    struct Project(PathBuf);
+
+    impl Project {
+        fn new(test: &str, installed: &[&str], locked: &[(&str, &str)]) -> Project {
+            let dir = std::env::temp_dir().join(format!(
+                "spwn-libraries-{}-{}",
+                test,
+                std::process::id()
+            ));
+            fs::remove_dir_all(&dir).ok();
+            for folder in installed {
+                fs::create_dir_all(dir.join(PACKAGE_DIR).join(folder)).unwrap();
+            }
+            let lock = locked
+                .iter()
+                .map(|(name, version)| format!("  - name: {}\n    version: {}\n", name, version))
+                .collect::<String>();
+            fs::write(dir.join(LOCK_FILE_NAME), format!("packages:\n{}", lock)).unwrap();
+            Project(dir)
+        }
+
+        fn find(&self, name: &str, version: Option<&str>) -> Result<String, Vec<String>> {
+            match find_library(&self.0, name, version) {
+                InstalledLibrary::Found(dir) => {
+                    Ok(dir.file_name().unwrap().to_string_lossy().to_string())
+                }
+                InstalledLibrary::NotInstalled => Err(Vec::new()),
+                InstalledLibrary::NoMatch(versions) => Err(versions),
+            }
+        }
+    }
+
+    impl Drop for Project {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn lock_file_version() {
+        let p = Project::new(
+            "lock",
+            &["foo@1.0.0", "foo@1.2.0", "bar"],
+            &[("foo", "1.0.0"), ("bar", "0.3.0")],
+        );
+        assert_eq!(p.find("foo", None), Ok("foo@1.0.0".to_string()));
+        // packages installed from their latest commit get their version from the lock file
+        assert_eq!(p.find("bar", Some("~0.3")), Ok("bar".to_string()));
+    }
+
+    #[test]
+    fn latest() {
+        let p = Project::new(
+            "latest",
+            &["foo", "foo@1.0.0", "bar@1.0.0", "bar@1.1.0"],
+            &[],
+        );
+        assert_eq!(p.find("foo", Some("latest")), Ok("foo".to_string()));
+        assert_eq!(p.find("bar", Some("latest")), Ok("bar@1.1.0".to_string()));
+        assert_eq!(p.find("bar", None), Ok("bar@1.1.0".to_string()));
+    }
+
+    #[test]
+    fn semver_range() {
+        let p = Project::new("range", &["foo@1.0.0", "foo@v1.2.0", "foo@2.0.0"], &[]);
+        assert_eq!(p.find("foo", Some("^1")), Ok("foo@v1.2.0".to_string()));
+        assert_eq!(
+            p.find("foo", Some(">=1.1, <3")),
+            Ok("foo@2.0.0".to_string())
+        );
+        assert_eq!(p.find("foo", Some("~1.0")), Ok("foo@1.0.0".to_string()));
+        // full versions are pinned
+        assert_eq!(p.find("foo", Some("1.0.0")), Ok("foo@1.0.0".to_string()));
+        assert_eq!(p.find("foo", Some("1.2.0")), Ok("foo@v1.2.0".to_string()));
+    }
+
+    #[test]
+    fn tag() {
+        let p = Project::new("tag", &["foo@nightly", "foo@1.0.0"], &[]);
+        assert_eq!(
+            p.find("foo", Some("nightly")),
+            Ok("foo@nightly".to_string())
+        );
+        assert_eq!(p.find("foo", Some("1.0.0")), Ok("foo@1.0.0".to_string()));
+    }
+
+    #[test]
+    fn prefix_collision() {
+        let p = Project::new(
+            "prefix",
+            &["foobar@1.0.0", "foobar"],
+            &[("foobar", "1.0.0")],
+        );
+        assert_eq!(p.find("foo", None), Err(Vec::new()));
+        assert_eq!(p.find("foo", Some("^1")), Err(Vec::new()));
+
+        let p = Project::new("prefix2", &["foo@2.0.0", "foobar@1.0.0"], &[]);
+        assert_eq!(p.find("foo", Some("^1")), Err(vec!["2.0.0".to_string()]));
+    }
+
+    #[test]
+    fn no_match() {
+        let p = Project::new("nomatch", &["foo@2.0.0", "foo", "foo@1.0.0"], &[]);
+        assert_eq!(
+            p.find("foo", Some("^3")),
+            Err(vec![
+                "1.0.0".to_string(),
+                "2.0.0".to_string(),
+                "latest".to_string()
+            ])
+        );
+    }
+}
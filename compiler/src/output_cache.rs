@@ -0,0 +1,702 @@
+// the on-disk cache of modules tagged with `#[cache_output]`, so unchanged libraries
+// (like the standard library) don't have to be evaluated again in every build
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ahash::{AHashMap, AHashSet};
+use errors::compiler_info::CodeArea;
+use internment::LocalIntern;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use shared::{ImportType, SpwnSource, StoredValue};
+
+use crate::builtins::*;
+use crate::compiler_types::{Implementations, TypeId};
+use crate::globals::Globals;
+use crate::value::*;
+use crate::value_storage::store_val_m;
+
+/// Environment variable that overrides the cache directory
+pub const CACHE_ENV: &str = "SPWN_CACHE";
+
+// changed whenever the format of cached modules changes
+const CACHE_FORMAT: u32 = 2;
+
+/// The cache directory: `SPWN_CACHE` if it is set, otherwise the user's cache folder
+pub fn cache_dir() -> PathBuf {
+    shared::cache_dir(CACHE_ENV, "spwn")
+}
+
+/// The files a module was built from, including everything it imports
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModuleDeps {
+    // every file and the hash of its source
+    files: Vec<(SpwnSource, String)>,
+    // false if building the module did something besides returning a value,
+    // like adding triggers or using arbitrary IDs, which a cached output wouldn't do
+    pure: bool,
+}
+
+/// Tracks which files the modules that are being built depend on
+#[derive(Default)]
+pub struct DepTracker {
+    // the modules being built, innermost last, with the effects before they started
+    building: Vec<(ModuleDeps, Effects)>,
+    // dependencies of the modules in `Globals::prev_imports`
+    finished: AHashMap<ImportType, ModuleDeps>,
+    // the modules that were written to or loaded from the cache in this build, by the name of their file
+    cached: AHashMap<String, CachedOutput>,
+    // the cached module and the index in its values of every value in `cached`
+    owners: AHashMap<StoredValue, (String, usize)>,
+}
+
+impl DepTracker {
+    /// The values of the cached modules, which other modules that are loaded later can refer to
+    pub fn roots(&self) -> impl Iterator<Item = StoredValue> + '_ {
+        self.cached.values().flat_map(|m| {
+            let implementations = m.implementations.values().flat_map(|imp| imp.values());
+            std::iter::once(m.output).chain(implementations.map(|(v, _)| *v))
+        })
+    }
+}
+
+// a module in the cache, as it is in this build
+struct CachedOutput {
+    // the hash of its file
+    stamp: String,
+    values: Vec<StoredValue>,
+    output: StoredValue,
+    implementations: Implementations,
+    deps: ModuleDeps,
+}
+
+// counters that change when a module does more than return a value
+type Effects = [usize; 7];
+
+fn effects(globals: &Globals) -> Effects {
+    [
+        globals.closed_groups as usize,
+        globals.closed_colors as usize,
+        globals.closed_blocks as usize,
+        globals.closed_items as usize,
+        globals.uid_counter,
+        globals.objects.len(),
+        globals.func_ids.len(),
+    ]
+}
+
+fn hash(source: &str) -> String {
+    hash_bytes(source.as_bytes())
+}
+
+fn hash_bytes(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// the source of a dependency as it is now, `None` if it's gone
+fn read_source(source: &SpwnSource) -> Option<String> {
+    match source {
+        SpwnSource::File(path) => fs::read_to_string(path).ok(),
+        SpwnSource::BuiltIn(path) => get_lib_file(path)?.contents_utf8().map(String::from),
+        SpwnSource::String(_) => None,
+    }
+}
+
+/// Starts tracking the dependencies of a module that is about to be built
+pub fn start_module(globals: &mut Globals, source: &SpwnSource, unparsed: &str) {
+    let deps = ModuleDeps {
+        files: vec![(source.clone(), hash(unparsed))],
+        pure: true,
+    };
+    let effects = effects(globals);
+    globals.module_deps.building.push((deps, effects));
+}
+
+/// Stops tracking the module that was being built, and adds its dependencies to the module that imported it
+pub fn finish_module(globals: &mut Globals, path: &ImportType, cached: bool) -> ModuleDeps {
+    let (mut deps, before) = globals.module_deps.building.pop().unwrap();
+    deps.pure &= effects(globals) == before;
+    add_deps(globals, &deps);
+    if cached {
+        globals
+            .module_deps
+            .finished
+            .insert(path.clone(), deps.clone());
+    }
+    deps
+}
+
+/// Records the dependencies of a module that was loaded from the cache, whose files are watched like the ones of built modules
+pub fn loaded_module(globals: &mut Globals, path: &ImportType, deps: ModuleDeps) {
+    for (source, _) in &deps.files {
+        if let SpwnSource::File(file) = source {
+            if !globals.import_cache.files.contains(file) {
+                globals.import_cache.files.push(file.clone());
+            }
+        }
+    }
+    add_deps(globals, &deps);
+    globals.module_deps.finished.insert(path.clone(), deps);
+}
+
+/// Adds the dependencies of a module that was imported before to the module that is being built
+pub fn reuse_module(globals: &mut Globals, path: &ImportType) {
+    if let Some(deps) = globals.module_deps.finished.get(path).cloned() {
+        add_deps(globals, &deps);
+    }
+}
+
+/// Stops the module that is being built from being cached, for built-in functions whose result
+/// isn't only the module's output (like printing, using files, reading the level and random numbers)
+pub fn impure(globals: &mut Globals) {
+    if let Some((deps, _)) = globals.module_deps.building.last_mut() {
+        deps.pure = false;
+    }
+}
+
+fn add_deps(globals: &mut Globals, deps: &ModuleDeps) {
+    if let Some((parent, _)) = globals.module_deps.building.last_mut() {
+        for file in &deps.files {
+            if !parent.files.contains(file) {
+                parent.files.push(file.clone());
+            }
+        }
+        parent.pure &= deps.pure;
+    }
+}
+
+/// The file a module's output is cached in, which depends on its source, the compiler version,
+/// the allowed built-in functions and the target
+pub fn cache_path(dir: &Path, source: &SpwnSource, unparsed: &str, globals: &Globals) -> PathBuf {
+    let permissions = BUILTIN_LIST
+        .iter()
+        .map(|b| {
+            if globals.permissions.is_allowed(*b) {
+                '1'
+            } else {
+                '0'
+            }
+        })
+        .collect::<String>();
+    let key = format!(
+        "{}\n{}\n{}\n{}\n{:?}\n{}",
+        env!("CARGO_PKG_VERSION"),
+        CACHE_FORMAT,
+        permissions,
+        globals.target,
+        source,
+        unparsed
+    );
+    dir.join("modules").join(hash(&key))
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedValue {
+    val: Value,
+    fn_context: Group,
+    mutable: bool,
+    def_area: CodeArea,
+}
+
+// the members implemented on a type, and if they were implemented in the module
+type CachedMembers = Vec<(LocalIntern<String>, StoredValue, bool)>;
+
+#[derive(Serialize, Deserialize)]
+struct CachedModule {
+    deps: ModuleDeps,
+    // every value the output and implementations refer to, except the ones of other cached modules
+    values: Vec<(StoredValue, CachedValue)>,
+    // the other cached modules whose values are used, with the hash of their file
+    imports: Vec<(String, String)>,
+    // the values of those modules: the value, the module's index in `imports` and the index of the value in the module
+    external: Vec<(StoredValue, usize, usize)>,
+    output: StoredValue,
+    // the `$` value of the build the module was cached in, which isn't cached like null
+    builtins: StoredValue,
+    implementations: Vec<(TypeId, CachedMembers)>,
+    // the types that are defined in the module or used by its values, which are
+    // found by name when the module is loaded since their IDs can be different
+    types: Vec<(TypeId, String, CodeArea, Option<String>)>,
+}
+
+// calls `val` on every value this value refers to, and `typ` on every type ID in it
+fn visit_refs(
+    value: &mut Value,
+    val: &mut impl FnMut(&mut StoredValue),
+    typ: &mut impl FnMut(&mut TypeId),
+) {
+    match value {
+        Value::Array(a) => a.iter_mut().for_each(val),
+        Value::Dict(d) => d.values_mut().for_each(val),
+        Value::Macro(m) => {
+            for arg in &mut m.args {
+                arg.default.iter_mut().for_each(&mut *val);
+                arg.pattern.iter_mut().for_each(&mut *val);
+            }
+            m.def_variables.values_mut().for_each(&mut *val);
+            m.ret_pattern.iter_mut().for_each(val);
+        }
+        Value::TypeIndicator(t) => typ(t),
+        Value::Pattern(p) => visit_pattern(p, val, typ),
+        _ => (),
+    }
+}
+
+fn visit_pattern(
+    pattern: &mut Pattern,
+    val: &mut impl FnMut(&mut StoredValue),
+    typ: &mut impl FnMut(&mut TypeId),
+) {
+    match pattern {
+        Pattern::Type(t) => typ(t),
+        Pattern::Array(a) => a.iter_mut().for_each(|p| visit_pattern(p, val, typ)),
+        Pattern::Either(a, b) | Pattern::Both(a, b) => {
+            visit_pattern(a, val, typ);
+            visit_pattern(b, val, typ);
+        }
+        Pattern::Not(a) => visit_pattern(a, val, typ),
+        Pattern::Macro { args, ret } => {
+            args.iter_mut().for_each(|p| visit_pattern(p, val, typ));
+            visit_pattern(ret, val, typ);
+        }
+        Pattern::Eq(v)
+        | Pattern::NotEq(v)
+        | Pattern::MoreThan(v)
+        | Pattern::LessThan(v)
+        | Pattern::MoreOrEq(v)
+        | Pattern::LessOrEq(v)
+        | Pattern::In(v) => val(v),
+        Pattern::Any => (),
+    }
+}
+
+/// Writes the output of a module to the cache. Modules that aren't pure aren't cached.
+/// Values of other cached modules (like the parts of the standard library the module uses)
+/// are written as references to their files instead of being written again
+pub fn save(
+    file: &Path,
+    deps: ModuleDeps,
+    output: StoredValue,
+    implementations: &Implementations,
+    types_before: &AHashSet<String>,
+    globals: &mut Globals,
+) {
+    if !deps.pure {
+        return;
+    }
+    let special = [globals.NULL_STORAGE, globals.BUILTIN_STORAGE];
+
+    let mut values = Vec::new();
+    let mut imports = Vec::<(String, String)>::new();
+    let mut external = Vec::new();
+    let mut seen = AHashSet::new();
+    let mut used_types = AHashSet::new();
+    let mut stack = vec![output];
+    for imp in implementations.values() {
+        stack.extend(imp.values().map(|(v, _)| *v));
+    }
+    used_types.extend(implementations.keys().copied());
+
+    while let Some(v) = stack.pop() {
+        if special.contains(&v) || !seen.insert(v) {
+            continue;
+        }
+        if let Some((module, index)) = globals.module_deps.owners.get(&v) {
+            let stamp = &globals.module_deps.cached[module].stamp;
+            let import = match imports.iter().position(|(m, _)| m == module) {
+                Some(i) => i,
+                None => {
+                    imports.push((module.clone(), stamp.clone()));
+                    imports.len() - 1
+                }
+            };
+            external.push((v, import, *index));
+            continue;
+        }
+        // a value that doesn't exist anymore can't be cached
+        let data = match globals.stored_values.map.get(v) {
+            Some(data) => data,
+            None => return,
+        };
+        let mut val = data.val.clone();
+        visit_refs(&mut val, &mut |v| stack.push(*v), &mut |t| {
+            used_types.insert(*t);
+        });
+        values.push((
+            v,
+            CachedValue {
+                val,
+                fn_context: data.fn_context,
+                mutable: data.mutable,
+                def_area: data.def_area,
+            },
+        ));
+    }
+
+    let types = globals
+        .type_ids
+        .iter()
+        .filter(|(name, (id, _))| used_types.contains(id) || !types_before.contains(*name))
+        .map(|(name, (id, area))| {
+            (
+                *id,
+                name.clone(),
+                *area,
+                globals.type_descriptions.get(id).cloned(),
+            )
+        })
+        .collect();
+
+    let own_values = values.iter().map(|(v, _)| *v).collect();
+    let module = CachedModule {
+        deps: deps.clone(),
+        values,
+        imports,
+        external,
+        output,
+        builtins: globals.BUILTIN_STORAGE,
+        implementations: implementations
+            .iter()
+            .map(|(t, imp)| (*t, imp.iter().map(|(k, (v, s))| (*k, *v, *s)).collect()))
+            .collect(),
+        types,
+    };
+    let bytes = match serde_cbor::to_vec(&module) {
+        Ok(bytes) => bytes,
+        Err(_) => return,
+    };
+    // the cache is only an optimisation, so it not being written isn't an error
+    if fs::create_dir_all(file.parent().unwrap()).is_err() {
+        return;
+    }
+    let tmp = file.with_extension("tmp");
+    if fs::write(&tmp, &bytes).is_ok() && fs::rename(&tmp, file).is_ok() {
+        // modules that are cached after this one refer to its values instead of writing them again
+        let cached = CachedOutput {
+            stamp: hash_bytes(&bytes),
+            values: own_values,
+            output,
+            implementations: implementations.clone(),
+            deps,
+        };
+        add_cached(globals, file, cached);
+    }
+}
+
+fn module_name(file: &Path) -> String {
+    file.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn add_cached(globals: &mut Globals, file: &Path, module: CachedOutput) {
+    let name = module_name(file);
+    for (i, v) in module.values.iter().enumerate() {
+        globals
+            .module_deps
+            .owners
+            .entry(*v)
+            .or_insert_with(|| (name.clone(), i));
+    }
+    globals.module_deps.cached.insert(name, module);
+}
+
+/// Loads the output of a module from the cache, if it's there and none of the files it was built from changed.
+/// The cached modules it refers to are loaded too. Returns the output, its implementations and the dependencies of the module
+pub fn load(
+    file: &Path,
+    globals: &mut Globals,
+) -> Option<(StoredValue, Implementations, ModuleDeps)> {
+    if let Some(m) = globals.module_deps.cached.get(&module_name(file)) {
+        return Some((m.output, m.implementations.clone(), m.deps.clone()));
+    }
+    let bytes = fs::read(file).ok()?;
+    let module = serde_cbor::from_slice::<CachedModule>(&bytes).ok()?;
+    for (source, h) in &module.deps.files {
+        if read_source(source).map(|s| hash(&s)).as_ref() != Some(h) {
+            return None;
+        }
+    }
+
+    let mut value_map = AHashMap::new();
+    for (name, stamp) in &module.imports {
+        let path = file.with_file_name(name);
+        load(&path, globals)?;
+        // the module was cached again after this one, so its values can be different
+        if &globals.module_deps.cached[name].stamp != stamp {
+            return None;
+        }
+    }
+    for (old, import, index) in &module.external {
+        let imported = &globals.module_deps.cached[&module.imports[*import].0];
+        value_map.insert(*old, *imported.values.get(*index)?);
+    }
+
+    let mut type_map = AHashMap::new();
+    for (id, name, area, desc) in module.types {
+        let new_id = match globals.type_ids.get(&name) {
+            Some((id, _)) => *id,
+            None => {
+                globals.type_id_count += 1;
+                globals.type_ids.insert(name, (globals.type_id_count, area));
+                if let Some(desc) = desc {
+                    globals
+                        .type_descriptions
+                        .insert(globals.type_id_count, desc);
+                }
+                globals.type_id_count
+            }
+        };
+        type_map.insert(id, new_id);
+    }
+
+    let mut own_values = Vec::new();
+    for (old, _) in &module.values {
+        let new = store_val_m(Value::Null, globals, Group::new(0), true, CodeArea::new());
+        value_map.insert(*old, new);
+        own_values.push(new);
+    }
+    let (null, builtins) = (globals.NULL_STORAGE, globals.BUILTIN_STORAGE);
+    let mut map_value = |v: &mut StoredValue| {
+        *v = match value_map.get(v) {
+            Some(new) => *new,
+            None if *v == module.builtins => builtins,
+            None => null,
+        }
+    };
+    let mut map_type = |t: &mut TypeId| {
+        if let Some(new) = type_map.get(t) {
+            *t = *new
+        }
+    };
+
+    for (old, mut cached) in module.values {
+        visit_refs(&mut cached.val, &mut map_value, &mut map_type);
+        let data = globals.stored_values.map.get_mut(value_map[&old]).unwrap();
+        data.val = cached.val;
+        data.fn_context = cached.fn_context;
+        data.mutable = cached.mutable;
+        data.def_area = cached.def_area;
+    }
+
+    let mut output = module.output;
+    map_value(&mut output);
+    let mut implementations = Implementations::default();
+    for (mut t, imp) in module.implementations {
+        map_type(&mut t);
+        let members = implementations.entry(t).or_default();
+        for (name, mut v, in_scope) in imp {
+            map_value(&mut v);
+            members.insert(name, (v, in_scope));
+        }
+    }
+    let cached = CachedOutput {
+        stamp: hash_bytes(&bytes),
+        values: own_values,
+        output,
+        implementations: implementations.clone(),
+        deps: module.deps.clone(),
+    };
+    add_cached(globals, file, cached);
+    Some((output, implementations, module.deps))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use parser::parser::parse_spwn;
+    use shared::SpwnSource;
+
+    use crate::builtins::{Builtin, BuiltinPermissions, BUILTIN_NAMES};
+    use crate::compiler::compile_spwn_quiet;
+    use crate::globals::ImportCache;
+    use crate::leveldata;
+
+    // a project folder with the output cache in `cache`, removed when dropped
+    struct Project(PathBuf);
+
+    impl Project {
+        fn new(name: &str) -> Project {
+            let dir =
+                std::env::temp_dir().join(format!("spwn-cache-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            Project(dir)
+        }
+
+        fn write(&self, file: &str, code: &str) {
+            fs::write(self.0.join(file), code).unwrap();
+        }
+
+        fn cached_modules(&self) -> usize {
+            fs::read_dir(self.0.join("cache").join("modules")).map_or(0, |d| d.count())
+        }
+
+        // builds `main.spwn` and returns what it printed, the level string
+        // and the files that had to be parsed
+        fn build(&self) -> (String, String, Vec<PathBuf>) {
+            let main = self.0.join("main.spwn");
+            let source = SpwnSource::File(main.clone());
+            let code = fs::read_to_string(&main).unwrap();
+            let (statements, notes) = parse_spwn(code, source.clone(), BUILTIN_NAMES).unwrap();
+            let mut import_cache = ImportCache {
+                output_dir: Some(self.0.join("cache")),
+                ..Default::default()
+            };
+            // like with `--allow readfile`
+            let mut permissions = BuiltinPermissions::new();
+            permissions.set(Builtin::ReadFile, true);
+            permissions.set(Builtin::WriteFile, true);
+            let mut std_out = Vec::<u8>::new();
+            let compiled = compile_spwn_quiet(
+                statements,
+                source,
+                vec![PathBuf::from("./")],
+                notes,
+                permissions,
+                Default::default(),
+                String::new(),
+                &mut std_out,
+                &mut import_cache,
+            )
+            .unwrap_or_else(|e| panic!("build failed: {:?}", e));
+
+            let mut objects = leveldata::apply_fn_ids(&compiled.func_ids);
+            objects.extend(compiled.objects);
+            let (level, _) = leveldata::append_objects(objects, "", Default::default()).unwrap();
+            let parsed = import_cache
+                .parsed
+                .keys()
+                .filter_map(|s| match s {
+                    SpwnSource::File(path) => path.file_name().map(PathBuf::from),
+                    _ => None,
+                })
+                .collect();
+            (String::from_utf8(std_out).unwrap(), level, parsed)
+        }
+    }
+
+    impl Drop for Project {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn parsed(files: &[PathBuf], file: &str) -> bool {
+        files.contains(&Path::new(file).to_path_buf())
+    }
+
+    const MAIN: &str = r"
+lib = import 'cached.spwn'
+c = counter()
+c.add(lib.value)
+$.print(lib.value, lib.scale(3))
+    ";
+
+    const CACHED: &str = r"
+#[no_std, cache_output]
+values = import 'values.spwn'
+return {
+    value: values.number * 2,
+    scale: (x) => x * values.number,
+}
+    ";
+
+    #[test]
+    fn loads_saved_output() {
+        let p = Project::new("load");
+        p.write("main.spwn", MAIN);
+        p.write("cached.spwn", CACHED);
+        p.write("values.spwn", "#[no_std]\nreturn { number: 5 }");
+
+        let (out, level, files) = p.build();
+        assert_eq!(out, "1015\n");
+        assert!(parsed(&files, "cached.spwn"));
+        assert!(p.cached_modules() > 0);
+
+        // the library and the modules of the standard library are loaded from the cache,
+        // and give the same output
+        let (cached_out, cached_level, files) = p.build();
+        assert_eq!((cached_out, cached_level), (out, level));
+        assert!(!parsed(&files, "cached.spwn"));
+        assert!(!parsed(&files, "values.spwn"));
+    }
+
+    #[test]
+    fn changed_dependency_invalidates() {
+        let p = Project::new("invalidate");
+        p.write("main.spwn", MAIN);
+        p.write("cached.spwn", CACHED);
+        p.write("values.spwn", "#[no_std]\nreturn { number: 5 }");
+        p.build();
+
+        // the library isn't changed, but a file it imports is
+        p.write("values.spwn", "#[no_std]\nreturn { number: 7 }");
+        let (out, _, files) = p.build();
+        assert_eq!(out, "1421\n");
+        assert!(parsed(&files, "cached.spwn"));
+        assert!(parsed(&files, "values.spwn"));
+
+        let (out, _, files) = p.build();
+        assert_eq!(out, "1421\n");
+        assert!(!parsed(&files, "cached.spwn"));
+    }
+
+    #[test]
+    fn impure_module_isnt_cached() {
+        let p = Project::new("impure");
+        p.write(
+            "main.spwn",
+            "#[no_std]\nlib = import 'impure.spwn'\n$.print(lib.value)",
+        );
+        // using an arbitrary group is something a cached output wouldn't do
+        p.write(
+            "impure.spwn",
+            "#[no_std, cache_output]\ng = ?g\nreturn { value: 3 }",
+        );
+
+        let (out, level, _) = p.build();
+        assert_eq!(out, "3\n");
+        assert_eq!(p.cached_modules(), 0);
+
+        let (cached_out, cached_level, files) = p.build();
+        assert_eq!((cached_out, cached_level), (out, level));
+        assert!(parsed(&files, "impure.spwn"));
+    }
+
+    #[test]
+    fn impure_builtins_arent_cached() {
+        for (name, code) in [
+            ("print", "$.print(1)"),
+            ("random", "n = $.random()"),
+            ("readfile", "data = $.readfile(path)"),
+            ("writefile", "$.writefile(path, 'hello')"),
+            ("level_string", "level = $.level_string()"),
+        ] {
+            let p = Project::new(&format!("impure-{}", name));
+            p.write("data.txt", "data");
+            p.write(
+                "main.spwn",
+                "#[no_std]\nlib = import 'lib.spwn'\n$.print(lib.value)",
+            );
+            p.write(
+                "lib.spwn",
+                &format!(
+                    "#[no_std, cache_output]\npath = {:?}\n{}\nreturn {{ value: 3 }}",
+                    p.0.join("data.txt").display().to_string(),
+                    code
+                ),
+            );
+
+            p.build();
+            assert_eq!(p.cached_modules(), 0, "{}", name);
+            let (_, _, files) = p.build();
+            assert!(parsed(&files, "lib.spwn"), "{}", name);
+        }
+    }
+}
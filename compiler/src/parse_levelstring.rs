@@ -40,7 +40,8 @@ pub fn parse_levelstring(ls: &str) -> Result<Vec<Value>, RuntimeError> {
                 }
                 4 | 5 | 11 | 13 | 15 | 16 | 17 | 34 | 41 | 42 | 48 | 56 | 58 | 59 | 60 | 62
                 | 64 | 65 | 66 | 67 | 70 | 81 | 86 | 87 | 89 | 93 | 94 | 96 | 98 | 104 | 100
-                | 102 | 103 | 106 | 36 => ObjParam::Bool(val.trim() == "1"),
+                | 102 | 103 | 106 | 36 | 441 | 468 | 469 | 471 | 472 | 475 | 491 | 492 | 493
+                | 581 => ObjParam::Bool(val.trim() == "1"),
                 21 | 22 | 23 | 50 => ObjParam::Color(Color {
                     id: Id::Specific(val.parse::<u16>().unwrap()),
                 }),
@@ -48,9 +49,15 @@ pub fn parse_levelstring(ls: &str) -> Result<Vec<Value>, RuntimeError> {
                 71 => ObjParam::Group(Group {
                     id: Id::Specific(val.parse::<u16>().unwrap()),
                 }),
-                95 => ObjParam::Block(Block {
-                    id: Id::Specific(val.parse::<u16>().unwrap()),
-                }),
+                95 => match obj_id {
+                    // item edit and item compare
+                    3619 | 3620 => ObjParam::Item(Item {
+                        id: Id::Specific(val.parse::<u16>().unwrap()),
+                    }),
+                    _ => ObjParam::Block(Block {
+                        id: Id::Specific(val.parse::<u16>().unwrap()),
+                    }),
+                },
 
                 57 => ObjParam::GroupList(
                     val.split('.')
@@ -59,6 +66,31 @@ pub fn parse_levelstring(ls: &str) -> Result<Vec<Value>, RuntimeError> {
                         })
                         .collect::<Vec<_>>(),
                 ),
+                // spawn trigger remaps
+                442 => ObjParam::GroupList(
+                    val.split('.')
+                        .filter(|g| !g.is_empty())
+                        .map(|g| Group {
+                            id: Id::Specific(g.parse::<u16>().unwrap()),
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+                // advanced random chances, sequence counts
+                152 | 435 => {
+                    let list = val.split('.').filter(|n| !n.is_empty()).collect::<Vec<_>>();
+                    ObjParam::WeightedGroups(
+                        list.chunks(2)
+                            .map(|pair| {
+                                (
+                                    Group {
+                                        id: Id::Specific(pair[0].parse::<u16>().unwrap()),
+                                    },
+                                    pair.get(1).map_or(0.0, |n| n.parse::<f64>().unwrap()),
+                                )
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                }
                 80 => match obj_id {
                     1815 => ObjParam::Block(Block {
                         id: Id::Specific(val.parse::<u16>().unwrap()),
@@ -82,6 +114,10 @@ pub fn parse_levelstring(ls: &str) -> Result<Vec<Value>, RuntimeError> {
                     899 => ObjParam::Color(Color {
                         id: Id::Specific(val.parse::<u16>().unwrap()),
                     }),
+                    // item edit trigger's target item
+                    3619 => ObjParam::Item(Item {
+                        id: Id::Specific(val.parse::<u16>().unwrap()),
+                    }),
                     _ => ObjParam::Group(Group {
                         id: Id::Specific(val.parse::<u16>().unwrap()),
                     }),
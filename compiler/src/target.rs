@@ -0,0 +1,47 @@
+// the Geometry Dash version a level is built for
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Target {
+    #[default]
+    Gd21,
+    Gd22,
+}
+
+impl Target {
+    pub const ALL: [Target; 2] = [Target::Gd21, Target::Gd22];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Gd21 => "gd2.1",
+            Target::Gd22 => "gd2.2",
+        }
+    }
+
+    // the highest ID of each class (group, color, block, item) the game lets a level use
+    pub fn id_limits(self) -> [u16; 4] {
+        match self {
+            Target::Gd21 => [999; 4],
+            // 2.2 raised everything but color channels to 9999
+            Target::Gd22 => [9999, 999, 9999, 9999],
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Target::ALL
+            .into_iter()
+            .find(|t| t.name() == s)
+            .ok_or_else(|| format!("Unknown target: {} (expected gd2.1 or gd2.2)", s))
+    }
+}
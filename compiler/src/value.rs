@@ -17,12 +17,13 @@ use shared::FileRange;
 //use std::boxed::Box;
 
 use internment::LocalIntern;
+use serde::{Deserialize, Serialize};
 
 use std::hash::Hash;
 
 use errors::RuntimeError;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Value {
     Group(Group),
     Color(Color),
@@ -48,7 +49,7 @@ pub type Slice = (Option<isize>, Option<isize>, Option<isize>);
 
 const MAX_DICT_EL_DISPLAY: usize = 10;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Macro {
     pub args: Vec<MacroArgDef>,
     pub def_variables: AHashMap<LocalIntern<String>, StoredValue>,
@@ -78,7 +79,7 @@ impl Hash for Macro {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MacroArgDef {
     pub name: LocalIntern<String>,
     pub default: Option<StoredValue>,
@@ -99,12 +100,12 @@ pub struct MacroArgDef {
 //         }
 //     }
 // }
-#[derive(Clone, Debug, PartialEq, Hash)]
+#[derive(Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
 pub struct TriggerFunction {
     pub start_group: Group,
     //pub all_groups: Vec<Group>,
 }
-#[derive(Clone, Debug, PartialEq, Hash)]
+#[derive(Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
 pub enum Pattern {
     Type(TypeId),
     Array(Vec<Pattern>),
@@ -1268,6 +1269,13 @@ pub fn convert_type(
                                 Value::Array(out)
                             }
 
+                            ObjParam::WeightedGroups(list) => weighted_groups_value(
+                                list,
+                                globals,
+                                context.start_group,
+                                info.position,
+                            ),
+
                             ObjParam::Epsilon => {
                                 let mut map = AHashMap::<
                                     LocalIntern<String>,
@@ -1314,6 +1322,72 @@ pub fn convert_type(
     })
 }
 
+/// Converts an array to an object parameter. Arrays can be lists of groups, or lists of
+/// `[group, number]` pairs, like the chances of an advanced random trigger. The numbers have to be
+/// whole, since they are separated by dots in the level string
+pub fn array_obj_param(
+    a: &[StoredValue],
+    globals: &Globals,
+    info: &CompilerInfo,
+) -> Result<ObjParam, RuntimeError> {
+    let err = || {
+        RuntimeError::CustomError(create_error(
+            info.clone(),
+            "Arrays in object parameters can only contain groups, or [group, whole number] pairs",
+            &[],
+            None,
+        ))
+    };
+    let mut groups = Vec::new();
+    let mut weighted = Vec::new();
+    for s in a {
+        match &globals.stored_values[*s] {
+            Value::Group(g) => groups.push(*g),
+            Value::TriggerFunc(f) => groups.push(f.start_group),
+            Value::Array(pair) => match pair[..] {
+                [g, n] => match (&globals.stored_values[g], &globals.stored_values[n]) {
+                    (Value::Group(g), Value::Number(n)) if n.fract() == 0.0 => {
+                        weighted.push((*g, *n))
+                    }
+                    (Value::TriggerFunc(f), Value::Number(n)) if n.fract() == 0.0 => {
+                        weighted.push((f.start_group, *n))
+                    }
+                    _ => return Err(err()),
+                },
+                _ => return Err(err()),
+            },
+            _ => return Err(err()),
+        }
+    }
+    match (groups.is_empty(), weighted.is_empty()) {
+        (_, true) => Ok(ObjParam::GroupList(groups)),
+        (true, false) => Ok(ObjParam::WeightedGroups(weighted)),
+        (false, false) => Err(err()),
+    }
+}
+
+fn weighted_groups_value(
+    list: &[(Group, f64)],
+    globals: &mut Globals,
+    fn_context: Group,
+    area: CodeArea,
+) -> Value {
+    let mut pairs = Vec::new();
+    for (g, n) in list {
+        let pair = vec![
+            store_const_value(Value::Group(*g), globals, fn_context, area),
+            store_const_value(Value::Number(*n), globals, fn_context, area),
+        ];
+        pairs.push(store_const_value(
+            Value::Array(pair),
+            globals,
+            fn_context,
+            area,
+        ));
+    }
+    Value::Array(pairs)
+}
+
 //copied from https://stackoverflow.com/questions/59401720/how-do-i-find-the-key-for-a-value-in-a-hashmap
 pub fn find_key_for_value(
     map: &AHashMap<String, (u16, CodeArea)>,
@@ -1593,7 +1667,7 @@ impl VariableFuncs for ast::Variable {
                         info.position,
                     )
                 }
-                ast::ValueBody::Dictionary(dict) => {
+                ast::ValueBody::Dictionary(dict, _) => {
                     eval_dict(dict.clone(), full_context, globals, info.clone(), constant)?
                 }
                 ast::ValueBody::CmpStmt(cmp_stmt) => {
@@ -2820,22 +2894,7 @@ impl VariableFuncs for ast::Variable {
 
                                         Value::Bool(b) => ObjParam::Bool(*b),
 
-                                        Value::Array(a) => ObjParam::GroupList({
-                                            let mut out = Vec::new();
-                                            for s in a {
-                                                out.push(match globals.stored_values[*s] {
-                                                    Value::Group(g) => g,
-                                                    _ => return Err(RuntimeError::CustomError(create_error(
-                                                        info,
-                                                        "Arrays in object parameters can only contain groups",
-                                                        &[],
-                                                        None,
-                                                    )))
-                                                })
-                                            }
-
-                                            out
-                                        }),
+                                        Value::Array(a) => array_obj_param(a, globals, &info)?,
                                         Value::Dict(d) => {
                                             if let Some(t) = d.get(&globals.TYPE_MEMBER_NAME) {
                                                 if let Value::TypeIndicator(t) = globals.stored_values[*t] {
@@ -3272,6 +3331,13 @@ impl VariableFuncs for ast::Variable {
                                                             Value::Array(out)
                                                         }
 
+                                                        ObjParam::WeightedGroups(list) => weighted_groups_value(
+                                                            list,
+                                                            globals,
+                                                            full_context.inner().start_group,
+                                                            info.position,
+                                                        ),
+
                                                         ObjParam::Epsilon => {
                                                             let mut map = AHashMap::<
                                                                 LocalIntern<String>,
@@ -3432,7 +3498,7 @@ impl VariableFuncs for ast::Variable {
                                         .to_variable(globals.get_area(val_ptr).pos)
                                         .to_expression(),
                                 )));
-                                ast::ValueBody::Dictionary(new_defs.clone())
+                                ast::ValueBody::Dictionary(new_defs.clone(), Vec::new())
                                     .to_variable(info.position.pos)
                                     .to_value(full_context, globals, info.clone(), constant)?;
                             }
@@ -3598,7 +3664,7 @@ impl VariableFuncs for ast::Variable {
             }
         }
 
-        merge_all_contexts(contexts, globals, true);
+        merge_all_contexts(contexts, globals, true, info.position);
 
         Ok(())
     }
@@ -6,13 +6,17 @@ use shared::{ImportType, SpwnSource, StoredValue};
 //use crate::ast::*;
 
 use compiler::builtins::BuiltinPermissions;
-use compiler::compiler::import_module;
+use compiler::compiler::import_module_returns;
 use errors::compiler_info::CompilerInfo;
 
 use compiler::context::FullContext;
 use compiler::globals::Globals;
+use compiler::value_storage::store_const_value;
 use compiler::{type_id, value::*};
 
+use crate::html::write_site;
+use crate::json::document_json;
+
 use std::fs::File;
 
 use ahash::AHashMap;
@@ -36,7 +40,18 @@ fn find_avaliable_name(dir: &mut PathBuf, name: &mut String) {
         dir.push(format!("{}.md", name));
     }
 }
-pub fn document_lib(path: &str) -> Result<(), RuntimeError> {
+/// The formats `spwn doc` can write documentation in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocFormat {
+    /// A folder of markdown files (`<name>-docs`), with a sidebar
+    Markdown,
+    /// A single JSON file (`<name>-docs.json`) with every exported value
+    Json,
+    /// A static site (`<name>-docs`), with search and links between types
+    Html,
+}
+
+pub fn document_lib(path: &str, format: DocFormat) -> Result<(), RuntimeError> {
     let mut globals_path = std::env::current_dir().unwrap();
     globals_path.push("temp"); // this folder doesn't actually exist, but it needs to be there because .parent is called in import_module
     let mut std_out = std::io::stdout();
@@ -48,11 +63,12 @@ pub fn document_lib(path: &str) -> Result<(), RuntimeError> {
     );
 
     let mut start_context = FullContext::new(&globals);
+    // `new` points the root context at its own local, which is gone once the context is moved
+    start_context.inner().root_context_ptr = &mut start_context;
 
     // store_value(Value::Builtins, 1, &mut globals, &start_context);
     // store_value(Value::Null, 1, &mut globals, &start_context);
 
-    let mut output_path = current_dir().unwrap();
     let is_module = path.contains('.');
 
     let name: String = if is_module {
@@ -67,17 +83,6 @@ pub fn document_lib(path: &str) -> Result<(), RuntimeError> {
     } else {
         path.to_string()
     };
-    let folder_name = format!("{}-docs", name);
-    output_path.push(PathBuf::from(&folder_name));
-    if !output_path.exists() {
-        std::fs::create_dir(output_path.clone()).unwrap();
-    } else {
-        // delete all files in the directory
-        for entry in std::fs::read_dir(output_path.clone()).unwrap() {
-            let entry = entry.unwrap();
-            std::fs::remove_file(entry.path()).unwrap();
-        }
-    }
     let info: CompilerInfo = CompilerInfo::new();
     globals
         .includes
@@ -90,7 +95,7 @@ pub fn document_lib(path: &str) -> Result<(), RuntimeError> {
             .to_path_buf(),
     );
 
-    import_module(
+    let returned = import_module_returns(
         &if is_module {
             ImportType::Script(PathBuf::from(path))
         } else {
@@ -102,14 +107,151 @@ pub fn document_lib(path: &str) -> Result<(), RuntimeError> {
         false,
     )?;
 
-    if let FullContext::Split(_, _) = start_context {
-        return Err(RuntimeError::CustomError(errors::create_error(
+    let split = merge_contexts(&returned, &mut start_context, &mut globals).map_err(|e| *e)?;
+
+    match format {
+        DocFormat::Markdown => {
+            document_markdown(name, is_module, &split, &mut globals, &mut start_context)
+        }
+        DocFormat::Json => {
+            let json = document_json(&name, is_module, &split, &mut globals, &mut start_context)
+                .map_err(|e| *e)?;
+            let mut output_path = current_dir().unwrap();
+            output_path.push(format!("{}-docs.json", name));
+            std::fs::write(&output_path, serde_json::to_string_pretty(&json).unwrap()).unwrap();
+            println!("written to {:?}", output_path);
+            Ok(())
+        }
+        DocFormat::Html => {
+            let json = document_json(&name, is_module, &split, &mut globals, &mut start_context)
+                .map_err(|e| *e)?;
+            write_site(&json, &output_folder(&name));
+            Ok(())
+        }
+    }
+}
+
+/// How a library splits the context when it is imported
+pub(crate) struct ContextSplit {
+    /// The number of contexts the library returns in (1 if it doesn't split the context)
+    pub contexts: usize,
+    /// Exports that are missing from some of the contexts, or are different between them
+    pub differing: Vec<String>,
+}
+
+// a context-splitting library is documented as one library, with the exports it returned in every
+// context merged (keeping the value from the first context that has them)
+fn merge_contexts(
+    exports: &[StoredValue],
+    start_context: &mut FullContext,
+    globals: &mut Globals,
+) -> Result<ContextSplit, Box<RuntimeError>> {
+    if exports.len() <= 1 {
+        return Ok(ContextSplit {
+            contexts: 1,
+            differing: Vec::new(),
+        });
+    }
+
+    let different_values = || {
+        Box::new(RuntimeError::CustomError(errors::create_error(
             CompilerInfo::new(),
-            "Documentation of context-splitting libraries is not yet supported!",
+            "This library exports different values in the contexts it splits into, and they can only be documented together if they are all dictionaries",
             &[],
             None,
-        )));
+        )))
+    };
+
+    // the same value in every context is documented like a library that doesn't split the context
+    if !matches!(globals.stored_values[exports[0]], Value::Dict(_)) {
+        if exports
+            .iter()
+            .all(|export| same_export(*export, exports[0], globals))
+        {
+            return Ok(ContextSplit {
+                contexts: exports.len(),
+                differing: Vec::new(),
+            });
+        }
+        return Err(different_values());
+    }
+
+    let mut merged = AHashMap::<LocalIntern<String>, StoredValue>::default();
+    let mut differing = Vec::new();
+    for (i, export) in exports.iter().enumerate() {
+        let dict = match &globals.stored_values[*export] {
+            Value::Dict(d) => d.clone(),
+            _ => return Err(different_values()),
+        };
+        for (key, val) in &dict {
+            match merged.get(key) {
+                Some(prev) if same_export(*prev, *val, globals) => (),
+                Some(_) => differing.push(key.to_string()),
+                None => {
+                    if i > 0 {
+                        differing.push(key.to_string());
+                    }
+                    merged.insert(*key, *val);
+                }
+            }
+        }
+        // exports the earlier contexts have, but this one doesn't
+        differing.extend(
+            merged
+                .keys()
+                .filter(|key| !dict.contains_key(*key))
+                .map(|key| key.to_string()),
+        );
     }
+    differing.sort();
+    differing.dedup();
+
+    let area = globals.get_area(exports[0]);
+    let start_group = start_context.inner().start_group;
+    start_context.inner().return_value =
+        store_const_value(Value::Dict(merged), globals, start_group, area);
+
+    Ok(ContextSplit {
+        contexts: exports.len(),
+        differing,
+    })
+}
+
+// macros are compared by where they are defined, since they capture different variables in each context
+fn same_export(a: StoredValue, b: StoredValue, globals: &Globals) -> bool {
+    match (&globals.stored_values[a], &globals.stored_values[b]) {
+        (Value::Macro(m1), Value::Macro(m2)) => {
+            m1.def_file == m2.def_file && m1.arg_pos == m2.arg_pos
+        }
+        _ => strict_value_equality(a, b, globals),
+    }
+}
+
+// creates the `<name>-docs` folder, or empties it if it already exists
+fn output_folder(name: &str) -> PathBuf {
+    let mut output_path = current_dir().unwrap();
+    output_path.push(format!("{}-docs", name));
+    if !output_path.exists() {
+        std::fs::create_dir(output_path.clone()).unwrap();
+    } else {
+        // delete all files in the directory
+        for entry in std::fs::read_dir(output_path.clone()).unwrap() {
+            let entry = entry.unwrap();
+            std::fs::remove_file(entry.path()).unwrap();
+        }
+    }
+    output_path
+}
+
+fn document_markdown(
+    name: String,
+    is_module: bool,
+    split: &ContextSplit,
+    globals: &mut Globals,
+    start_context: &mut FullContext,
+) -> Result<(), RuntimeError> {
+    let output_path = output_folder(&name);
+    let folder_name = format!("{}-docs", name);
 
     let mut doc = format!("# Documentation for `{}`\n\n", name);
 
@@ -121,7 +263,6 @@ pub fn document_lib(path: &str) -> Result<(), RuntimeError> {
     globals.push_preserved_val(start_context.inner().return_value);
 
     let exports = globals.stored_values[start_context.inner().return_value].clone();
-    let implementations = globals.implementations.clone();
 
     doc += "_Generated using `spwn doc [file name]`_\n";
 
@@ -150,37 +291,16 @@ pub fn document_lib(path: &str) -> Result<(), RuntimeError> {
 ",
         used_groups, used_colors, used_blocks, used_items, total_objects
     );
+    if split.contexts > 1 {
+        doc += &format!("\n- Splits the context into {} contexts\n", split.contexts);
+    }
 
     let mut type_links = AHashMap::<u16, String>::default();
     let mut type_paths = AHashMap::<u16, String>::default();
 
-    let mut impl_list = Vec::new();
-    let doc_implementations =
-        !implementations.is_empty() && implementations.iter().any(|(_, a)| !a.is_empty());
+    let impl_list = implementations(globals, is_module);
+    let doc_implementations = !impl_list.is_empty();
     if doc_implementations {
-        impl_list = implementations
-            .into_iter()
-            .map(|(a, map)| {
-                (
-                    a,
-                    if is_module {
-                        map.into_iter().filter(|(_, (_, a))| *a).collect()
-                    } else {
-                        map
-                    },
-                )
-            })
-            .filter(|(_, a)| !a.is_empty())
-            .map(|(key, val)| {
-                (
-                    key,
-                    val.iter()
-                        .map(|(key, val)| (*key, val.0))
-                        .collect::<AHashMap<LocalIntern<String>, StoredValue>>(),
-                )
-            })
-            .collect();
-        impl_list.sort_by(|a, b| a.0.cmp(&b.0));
         for (typ, _) in impl_list.iter() {
             let mut type_name = find_key_for_value(&globals.type_ids, *typ)
                 .expect("Implemented type was not found!")
@@ -198,8 +318,8 @@ pub fn document_lib(path: &str) -> Result<(), RuntimeError> {
 
     let (doc_content, sidebar_content) = document_val(
         &exports,
-        &mut globals,
-        &mut start_context,
+        globals,
+        start_context,
         &type_links,
         &format!("{}/{}", folder_name, main_file),
         None,
@@ -211,7 +331,19 @@ pub fn document_lib(path: &str) -> Result<(), RuntimeError> {
         .collect::<Vec<_>>()
         .join("");
 
-    doc += &format!("\n## Exports\n\n{}", doc_content);
+    doc += "\n## Exports\n\n";
+    if !split.differing.is_empty() {
+        doc += &format!(
+            "?> These exports are different or missing in some of the contexts the library splits into, and are documented as they are in the first context that has them: {}\n\n",
+            split
+                .differing
+                .iter()
+                .map(|key| format!("`{}`", key))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    doc += &doc_content;
 
     if doc_implementations {
         for (typ, dict) in impl_list.iter() {
@@ -220,8 +352,8 @@ pub fn document_lib(path: &str) -> Result<(), RuntimeError> {
                 .clone();
             let (doc_content, sidebar_content) = document_dict(
                 dict,
-                &mut globals,
-                &mut start_context,
+                globals,
+                start_context,
                 &type_links,
                 &type_paths[typ],
                 Some(&type_name),
@@ -252,6 +384,39 @@ pub fn document_lib(path: &str) -> Result<(), RuntimeError> {
     Ok(())
 }
 
+// the types the library implements members for, with those members
+pub(crate) fn implementations(
+    globals: &Globals,
+    is_module: bool,
+) -> Vec<(u16, AHashMap<LocalIntern<String>, StoredValue>)> {
+    let mut impl_list: Vec<_> = globals
+        .implementations
+        .clone()
+        .into_iter()
+        .map(|(a, map)| {
+            (
+                a,
+                if is_module {
+                    map.into_iter().filter(|(_, (_, a))| *a).collect()
+                } else {
+                    map
+                },
+            )
+        })
+        .filter(|(_, a)| !a.is_empty())
+        .map(|(key, val)| {
+            (
+                key,
+                val.iter()
+                    .map(|(key, val)| (*key, val.0))
+                    .collect::<AHashMap<LocalIntern<String>, StoredValue>>(),
+            )
+        })
+        .collect();
+    impl_list.sort_by_key(|a| a.0);
+    impl_list
+}
+
 fn document_dict(
     dict: &AHashMap<LocalIntern<String>, StoredValue>,
     globals: &mut Globals,
@@ -469,7 +634,7 @@ fn document_macro(
     Ok(doc)
 }
 
-fn display_pattern(
+pub(crate) fn display_pattern(
     pat: &Pattern,
     full_context: &mut FullContext,
     globals: &mut Globals,
@@ -600,3 +765,87 @@ fn document_val(
 //     formatted.pop();
 //     (*string) = formatted
 // }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::json::document_json;
+    use serde_json::Value as Json;
+
+    // documents `code` like `spwn doc --format json` does for a module
+    pub(crate) fn document(code: &str) -> Json {
+        let dir =
+            std::env::temp_dir().join(format!("spwn-docgen-{}-{}", std::process::id(), code.len()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lib.spwn");
+        std::fs::write(&path, code).unwrap();
+
+        let mut std_out = Vec::new();
+        let mut globals = Globals::new(
+            SpwnSource::File(dir.join("temp")),
+            BuiltinPermissions::new(),
+            String::new(),
+            &mut std_out,
+        );
+        globals.includes.push(PathBuf::from("../"));
+        let mut start_context = FullContext::new(&globals);
+        start_context.inner().root_context_ptr = &mut start_context;
+        let returned = import_module_returns(
+            &ImportType::Script(path),
+            &mut start_context,
+            &mut globals,
+            CompilerInfo::new(),
+            false,
+        )
+        .unwrap();
+
+        let split = merge_contexts(&returned, &mut start_context, &mut globals).unwrap();
+        let json = document_json("lib", true, &split, &mut globals, &mut start_context).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        json
+    }
+
+    #[test]
+    fn merges_split_contexts() {
+        let json = document(
+            r#"
+c = counter(0)
+if c == 0 {
+    return { shared: 1, changed: 2, only_first: 3 }
+} else {
+    return { shared: 1, changed: 4, only_second: 5 }
+}
+"#,
+        );
+
+        assert_eq!(json["info"]["contexts"], 2);
+        assert_eq!(
+            json["info"]["differing_exports"],
+            serde_json::json!(["changed", "only_first", "only_second"])
+        );
+
+        // every export is documented, as it is in the first context that has it
+        let members = &json["exports"]["members"];
+        let printed = ["shared", "changed", "only_first", "only_second"]
+            .map(|key| members[key]["printed"].as_str().unwrap());
+        assert_eq!(printed, ["1", "2", "3", "5"]);
+    }
+
+    #[test]
+    fn same_value_in_split_contexts() {
+        let json = document(
+            r#"
+c = counter(0)
+if c == 0 {
+    return 5
+} else {
+    return 5
+}
+"#,
+        );
+
+        assert_eq!(json["info"]["contexts"], 2);
+        assert_eq!(json["exports"]["type"], "@number");
+        assert_eq!(json["exports"]["printed"], "5");
+    }
+}
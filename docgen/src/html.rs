@@ -0,0 +1,522 @@
+// a static HTML site for a library's documentation (spwn doc --format html),
+// generated from the same model as the JSON output
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use ahash::AHashSet;
+use logos::Logos;
+use parser::parser::Token;
+use serde_json::{json, Map, Value as Json};
+
+const STYLE: &str = include_str!("html/style.css");
+const SEARCH: &str = include_str!("html/search.js");
+
+/// Writes the site for `doc` (made by `document_json`) into `dir`:
+/// an index page with the exports, a page for every type the library implements members for,
+/// and the search index
+pub(crate) fn write_site(doc: &Json, dir: &Path) {
+    let name = doc["name"].as_str().unwrap();
+    let impls = doc["implementations"].as_array().unwrap();
+
+    let mut site = Site {
+        types: impls.iter().map(|i| i["type"].as_str().unwrap()).collect(),
+        search: Vec::new(),
+    };
+
+    let mut sidebar = format!(
+        "<a class=\"title\" href=\"index.html\">{}</a>\n<h4>Exports</h4>\n<ul>\n",
+        escape(name)
+    );
+    for (key, _) in sorted_members(&doc["exports"]["members"]) {
+        writeln!(
+            sidebar,
+            "<li><a href=\"index.html#{0}\">{0}</a></li>",
+            escape(key)
+        )
+        .unwrap();
+    }
+    sidebar += "</ul>\n";
+    if !impls.is_empty() {
+        sidebar += "<h4>Types</h4>\n<ul>\n";
+        for imp in impls {
+            let typ = imp["type"].as_str().unwrap();
+            writeln!(
+                sidebar,
+                "<li><a href=\"{}\">{}</a></li>",
+                type_page(typ),
+                typ
+            )
+            .unwrap();
+        }
+        sidebar += "</ul>\n";
+    }
+
+    // index page
+    let info = &doc["info"];
+    let mut content = format!(
+        "<h1>Documentation for <code>{}</code></h1>\n<p class=\"type\">Generated using <code>spwn doc</code></p>\n",
+        escape(name)
+    );
+    content += "<h2>Info</h2>\n<ul>\n";
+    for (key, label) in [
+        ("groups", "groups"),
+        ("colors", "colors"),
+        ("blocks", "block IDs"),
+        ("items", "item IDs"),
+    ] {
+        writeln!(content, "<li>Uses {} {}</li>", info[key], label).unwrap();
+    }
+    writeln!(content, "<li>Adds {} objects</li>", info["objects"]).unwrap();
+    if info["contexts"].as_u64().unwrap_or(1) > 1 {
+        writeln!(
+            content,
+            "<li>Splits the context into {} contexts</li>",
+            info["contexts"]
+        )
+        .unwrap();
+    }
+    content += "</ul>\n<h2>Exports</h2>\n";
+    let differing = info["differing_exports"].as_array().unwrap();
+    if !differing.is_empty() {
+        writeln!(
+            content,
+            "<p>These exports are different or missing in some of the contexts the library splits into, and are documented as they are in the first context that has them: {}</p>",
+            differing
+                .iter()
+                .map(|key| format!("<a href=\"#{0}\"><code>{0}</code></a>", escape(key.as_str().unwrap())))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+        .unwrap();
+    }
+    content += &site.value(&doc["exports"], "index.html", "", "");
+    write_page(dir, "index.html", name, &sidebar, &content);
+
+    // type pages
+    for imp in impls {
+        let typ = imp["type"].as_str().unwrap();
+        let page = type_page(typ);
+        site.search.push(json!({
+            "name": typ,
+            "path": page,
+            "description": imp["description"],
+        }));
+
+        let mut content = format!("<h1><code>{}</code></h1>\n", typ);
+        if let Some(desc) = imp["description"].as_str() {
+            writeln!(content, "<p>{}</p>", inline(desc)).unwrap();
+        }
+        content += &site.members(&imp["members"], &page, &format!("{}::", typ), "");
+        write_page(dir, &page, typ, &sidebar, &content);
+    }
+
+    fs::write(dir.join("style.css"), STYLE).unwrap();
+    fs::write(dir.join("search.js"), SEARCH).unwrap();
+    fs::write(
+        dir.join("search-index.js"),
+        format!("var searchIndex = {};\n", Json::Array(site.search)),
+    )
+    .unwrap();
+    println!("written to {:?}", dir);
+}
+
+struct Site<'a> {
+    // documented types (like `@counter`), which get linked to their page
+    types: AHashSet<&'a str>,
+    search: Vec<Json>,
+}
+
+impl Site<'_> {
+    // the members of a dictionary, in the same categories as the markdown documentation
+    fn members(&mut self, members: &Json, page: &str, prefix: &str, anchor: &str) -> String {
+        let mut categories = [
+            ("Constructors", Vec::new()),
+            ("Macros", Vec::new()),
+            ("Operator Implementations", Vec::new()),
+            ("Values", Vec::new()),
+        ];
+        for (key, val) in sorted_members(members) {
+            let category = if val["type"] != "@macro" {
+                3
+            } else if val["constructor"] == true {
+                0
+            } else if key.starts_with('_') && key.ends_with('_') {
+                2
+            } else {
+                1
+            };
+            categories[category].1.push((key, val));
+        }
+
+        let mut out = String::new();
+        for (category, list) in categories {
+            if list.is_empty() {
+                continue;
+            }
+            writeln!(out, "<h2>{}</h2>", category).unwrap();
+            for (key, val) in list {
+                let id = format!("{}{}", anchor, key);
+                self.search.push(json!({
+                    "name": format!("{}{}", prefix, key),
+                    "path": format!("{}#{}", page, id),
+                    "description": val["description"],
+                }));
+                writeln!(
+                    out,
+                    "<section class=\"member\" id=\"{0}\">\n<h3><a href=\"#{0}\"><code>{1}{2}</code></a></h3>",
+                    escape(&id),
+                    escape(prefix),
+                    escape(key)
+                )
+                .unwrap();
+                out += &self.value(
+                    val,
+                    page,
+                    &format!("{}{}.", prefix, key),
+                    &format!("{}.", id),
+                );
+                out += "</section>\n";
+            }
+        }
+        out
+    }
+
+    fn value(&mut self, val: &Json, page: &str, prefix: &str, anchor: &str) -> String {
+        let mut out = format!(
+            "<p class=\"type\">Type: {}</p>\n",
+            self.link_types(val["type"].as_str().unwrap())
+        );
+        if let Some(desc) = val["description"].as_str() {
+            writeln!(out, "<p>{}</p>", inline(desc)).unwrap();
+        }
+
+        if val["type"] == "@macro" {
+            let args = val["arguments"].as_array().unwrap();
+            if !args.is_empty() {
+                out += "<h4>Arguments</h4>\n<table>\n<tr><th>#</th><th>name</th><th>type</th><th>default value</th><th>description</th></tr>\n";
+                for (i, arg) in args.iter().enumerate() {
+                    writeln!(
+                        out,
+                        "<tr><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                        i + 1,
+                        escape(arg["name"].as_str().unwrap()),
+                        arg["pattern"]
+                            .as_str()
+                            .map(|p| self.link_types(p))
+                            .unwrap_or_else(|| String::from("any")),
+                        arg["default"]
+                            .as_str()
+                            .map(|d| format!("<code>{}</code>", self.highlight(d)))
+                            .unwrap_or_default(),
+                        arg["description"].as_str().map(inline).unwrap_or_default(),
+                    )
+                    .unwrap();
+                }
+                out += "</table>\n";
+            }
+            if let Some(ret) = val["returns"].as_str() {
+                if ret != "@NULL" {
+                    writeln!(
+                        out,
+                        "<p><strong>Returns:</strong> {}</p>",
+                        self.link_types(ret)
+                    )
+                    .unwrap();
+                }
+            }
+            if let Some(example) = val["example"].as_str() {
+                writeln!(
+                    out,
+                    "<h4>Example</h4>\n<pre><code>{}</code></pre>",
+                    self.highlight(example.trim())
+                )
+                .unwrap();
+            }
+        } else if let Some(members) = val.get("members") {
+            out += &self.members(members, page, prefix, anchor);
+        } else if let Some(printed) = val["printed"].as_str() {
+            writeln!(out, "<pre><code>{}</code></pre>", self.highlight(printed)).unwrap();
+        }
+        out
+    }
+
+    fn type_link(&self, typ: &str) -> String {
+        if self.types.contains(typ) {
+            format!("<a href=\"{}\">{}</a>", type_page(typ), typ)
+        } else {
+            typ.to_string()
+        }
+    }
+
+    // escapes text, and links the documented types in it
+    fn link_types(&self, text: &str) -> String {
+        let mut out = String::new();
+        let mut rest = text;
+        while let Some(i) = rest.find('@') {
+            out += &escape(&rest[..i]);
+            let len = rest[i + 1..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len() - i - 1);
+            out += &self.type_link(&rest[i..i + 1 + len]);
+            rest = &rest[i + 1 + len..];
+        }
+        out + &escape(rest)
+    }
+
+    // SPWN code as HTML, with a span around every token that gets highlighted
+    fn highlight(&self, code: &str) -> String {
+        use Token::*;
+
+        let mut out = String::new();
+        let mut lexer = Token::lexer(code);
+        let mut end = 0;
+        while let Some(token) = lexer.next() {
+            let span = lexer.span();
+            // whitespace is skipped by the lexer
+            out += &escape(&code[end..span.start]);
+            end = span.end;
+            let text = &code[span.clone()];
+
+            let class = match token {
+                Comment => "comment",
+                StringLiteral => "string",
+                Number | BinaryLiteral | HexLiteral | OctalLiteral => "number",
+                Id => "id",
+                True | False | Null => "literal",
+                Return | Implement | For | In | ErrorStatement | If | Else | Switch | Match
+                | Break | Continue | While | Object | Trigger | Import | Extract | Type | Let
+                | SelfVal | Sync | Is | As => "keyword",
+                Symbol if text == "$" => "builtins",
+                At => {
+                    let mut next = lexer.clone();
+                    if let (Some(Symbol), true) = (next.next(), next.span().start == span.end) {
+                        end = next.span().end;
+                        lexer = next;
+                        let typ = &code[span.start..end];
+                        write!(
+                            out,
+                            "<span class=\"hl-type\">{}</span>",
+                            self.type_link(typ)
+                        )
+                        .unwrap();
+                        continue;
+                    }
+                    ""
+                }
+                _ => "",
+            };
+            if class.is_empty() {
+                out += &escape(text);
+            } else {
+                write!(out, "<span class=\"hl-{}\">{}</span>", class, escape(text)).unwrap();
+            }
+        }
+        out + &escape(&code[end..])
+    }
+}
+
+fn write_page(dir: &Path, file: &str, title: &str, sidebar: &str, content: &str) {
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{}</title>
+<link rel="stylesheet" href="style.css">
+</head>
+<body>
+<nav id="sidebar">
+<input id="search" type="search" placeholder="Search..." autocomplete="off">
+<ul id="search-results"></ul>
+<div id="nav">
+{}</div>
+</nav>
+<main>
+{}</main>
+<script src="search-index.js"></script>
+<script src="search.js"></script>
+</body>
+</html>
+"#,
+        escape(title),
+        sidebar,
+        content
+    );
+    fs::write(dir.join(file), page).unwrap();
+}
+
+fn sorted_members(members: &Json) -> Vec<(&String, &Json)> {
+    let mut members = members
+        .as_object()
+        .map(Map::iter)
+        .map(Iterator::collect::<Vec<_>>)
+        .unwrap_or_default();
+    members.sort_by_key(|(key, _)| *key);
+    members
+}
+
+// `@counter` -> `type.counter.html`
+fn type_page(typ: &str) -> String {
+    format!("type.{}.html", &typ[1..])
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// descriptions are written in markdown, but only `code` is used in practice
+fn inline(text: &str) -> String {
+    let mut out = String::new();
+    for (i, part) in escape(text).split('`').enumerate() {
+        if i % 2 == 1 {
+            write!(out, "<code>{}</code>", part).unwrap();
+        } else {
+            out += part;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_site() {
+        let doc = json!({
+            "name": "lib",
+            "info": {
+                "groups": 2,
+                "colors": 0,
+                "blocks": 0,
+                "items": 1,
+                "objects": 3,
+                "contexts": 1,
+                "differing_exports": [],
+            },
+            "exports": {
+                "type": "@dictionary",
+                "members": {
+                    "origin": { "type": "@point", "printed": "@point::{x: 0, y: 0}" },
+                    "double": {
+                        "type": "@macro",
+                        "description": "Doubles `n`",
+                        "example": "$.assert(double(2) == 4)",
+                        "constructor": false,
+                        "method": false,
+                        "returns": "@number",
+                        "arguments": [{
+                            "name": "n",
+                            "pattern": "@number or @point",
+                            "default": null,
+                            "description": null,
+                        }],
+                    },
+                },
+            },
+            "implementations": [{
+                "type": "@point",
+                "description": "A <point>",
+                "members": {
+                    "new": {
+                        "type": "@macro",
+                        "description": "Makes a point",
+                        "example": null,
+                        "constructor": true,
+                        "method": false,
+                        "returns": "@point",
+                        "arguments": [{
+                            "name": "x",
+                            "pattern": null,
+                            "default": "0",
+                            "description": null,
+                        }],
+                    },
+                    "_plus_": {
+                        "type": "@macro",
+                        "description": null,
+                        "example": null,
+                        "constructor": false,
+                        "method": true,
+                        "returns": "@NULL",
+                        "arguments": [],
+                    },
+                },
+            }],
+        });
+
+        let dir = std::env::temp_dir().join(format!("spwn-docgen-html-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        write_site(&doc, &dir);
+        let read = |file: &str| fs::read_to_string(dir.join(file)).unwrap();
+        let (index, point, search) = (
+            read("index.html"),
+            read("type.point.html"),
+            read("search-index.js"),
+        );
+        assert_eq!(read("style.css"), STYLE);
+        assert_eq!(read("search.js"), SEARCH);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // every page has the sidebar
+        for page in [&index, &point] {
+            assert!(page.contains("<li><a href=\"index.html#double\">double</a></li>\n<li><a href=\"index.html#origin\">origin</a></li>"));
+            assert!(page
+                .contains("<h4>Types</h4>\n<ul>\n<li><a href=\"type.point.html\">@point</a></li>"));
+        }
+
+        assert!(index.contains("<title>lib</title>"));
+        assert!(index.contains("<li>Uses 1 item IDs</li>\n<li>Adds 3 objects</li>"));
+        // documented types are linked, descriptions and examples are formatted
+        assert!(index.contains("<td>@number or <a href=\"type.point.html\">@point</a></td>"));
+        assert!(index.contains("<p>Doubles <code>n</code></p>"));
+        assert!(index.contains("<span class=\"hl-builtins\">$</span>.assert(double(<span class=\"hl-number\">2</span>)"));
+        assert!(index.contains("<h2>Values</h2>\n<section class=\"member\" id=\"origin\">"));
+
+        assert!(point.contains(
+            "<p>A &lt;point&gt;</p>\n<h2>Constructors</h2>\n<section class=\"member\" id=\"new\">"
+        ));
+        assert!(
+            point.contains("<td>any</td><td><code><span class=\"hl-number\">0</span></code></td>")
+        );
+        assert!(point.contains(
+            "<h2>Operator Implementations</h2>\n<section class=\"member\" id=\"_plus_\">"
+        ));
+        // `@NULL` isn't shown as a return type
+        assert_eq!(point.matches("Returns:").count(), 1);
+
+        let search: Json = serde_json::from_str(
+            search
+                .strip_prefix("var searchIndex = ")
+                .and_then(|s| s.strip_suffix(";\n"))
+                .unwrap(),
+        )
+        .unwrap();
+        let paths = search
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                (
+                    entry["name"].as_str().unwrap(),
+                    entry["path"].as_str().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                ("double", "index.html#double"),
+                ("origin", "index.html#origin"),
+                ("@point", "type.point.html"),
+                ("@point::new", "type.point.html#new"),
+                ("@point::_plus_", "type.point.html#_plus_"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,45 @@
+// filters the search index (search-index.js) as you type
+(function () {
+    var input = document.getElementById("search");
+    var results = document.getElementById("search-results");
+    var nav = document.getElementById("nav");
+
+    input.addEventListener("input", function () {
+        var query = input.value.trim().toLowerCase();
+        results.innerHTML = "";
+        nav.hidden = query.length > 0;
+        if (!query) {
+            return;
+        }
+
+        var matches = searchIndex.filter(function (entry) {
+            return entry.name.toLowerCase().indexOf(query) !== -1;
+        });
+        // names that start with the query come first
+        matches.sort(function (a, b) {
+            var a_starts = a.name.toLowerCase().indexOf(query) === 0;
+            var b_starts = b.name.toLowerCase().indexOf(query) === 0;
+            return b_starts - a_starts || a.name.length - b.name.length;
+        });
+
+        if (!matches.length) {
+            var empty = document.createElement("li");
+            empty.textContent = "No results";
+            results.appendChild(empty);
+        }
+        matches.slice(0, 50).forEach(function (entry) {
+            var item = document.createElement("li");
+            var link = document.createElement("a");
+            link.href = entry.path;
+            link.textContent = entry.name;
+            item.appendChild(link);
+            if (entry.description) {
+                var desc = document.createElement("span");
+                desc.className = "search-desc";
+                desc.textContent = entry.description;
+                item.appendChild(desc);
+            }
+            results.appendChild(item);
+        });
+    });
+})();
@@ -0,0 +1,252 @@
+// a structured model of a library's documentation (spwn doc --format json),
+// for tools like editor plugins and website generators
+use ahash::AHashMap;
+use compiler::context::FullContext;
+use compiler::globals::Globals;
+use compiler::value::*;
+use errors::compiler_info::CompilerInfo;
+use errors::RuntimeError;
+use internment::LocalIntern;
+use serde_json::{json, Map, Value as Json};
+use shared::StoredValue;
+
+use crate::documentation::{display_pattern, implementations, ContextSplit};
+
+/// Values that are printed longer than this are documented without the printed value
+const MAX_PRINTED_LEN: usize = 300;
+
+pub(crate) fn document_json(
+    name: &str,
+    is_module: bool,
+    split: &ContextSplit,
+    globals: &mut Globals,
+    full_context: &mut FullContext,
+) -> Result<Json, Box<RuntimeError>> {
+    globals.push_new_preserved();
+    globals.push_preserved_val(full_context.inner().return_value);
+
+    // every type is written as `@name`
+    let type_names = globals
+        .type_ids
+        .iter()
+        .map(|(name, (id, _))| (*id, format!("@{}", name)))
+        .collect::<AHashMap<_, _>>();
+
+    let total_objects = globals
+        .func_ids
+        .iter()
+        .map(|f| f.obj_list.len())
+        .sum::<usize>()
+        + globals.objects.len();
+    let info = json!({
+        "groups": globals.closed_groups,
+        "colors": globals.closed_colors,
+        "blocks": globals.closed_blocks,
+        "items": globals.closed_items,
+        "objects": total_objects,
+        "contexts": split.contexts,
+        "differing_exports": split.differing,
+    });
+
+    let exports = globals.stored_values[full_context.inner().return_value].clone();
+    let exports = document_val(&exports, globals, full_context, &type_names)?;
+
+    let mut impls = Vec::new();
+    for (typ, members) in implementations(globals, is_module) {
+        impls.push(json!({
+            "type": type_names[&typ],
+            "description": globals.type_descriptions.get(&typ),
+            "members": document_dict(&members, globals, full_context, &type_names)?,
+        }));
+    }
+
+    globals.pop_preserved();
+
+    Ok(json!({
+        "name": name,
+        "info": info,
+        "exports": exports,
+        "implementations": impls,
+    }))
+}
+
+fn document_dict(
+    dict: &AHashMap<LocalIntern<String>, StoredValue>,
+    globals: &mut Globals,
+    full_context: &mut FullContext,
+    type_names: &AHashMap<u16, String>,
+) -> Result<Json, Box<RuntimeError>> {
+    let mut members = dict.iter().collect::<Vec<_>>();
+    members.sort_by_key(|(key, _)| **key);
+
+    let mut out = Map::new();
+    for (key, val) in members {
+        let val = globals.stored_values[*val].clone();
+        out.insert(
+            key.to_string(),
+            document_val(&val, globals, full_context, type_names)?,
+        );
+    }
+    Ok(Json::Object(out))
+}
+
+fn document_val(
+    val: &Value,
+    globals: &mut Globals,
+    full_context: &mut FullContext,
+    type_names: &AHashMap<u16, String>,
+) -> Result<Json, Box<RuntimeError>> {
+    let type_id = val.to_num(globals);
+    let mut out = Map::new();
+    out.insert("type".into(), json!(type_names[&type_id]));
+
+    let printed = val.display(full_context, globals, &CompilerInfo::new())?;
+    if printed.len() < MAX_PRINTED_LEN {
+        out.insert("printed".into(), json!(printed));
+    }
+
+    match val {
+        Value::Dict(d) => {
+            out.insert(
+                "members".into(),
+                document_dict(d, globals, full_context, type_names)?,
+            );
+        }
+        Value::Macro(m) => document_macro(m, &mut out, globals, full_context, type_names)?,
+        _ => (),
+    }
+    Ok(Json::Object(out))
+}
+
+fn document_macro(
+    mac: &Macro,
+    out: &mut Map<String, Json>,
+    globals: &mut Globals,
+    full_context: &mut FullContext,
+    type_names: &AHashMap<u16, String>,
+) -> Result<(), Box<RuntimeError>> {
+    out.insert("description".into(), json!(mac.tag.get_desc()));
+    out.insert("example".into(), json!(mac.tag.get_example(false)));
+    out.insert(
+        "constructor".into(),
+        json!(mac.tag.get("constructor").is_some()),
+    );
+    out.insert(
+        "method".into(),
+        json!(mac
+            .args
+            .first()
+            .is_some_and(|arg| arg.name == globals.SELF_MEMBER_NAME)),
+    );
+
+    let returns = match mac.ret_pattern {
+        Some(ret) => Some(pattern_string(ret, globals, full_context, type_names)?),
+        None => None,
+    };
+    out.insert("returns".into(), json!(returns));
+
+    let mut args = Vec::new();
+    for arg in mac.args.iter() {
+        if arg.name == globals.SELF_MEMBER_NAME {
+            continue;
+        }
+        let pattern = match arg.pattern {
+            Some(p) => Some(pattern_string(p, globals, full_context, type_names)?),
+            None => None,
+        };
+        let default = match arg.default {
+            Some(d) => Some(globals.stored_values[d].clone().display(
+                full_context,
+                globals,
+                &CompilerInfo::new(),
+            )?),
+            None => None,
+        };
+        args.push(json!({
+            "name": arg.name.as_str(),
+            "pattern": pattern,
+            "default": default,
+            "description": arg.attribute.get_desc(),
+        }));
+    }
+    out.insert("arguments".into(), Json::Array(args));
+    Ok(())
+}
+
+// argument and return patterns can be patterns, types, or any other value (which has to be equal)
+fn pattern_string(
+    val: StoredValue,
+    globals: &mut Globals,
+    full_context: &mut FullContext,
+    type_names: &AHashMap<u16, String>,
+) -> Result<String, Box<RuntimeError>> {
+    Ok(match globals.stored_values[val].clone() {
+        Value::Pattern(p) => display_pattern(&p, full_context, globals, type_names)?,
+        Value::TypeIndicator(t) => type_names[&t].clone(),
+        a => a.display(full_context, globals, &CompilerInfo::new())?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::documentation::tests::document;
+
+    #[test]
+    fn documents_exports_and_implementations() {
+        let json = document(
+            r#"#[no_std]
+type @point
+impl @point {
+    new: #[constructor, desc("Makes a point")] (x: @number, y: @number = 0) -> @point {
+        return @point::{ x, y }
+    },
+    length: (self) -> @number { return self.x + self.y },
+}
+return {
+    origin: 10,
+    double: #[desc("Doubles a number"), example("$.assert(double(2) == 4)")] (n: @number | @string) -> @number {
+        return n * 2
+    },
+}
+"#,
+        );
+
+        assert_eq!(json["name"], "lib");
+        assert_eq!(json["info"]["objects"], 0);
+
+        let exports = &json["exports"];
+        assert_eq!(exports["type"], "@dictionary");
+        assert_eq!(
+            exports["members"]["origin"],
+            json!({ "type": "@number", "printed": "10" })
+        );
+        let double = &exports["members"]["double"];
+        assert_eq!(double["description"], "Doubles a number");
+        assert_eq!(double["example"], "$.assert(double(2) == 4)");
+        assert_eq!(double["returns"], "@number");
+        assert_eq!(double["method"], false);
+        assert_eq!(
+            double["arguments"],
+            json!([{
+                "name": "n",
+                "pattern": "@number or @string",
+                "default": null,
+                "description": null,
+            }])
+        );
+
+        let impls = json["implementations"].as_array().unwrap();
+        assert_eq!(impls.len(), 1);
+        assert_eq!(impls[0]["type"], "@point");
+        let new = &impls[0]["members"]["new"];
+        assert_eq!(new["constructor"], true);
+        assert_eq!(new["description"], "Makes a point");
+        assert_eq!(new["returns"], "@point");
+        assert_eq!(new["arguments"][1]["default"], "0");
+        // `self` isn't an argument
+        let length = &impls[0]["members"]["length"];
+        assert_eq!(length["method"], true);
+        assert_eq!(length["arguments"], json!([]));
+    }
+}
@@ -1 +1,3 @@
 pub mod documentation;
+mod html;
+mod json;
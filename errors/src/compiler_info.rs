@@ -1,4 +1,5 @@
 use internment::LocalIntern;
+use serde::{Deserialize, Serialize};
 use shared::FileRange;
 use std::path::PathBuf;
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -46,7 +47,7 @@ impl Default for CompilerInfo {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CodeArea {
     pub file: LocalIntern<shared::SpwnSource>,
     pub pos: FileRange,
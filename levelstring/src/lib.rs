@@ -151,306 +151,618 @@ pub fn serialize_triggers_old(func_ids: Vec<FunctionID>) -> String {
 //</OLD>
 
 use libflate::{gzip, zlib};
-use std::io::Read;
+use quick_xml::events::{BytesText, Event};
+use quick_xml::{Reader, Writer};
+use std::fmt;
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const IOS_KEY: &[u8] = &[
+    0x69, 0x70, 0x75, 0x39, 0x54, 0x55, 0x76, 0x35, 0x34, 0x79, 0x76, 0x5D, 0x69, 0x73, 0x46, 0x4D,
+    0x68, 0x35, 0x40, 0x3B, 0x74, 0x2E, 0x35, 0x77, 0x33, 0x34, 0x45, 0x32, 0x52, 0x79, 0x40, 0x7B,
+];
+
+type AesEcb = block_modes::Ecb<aes::Aes256, block_modes::block_padding::Pkcs7>;
+
+#[derive(Debug)]
+pub enum SaveFileError {
+    Io(std::io::Error),
+    // the file is not in any of the formats GD uses
+    UnknownFormat,
+    // the file looks like a save file, but could not be decrypted or decompressed
+    Corrupted(String),
+    Xml { position: usize, message: String },
+    LevelNotFound(Option<String>),
+    LevelNotInitialized(String),
+    LevelExists(String),
+    NoBackups,
+}
+
+impl fmt::Display for SaveFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveFileError::Io(e) => write!(f, "Could not access the save file: {}", e),
+            SaveFileError::UnknownFormat => write!(f, "This is not a Geometry Dash save file"),
+            SaveFileError::Corrupted(e) => write!(f, "The save file is corrupted: {}", e),
+            SaveFileError::Xml { position, message } => write!(
+                f,
+                "The save file is corrupted: invalid XML at position {}: {}",
+                position, message
+            ),
+            SaveFileError::LevelNotFound(Some(name)) => {
+                write!(f, "Level named \"{}\" was not found!", name)
+            }
+            SaveFileError::LevelNotFound(None) => write!(
+                f,
+                "No level found! Please create a level for SPWN to operate on!"
+            ),
+            SaveFileError::LevelNotInitialized(name) => write!(
+                f,
+                "Level \"{}\" is not initialized! Please open the level, place some objects, then save and quit to initialize the level.",
+                name
+            ),
+            SaveFileError::LevelExists(name) => {
+                write!(f, "A level named \"{}\" already exists", name)
+            }
+            SaveFileError::NoBackups => write!(f, "There are no backups of this save file"),
+        }
+    }
+}
+
+impl std::error::Error for SaveFileError {}
+
+impl From<std::io::Error> for SaveFileError {
+    fn from(e: std::io::Error) -> Self {
+        SaveFileError::Io(e)
+    }
+}
+
+/// How a save file is encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encryption {
+    // windows, linux (proton) and android: xor with 11, then url-safe base64 and gzip
+    Xor,
+    // macos: AES-256 in ECB mode
+    Aes,
+    // not encrypted at all
+    None,
+}
+
+impl Encryption {
+    /// Finds the encryption scheme from the first bytes of a save file
+    pub fn detect(bytes: &[u8]) -> Result<Self, SaveFileError> {
+        let start = bytes
+            .iter()
+            .copied()
+            .skip_while(u8::is_ascii_whitespace)
+            .take(5)
+            .collect::<Vec<_>>();
+
+        if start.starts_with(b"<?xml") || start.starts_with(b"<plis") {
+            Ok(Encryption::None)
+        } else if xor(bytes.iter().take(4).copied().collect(), 11) == b"H4sI" {
+            Ok(Encryption::Xor)
+        } else if !bytes.is_empty() && bytes.len().is_multiple_of(16) {
+            Ok(Encryption::Aes)
+        } else {
+            Err(SaveFileError::UnknownFormat)
+        }
+    }
+}
 
 fn xor(data: Vec<u8>, key: u8) -> Vec<u8> {
     data.into_iter().map(|b| b ^ key).collect()
 }
-fn base_64_decrypt(encoded: Vec<u8>) -> Vec<u8> {
-    let l = encoded.len();
-    base64::decode(
-        String::from_utf8([encoded, b"=".repeat(l % 4)].concat())
-            .unwrap()
-            .as_str(),
-    )
-    .unwrap()
+
+fn base_64_decrypt(encoded: &str) -> Result<Vec<u8>, SaveFileError> {
+    let mut encoded = encoded
+        .trim_end_matches('\0')
+        .trim_end_matches('=')
+        .to_string();
+    while !encoded.len().is_multiple_of(4) {
+        encoded.push('=');
+    }
+    base64::decode_config(&encoded, base64::URL_SAFE)
+        .map_err(|e| SaveFileError::Corrupted(format!("invalid base64 ({})", e)))
 }
 
-use quick_xml::events::{BytesText, Event};
-use quick_xml::Reader;
-//use std::io::BufReader;
-fn decrypt_savefile(mut sf: Vec<u8>) -> Result<Vec<u8>, String> {
-    if cfg!(target_os = "macos") {
-        use aes::Aes256;
+fn gzip_decompress(data: &[u8]) -> Result<Vec<u8>, SaveFileError> {
+    let mut out = Vec::new();
+    gzip::Decoder::new(data)
+        .and_then(|mut decoder| decoder.read_to_end(&mut out))
+        .map_err(|e| SaveFileError::Corrupted(format!("invalid gzip data ({})", e)))?;
+    Ok(out)
+}
 
-        use block_modes::block_padding::Pkcs7;
-        use block_modes::{BlockMode, Ecb};
+/// Decompresses a level string from the save file (url-safe base64, then gzip)
+pub fn decompress_level_string(ls: &str) -> Result<String, SaveFileError> {
+    let data = gzip_decompress(&base_64_decrypt(ls)?)?;
+    String::from_utf8(data)
+        .map_err(|_| SaveFileError::Corrupted("the level string is not valid UTF-8".to_string()))
+}
 
-        const IOS_KEY: &[u8] = &[
-            0x69, 0x70, 0x75, 0x39, 0x54, 0x55, 0x76, 0x35, 0x34, 0x79, 0x76, 0x5D, 0x69, 0x73,
-            0x46, 0x4D, 0x68, 0x35, 0x40, 0x3B, 0x74, 0x2E, 0x35, 0x77, 0x33, 0x34, 0x45, 0x32,
-            0x52, 0x79, 0x40, 0x7B,
-        ];
+/// A level in the save file
+#[derive(Debug, Clone)]
+pub struct Level {
+    pub name: String,
+    // the compressed level string, if the level is initialized
+    pub data: Option<String>,
+    // where `data` is in the XML
+    data_range: Option<Range<usize>>,
+    // where the key of the level (k_0, k_1, ...) is in the XML
+    key_range: Range<usize>,
+    // where the level's dictionary ends in the XML
+    end: usize,
+}
 
-        type AesEcb = Ecb<Aes256, Pkcs7>;
+impl Level {
+    pub fn level_string(&self) -> Result<String, SaveFileError> {
+        match &self.data {
+            Some(data) => decompress_level_string(data),
+            None => Err(SaveFileError::LevelNotInitialized(self.name.clone())),
+        }
+    }
 
-        // re-create cipher mode instance
-        let cipher = AesEcb::new_from_slices(IOS_KEY, &[]).unwrap();
+    pub fn is_initialized(&self) -> bool {
+        self.data.is_some()
+    }
 
-        Ok(match cipher.decrypt(&mut sf) {
-            Ok(v) => v,
-            Err(e) => return Err(format!("{}", e)),
+    /// Number of objects in the level (0 if it is not initialized)
+    pub fn object_count(&self) -> Result<usize, SaveFileError> {
+        if !self.is_initialized() {
+            return Ok(0);
         }
-        .to_vec())
-    } else {
-        let xor = xor(sf.to_vec(), 11);
-        let replaced = String::from_utf8_lossy(&xor)
-            .replace('-', "+")
-            .replace('_', "/")
-            .replace('\0', "");
-        let b64 = match base64::decode(replaced.as_str()) {
-            Ok(b) => b,
-            Err(e) => return Err(format!("{}", e)),
-        };
-        let mut decoder = gzip::Decoder::new(&b64[..]).unwrap();
-        let mut data = Vec::new();
-        decoder.read_to_end(&mut data).unwrap();
-        Ok(data)
+        // the first part is the level settings
+        Ok(self
+            .level_string()?
+            .split(';')
+            .skip(1)
+            .filter(|obj| !obj.is_empty())
+            .count())
     }
 }
-pub fn get_level_string(ls: Vec<u8>, level_name: Option<&String>) -> Result<String, String> {
-    //decrypting the savefile
-    let content = decrypt_savefile(ls)?;
-    let string_content = String::from_utf8_lossy(&content);
-
-    let mut reader = Reader::from_str(&string_content);
-    reader.trim_text(true);
-
-    let mut buf = Vec::new();
-
-    // The `Reader` does not implement `Iterator` because it outputs borrowed data (`Cow`s)
-    let mut level_string = String::new();
-    let mut k4_detected = false;
-    let mut k2_detected = false;
-    let mut level_detected = false;
-
-    loop {
-        match reader.read_event(&mut buf) {
-            // unescape and decode the text event using the reader encoding
-            Ok(Event::Text(e)) => {
-                let text = e.unescape_and_decode(&reader).unwrap();
-
-                if text == "k2" {
-                    k2_detected = true;
-                    if level_detected {
-                        return Err(
-                            "Level is not initialized! Please open the level, place some objects, then save and quit to initialize the level."
-                            .to_string()
-                        );
+
+/// The level string GD creates for a new level (just the level settings)
+pub const DEFAULT_LEVEL_STRING: &str = "kS38,1_40_2_125_3_255_11_255_12_255_13_1_4_-1_6_1000_7_1_15_1_18_0_8_1|1_0_2_102_3_255_11_255_12_255_13_1_4_-1_6_1001_7_1_15_1_18_0_8_1|1_0_2_102_3_255_11_255_12_255_13_1_4_-1_6_1009_7_1_15_1_18_0_8_1|1_255_2_255_3_255_11_255_12_255_13_1_4_-1_6_1002_5_1_7_1_15_1_18_0_8_1|1_40_2_125_3_255_11_255_12_255_13_1_4_-1_6_1013_7_1_15_1_18_0_8_1|1_40_2_125_3_255_11_255_12_255_13_1_4_-1_6_1014_7_1_15_1_18_0_8_1|,kA13,0,kA15,0,kA16,0,kA14,,kA6,0,kA7,0,kA17,0,kA18,0,kS39,0,kA2,0,kA3,0,kA8,0,kA4,0,kA9,0,kA10,0,kA11,0;";
+
+/// A decrypted CCLocalLevels.dat
+#[derive(Debug, Clone)]
+pub struct SaveFile {
+    pub xml: String,
+    pub encryption: Encryption,
+}
+
+impl SaveFile {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SaveFileError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SaveFileError> {
+        let encryption = Encryption::detect(bytes)?;
+
+        let decrypted = match encryption {
+            Encryption::None => bytes.to_vec(),
+            Encryption::Xor => {
+                let xored = xor(bytes.to_vec(), 11);
+                let b64 = String::from_utf8_lossy(&xored);
+                gzip_decompress(&base_64_decrypt(&b64)?)?
+            }
+            Encryption::Aes => {
+                use block_modes::BlockMode;
+
+                let cipher = AesEcb::new_from_slices(IOS_KEY, &[])
+                    .map_err(|e| SaveFileError::Corrupted(e.to_string()))?;
+                cipher
+                    .decrypt_vec(bytes)
+                    .map_err(|e| SaveFileError::Corrupted(format!("could not decrypt ({})", e)))?
+            }
+        };
+
+        Ok(SaveFile {
+            xml: String::from_utf8_lossy(&decrypted).to_string(),
+            encryption,
+        })
+    }
+
+    /// Encrypts the save file again, with the scheme it was read with
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let bytes = self.xml.as_bytes();
+        match self.encryption {
+            Encryption::None => bytes.to_vec(),
+            Encryption::Xor => {
+                let mut encoder = zlib::Encoder::new(Vec::new()).unwrap();
+                encoder.write_all(bytes).unwrap();
+                let compressed = encoder.finish().into_result().unwrap();
+                use crc32fast::Hasher;
+
+                let mut hasher = Hasher::new();
+                hasher.update(bytes);
+                let checksum = hasher.finalize();
+
+                let data_size = bytes.len() as u32;
+
+                let mut with_signature = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x0b".to_vec();
+                with_signature.extend(&compressed[2..compressed.len() - 4]);
+                with_signature.extend(checksum.to_le_bytes().to_vec());
+                with_signature.extend(data_size.to_le_bytes().to_vec());
+
+                let encoded = base64::encode_config(&with_signature, base64::URL_SAFE)
+                    .as_bytes()
+                    .to_vec();
+
+                xor(encoded, 11)
+            }
+            Encryption::Aes => {
+                use block_modes::BlockMode;
+
+                // the key is a valid length, so this can't fail
+                let cipher = AesEcb::new_from_slices(IOS_KEY, &[]).unwrap();
+                cipher.encrypt_vec(bytes)
+            }
+        }
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), SaveFileError> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    /// All levels in the save file, in the order they appear in the editor
+    pub fn levels(&self) -> Result<Vec<Level>, SaveFileError> {
+        Ok(self.walk()?.0)
+    }
+
+    // finds the levels, and where the level list ends
+    fn walk(&self) -> Result<(Vec<Level>, Option<usize>), SaveFileError> {
+        let mut reader = Reader::from_str(&self.xml);
+        let mut buf = Vec::new();
+
+        let xml_error = |reader: &Reader<&[u8]>, e: quick_xml::Error| SaveFileError::Xml {
+            position: reader.buffer_position(),
+            message: e.to_string(),
+        };
+
+        let mut levels = Vec::new();
+        // the key of every dictionary we are in
+        let mut dicts: Vec<Option<String>> = Vec::new();
+        // the last key in the current dictionary
+        let mut key: Option<String> = None;
+        let mut key_range = 0..0;
+        let mut in_key = false;
+        let mut list_end = None;
+        // the level being read, and how many dictionaries deep it is
+        let mut level: Option<(Level, usize)> = None;
+
+        loop {
+            let start = reader.buffer_position();
+            match reader.read_event(&mut buf) {
+                Ok(Event::Start(e)) => match e.name() {
+                    b"k" => in_key = true,
+                    b"d" | b"dict" => {
+                        dicts.push(key.take());
+                        let len = dicts.len();
+                        if len >= 2
+                            && dicts[len - 2].as_deref() == Some("LLM_01")
+                            && matches!(&dicts[len - 1], Some(k) if k.starts_with("k_"))
+                        {
+                            let new = Level {
+                                name: String::new(),
+                                data: None,
+                                data_range: None,
+                                key_range: key_range.clone(),
+                                end: 0,
+                            };
+                            level = Some((new, len));
+                        }
                     }
-                } else if k2_detected {
-                    if let Some(level_name) = level_name {
-                        if text == *level_name {
-                            level_detected = true
+                    _ => (),
+                },
+                Ok(Event::Text(e)) => {
+                    let text = e
+                        .unescape_and_decode(&reader)
+                        .map_err(|e| xml_error(&reader, e))?;
+                    if in_key {
+                        key = Some(text);
+                        key_range = start..reader.buffer_position();
+                    } else if let Some((level, depth)) = &mut level {
+                        if *depth == dicts.len() {
+                            match key.as_deref() {
+                                Some("k2") => level.name = text,
+                                Some("k4") => {
+                                    level.data = Some(text);
+                                    level.data_range = Some(start..reader.buffer_position());
+                                }
+                                _ => (),
+                            }
                         }
-                    } else {
-                        level_detected = true
                     }
-                    k2_detected = false
-                }
-                if level_detected && text == "k4" {
-                    k4_detected = true
-                } else if k4_detected {
-                    level_string = text;
-                    break;
                 }
+                Ok(Event::End(e)) => match e.name() {
+                    b"k" => in_key = false,
+                    b"d" | b"dict" => {
+                        if matches!(&level, Some((_, depth)) if *depth == dicts.len()) {
+                            let (mut done, _) = level.take().unwrap();
+                            done.end = start;
+                            levels.push(done);
+                        } else if matches!(dicts.last(), Some(Some(k)) if k == "LLM_01") {
+                            list_end = Some(start);
+                        }
+                        dicts.pop();
+                        key = None;
+                    }
+                    _ => key = None,
+                },
+                Ok(Event::Empty(_)) => key = None,
+                Ok(Event::Eof) => break,
+                Err(e) => return Err(xml_error(&reader, e)),
+                _ => (),
             }
-
-            Ok(Event::Eof) => break, // exits the loop when reaching end of file
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-            _ => (), // There are several other `Event`s we do not consider here
+            buf.clear();
         }
 
-        // if we don't keep a borrow elsewhere, we can clear the buffer to keep memory usage low
-        buf.clear();
+        Ok((levels, list_end))
     }
-    buf.clear();
-    if level_detected && !k4_detected {
-        return Err(
-            "Level is not initialized! Please open the level, place some objects, then save and quit to initialize the level."
-            .to_string()
-        );
-    } else if !k4_detected {
-        if let Some(level_name) = level_name {
-            return Err(format!("Level named \"{}\" was not found!", level_name));
-        } else {
-            return Err(
-                "No level found! Please create a level for SPWN to operate on!".to_string(),
-            );
+
+    /// Finds a level by name, or the first level if no name is given
+    pub fn level(&self, name: Option<&str>) -> Result<Level, SaveFileError> {
+        self.levels()?
+            .into_iter()
+            .find(|l| name.is_none_or(|n| l.name == n))
+            .ok_or_else(|| SaveFileError::LevelNotFound(name.map(str::to_string)))
+    }
+
+    /// Replaces the (decompressed) level string of a level, and returns the name of that level.
+    /// Levels that are not initialized get a level string added.
+    pub fn set_level_string(
+        &mut self,
+        name: Option<&str>,
+        ls: &str,
+    ) -> Result<String, SaveFileError> {
+        let level = self.level(name)?;
+        // compressed level strings only contain url-safe base64, so there's nothing to escape
+        let compressed = compress_level_string(ls);
+        match level.data_range {
+            Some(range) => self.xml.replace_range(range, &compressed),
+            None => self
+                .xml
+                .insert_str(level.end, &format!("<k>k4</k><s>{}</s>", compressed)),
         }
+        Ok(level.name)
     }
 
-    /*let mut k4_detected = false;
-    for token in xmlparser::Tokenizer::from(String::from_utf8(buf).unwrap().as_str()) {
-        if let xmlparser::Token::Text { text } = token.unwrap() {
-            if k4_detected {
-                level_string = text.as_str().to_string();
-                break;
-            }
-            if text.as_str() == "k4" {
-                k4_detected = true;
-            }
+    /// Adds a new, initialized level at the top of the level list (like GD does)
+    pub fn create_level(&mut self, name: &str) -> Result<(), SaveFileError> {
+        let (levels, list_end) = self.walk()?;
+        if levels.iter().any(|l| l.name == name) {
+            return Err(SaveFileError::LevelExists(name.to_string()));
+        }
+        let list_end = list_end.ok_or_else(|| {
+            SaveFileError::Corrupted("the save file has no level list".to_string())
+        })?;
+
+        // move the other levels down, starting at the end so the positions stay correct
+        for (i, level) in levels.iter().enumerate().rev() {
+            self.xml
+                .replace_range(level.key_range.clone(), &format!("k_{}", i + 1));
         }
-    }*/
-    //decrypting level string
-    let ls_b64 = base_64_decrypt(
-        level_string
-            .replace('-', "+")
-            .replace('_', "/")
-            .replace('\0', "")
-            .as_bytes()
-            .to_vec(),
-    );
 
-    //println!("{}", String::from_utf8(ls_b64.clone()).unwrap());
+        let new_level = format!(
+            "<k>k_0</k><d><k>kCEK</k><i>4</i><k>k2</k><s>{}</s><k>k4</k><s>{}</s><k>k13</k><t /><k>k21</k><i>2</i><k>k50</k><i>35</i></d>",
+            String::from_utf8_lossy(&quick_xml::escape::escape(name.as_bytes())),
+            compress_level_string(DEFAULT_LEVEL_STRING),
+        );
+        // the key range is inside `<k>...</k>`, and the renaming above only happens after it
+        let position = match levels.first() {
+            Some(first) => first.key_range.start - "<k>".len(),
+            None => list_end,
+        };
+        self.xml.insert_str(position, &new_level);
+        Ok(())
+    }
+}
 
-    let mut ls_decoder = gzip::Decoder::new(&ls_b64[..]).unwrap();
-    let mut ls_buf = Vec::new();
-    ls_decoder.read_to_end(&mut ls_buf).unwrap();
+const BACKUP_FOLDER: &str = "spwn_backups";
+/// Older automatic backups are deleted
+pub const MAX_BACKUPS: usize = 20;
+// at the end of the names of manual backups
+const MANUAL_SUFFIX: &str = "_manual";
+
+/// Who made a backup of the save file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupKind {
+    /// made by SPWN before it changes the save file. Only the newest of these are kept
+    Automatic,
+    /// asked for by the user, these are never deleted
+    Manual,
+}
 
-    Ok(String::from_utf8(ls_buf).unwrap())
+/// Copies the save file into a `spwn_backups` folder next to it, with the current time (UTC) in the name.
+/// Only the newest automatic backups are kept.
+pub fn backup_save_file(
+    path: impl AsRef<Path>,
+    kind: BackupKind,
+) -> Result<PathBuf, SaveFileError> {
+    let path = path.as_ref();
+    let folder = path.with_file_name(BACKUP_FOLDER);
+    fs::create_dir_all(&folder)?;
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let suffix = match kind {
+        BackupKind::Automatic => "",
+        BackupKind::Manual => MANUAL_SUFFIX,
+    };
+    let time = timestamp();
+    let mut name = format!("{}_{}", stem, time);
+    // more than one backup in the same second (padded so they still sort by name)
+    let mut i = 1;
+    while [name.clone(), name.clone() + MANUAL_SUFFIX]
+        .iter()
+        .any(|n| folder.join(format!("{}.dat", n)).exists())
+    {
+        name = format!("{}_{}_{:03}", stem, time, i);
+        i += 1;
+    }
+    let backup = folder.join(format!("{}{}.dat", name, suffix));
+    fs::copy(path, &backup)?;
+
+    let automatic = list_backups(path)?
+        .into_iter()
+        .filter(|b| !is_manual(b))
+        .collect::<Vec<_>>();
+    if automatic.len() > MAX_BACKUPS {
+        for old in &automatic[..automatic.len() - MAX_BACKUPS] {
+            fs::remove_file(old)?;
+        }
+    }
+    Ok(backup)
 }
 
-use quick_xml::Writer;
-use std::fs;
-use std::io::Cursor;
-use std::path::PathBuf;
-
-pub fn encrypt_level_string(
-    ls: String,
-    old_ls: String,
-    path: PathBuf,
-    level_name: Option<String>,
-) -> Result<(), String> {
-    let mut file = fs::File::open(path.clone()).unwrap();
-    let mut file_content = Vec::new();
-    file.read_to_end(&mut file_content).unwrap();
-
-    //decrypting the savefile
-    let content = decrypt_savefile(file_content)?;
-    let string_content = String::from_utf8_lossy(&content);
-
-    let mut reader = Reader::from_str(&string_content);
-    reader.trim_text(true);
+fn is_manual(backup: &Path) -> bool {
+    backup
+        .file_stem()
+        .is_some_and(|s| s.to_string_lossy().ends_with(MANUAL_SUFFIX))
+}
 
-    let mut writer = Writer::new(Cursor::new(Vec::new()));
+/// The backups of a save file, oldest first
+pub fn list_backups(path: impl AsRef<Path>) -> Result<Vec<PathBuf>, SaveFileError> {
+    let path = path.as_ref();
+    let folder = path.with_file_name(BACKUP_FOLDER);
+    if !folder.is_dir() {
+        return Ok(Vec::new());
+    }
 
-    let mut buf = Vec::new();
-
-    let mut k4_detected = false;
-    let mut done = false;
-    let mut k2_detected = false;
-    let mut level_detected = false;
-
-    //println!("{}", old_ls);
-
-    let full_ls = old_ls + &ls;
-
-    loop {
-        match reader.read_event(&mut buf) {
-            // unescape and decode the text event using the reader encoding
-            Ok(Event::Text(e)) => {
-                let text = e.unescape_and_decode(&reader).unwrap();
-                if k4_detected && level_detected {
-                    let encrypted_ls: String = {
-                        let mut ls_encoder = gzip::Encoder::new(Vec::new()).unwrap();
-                        ls_encoder.write_all(full_ls.as_bytes()).unwrap();
-                        let b64_encrypted =
-                            base64::encode(&ls_encoder.finish().into_result().unwrap());
-                        let fin = b64_encrypted.replace('+', "-").replace('/', "_");
-                        "H4sIAAAAAAAAC".to_string() + &fin[13..]
-                    };
-
-                    assert!(writer
-                        .write_event(Event::Text(BytesText::from_plain_str(&encrypted_ls)))
-                        .is_ok());
-                    done = true;
-                    k4_detected = false;
-                } else {
-                    if k4_detected {
-                        k4_detected = false;
-                    }
-                    assert!(writer.write_event(Event::Text(e)).is_ok());
+    let prefix = format!(
+        "{}_",
+        path.file_stem().unwrap_or_default().to_string_lossy()
+    );
+    let mut backups = fs::read_dir(folder)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    backups.retain(|b| {
+        b.file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with(&prefix))
+    });
+    // the timestamps sort the same way as the names
+    backups.sort_by_key(|b| {
+        let stem = b.file_stem().unwrap_or_default().to_string_lossy();
+        stem.strip_suffix(MANUAL_SUFFIX)
+            .unwrap_or(&stem)
+            .to_string()
+    });
+    Ok(backups)
+}
 
-                    if k2_detected {
-                        if let Some(level_name) = &level_name {
-                            if level_name == &text {
-                                level_detected = true;
-                                println!("Writing to level: {}", text);
-                            }
-                        } else {
-                            level_detected = true;
-                            println!("Writing to level: {}", text);
-                        }
+// YYYY-MM-DD_HH-MM-SS
+fn timestamp() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, time) = (secs / 86400, secs % 86400);
+
+    // days to a civil date, from http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
 
-                        k2_detected = false;
-                    }
-                }
+/// The level settings at the start of a level string (everything before the first object),
+/// or the ones of a new level if it doesn't start with them
+pub fn level_header(ls: &str) -> &str {
+    match ls.find(';') {
+        // the settings have keys like `kS38` and `kA13`, objects only have numbers
+        Some(end) if ls.starts_with('k') => &ls[..=end],
+        _ => DEFAULT_LEVEL_STRING,
+    }
+}
 
-                if !done && text == "k4" {
-                    k4_detected = true
-                }
+/// Compresses a level string the way GD stores it (gzip, then url-safe base64)
+pub fn compress_level_string(ls: &str) -> String {
+    let mut ls_encoder = gzip::Encoder::new(Vec::new()).unwrap();
+    ls_encoder.write_all(ls.as_bytes()).unwrap();
+    let b64_encrypted = base64::encode(ls_encoder.finish().into_result().unwrap());
+    let fin = b64_encrypted.replace('+', "-").replace('/', "_");
+    "H4sIAAAAAAAAC".to_string() + &fin[13..]
+}
 
-                if !done && text == "k2" {
-                    k2_detected = true
-                }
+/// Level metadata for exported `.gmd` files
+#[derive(Debug, Clone, Default)]
+pub struct LevelInfo {
+    pub name: String,
+    pub description: String,
+    // index of one of the official songs
+    pub official_song: Option<u32>,
+    // newgrounds id of a custom song
+    pub custom_song: Option<u32>,
+}
+
+/// Creates an exported level file (`.gmd`), which can be imported into GD with tools like GDShare
+pub fn level_to_gmd(ls: &str, info: &LevelInfo) -> String {
+    use quick_xml::events::{BytesDecl, BytesEnd, BytesStart};
+
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+
+    let mut write = |event: Event| writer.write_event(event).unwrap();
+
+    write(Event::Decl(BytesDecl::new(b"1.0", None, None)));
+    write(Event::Start(
+        BytesStart::borrowed_name(b"plist").with_attributes([("version", "1.0"), ("gjver", "2.0")]),
+    ));
+    write(Event::Start(BytesStart::borrowed_name(b"dict")));
+
+    let mut entry = |key: &str, typ: &[u8], value: Option<&str>| {
+        write(Event::Start(BytesStart::borrowed_name(b"k")));
+        write(Event::Text(BytesText::from_plain_str(key)));
+        write(Event::End(BytesEnd::borrowed(b"k")));
+        match value {
+            Some(value) => {
+                write(Event::Start(BytesStart::borrowed_name(typ)));
+                write(Event::Text(BytesText::from_plain_str(value)));
+                write(Event::End(BytesEnd::borrowed(typ)));
             }
-            Ok(Event::Eof) => break, // exits the loop when reaching end of file
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-            Ok(e) => assert!(writer.write_event(e).is_ok()),
+            None => write(Event::Empty(BytesStart::borrowed_name(typ))),
         }
-
-        // if we don't keep a borrow elsewhere, we can clear the buffer to keep memory usage low
-        buf.clear();
+    };
+
+    entry("kCEK", b"i", Some("4"));
+    entry("k2", b"s", Some(&info.name));
+    if !info.description.is_empty() {
+        entry(
+            "k3",
+            b"s",
+            Some(&base64::encode_config(&info.description, base64::URL_SAFE)),
+        );
     }
-    let bytes = writer.into_inner().into_inner();
-    //encrypt level save
-    use std::io::Write;
-
-    if cfg!(target_os = "macos") {
-        use aes::Aes256;
-
-        use block_modes::block_padding::Pkcs7;
-        use block_modes::{BlockMode, Ecb};
-
-        const IOS_KEY: &[u8] = &[
-            0x69, 0x70, 0x75, 0x39, 0x54, 0x55, 0x76, 0x35, 0x34, 0x79, 0x76, 0x5D, 0x69, 0x73,
-            0x46, 0x4D, 0x68, 0x35, 0x40, 0x3B, 0x74, 0x2E, 0x35, 0x77, 0x33, 0x34, 0x45, 0x32,
-            0x52, 0x79, 0x40, 0x7B,
-        ];
-
-        type AesEcb = Ecb<Aes256, Pkcs7>;
-
-        // re-create cipher mode instance
-        let cipher = AesEcb::new_from_slices(IOS_KEY, &[]).unwrap();
-
-        let fin = cipher.encrypt_vec(&bytes);
-        assert!(fs::write(path, fin).is_ok());
-    } else {
-        let mut encoder = zlib::Encoder::new(Vec::new()).unwrap();
-        encoder.write_all(&bytes).unwrap();
-        let compressed = encoder.finish().into_result().unwrap();
-        use crc32fast::Hasher;
-
-        let mut hasher = Hasher::new();
-        hasher.update(&bytes);
-        let checksum = hasher.finalize();
-
-        let data_size = bytes.len() as u32;
-
-        let mut with_signature = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x0b".to_vec();
-        with_signature.extend(&compressed[2..compressed.len() - 4]);
-        with_signature.extend(checksum.to_le_bytes().to_vec());
-        with_signature.extend(data_size.to_le_bytes().to_vec());
-
-        let encoded = base64::encode(&with_signature)
-            .replace('+', "-")
-            .replace('/', "_")
-            .as_bytes()
-            .to_vec();
-
-        let fin = xor(encoded, 11);
-        assert!(fs::write(path, fin).is_ok());
+    entry("k4", b"s", Some(&compress_level_string(ls)));
+    if let Some(song) = info.official_song {
+        entry("k8", b"i", Some(&song.to_string()));
     }
-    Ok(())
+    if let Some(song) = info.custom_song {
+        entry("k45", b"i", Some(&song.to_string()));
+    }
+    entry("k13", b"t", None);
+    entry("k21", b"i", Some("2"));
+    entry("k50", b"i", Some("35"));
+
+    write(Event::End(BytesEnd::borrowed(b"dict")));
+    write(Event::End(BytesEnd::borrowed(b"plist")));
+
+    String::from_utf8(writer.into_inner().into_inner()).unwrap()
 }
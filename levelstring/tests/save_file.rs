@@ -0,0 +1,209 @@
+use levelstring::{BackupKind, Encryption, SaveFile, SaveFileError};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(format!("{}/{}", FIXTURES, name)).unwrap()
+}
+
+fn fixture_text(name: &str) -> String {
+    String::from_utf8(fixture(name)).unwrap()
+}
+
+#[test]
+fn detects_encryption() {
+    for (file, encryption) in [
+        ("CCLocalLevels.xml", Encryption::None),
+        ("windows.dat", Encryption::Xor),
+        ("macos.dat", Encryption::Aes),
+    ] {
+        let save = SaveFile::from_bytes(&fixture(file)).unwrap();
+        assert_eq!(save.encryption, encryption, "{}", file);
+        assert_eq!(save.xml, fixture_text("CCLocalLevels.xml"), "{}", file);
+    }
+}
+
+#[test]
+fn reads_levels() {
+    let save = SaveFile::from_bytes(&fixture("windows.dat")).unwrap();
+
+    let names = save
+        .levels()
+        .unwrap()
+        .into_iter()
+        .map(|l| l.name)
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["first level", "k4 & friends", "empty"]);
+
+    let first = save.level(None).unwrap().level_string().unwrap();
+    assert_eq!(first, fixture_text("level_first.txt"));
+
+    let second = save.level(Some("k4 & friends")).unwrap();
+    assert_eq!(
+        second.level_string().unwrap(),
+        fixture_text("level_second.txt")
+    );
+
+    assert!(matches!(
+        save.level(Some("empty")).unwrap().level_string(),
+        Err(SaveFileError::LevelNotInitialized(_))
+    ));
+    assert!(matches!(
+        save.level(Some("missing")),
+        Err(SaveFileError::LevelNotFound(Some(_)))
+    ));
+}
+
+#[test]
+fn round_trip() {
+    for file in ["windows.dat", "macos.dat"] {
+        let mut save = SaveFile::from_bytes(&fixture(file)).unwrap();
+        let ls = fixture_text("level_second.txt") + "1,1,2,105,3,15;";
+
+        let name = save.set_level_string(Some("k4 & friends"), &ls).unwrap();
+        assert_eq!(name, "k4 & friends");
+
+        let reread = SaveFile::from_bytes(&save.to_bytes()).unwrap();
+        assert_eq!(reread.encryption, save.encryption);
+        assert_eq!(
+            reread
+                .level(Some("k4 & friends"))
+                .unwrap()
+                .level_string()
+                .unwrap(),
+            ls
+        );
+        assert_eq!(
+            reread.level(None).unwrap().level_string().unwrap(),
+            fixture_text("level_first.txt")
+        );
+    }
+}
+
+#[test]
+fn corrupted_files_are_errors() {
+    let mut truncated = fixture("windows.dat");
+    truncated.truncate(truncated.len() / 2);
+    assert!(SaveFile::from_bytes(&truncated).is_err());
+
+    let mut bad_aes = fixture("macos.dat");
+    bad_aes.truncate(bad_aes.len() - 16);
+    bad_aes.extend([0; 16]);
+    assert!(SaveFile::from_bytes(&bad_aes).is_err());
+
+    assert!(matches!(
+        SaveFile::from_bytes(b"not a save file"),
+        Err(SaveFileError::UnknownFormat)
+    ));
+
+    let bad_xml =
+        SaveFile::from_bytes(b"<?xml version=\"1.0\"?><plist><dict></d></plist>").unwrap();
+    assert!(matches!(bad_xml.levels(), Err(SaveFileError::Xml { .. })));
+}
+
+#[test]
+fn creates_levels() {
+    let mut save = SaveFile::from_bytes(&fixture("windows.dat")).unwrap();
+    save.create_level("new <level>").unwrap();
+    assert!(matches!(
+        save.create_level("empty"),
+        Err(SaveFileError::LevelExists(_))
+    ));
+
+    let save = SaveFile::from_bytes(&save.to_bytes()).unwrap();
+    let levels = save.levels().unwrap();
+    let names = levels.iter().map(|l| l.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        ["new <level>", "first level", "k4 & friends", "empty"]
+    );
+
+    assert_eq!(
+        levels[0].level_string().unwrap(),
+        levelstring::DEFAULT_LEVEL_STRING
+    );
+    let counts = levels
+        .iter()
+        .map(|l| l.object_count().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(counts, [0, 2, 1, 0]);
+}
+
+#[test]
+fn initializes_levels() {
+    let mut save = SaveFile::from_bytes(&fixture("macos.dat")).unwrap();
+    save.set_level_string(Some("empty"), "1,1,2,15,3,15;")
+        .unwrap();
+
+    let save = SaveFile::from_bytes(&save.to_bytes()).unwrap();
+    let level = save.level(Some("empty")).unwrap();
+    assert_eq!(level.level_string().unwrap(), "1,1,2,15,3,15;");
+}
+
+#[test]
+fn backups() {
+    let dir = std::env::temp_dir().join(format!("spwn_backup_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("CCLocalLevels.dat");
+    std::fs::write(&path, fixture("windows.dat")).unwrap();
+
+    let backup = levelstring::backup_save_file(&path, BackupKind::Automatic).unwrap();
+    assert_eq!(std::fs::read(&backup).unwrap(), fixture("windows.dat"));
+    assert_eq!(
+        levelstring::list_backups(&path).unwrap(),
+        std::slice::from_ref(&backup)
+    );
+
+    // only the newest automatic backups are kept, and manual ones are never deleted
+    let manual = levelstring::backup_save_file(&path, BackupKind::Manual).unwrap();
+    let mut automatic = Vec::new();
+    for _ in 0..levelstring::MAX_BACKUPS {
+        automatic.push(levelstring::backup_save_file(&path, BackupKind::Automatic).unwrap());
+    }
+    let backups = levelstring::list_backups(&path).unwrap();
+    assert_eq!(backups.len(), levelstring::MAX_BACKUPS + 1);
+    assert!(!backup.exists());
+    assert!(backups.contains(&manual));
+    assert!(automatic.iter().all(|b| backups.contains(b)));
+    // sorted by when they were made
+    assert_eq!(backups[0], manual);
+    assert_eq!(backups[1..], automatic[..]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn exported_levels_have_headers() {
+    let objects = "1,1,2,15,3,15;";
+
+    // objects alone get the settings of a new level
+    assert_eq!(
+        levelstring::level_header(objects),
+        levelstring::DEFAULT_LEVEL_STRING
+    );
+    let full = levelstring::level_header("").to_string() + objects;
+    let compressed = levelstring::compress_level_string(&full);
+    let decompressed = levelstring::decompress_level_string(&compressed).unwrap();
+    assert!(decompressed.starts_with("kS38,"));
+    assert_eq!(
+        decompressed,
+        levelstring::DEFAULT_LEVEL_STRING.to_string() + objects
+    );
+
+    let gmd = levelstring::level_to_gmd(&full, &Default::default());
+    let k4 = gmd.split("<k>k4</k><s>").nth(1).unwrap();
+    let k4 = &k4[..k4.find("</s>").unwrap()];
+    assert_eq!(levelstring::decompress_level_string(k4).unwrap(), full);
+
+    // levels in the save file keep their own settings
+    let save = SaveFile::from_bytes(&fixture("windows.dat")).unwrap();
+    let level_string = save
+        .level(Some("first level"))
+        .unwrap()
+        .level_string()
+        .unwrap();
+    let header = levelstring::level_header(&level_string);
+    assert!(header.starts_with('k') && header.ends_with(';'));
+    assert!(level_string.starts_with(header));
+    assert!(!header[..header.len() - 1].contains(';'));
+}
@@ -0,0 +1,411 @@
+// symbol lookup for hover, go-to-definition and completion
+
+use parser::ast::*;
+use shared::FileRange;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Variable,
+    Macro,
+    Argument,
+    Type,
+}
+
+#[derive(Debug, Clone)]
+pub struct SymbolDef {
+    pub name: String,
+    pub kind: SymbolKind,
+    // where the name itself is written
+    pub pos: FileRange,
+    // the part of the file where the name can be used
+    pub scope: FileRange,
+    pub desc: Option<String>,
+}
+
+/// All the definitions in a parsed file
+#[derive(Debug, Clone, Default)]
+pub struct SymbolIndex {
+    pub defs: Vec<SymbolDef>,
+}
+
+impl SymbolIndex {
+    pub fn new(statements: &[Statement], file_len: usize) -> Self {
+        let mut index = SymbolIndex::default();
+        index.statements(statements, (0, file_len));
+        index
+    }
+
+    /// Finds the definition of `name` that is visible at `offset`
+    pub fn lookup(
+        &self,
+        name: &str,
+        offset: usize,
+        kind: Option<SymbolKind>,
+    ) -> Option<&SymbolDef> {
+        let mut candidates = self
+            .defs
+            .iter()
+            .filter(|d| {
+                d.name == name
+                    && d.scope.0 <= offset
+                    && offset <= d.scope.1
+                    && kind.map_or(d.kind != SymbolKind::Type, |k| d.kind == k)
+            })
+            .collect::<Vec<_>>();
+
+        // innermost scope first, then the closest definition before the offset
+        candidates.sort_by_key(|d| {
+            (
+                d.scope.1 - d.scope.0,
+                d.pos.0 > offset,
+                usize::MAX - d.pos.0,
+            )
+        });
+        candidates.first().copied()
+    }
+
+    /// All non-type symbols that can be used at `offset`
+    pub fn visible(&self, offset: usize) -> Vec<&SymbolDef> {
+        let mut out: Vec<&SymbolDef> = Vec::new();
+        for def in &self.defs {
+            if def.kind != SymbolKind::Type
+                && def.scope.0 <= offset
+                && offset <= def.scope.1
+                && !out.iter().any(|d| d.name == def.name)
+            {
+                out.push(def);
+            }
+        }
+        out
+    }
+
+    pub fn types(&self) -> impl Iterator<Item = &SymbolDef> {
+        self.defs.iter().filter(|d| d.kind == SymbolKind::Type)
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        kind: SymbolKind,
+        pos: FileRange,
+        scope: FileRange,
+        desc: Option<String>,
+    ) {
+        self.defs.push(SymbolDef {
+            name: name.to_string(),
+            kind,
+            pos,
+            scope,
+            desc,
+        });
+    }
+
+    fn statements(&mut self, statements: &[Statement], scope: FileRange) {
+        for statement in statements {
+            self.statement(statement, scope);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement, scope: FileRange) {
+        match &statement.body {
+            StatementBody::Definition(def) => {
+                if let Some(value) = &def.value {
+                    self.expression(value, scope);
+                    self.define_variable(&def.symbol, Some(value), scope);
+                } else {
+                    self.define_variable(&def.symbol, None, scope);
+                }
+            }
+            StatementBody::Expr(expr) => {
+                if expr.operators.first() == Some(&Operator::Assign) {
+                    self.define_variable(
+                        &expr.values[0],
+                        expr.values.get(1).map(|v| v.to_expression()).as_ref(),
+                        scope,
+                    );
+                }
+                self.expression(expr, scope);
+            }
+            StatementBody::Call(call) => self.variable(&call.function, scope),
+            StatementBody::TypeDef { name, attr } => self.define(
+                name,
+                SymbolKind::Type,
+                statement.pos,
+                scope,
+                attr.get_desc(),
+            ),
+            StatementBody::Return(Some(expr))
+            | StatementBody::Extract(expr)
+            | StatementBody::Error(Error { message: expr }) => self.expression(expr, scope),
+            StatementBody::Impl(imp) => {
+                self.variable(&imp.symbol, scope);
+                self.dict(&imp.members, scope);
+            }
+            StatementBody::If(i) => {
+                self.expression(&i.condition, scope);
+                self.statements(&i.if_body, statement.pos);
+                if let Some(body) = &i.else_body {
+                    self.statements(body, statement.pos);
+                }
+            }
+            StatementBody::For(f) => {
+                self.expression(&f.array, scope);
+                for symbol in &f.symbol.values {
+                    if let ValueBody::Symbol(s) = &symbol.value.body {
+                        self.define(s, SymbolKind::Variable, symbol.pos, statement.pos, None);
+                    }
+                }
+                self.statements(&f.body, statement.pos);
+            }
+            StatementBody::While(w) => {
+                self.expression(&w.condition, scope);
+                self.statements(&w.body, statement.pos);
+            }
+            StatementBody::Return(None) | StatementBody::Break | StatementBody::Continue => (),
+        }
+    }
+
+    fn define_variable(&mut self, symbol: &Variable, value: Option<&Expression>, scope: FileRange) {
+        if !symbol.path.is_empty() {
+            return;
+        }
+        if let ValueBody::Symbol(name) = &symbol.value.body {
+            let (kind, desc) = match value.map(|v| &v.values[..]) {
+                Some(
+                    [Variable {
+                        value:
+                            ValueLiteral {
+                                body: ValueBody::Macro(m),
+                            },
+                        ..
+                    }],
+                ) => (SymbolKind::Macro, m.properties.get_desc()),
+                _ => (SymbolKind::Variable, None),
+            };
+            self.define(name, kind, symbol.pos, scope, desc);
+        }
+    }
+
+    fn dict(&mut self, dict: &[DictDef], scope: FileRange) {
+        for def in dict {
+            match def {
+                DictDef::Def((_, expr)) | DictDef::Extract(expr) => self.expression(expr, scope),
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression, scope: FileRange) {
+        for value in &expr.values {
+            self.variable(value, scope);
+        }
+    }
+
+    fn arguments(&mut self, args: &[Argument], scope: FileRange) {
+        for arg in args {
+            self.expression(&arg.value, scope);
+        }
+    }
+
+    fn variable(&mut self, var: &Variable, scope: FileRange) {
+        match &var.value.body {
+            ValueBody::Macro(m) => {
+                for (name, default, attr, pattern, pos, _) in &m.args {
+                    if let Some(default) = default {
+                        self.expression(default, scope);
+                    }
+                    if let Some(pattern) = pattern {
+                        self.expression(pattern, scope);
+                    }
+                    self.define(name, SymbolKind::Argument, *pos, var.pos, attr.get_desc());
+                }
+                self.statements(&m.body.statements, var.pos);
+            }
+            ValueBody::CmpStmt(c) => self.statements(&c.statements, var.pos),
+            ValueBody::Dictionary(d, _) => self.dict(d, scope),
+            ValueBody::Array(a) => {
+                for el in a {
+                    self.expression(&el.value, scope);
+                }
+            }
+            ValueBody::Expression(e) => self.expression(e, scope),
+            ValueBody::Obj(o) => {
+                for (key, value) in &o.props {
+                    self.expression(key, scope);
+                    self.expression(value, scope);
+                }
+            }
+            ValueBody::Ternary(t) => {
+                self.expression(&t.condition, scope);
+                self.expression(&t.if_expr, scope);
+                self.expression(&t.else_expr, scope);
+            }
+            ValueBody::Match(value, cases) => {
+                self.expression(value, scope);
+                for case in cases {
+                    if let CaseType::Pattern(p) = &case.typ {
+                        self.expression(p, scope);
+                    }
+                    self.expression(&case.body, scope);
+                }
+            }
+            ValueBody::ListComp(c) => {
+                self.expression(&c.iterator, scope);
+                self.define(&c.symbol, SymbolKind::Variable, var.pos, var.pos, None);
+                if let Some(cond) = &c.condition {
+                    self.expression(cond, var.pos);
+                }
+                self.expression(&c.body, var.pos);
+            }
+            ValueBody::MacroPattern(p) => {
+                for arg in &p.args {
+                    self.expression(arg, scope);
+                }
+                self.expression(&p.ret, scope);
+            }
+            _ => (),
+        }
+
+        for path in &var.path {
+            match path {
+                Path::Call(args) => self.arguments(args, scope),
+                Path::Index(expr) => self.expression(expr, scope),
+                Path::Constructor(dict) => self.dict(dict, scope),
+                Path::NSlice(slices) => {
+                    for slice in slices {
+                        for expr in [&slice.left, &slice.right, &slice.step]
+                            .into_iter()
+                            .flatten()
+                        {
+                            self.expression(expr, scope);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// What the cursor is on, found by scanning the text around it
+#[derive(Debug, PartialEq, Eq)]
+pub enum Word {
+    Symbol(String),
+    Type(String),
+    Builtin(String),
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// The word at `offset` (and its range)
+pub fn word_at(text: &str, offset: usize) -> Option<(Word, FileRange)> {
+    let offset = offset.min(text.len());
+    let start = text[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_symbol_char(*c))
+        .last()
+        .map_or(offset, |(i, _)| i);
+    let end = text[offset..]
+        .char_indices()
+        .find(|(_, c)| !is_symbol_char(*c))
+        .map_or(text.len(), |(i, _)| offset + i);
+
+    if start == end {
+        return None;
+    }
+    let word = text[start..end].to_string();
+    if word.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let before = &text[..start];
+
+    Some((
+        if before.ends_with('@') {
+            Word::Type(word)
+        } else if before.ends_with("$.") {
+            Word::Builtin(word)
+        } else {
+            Word::Symbol(word)
+        },
+        (start, end),
+    ))
+}
+
+/// What should be completed at `offset`
+#[derive(Debug, PartialEq, Eq)]
+pub enum CompletionContext {
+    Symbol,
+    Type,
+    Builtin,
+    Member,
+}
+
+pub fn completion_context(text: &str, offset: usize) -> CompletionContext {
+    let offset = offset.min(text.len());
+    let before = text[..offset].trim_end_matches(is_symbol_char);
+    if before.ends_with("$.") {
+        CompletionContext::Builtin
+    } else if before.ends_with('@') {
+        CompletionContext::Type
+    } else if before.ends_with('.') {
+        CompletionContext::Member
+    } else {
+        CompletionContext::Symbol
+    }
+}
+
+pub const KEYWORDS: &[&str] = &[
+    "return", "impl", "for", "in", "throw", "if", "else", "match", "break", "continue", "while",
+    "obj", "trigger", "import", "extract", "null", "type", "let", "self", "true", "false", "is",
+    "as",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compiler::builtins::BUILTIN_NAMES;
+    use parser::parser::parse_spwn;
+    use shared::SpwnSource;
+
+    fn index(code: &str) -> SymbolIndex {
+        let source = SpwnSource::String(internment::LocalIntern::new(code.to_string()));
+        let (statements, _) = parse_spwn(code.to_string(), source, BUILTIN_NAMES).unwrap();
+        SymbolIndex::new(&statements, code.len())
+    }
+
+    #[test]
+    fn finds_innermost_definition() {
+        let code = "a = 1\nm = (a) {\n    $.print(a)\n}\n$.print(a)\n";
+        let index = index(code);
+
+        let inner = code.find("print(a)").unwrap() + 6;
+        let def = index.lookup("a", inner, None).unwrap();
+        assert_eq!(def.kind, SymbolKind::Argument);
+
+        let outer = code.rfind("print(a)").unwrap() + 6;
+        let def = index.lookup("a", outer, None).unwrap();
+        assert_eq!(def.kind, SymbolKind::Variable);
+        assert_eq!(def.pos, (0, 1));
+
+        assert_eq!(
+            index.lookup("m", outer, None).unwrap().kind,
+            SymbolKind::Macro
+        );
+    }
+
+    #[test]
+    fn words_and_contexts() {
+        let code = "c = @counter::new()\n$.pri";
+        assert_eq!(
+            word_at(code, code.find("counter").unwrap() + 2).map(|w| w.0),
+            Some(Word::Type("counter".to_string()))
+        );
+        assert_eq!(
+            completion_context(code, code.len()),
+            CompletionContext::Builtin
+        );
+        assert_eq!(completion_context(code, 2), CompletionContext::Symbol);
+    }
+}
@@ -0,0 +1,190 @@
+// turns spwn error reports into lsp diagnostics
+
+use std::fs;
+
+use errors::compiler_info::CodeArea;
+use errors::ErrorReport;
+use lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, Position, Range, Url,
+};
+use shared::{FileRange, SpwnSource};
+
+/// Maps the byte offsets used by the parser to lsp line/character positions.
+/// The text is normalized the same way `parse_spwn` normalizes it,
+/// so offsets from the AST line up with the positions in the editor.
+pub struct LineIndex {
+    text: String,
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let text = text.replace("\r\n", "\n");
+        let mut line_starts = vec![0];
+        for (i, c) in text.char_indices() {
+            if c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        LineIndex { text, line_starts }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.text.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(l) => l,
+            Err(l) => l - 1,
+        };
+        let start = self.line_starts[line];
+        let character = self.text[start..offset]
+            .chars()
+            .map(char::len_utf16)
+            .sum::<usize>();
+        Position::new(line as u32, character as u32)
+    }
+
+    pub fn offset(&self, pos: Position) -> usize {
+        let line = pos.line as usize;
+        if line >= self.line_starts.len() {
+            return self.text.len();
+        }
+        let start = self.line_starts[line];
+        let mut utf16 = 0;
+        for (i, c) in self.text[start..].char_indices() {
+            if utf16 >= pos.character as usize || c == '\n' {
+                return start + i;
+            }
+            utf16 += c.len_utf16();
+        }
+        self.text.len()
+    }
+
+    pub fn range(&self, pos: FileRange) -> Range {
+        Range::new(self.position(pos.0), self.position(pos.1))
+    }
+}
+
+// labels are colored with ariadne, which doesn't mean anything to an editor
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn is_in_document(area: &CodeArea, document: &SpwnSource) -> bool {
+    area.file.as_ref() == document
+}
+
+fn location_of(area: &CodeArea, document: &SpwnSource, index: &LineIndex) -> Option<Location> {
+    if is_in_document(area, document) {
+        if let SpwnSource::File(path) = document {
+            return Some(Location::new(
+                Url::from_file_path(path).ok()?,
+                index.range(area.pos),
+            ));
+        }
+    }
+    match area.file.as_ref() {
+        SpwnSource::File(path) => {
+            let other = LineIndex::new(&fs::read_to_string(path).ok()?);
+            Some(Location::new(
+                Url::from_file_path(path).ok()?,
+                other.range(area.pos),
+            ))
+        }
+        // built in and string sources can't be opened in an editor
+        _ => None,
+    }
+}
+
+/// Converts an error report into a single diagnostic for `document`.
+/// The primary range is the error position if it is in the document, otherwise
+/// the innermost macro call in the document that led to the error.
+/// Every other label is attached as related information.
+pub fn report_to_diagnostic(
+    report: ErrorReport,
+    document: &SpwnSource,
+    index: &LineIndex,
+) -> Diagnostic {
+    let info = &report.info;
+
+    let primary = if is_in_document(&info.position, document) {
+        Some(info.position)
+    } else {
+        info.call_stack
+            .iter()
+            .rev()
+            .find(|a| is_in_document(a, document))
+            .copied()
+    }
+    .or_else(|| {
+        report
+            .labels
+            .iter()
+            .find(|(a, _)| is_in_document(a, document))
+            .map(|(a, _)| *a)
+    });
+
+    let range = match primary {
+        Some(area) => index.range(area.pos),
+        None => Range::default(),
+    };
+
+    let mut message = strip_ansi(&report.message);
+    let mut related = Vec::new();
+
+    for (area, label) in &report.labels {
+        let label = strip_ansi(label);
+        if Some(*area) == primary {
+            message += &format!("\n{}", label);
+        } else if let Some(location) = location_of(area, document, index) {
+            related.push(DiagnosticRelatedInformation {
+                location,
+                message: label,
+            });
+        }
+    }
+
+    for area in &info.call_stack {
+        if Some(*area) != primary {
+            if let Some(location) = location_of(area, document, index) {
+                related.push(DiagnosticRelatedInformation {
+                    location,
+                    message: "Error comes from this macro call".to_string(),
+                });
+            }
+        }
+    }
+
+    if let Some(note) = &report.note {
+        message += &format!("\nNote: {}", strip_ansi(note));
+    }
+
+    Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("spwn".to_string()),
+        message,
+        related_information: if related.is_empty() {
+            None
+        } else {
+            Some(related)
+        },
+        ..Default::default()
+    }
+}
@@ -0,0 +1,648 @@
+// language server for spwn, talks to the editor over stdio
+
+pub mod analysis;
+pub mod diagnostics;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::thread;
+
+use compiler::builtins::{Builtin, BuiltinPermissions, BUILTIN_DESCRIPTIONS, BUILTIN_NAMES};
+use compiler::compiler::{compile_spwn_quiet, import_module};
+use compiler::context::FullContext;
+use compiler::globals::Globals;
+use compiler::value::Value;
+use compiler::STD_PATH;
+use crossbeam_channel::{select, unbounded, Sender};
+use errors::compiler_info::CompilerInfo;
+use errors::ErrorReport;
+use internment::LocalIntern;
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, InitializeParams, Location, MarkupContent, MarkupKind, OneOf,
+    PublishDiagnosticsParams, SaveOptions, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, TextDocumentSyncSaveOptions, Url,
+};
+use parser::parser::parse_spwn;
+use shared::{ImportType, SpwnSource};
+
+use analysis::{completion_context, word_at, CompletionContext, SymbolIndex, SymbolKind, Word};
+use diagnostics::{report_to_diagnostic, LineIndex};
+
+// compiling a script recurses a lot, the main thread's stack isn't always enough
+const COMPILER_STACK_SIZE: usize = 32 * 1024 * 1024;
+
+struct Document {
+    version: i32,
+    lines: LineIndex,
+    // kept from the last version that parsed, so hover keeps working while typing
+    symbols: SymbolIndex,
+}
+
+/// Something exported by the standard library
+#[derive(Debug, Clone)]
+struct StdItem {
+    name: String,
+    detail: String,
+    desc: Option<String>,
+    is_macro: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct StdItems {
+    exports: Vec<StdItem>,
+    members: Vec<StdItem>,
+    types: Vec<StdItem>,
+}
+
+// results of work done on other threads
+enum Task {
+    Compiled {
+        uri: Url,
+        version: i32,
+        diagnostics: Vec<Diagnostic>,
+    },
+    StdLoaded(StdItems),
+}
+
+struct Server {
+    connection: Connection,
+    tasks: Sender<Task>,
+    documents: HashMap<Url, Document>,
+    includes: Vec<PathBuf>,
+    std_items: StdItems,
+}
+
+/// Runs the language server on stdin/stdout until the editor shuts it down
+pub fn start() -> Result<(), Box<dyn Error + Sync + Send>> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::FULL),
+                save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                    include_text: Some(true),
+                })),
+                ..Default::default()
+            },
+        )),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![".".to_string(), "@".to_string()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let params = connection.initialize(serde_json::to_value(capabilities)?)?;
+    let params: InitializeParams = serde_json::from_value(params)?;
+
+    // same search paths as `spwn build`, plus the workspace
+    let mut includes = Vec::new();
+    if let Some(root) = params.root_uri.and_then(|u| u.to_file_path().ok()) {
+        includes.push(root);
+    }
+    if let Ok(dir) = std::env::current_dir() {
+        includes.push(dir);
+    }
+    if let Some(dir) = std::env::current_exe()
+        .ok()
+        .and_then(|e| e.parent().map(|p| p.to_path_buf()))
+    {
+        includes.push(dir);
+    }
+
+    let (tasks, task_receiver) = unbounded();
+
+    let mut server = Server {
+        connection,
+        tasks,
+        documents: HashMap::new(),
+        includes,
+        std_items: StdItems::default(),
+    };
+
+    server.load_std();
+
+    loop {
+        select! {
+            recv(server.connection.receiver) -> msg => match msg? {
+                Message::Request(req) => {
+                    if server.connection.handle_shutdown(&req)? {
+                        break;
+                    }
+                    server.handle_request(req)?;
+                }
+                Message::Notification(not) => server.handle_notification(not)?,
+                Message::Response(_) => (),
+            },
+            recv(task_receiver) -> task => server.handle_task(task?)?,
+        }
+    }
+
+    // the writer thread only stops once the connection is dropped
+    drop(server);
+    io_threads.join()?;
+    Ok(())
+}
+
+fn document_source(uri: &Url, text: &str) -> SpwnSource {
+    match uri.to_file_path() {
+        Ok(path) => SpwnSource::File(path),
+        Err(_) => SpwnSource::String(LocalIntern::new(text.to_string())),
+    }
+}
+
+fn lsp_permissions() -> BuiltinPermissions {
+    let mut permissions = BuiltinPermissions::new();
+    // stdin is the connection to the editor
+    permissions.set(Builtin::GetInput, false);
+    permissions
+}
+
+// runs the whole compiler on a document, like `spwn build --no-level`
+fn compile_document(uri: &Url, text: String, includes: Vec<PathBuf>) -> Vec<Diagnostic> {
+    let lines = LineIndex::new(&text);
+    let source = document_source(uri, &text);
+
+    let (statements, notes) = match parse_spwn(text, source.clone(), BUILTIN_NAMES) {
+        Ok(a) => a,
+        Err(e) => return vec![report_to_diagnostic(ErrorReport::from(e), &source, &lines)],
+    };
+
+    let mut std_out = Vec::<u8>::new();
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        compile_spwn_quiet(
+            statements,
+            source.clone(),
+            includes,
+            notes,
+            lsp_permissions(),
+            Default::default(),
+            String::new(),
+            &mut std_out,
+            &mut Default::default(),
+        )
+        .err()
+        .map(ErrorReport::from)
+    }));
+
+    match result {
+        Ok(None) => Vec::new(),
+        Ok(Some(report)) => vec![report_to_diagnostic(report, &source, &lines)],
+        // the compiler still panics in some places, which shouldn't take down the server
+        Err(_) => vec![Diagnostic {
+            severity: Some(lsp_types::DiagnosticSeverity::ERROR),
+            source: Some("spwn".to_string()),
+            message: "The compiler crashed while building this file".to_string(),
+            ..Default::default()
+        }],
+    }
+}
+
+fn describe_value(value: &Value, globals: &Globals, name: String) -> StdItem {
+    match value {
+        Value::Macro(m) => StdItem {
+            detail: format!(
+                "{}: ({})",
+                name,
+                m.args
+                    .iter()
+                    .map(|a| a.name.as_ref().clone())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            name,
+            desc: m.tag.get_desc(),
+            is_macro: true,
+        },
+        _ => StdItem {
+            detail: format!("{}: @{}", name, value.get_type_str(globals)),
+            name,
+            desc: None,
+            is_macro: false,
+        },
+    }
+}
+
+// imports the standard library the same way a script would, and collects what it exports
+fn load_std_items(includes: Vec<PathBuf>) -> StdItems {
+    let mut std_out = Vec::<u8>::new();
+
+    let mut path = includes.first().cloned().unwrap_or_default();
+    path.push("temp"); // doesn't need to exist, import_module only looks at the parent
+
+    let mut globals = Globals::new(
+        SpwnSource::File(path),
+        lsp_permissions(),
+        String::new(),
+        &mut std_out,
+    );
+    globals.includes = includes;
+
+    let mut context = FullContext::new(&globals);
+    // `new` points the root context at its own local, which is gone once the context is moved
+    context.inner().root_context_ptr = &mut context;
+
+    if import_module(
+        &ImportType::Lib(STD_PATH.to_string()),
+        &mut context,
+        &mut globals,
+        CompilerInfo::new(),
+        false,
+    )
+    .is_err()
+    {
+        return StdItems::default();
+    }
+
+    let mut items = StdItems::default();
+
+    if let Value::Dict(d) = &globals.stored_values[context.inner().return_value] {
+        for (name, val) in d {
+            items.exports.push(describe_value(
+                &globals.stored_values[*val],
+                &globals,
+                name.as_ref().clone(),
+            ));
+        }
+    }
+
+    for (name, (id, _)) in &globals.type_ids {
+        items.types.push(StdItem {
+            name: name.clone(),
+            detail: format!("@{}", name),
+            desc: globals.type_descriptions.get(id).cloned(),
+            is_macro: false,
+        });
+
+        if let Some(members) = globals.implementations.get(id) {
+            for (member, (val, _)) in members {
+                let mut item = describe_value(
+                    &globals.stored_values[*val],
+                    &globals,
+                    member.as_ref().clone(),
+                );
+                item.detail = format!("@{}::{}", name, item.detail);
+                items.members.push(item);
+            }
+        }
+    }
+
+    items.exports.sort_by(|a, b| a.name.cmp(&b.name));
+    items.members.sort_by(|a, b| a.name.cmp(&b.name));
+    items.types.sort_by(|a, b| a.name.cmp(&b.name));
+    items
+}
+
+fn markdown(detail: &str, desc: Option<&str>) -> HoverContents {
+    let mut value = format!("```spwn\n{}\n```", detail);
+    if let Some(desc) = desc {
+        value += &format!("\n\n{}", desc);
+    }
+    HoverContents::Markup(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value,
+    })
+}
+
+fn completion(
+    label: &str,
+    kind: CompletionItemKind,
+    detail: Option<String>,
+    doc: Option<String>,
+) -> CompletionItem {
+    CompletionItem {
+        label: label.to_string(),
+        kind: Some(kind),
+        detail,
+        documentation: doc.map(lsp_types::Documentation::String),
+        ..Default::default()
+    }
+}
+
+impl Server {
+    fn load_std(&self) {
+        let includes = self.includes.clone();
+        let tasks = self.tasks.clone();
+        thread::Builder::new()
+            .stack_size(COMPILER_STACK_SIZE)
+            .spawn(move || {
+                let items = catch_unwind(|| load_std_items(includes)).unwrap_or_default();
+                tasks.send(Task::StdLoaded(items)).ok();
+            })
+            .expect("Could not start the standard library thread");
+    }
+
+    fn compile(&self, uri: Url, version: i32, text: String) {
+        let includes = self.includes.clone();
+        let tasks = self.tasks.clone();
+        thread::Builder::new()
+            .stack_size(COMPILER_STACK_SIZE)
+            .spawn(move || {
+                let diagnostics = compile_document(&uri, text, includes);
+                tasks
+                    .send(Task::Compiled {
+                        uri,
+                        version,
+                        diagnostics,
+                    })
+                    .ok();
+            })
+            .expect("Could not start the compiler thread");
+    }
+
+    fn publish(
+        &self,
+        uri: Url,
+        diagnostics: Vec<Diagnostic>,
+        version: Option<i32>,
+    ) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let params = PublishDiagnosticsParams::new(uri, diagnostics, version);
+        self.connection
+            .sender
+            .send(Message::Notification(Notification::new(
+                PublishDiagnostics::METHOD.to_string(),
+                params,
+            )))?;
+        Ok(())
+    }
+
+    // reparses a document, returns whether it parsed
+    fn update(
+        &mut self,
+        uri: Url,
+        version: i32,
+        text: &str,
+    ) -> Result<bool, Box<dyn Error + Sync + Send>> {
+        let lines = LineIndex::new(text);
+        let source = document_source(&uri, text);
+
+        let (symbols, diagnostics) =
+            match parse_spwn(lines.text().to_string(), source.clone(), BUILTIN_NAMES) {
+                Ok((statements, _)) => (
+                    Some(SymbolIndex::new(&statements, lines.text().len())),
+                    Vec::new(),
+                ),
+                Err(e) => (
+                    None,
+                    vec![report_to_diagnostic(ErrorReport::from(e), &source, &lines)],
+                ),
+            };
+        let parsed = symbols.is_some();
+
+        let symbols = symbols.unwrap_or_else(|| {
+            self.documents
+                .remove(&uri)
+                .map(|d| d.symbols)
+                .unwrap_or_default()
+        });
+        self.documents.insert(
+            uri.clone(),
+            Document {
+                version,
+                lines,
+                symbols,
+            },
+        );
+
+        self.publish(uri, diagnostics, Some(version))?;
+        Ok(parsed)
+    }
+
+    fn handle_notification(
+        &mut self,
+        not: Notification,
+    ) -> Result<(), Box<dyn Error + Sync + Send>> {
+        match not.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: <DidOpenTextDocument as lsp_types::notification::Notification>::Params =
+                    serde_json::from_value(not.params)?;
+                let doc = params.text_document;
+                if self.update(doc.uri.clone(), doc.version, &doc.text)? {
+                    self.compile(doc.uri, doc.version, doc.text);
+                }
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: <DidChangeTextDocument as lsp_types::notification::Notification>::Params =
+                    serde_json::from_value(not.params)?;
+                // only full sync is supported, so the last change is the whole file
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    let doc = params.text_document;
+                    self.update(doc.uri, doc.version, &change.text)?;
+                }
+            }
+            DidSaveTextDocument::METHOD => {
+                let params: <DidSaveTextDocument as lsp_types::notification::Notification>::Params =
+                    serde_json::from_value(not.params)?;
+                let uri = params.text_document.uri;
+                if let Some(doc) = self.documents.get(&uri) {
+                    let text = params.text.unwrap_or_else(|| doc.lines.text().to_string());
+                    let version = doc.version;
+                    if self.update(uri.clone(), version, &text)? {
+                        self.compile(uri, version, text);
+                    }
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: <DidCloseTextDocument as lsp_types::notification::Notification>::Params =
+                    serde_json::from_value(not.params)?;
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                self.publish(uri, Vec::new(), None)?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn handle_task(&mut self, task: Task) -> Result<(), Box<dyn Error + Sync + Send>> {
+        match task {
+            Task::Compiled {
+                uri,
+                version,
+                diagnostics,
+            } => {
+                // the document was changed or closed while it was compiling
+                if self.documents.get(&uri).map(|d| d.version) == Some(version) {
+                    self.publish(uri, diagnostics, Some(version))?;
+                }
+            }
+            Task::StdLoaded(items) => self.std_items = items,
+        }
+        Ok(())
+    }
+
+    fn handle_request(&mut self, req: Request) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let id = req.id.clone();
+        let result = match req.method.as_str() {
+            HoverRequest::METHOD => {
+                let params: HoverParams = serde_json::from_value(req.params)?;
+                serde_json::to_value(self.hover(params))?
+            }
+            GotoDefinition::METHOD => {
+                let params: GotoDefinitionParams = serde_json::from_value(req.params)?;
+                serde_json::to_value(self.definition(params))?
+            }
+            Completion::METHOD => {
+                let params: CompletionParams = serde_json::from_value(req.params)?;
+                serde_json::to_value(self.completion(params))?
+            }
+            _ => return self.respond_unknown(id),
+        };
+        self.connection
+            .sender
+            .send(Message::Response(Response::new_ok(id, result)))?;
+        Ok(())
+    }
+
+    fn respond_unknown(&self, id: RequestId) -> Result<(), Box<dyn Error + Sync + Send>> {
+        self.connection
+            .sender
+            .send(Message::Response(Response::new_err(
+                id,
+                lsp_server::ErrorCode::MethodNotFound as i32,
+                "Unsupported request".to_string(),
+            )))?;
+        Ok(())
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let pos = params.text_document_position_params;
+        let doc = self.documents.get(&pos.text_document.uri)?;
+        let offset = doc.lines.offset(pos.position);
+        let (word, range) = word_at(doc.lines.text(), offset)?;
+
+        let contents = match word {
+            Word::Symbol(name) => {
+                if let Some(def) = doc.symbols.lookup(&name, offset, None) {
+                    // show the line the symbol is defined on
+                    let text = doc.lines.text();
+                    let start = text[..def.pos.0].rfind('\n').map_or(0, |i| i + 1);
+                    let end = text[def.pos.0..]
+                        .find('\n')
+                        .map_or(text.len(), |i| def.pos.0 + i);
+                    markdown(text[start..end].trim(), def.desc.as_deref())
+                } else {
+                    let item = self.std_items.exports.iter().find(|i| i.name == name)?;
+                    markdown(&item.detail, item.desc.as_deref())
+                }
+            }
+            Word::Type(name) => {
+                if let Some(def) = doc.symbols.lookup(&name, offset, Some(SymbolKind::Type)) {
+                    markdown(&format!("type @{}", def.name), def.desc.as_deref())
+                } else {
+                    let item = self.std_items.types.iter().find(|i| i.name == name)?;
+                    markdown(&format!("type {}", item.detail), item.desc.as_deref())
+                }
+            }
+            Word::Builtin(name) => {
+                let (_, desc) = BUILTIN_DESCRIPTIONS.iter().find(|(n, _)| *n == name)?;
+                markdown(&format!("$.{}", name), Some(desc))
+            }
+        };
+
+        Some(Hover {
+            contents,
+            range: Some(doc.lines.range(range)),
+        })
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let pos = params.text_document_position_params;
+        let doc = self.documents.get(&pos.text_document.uri)?;
+        let offset = doc.lines.offset(pos.position);
+
+        let def = match word_at(doc.lines.text(), offset)? {
+            (Word::Symbol(name), _) => doc.symbols.lookup(&name, offset, None)?,
+            (Word::Type(name), _) => doc.symbols.lookup(&name, offset, Some(SymbolKind::Type))?,
+            (Word::Builtin(_), _) => return None,
+        };
+
+        Some(GotoDefinitionResponse::Scalar(Location::new(
+            pos.text_document.uri,
+            doc.lines.range(def.pos),
+        )))
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let pos = params.text_document_position;
+        let doc = self.documents.get(&pos.text_document.uri)?;
+        let offset = doc.lines.offset(pos.position);
+
+        let std_item = |item: &StdItem| {
+            completion(
+                &item.name,
+                if item.is_macro {
+                    CompletionItemKind::FUNCTION
+                } else {
+                    CompletionItemKind::VARIABLE
+                },
+                Some(item.detail.clone()),
+                item.desc.clone(),
+            )
+        };
+
+        let items = match completion_context(doc.lines.text(), offset) {
+            CompletionContext::Builtin => BUILTIN_DESCRIPTIONS
+                .iter()
+                .map(|(name, desc)| {
+                    completion(
+                        name,
+                        CompletionItemKind::FUNCTION,
+                        None,
+                        Some(desc.to_string()),
+                    )
+                })
+                .collect(),
+            CompletionContext::Type => {
+                doc.symbols
+                    .types()
+                    .map(|d| completion(&d.name, CompletionItemKind::CLASS, None, d.desc.clone()))
+                    .chain(self.std_items.types.iter().map(|t| {
+                        completion(&t.name, CompletionItemKind::CLASS, None, t.desc.clone())
+                    }))
+                    .collect()
+            }
+            CompletionContext::Member => {
+                let mut items: Vec<CompletionItem> = Vec::new();
+                for member in &self.std_items.members {
+                    if !items.iter().any(|i| i.label == member.name) {
+                        items.push(std_item(member));
+                    }
+                }
+                items
+            }
+            CompletionContext::Symbol => analysis::KEYWORDS
+                .iter()
+                .map(|k| completion(k, CompletionItemKind::KEYWORD, None, None))
+                .chain(doc.symbols.visible(offset).into_iter().map(|d| {
+                    completion(
+                        &d.name,
+                        match d.kind {
+                            SymbolKind::Macro => CompletionItemKind::FUNCTION,
+                            _ => CompletionItemKind::VARIABLE,
+                        },
+                        None,
+                        d.desc.clone(),
+                    )
+                }))
+                .chain(self.std_items.exports.iter().map(std_item))
+                .collect(),
+        };
+
+        Some(CompletionResponse::Array(items))
+    }
+}
@@ -74,7 +74,10 @@ fn check_for_dead_code<'a>(
             //     }
             // }
 
-            if !reserved.object_groups.contains(i) && !reserved.trigger_groups.contains(i) {
+            if !reserved.object_groups.contains(i)
+                && !reserved.trigger_groups.contains(i)
+                && !reserved.activated_groups.contains(i)
+            {
                 return Delete;
             }
         }
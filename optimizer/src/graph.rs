@@ -0,0 +1,231 @@
+// exports the trigger network as a graphviz (DOT) graph, for debugging
+use compiler::builtins::{Group, Id};
+use compiler::compiler_types::FunctionId;
+use compiler::leveldata::ObjParam;
+
+use crate::optimize::build_network;
+use crate::{activated_groups, obj_ids, obj_props, TriggerRole, NO_GROUP};
+
+use std::fmt::Write;
+
+/// A DOT graph of trigger networks, with one cluster for every stage that is added
+/// (for example before and after optimization)
+#[derive(Default)]
+pub struct TriggerGraph {
+    clusters: Vec<String>,
+}
+
+impl TriggerGraph {
+    /// Adds the network of `func_ids` as a new cluster.
+    /// Nodes are groups, edges are the targets of the triggers in that group
+    pub fn add_stage(&mut self, name: &str, func_ids: &[FunctionId]) {
+        let stage = self.clusters.len();
+        let node = |g: &Group| match g.id {
+            Id::Specific(n) => format!("s{}_{}", stage, n),
+            Id::Arbitrary(n) => format!("s{}_a{}", stage, n),
+        };
+
+        let network = build_network(func_ids);
+        let mut gangs = network.map.iter().collect::<Vec<_>>();
+        gangs.sort_by_key(|(g, _)| **g);
+
+        let mut out = String::new();
+        writeln!(out, "    subgraph cluster_{} {{", stage).unwrap();
+        writeln!(out, "        label=\"{}\";", name.replace('"', "\\\"")).unwrap();
+
+        let mut edges = String::new();
+        let mut targets = Vec::new();
+
+        for (group, gang) in &gangs {
+            let label = if **group == NO_GROUP {
+                String::from("start")
+            } else {
+                format!("{:?}", group)
+            };
+            writeln!(
+                out,
+                "        {} [shape=box, label=\"{}\\n{} trigger{}\"];",
+                node(group),
+                label,
+                gang.triggers.len(),
+                if gang.triggers.len() == 1 { "" } else { "s" }
+            )
+            .unwrap();
+
+            for trigger in &gang.triggers {
+                let (obj, _) = &func_ids[trigger.obj.0].obj_list[trigger.obj.1];
+                let mut trigger_targets = match obj.params.get(&obj_props::TARGET) {
+                    Some(ObjParam::Group(g)) => vec![*g],
+                    _ => Vec::new(),
+                };
+                for g in activated_groups(obj) {
+                    if !trigger_targets.contains(&g) {
+                        trigger_targets.push(g);
+                    }
+                }
+                if trigger_targets.is_empty() {
+                    continue;
+                }
+
+                let mut label = format!("{:?}", trigger.role);
+                if let (Some(ObjParam::Number(id)), false) =
+                    (obj.params.get(&1), trigger.role == TriggerRole::Spawn)
+                {
+                    if let Some(name) = trigger_name(*id as u16) {
+                        write!(label, " ({})", name).unwrap();
+                    }
+                }
+                if let Some(ObjParam::Number(delay)) = obj.params.get(&obj_props::SPAWN_DELAY) {
+                    if *delay != 0.0 {
+                        write!(label, "\\n{}s", delay).unwrap();
+                    }
+                }
+                let style = match trigger.role {
+                    TriggerRole::Spawn => "solid",
+                    TriggerRole::Func => "dashed",
+                    TriggerRole::Output => "bold",
+                };
+                for target in trigger_targets {
+                    writeln!(
+                        edges,
+                        "        {} -> {} [label=\"{}\", style={}];",
+                        node(group),
+                        node(&target),
+                        label,
+                        style
+                    )
+                    .unwrap();
+                    targets.push(target);
+                }
+            }
+        }
+
+        // groups that are targeted, but have no triggers (objects, or nothing at all)
+        targets.sort();
+        targets.dedup();
+        for target in targets {
+            if !network.map.contains_key(&target) {
+                writeln!(
+                    out,
+                    "        {} [shape=ellipse, label=\"{:?}\"];",
+                    node(&target),
+                    target
+                )
+                .unwrap();
+            }
+        }
+
+        out += &edges;
+        out += "    }\n";
+        self.clusters.push(out);
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph triggers {\n    rankdir=LR;\n");
+        for cluster in &self.clusters {
+            out += cluster;
+        }
+        out += "}\n";
+        out
+    }
+}
+
+fn trigger_name(id: u16) -> Option<&'static str> {
+    Some(match id {
+        obj_ids::MOVE => "move",
+        obj_ids::ROTATE => "rotate",
+        obj_ids::ANIMATE => "animate",
+        obj_ids::PULSE => "pulse",
+        obj_ids::COUNT => "count",
+        obj_ids::ALPHA => "alpha",
+        obj_ids::TOGGLE => "toggle",
+        obj_ids::FOLLOW => "follow",
+        obj_ids::SPAWN => "spawn",
+        obj_ids::STOP => "stop",
+        obj_ids::TOUCH => "touch",
+        obj_ids::INSTANT_COUNT => "instant count",
+        obj_ids::ON_DEATH => "on death",
+        obj_ids::FOLLOW_PLAYER_Y => "follow player y",
+        obj_ids::COLLISION => "collision",
+        obj_ids::PICKUP => "pickup",
+        obj_ids::RANDOM => "random",
+        obj_ids::ADVANCED_RANDOM => "advanced random",
+        obj_ids::SEQUENCE => "sequence",
+        obj_ids::TIME => "time",
+        obj_ids::TIME_EVENT => "time event",
+        obj_ids::TIME_CONTROL => "time control",
+        obj_ids::ITEM_EDIT => "item edit",
+        obj_ids::ITEM_COMPARE => "item compare",
+        obj_ids::ITEM_PERSIST => "item persist",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{group, trigger};
+
+    #[test]
+    fn spawn_count_network() {
+        let number = |n: u16| ObjParam::Number(n as f64);
+        let obj_list = vec![
+            // start -> 1
+            trigger(&[(1, number(obj_ids::SPAWN)), (obj_props::TARGET, group(1))]),
+            // 1 -> 2 after half a second
+            trigger(&[
+                (1, number(obj_ids::SPAWN)),
+                (obj_props::GROUPS, group(1)),
+                (obj_props::TARGET, group(2)),
+                (obj_props::SPAWN_DELAY, ObjParam::Number(0.5)),
+            ]),
+            // 1 -> 3 when item 4 reaches 10
+            trigger(&[
+                (1, number(obj_ids::COUNT)),
+                (obj_props::GROUPS, group(1)),
+                (obj_props::TARGET, group(3)),
+                (obj_props::ACTIVATE_GROUP, ObjParam::Bool(true)),
+                (80, number(4)),
+                (77, number(10)),
+            ]),
+            // moves the objects in 4
+            trigger(&[
+                (1, number(obj_ids::MOVE)),
+                (obj_props::GROUPS, group(2)),
+                (obj_props::TARGET, group(4)),
+            ]),
+        ];
+        let func_ids = [FunctionId {
+            parent: None,
+            width: None,
+            obj_list,
+        }];
+
+        let mut graph = TriggerGraph::default();
+        graph.add_stage("before", &func_ids);
+        assert_eq!(
+            graph.to_dot(),
+            r#"digraph triggers {
+    rankdir=LR;
+    subgraph cluster_0 {
+        label="before";
+        s0_0 [shape=box, label="start\n1 trigger"];
+        s0_a1 [shape=box, label="1?g\n2 triggers"];
+        s0_a2 [shape=box, label="2?g\n1 trigger"];
+        s0_a3 [shape=ellipse, label="3?g"];
+        s0_a4 [shape=ellipse, label="4?g"];
+        s0_0 -> s0_a1 [label="Spawn", style=solid];
+        s0_a1 -> s0_a2 [label="Spawn\n0.5s", style=solid];
+        s0_a1 -> s0_a3 [label="Func (count)", style=dashed];
+        s0_a2 -> s0_a4 [label="Output (move)", style=bold];
+    }
+}
+"#
+        );
+
+        // every stage gets its own cluster and node names
+        graph.add_stage("after", &func_ids[..0]);
+        let dot = graph.to_dot();
+        assert!(dot.contains("    subgraph cluster_1 {\n        label=\"after\";\n    }\n}\n"));
+    }
+}
@@ -0,0 +1,567 @@
+use ahash::{AHashMap, AHashSet};
+use compiler::builtins::{Group, Id};
+use compiler::leveldata::{GdObj, ObjParam};
+use compiler::target::Target;
+use parser::ast::ObjectMode;
+
+use crate::{obj_ids, obj_props, ObjPtr, TriggerNetwork, Triggerlist};
+
+// gives arbitrary groups that are never in use by the same triggers the same ID,
+// like a register allocator does with variables that are never live at the same time
+//
+// Everything in a group stays in it for the whole level, so what decides whether two groups
+// can share an ID is what the triggers targeting them do: a spawn trigger only runs the spawn
+// triggered triggers in its target group, and a move, rotate, alpha, pulse, animate or follow
+// trigger only changes things that are seen, which spawn triggered triggers aren't
+// (2.1 runs them in the order they were loaded in, wherever they are moved to).
+// Everything else a group can be used for (toggling it, stopping it, counting it,
+// or using where it is) changes everything in it.
+// Two groups interfere when what targets one of them changes something that is in the other,
+// unless both have the same things in them. Groups that don't interfere with each other
+// are given the same ID, by coloring the interference graph.
+//
+// Only triggers that can run use a group. Triggers can run when they aren't spawn triggered,
+// or when one of their groups is used by an object or by another trigger that can run.
+// Groups that spawn triggers remap keep their own ID, since the remapped copies tell them apart
+
+// what can be in a group: triggers that are spawn triggered, and everything else
+const TRIGGERS: u8 = 1;
+const OBJECTS: u8 = 2;
+
+#[derive(Default)]
+struct GroupUse {
+    // the objects in the group, by their index in all objects
+    members: Vec<usize>,
+    // the kinds of things in the group
+    contains: u8,
+    // the kinds of things the triggers targeting the group change
+    changes: u8,
+}
+
+impl GroupUse {
+    fn interferes(&self, other: &GroupUse) -> bool {
+        (self.changes & other.contains != 0 || other.changes & self.contains != 0)
+            && self.members != other.members
+    }
+}
+
+pub fn group_coalescing(
+    network: &TriggerNetwork,
+    objects: &mut Triggerlist,
+    level_objects: &mut [GdObj],
+    target: Target,
+) {
+    let mut triggers = network
+        .map
+        .values()
+        .flat_map(|gang| gang.triggers.iter())
+        .filter(|t| !t.deleted)
+        .map(|t| t.obj)
+        .collect::<Vec<ObjPtr>>();
+    triggers.sort();
+
+    let swaps = {
+        let all = triggers
+            .iter()
+            .map(|t| &objects[*t].0)
+            .chain(level_objects.iter())
+            .collect::<Vec<_>>();
+        allocate(&all, target)
+    };
+    if swaps.is_empty() {
+        return;
+    }
+
+    for fn_id in objects.list.iter_mut() {
+        for (obj, _) in fn_id.obj_list.iter_mut() {
+            replace_groups(obj, &swaps);
+        }
+    }
+    for obj in level_objects.iter_mut() {
+        replace_groups(obj, &swaps);
+    }
+}
+
+// the ID every arbitrary group that shares one should get
+fn allocate(objects: &[&GdObj], target: Target) -> AHashMap<Group, Group> {
+    let mut uses = AHashMap::<Group, GroupUse>::default();
+    for (i, obj) in objects.iter().enumerate() {
+        for g in obj.params.values().flat_map(groups) {
+            uses.entry(g).or_default();
+        }
+        if let Some(p) = obj.params.get(&obj_props::GROUPS) {
+            let kind = if is_spawn_triggered(obj) {
+                TRIGGERS
+            } else {
+                OBJECTS
+            };
+            for g in groups(p) {
+                let group_use = uses.get_mut(&g).unwrap();
+                group_use.members.push(i);
+                group_use.contains |= kind;
+            }
+        }
+    }
+
+    let mut live = objects
+        .iter()
+        .map(|obj| {
+            !is_spawn_triggered(obj)
+                || obj.params.get(&obj_props::GROUPS).is_some_and(|p| {
+                    groups(p)
+                        .iter()
+                        .any(|g| matches!(g.id, Id::Specific(id) if id != 0))
+                })
+        })
+        .collect::<Vec<_>>();
+
+    let mut to_visit = (0..objects.len()).filter(|i| live[*i]).collect::<Vec<_>>();
+    while let Some(i) = to_visit.pop() {
+        let obj_id = match objects[i].params.get(&1) {
+            Some(ObjParam::Number(n)) => Some(*n as u16),
+            _ => None,
+        };
+        for (prop, param) in objects[i].params.iter() {
+            if *prop == obj_props::GROUPS {
+                continue;
+            }
+            let changes = changes(obj_id, *prop, target);
+            for g in groups(param) {
+                let group_use = uses.get_mut(&g).unwrap();
+                let spawns = changes & TRIGGERS != 0 && group_use.changes & TRIGGERS == 0;
+                group_use.changes |= changes;
+                if spawns {
+                    for member in &group_use.members {
+                        if !live[*member] {
+                            live[*member] = true;
+                            to_visit.push(*member);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let remapped = objects
+        .iter()
+        .filter_map(|obj| obj.params.get(&obj_props::REMAPS))
+        .flat_map(groups)
+        .collect::<AHashSet<_>>();
+    let mut all_groups = uses
+        .keys()
+        .filter(|g| matches!(g.id, Id::Arbitrary(_)) && !remapped.contains(g))
+        .copied()
+        .collect::<Vec<_>>();
+    all_groups.sort();
+
+    // greedy coloring, in the order of the groups so the first group of a color keeps its ID
+    let mut colors = Vec::<Vec<Group>>::new();
+    let mut swaps = AHashMap::default();
+    for g in all_groups {
+        let group_use = &uses[&g];
+        match colors
+            .iter_mut()
+            .find(|color| color.iter().all(|other| !uses[other].interferes(group_use)))
+        {
+            Some(color) => {
+                swaps.insert(g, color[0]);
+                color.push(g);
+            }
+            None => colors.push(vec![g]),
+        }
+    }
+    swaps
+}
+
+// triggers in a group are made spawn triggered when they are added to the level
+// (see `leveldata::apply_fn_ids`)
+fn is_spawn_triggered(obj: &GdObj) -> bool {
+    match obj.params.get(&obj_props::SPAWN_TRIGGERED) {
+        Some(ObjParam::Bool(b)) => *b,
+        _ => {
+            obj.mode == ObjectMode::Trigger
+                && match obj.params.get(&obj_props::GROUPS) {
+                    Some(ObjParam::Group(g)) => g.id != Id::Specific(0),
+                    Some(ObjParam::GroupList(l)) => {
+                        l.first().is_some_and(|g| g.id != Id::Specific(0))
+                    }
+                    _ => false,
+                }
+        }
+    }
+}
+
+// the kinds of things a trigger changes in a group it uses for this property
+fn changes(obj_id: Option<u16>, prop: u16, target: Target) -> u8 {
+    match (obj_id, prop) {
+        (Some(obj_ids::SPAWN), obj_props::TARGET) => TRIGGERS,
+        (
+            Some(
+                obj_ids::MOVE
+                | obj_ids::ROTATE
+                | obj_ids::ALPHA
+                | obj_ids::PULSE
+                | obj_ids::ANIMATE
+                | obj_ids::FOLLOW
+                | obj_ids::FOLLOW_PLAYER_Y,
+            ),
+            obj_props::TARGET,
+        ) => match target {
+            Target::Gd21 => OBJECTS,
+            // spawn ordered spawn triggers run triggers in the order they are placed in
+            Target::Gd22 => OBJECTS | TRIGGERS,
+        },
+        _ => OBJECTS | TRIGGERS,
+    }
+}
+
+fn groups(param: &ObjParam) -> Vec<Group> {
+    match param {
+        ObjParam::Group(g) => vec![*g],
+        ObjParam::GroupList(l) => l.clone(),
+        ObjParam::WeightedGroups(l) => l.iter().map(|(g, _)| *g).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn replace_groups(obj: &mut GdObj, swaps: &AHashMap<Group, Group>) {
+    for (prop, param) in obj.params.iter_mut() {
+        match param {
+            ObjParam::Group(g) => {
+                if let Some(to) = swaps.get(g) {
+                    *g = *to;
+                }
+            }
+            ObjParam::GroupList(l) => {
+                for g in l.iter_mut() {
+                    if let Some(to) = swaps.get(g) {
+                        *g = *to;
+                    }
+                }
+                // only an object's own groups are a set, other group lists come in pairs
+                if *prop == obj_props::GROUPS {
+                    let mut seen = AHashSet::<Group>::default();
+                    l.retain(|g| seen.insert(*g));
+                }
+            }
+            ObjParam::WeightedGroups(l) => {
+                for (g, _) in l.iter_mut() {
+                    if let Some(to) = swaps.get(g) {
+                        *g = *to;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use compiler::compiler_types::{FunctionId, TriggerOrder};
+
+    use super::*;
+    use crate::optimize::{build_network, optimize};
+    use crate::passes::OptOptions;
+    use crate::test_util::{group, item, obj};
+    use crate::ReservedIds;
+
+    // a spawn triggered trigger in `in_group` that targets `target`
+    fn trigger(obj_id: u16, in_group: u16, target: u16) -> (GdObj, TriggerOrder) {
+        let params = [
+            (1, ObjParam::Number(obj_id as f64)),
+            (obj_props::GROUPS, group(in_group)),
+            (obj_props::SPAWN_TRIGGERED, ObjParam::Bool(true)),
+            (obj_props::TARGET, group(target)),
+        ];
+        (obj(ObjectMode::Trigger, &params), TriggerOrder(0.0))
+    }
+
+    // a spawn trigger that runs at the start of the level
+    fn start(target: u16) -> (GdObj, TriggerOrder) {
+        let params = [
+            (1, ObjParam::Number(obj_ids::SPAWN as f64)),
+            (obj_props::TARGET, group(target)),
+        ];
+        (obj(ObjectMode::Trigger, &params), TriggerOrder(0.0))
+    }
+
+    fn block(in_group: u16) -> GdObj {
+        obj(
+            ObjectMode::Object,
+            &[
+                (1, ObjParam::Number(1.0)),
+                (obj_props::GROUPS, group(in_group)),
+            ],
+        )
+    }
+
+    // the group of every trigger and object, and the target of every trigger, after group coalescing
+    fn coalesce(
+        obj_list: Vec<(GdObj, TriggerOrder)>,
+        mut level_objects: Vec<GdObj>,
+        target: Target,
+    ) -> Vec<Option<Id>> {
+        let mut list = vec![FunctionId {
+            parent: None,
+            width: None,
+            obj_list,
+        }];
+        let network = build_network(&list);
+        group_coalescing(
+            &network,
+            &mut Triggerlist { list: &mut list },
+            &mut level_objects,
+            target,
+        );
+        let get = |obj: &GdObj, prop| match obj.params.get(&prop) {
+            Some(ObjParam::Group(g)) => Some(g.id),
+            _ => None,
+        };
+        list[0]
+            .obj_list
+            .iter()
+            .flat_map(|(obj, _)| [get(obj, obj_props::GROUPS), get(obj, obj_props::TARGET)])
+            .chain(level_objects.iter().map(|obj| get(obj, obj_props::GROUPS)))
+            .collect()
+    }
+
+    #[test]
+    fn shares_trigger_and_object_groups() {
+        // a spawned group with a move trigger in it, which moves a block in another group
+        let ids = coalesce(
+            vec![start(1), trigger(obj_ids::MOVE, 1, 2)],
+            vec![block(2)],
+            Target::Gd21,
+        );
+        assert_eq!(ids[1], Some(Id::Arbitrary(1)));
+        assert_eq!(ids[2], Some(Id::Arbitrary(1)));
+        assert_eq!(ids[3], Some(Id::Arbitrary(1)));
+        assert_eq!(ids[4], Some(Id::Arbitrary(1)));
+    }
+
+    #[test]
+    fn keeps_spawned_groups_apart() {
+        let ids = coalesce(
+            vec![
+                start(1),
+                start(2),
+                trigger(obj_ids::MOVE, 1, 3),
+                trigger(obj_ids::MOVE, 2, 3),
+            ],
+            vec![block(3)],
+            Target::Gd21,
+        );
+        assert_eq!(ids[1], Some(Id::Arbitrary(1)));
+        assert_eq!(ids[3], Some(Id::Arbitrary(2)));
+    }
+
+    #[test]
+    fn keeps_groups_that_are_toggled_apart() {
+        // toggling the group of the block would also turn off the move trigger
+        let ids = coalesce(
+            vec![
+                start(1),
+                trigger(obj_ids::MOVE, 1, 2),
+                trigger(obj_ids::TOGGLE, 1, 2),
+            ],
+            vec![block(2)],
+            Target::Gd21,
+        );
+        assert_eq!(ids[2], Some(Id::Arbitrary(1)));
+        assert_eq!(ids[3], Some(Id::Arbitrary(2)));
+    }
+
+    #[test]
+    fn keeps_moved_triggers_apart_in_2_2() {
+        let ids = coalesce(
+            vec![start(1), trigger(obj_ids::MOVE, 1, 2)],
+            vec![block(2)],
+            Target::Gd22,
+        );
+        assert_eq!(ids[2], Some(Id::Arbitrary(1)));
+        assert_eq!(ids[3], Some(Id::Arbitrary(2)));
+    }
+
+    #[test]
+    fn shares_unused_groups() {
+        // nothing targets the blocks, and nothing spawns the trigger in group 4,
+        // so it doesn't matter that the block groups are toggled with it
+        let ids = coalesce(
+            vec![trigger(obj_ids::TOGGLE, 4, 1)],
+            vec![block(2), block(3)],
+            Target::Gd21,
+        );
+        assert_eq!(ids, vec![Some(Id::Arbitrary(1)); 4]);
+    }
+
+    #[test]
+    fn keeps_remapped_groups_apart() {
+        // the blocks would share a group if the spawn trigger didn't remap one to the other
+        let mut spawn = start(1);
+        spawn.0.params.insert(
+            obj_props::REMAPS,
+            ObjParam::GroupList(
+                [2, 3]
+                    .map(|id| Group {
+                        id: Id::Arbitrary(id),
+                    })
+                    .to_vec(),
+            ),
+        );
+        let ids = coalesce(vec![spawn], vec![block(2), block(3)], Target::Gd22);
+        assert_eq!(ids[2], Some(Id::Arbitrary(2)));
+        assert_eq!(ids[3], Some(Id::Arbitrary(3)));
+    }
+
+    #[test]
+    fn keeps_groups_that_are_picked_between() {
+        // a random trigger, a sequence trigger and a spawn trigger with remaps at the start of
+        // the level, that each activate groups with a different pickup trigger in them
+        let pickup = |in_group: u16| {
+            let params = [
+                (1, ObjParam::Number(obj_ids::PICKUP as f64)),
+                (obj_props::GROUPS, group(in_group)),
+                (obj_props::SPAWN_TRIGGERED, ObjParam::Bool(true)),
+                (
+                    obj_props::ITEM,
+                    ObjParam::Item(compiler::builtins::Item::new(in_group)),
+                ),
+            ];
+            (obj(ObjectMode::Trigger, &params), TriggerOrder(0.0))
+        };
+        let starts = [
+            vec![
+                (1, ObjParam::Number(obj_ids::RANDOM as f64)),
+                (obj_props::TARGET, group(1)),
+                (obj_props::SECOND_TARGET, group(2)),
+            ],
+            vec![
+                (1, ObjParam::Number(obj_ids::SEQUENCE as f64)),
+                (
+                    obj_props::SEQUENCE,
+                    ObjParam::WeightedGroups(
+                        [3, 4]
+                            .map(|id| {
+                                (
+                                    Group {
+                                        id: Id::Arbitrary(id),
+                                    },
+                                    1.0,
+                                )
+                            })
+                            .to_vec(),
+                    ),
+                ),
+            ],
+            vec![
+                (1, ObjParam::Number(obj_ids::SPAWN as f64)),
+                (obj_props::TARGET, group(5)),
+                (
+                    obj_props::REMAPS,
+                    ObjParam::GroupList(vec![
+                        Group {
+                            id: Id::Arbitrary(6),
+                        };
+                        2
+                    ]),
+                ),
+            ],
+        ];
+        let obj_list = starts
+            .iter()
+            .map(|params| (obj(ObjectMode::Trigger, params), TriggerOrder(0.0)))
+            .chain((1..=5).map(pickup))
+            .collect::<Vec<_>>();
+        let list = vec![FunctionId {
+            parent: None,
+            width: None,
+            obj_list,
+        }];
+        let reserved = ReservedIds::from_objects(&[], &list);
+
+        let (optimized, _) = optimize(
+            list,
+            &mut [],
+            0,
+            reserved,
+            &OptOptions {
+                target: Target::Gd22,
+                ..Default::default()
+            },
+        );
+        let mut picked = optimized
+            .iter()
+            .flat_map(|f| f.obj_list.iter())
+            .filter(|(obj, _)| {
+                obj.params.get(&1) == Some(&ObjParam::Number(obj_ids::PICKUP as f64))
+            })
+            .flat_map(|(obj, _)| groups(&obj.params[&obj_props::GROUPS]))
+            .collect::<Vec<_>>();
+        picked.sort();
+        picked.dedup();
+        assert_eq!(picked.len(), 5);
+    }
+
+    #[test]
+    fn keeps_groups_that_wait_for_a_timer() {
+        // a time trigger that calls group 1 when it stops, next to a spawn trigger that
+        // calls group 2, which each have a move trigger for a different block in them
+        let time = obj(
+            ObjectMode::Trigger,
+            &[
+                (1, ObjParam::Number(obj_ids::TIME as f64)),
+                (obj_props::ITEM, item(1)),
+                (obj_props::TARGET, group(1)),
+            ],
+        );
+        let obj_list = vec![
+            (time, TriggerOrder(0.0)),
+            start(2),
+            trigger(obj_ids::MOVE, 1, 3),
+            trigger(obj_ids::MOVE, 2, 4),
+        ];
+        let list = vec![FunctionId {
+            parent: None,
+            width: None,
+            obj_list,
+        }];
+        let mut level_objects = [block(3), block(4)];
+        let reserved = ReservedIds::from_objects(&level_objects, &list);
+
+        let (optimized, _) = optimize(
+            list,
+            &mut level_objects,
+            // the last arbitrary group used, so the groups the optimizer adds are new
+            4,
+            reserved,
+            &OptOptions {
+                level: 3,
+                target: Target::Gd22,
+                ..Default::default()
+            },
+        );
+        let objects = optimized
+            .iter()
+            .flat_map(|f| f.obj_list.iter())
+            .map(|(obj, _)| obj)
+            .collect::<Vec<_>>();
+        let group_of = |obj: &GdObj, prop| match obj.params.get(&prop) {
+            Some(ObjParam::Group(g)) => g.id,
+            _ => panic!("expected a group"),
+        };
+        let timer_target = objects
+            .iter()
+            .find(|obj| obj.params.get(&1) == Some(&ObjParam::Number(obj_ids::TIME as f64)))
+            .map(|obj| group_of(obj, obj_props::TARGET))
+            .unwrap();
+        let moves = objects
+            .iter()
+            .filter(|obj| obj.params.get(&1) == Some(&ObjParam::Number(obj_ids::MOVE as f64)))
+            .map(|obj| group_of(obj, obj_props::GROUPS))
+            .collect::<Vec<_>>();
+        assert_eq!(moves.len(), 2);
+        assert!(moves.contains(&timer_target));
+        assert_ne!(moves[0], moves[1]);
+    }
+}
@@ -427,6 +427,7 @@ pub fn create_toggle_trigger(
         func_id: obj.0,
         mode: ObjectMode::Trigger,
         unique_id: objects[obj].0.unique_id,
+        source: objects[obj].0.source,
     };
 
     (*objects.list)[obj.0]
@@ -0,0 +1,681 @@
+use ahash::{AHashMap, AHashSet};
+use compiler::builtins::{Group, Id};
+use compiler::leveldata::ObjParam;
+
+use crate::optimize::is_start_group;
+use crate::{
+    activated_groups, obj_ids, obj_props, ObjPtr, ReservedIds, TriggerNetwork, Triggerlist,
+};
+
+// gives arbitrary item IDs and color channels that are never in use at the same time the same ID,
+// so they only take up one ID in the level
+
+// traces with more triggers than this are given up on (probably a huge unrolled loop)
+const MAX_TRACE_LEN: usize = 1 << 20;
+
+// A cascade is everything that runs instantly when a root group is activated:
+// its triggers in order, where instant count triggers, spawn triggers without a delay
+// and the 2.2 triggers that pick a group (item compare, random and sequence triggers)
+// run all of their target group before the next trigger in the group.
+// All the groups one of those can pick are traced, one after another.
+// Roots are the groups that are activated some other way (a delay, an event, or the level start)
+//
+// Pickup and instant count triggers are the only triggers that use an item without
+// keeping an eye on it, so an item that is only used by them in one cascade only needs its value
+// between the first and last trigger of that cascade that uses it, as long as it's back at 0
+// once the cascade is done. That is worked out by following the range of values every item can
+// have through the cascade, which shows that the temporary counters std's @counter operations
+// make are emptied again (a counter that the cascade adds to, like one counting touches, isn't).
+// Items in different cascades (or in the same cascade, but not at the same time) can then share
+// an ID. Items used in more than one cascade keep their value between them, so they keep their own ID
+
+#[derive(Clone, Copy)]
+enum Lifetime {
+    // the root of the cascade, and the first and last trigger in it that uses the item
+    Cascade(Group, usize, usize),
+    // the item might keep its value outside of a cascade
+    Pinned,
+}
+
+struct TracedTrigger {
+    items: Vec<Id>,
+    // the item a pickup trigger adds to, and how much (`None` if that isn't known)
+    adds: Option<(Id, Option<i64>)>,
+    // the groups this trigger might activate instantly
+    calls: Vec<Group>,
+    // what an instant count trigger checks before it activates its target
+    condition: Option<(Id, Comparison)>,
+    // spawn triggers always activate their target, the others might not activate any of `calls`
+    always: bool,
+}
+
+// the lowest and highest value an item can have
+type Range = (i64, i64);
+
+const ZERO: Range = (0, 0);
+const ANY: Range = (i64::MIN, i64::MAX);
+
+// the ranges of the items at a point in a cascade. Items that aren't in it are 0
+type Values = AHashMap<Id, Range>;
+
+#[derive(Clone, Copy)]
+enum Comparison {
+    Equal(i64),
+    Larger(i64),
+    Smaller(i64),
+}
+
+impl Comparison {
+    // the part of the range for which the comparison is `passed`, `None` if there isn't any
+    fn refine(self, (lo, hi): Range, passed: bool) -> Option<Range> {
+        let (lo, hi) = match (self, passed) {
+            (Comparison::Equal(n), true) => (lo.max(n), hi.min(n)),
+            (Comparison::Equal(n), false) if lo == n => (n.saturating_add(1), hi),
+            (Comparison::Equal(n), false) if hi == n => (lo, n.saturating_sub(1)),
+            (Comparison::Equal(_), false) => (lo, hi),
+            (Comparison::Larger(n), true) => (lo.max(n.saturating_add(1)), hi),
+            (Comparison::Larger(n), false) => (lo, hi.min(n)),
+            (Comparison::Smaller(n), true) => (lo, hi.min(n.saturating_sub(1))),
+            (Comparison::Smaller(n), false) => (lo.max(n), hi),
+        };
+        (lo <= hi).then_some((lo, hi))
+    }
+}
+
+pub fn item_coalescing(
+    network: &TriggerNetwork,
+    objects: &mut Triggerlist,
+    reserved: &ReservedIds,
+) {
+    let mut groups = AHashMap::<Group, Vec<TracedTrigger>>::new();
+    let mut lifetimes = AHashMap::<Id, Lifetime>::new();
+    let mut instant_targets = AHashSet::<Group>::new();
+    let mut other_targets = AHashSet::<Group>::new();
+
+    for id in &reserved.object_items {
+        lifetimes.insert(*id, Lifetime::Pinned);
+    }
+
+    for (group, gang) in network.map.iter() {
+        let mut triggers = gang
+            .triggers
+            .iter()
+            .filter(|t| !t.deleted)
+            .map(|t| t.obj)
+            .collect::<Vec<ObjPtr>>();
+        triggers.sort_by(|a, b| objects[*a].1 .0.partial_cmp(&objects[*b].1 .0).unwrap());
+
+        let traced = triggers
+            .into_iter()
+            .map(|ptr| {
+                let obj = &objects[ptr].0;
+                let obj_id = match obj.params.get(&1) {
+                    Some(ObjParam::Number(n)) => *n as u16,
+                    _ => 0,
+                };
+                let mut items = Vec::new();
+                for (prop, param) in obj.params.iter() {
+                    if let ObjParam::Item(item) = param {
+                        if *prop == obj_props::ITEM
+                            && matches!(obj_id, obj_ids::PICKUP | obj_ids::INSTANT_COUNT)
+                        {
+                            items.push(item.id);
+                        } else {
+                            // used by something that might need it at any time (like a count trigger)
+                            lifetimes.insert(item.id, Lifetime::Pinned);
+                        }
+                    }
+                }
+                let count = match obj.params.get(&obj_props::COUNT) {
+                    Some(ObjParam::Number(n)) if n.fract() == 0.0 => Some(*n as i64),
+                    _ => None,
+                };
+                let adds = match (obj_id, items.first()) {
+                    (obj_ids::PICKUP, Some(id)) => Some((*id, count)),
+                    _ => None,
+                };
+                let condition = match (obj_id, items.first(), count) {
+                    (obj_ids::INSTANT_COUNT, Some(id), Some(n)) => {
+                        let comparison = match obj.params.get(&obj_props::COMPARISON) {
+                            Some(ObjParam::Number(m)) if *m == 1.0 => Comparison::Larger(n),
+                            Some(ObjParam::Number(m)) if *m == 2.0 => Comparison::Smaller(n),
+                            _ => Comparison::Equal(n),
+                        };
+                        Some((*id, comparison))
+                    }
+                    _ => None,
+                };
+
+                let mut targets = match obj.params.get(&obj_props::TARGET) {
+                    Some(ObjParam::Group(target)) => vec![*target],
+                    _ => Vec::new(),
+                };
+                for g in activated_groups(obj) {
+                    if !targets.contains(&g) {
+                        targets.push(g);
+                    }
+                }
+                let calls = if activates_instantly(obj_id, &obj.params) {
+                    instant_targets.extend(targets.iter().copied());
+                    targets
+                } else {
+                    other_targets.extend(targets);
+                    Vec::new()
+                };
+                TracedTrigger {
+                    items,
+                    adds,
+                    calls,
+                    condition,
+                    always: obj_id == obj_ids::SPAWN,
+                }
+            })
+            .collect();
+        groups.insert(*group, traced);
+    }
+
+    let mut roots = groups
+        .keys()
+        .filter(|g| {
+            is_start_group(**g, reserved)
+                || other_targets.contains(g)
+                || !instant_targets.contains(g)
+        })
+        .copied()
+        .collect::<Vec<_>>();
+    roots.sort();
+
+    let mut cascades = Vec::new();
+    for root in roots {
+        match trace(root, &groups, &mut lifetimes) {
+            Some(items) => cascades.push((root, items)),
+            None => return,
+        }
+    }
+
+    // an item only shares its ID if it's back at 0 once its cascade is done. Items that aren't
+    // can have any value when the cascade starts again, so it's checked again without assuming
+    // they start at 0, until no more items have to keep their own ID
+    for (root, items) in &cascades {
+        loop {
+            let zero = items
+                .iter()
+                .filter(|id| {
+                    matches!(id, Id::Arbitrary(_))
+                        && matches!(lifetimes.get(id), Some(Lifetime::Cascade(r, ..)) if r == root)
+                })
+                .copied()
+                .collect::<AHashSet<_>>();
+            let values = final_values(*root, &groups, items, &zero);
+            let mut pinned = false;
+            for id in zero {
+                let range = values.as_ref().map(|v| v.get(&id).copied().unwrap_or(ZERO));
+                if range != Some(ZERO) {
+                    lifetimes.insert(id, Lifetime::Pinned);
+                    pinned = true;
+                }
+            }
+            if !pinned {
+                break;
+            }
+        }
+    }
+
+    // which items each cascade uses, and when
+    let mut cascades = AHashMap::<Group, Vec<(usize, usize, Id)>>::new();
+    for (id, lifetime) in &lifetimes {
+        if let (Id::Arbitrary(_), Lifetime::Cascade(root, first, last)) = (id, lifetime) {
+            cascades
+                .entry(*root)
+                .or_default()
+                .push((*first, *last, *id));
+        }
+    }
+    let mut cascades = cascades.into_iter().collect::<Vec<_>>();
+    for (_, items) in &mut cascades {
+        items.sort();
+    }
+    cascades.sort_by_key(|(root, _)| *root);
+
+    // like register allocation: every cascade starts with all the shared IDs free,
+    // and an item takes the first one that isn't used by another item in the cascade at that point
+    let mut shared = Vec::<Id>::new();
+    let mut swaps = AHashMap::<Id, Id>::new();
+    for (_, items) in cascades {
+        let mut busy_until = vec![None; shared.len()];
+        for (first, last, id) in items {
+            let free = busy_until
+                .iter()
+                .position(|b| !matches!(b, Some(end) if *end >= first));
+            let index = match free {
+                Some(i) => i,
+                None => {
+                    shared.push(id);
+                    busy_until.push(None);
+                    shared.len() - 1
+                }
+            };
+            busy_until[index] = Some(last);
+            if shared[index] != id {
+                swaps.insert(id, shared[index]);
+            }
+        }
+    }
+
+    replace_ids(
+        objects,
+        |param| match param {
+            ObjParam::Item(item) => Some(&mut item.id),
+            _ => None,
+        },
+        &swaps,
+    );
+}
+
+// color channels are only seen through the objects that use them, and color triggers only set them,
+// so a channel that isn't used by any object and isn't copied or pulsed is never needed, and they can
+// all share one ID
+pub fn color_coalescing(
+    network: &TriggerNetwork,
+    objects: &mut Triggerlist,
+    reserved: &ReservedIds,
+) {
+    let mut unseen = AHashSet::<Id>::new();
+    let mut seen = reserved.object_colors.clone();
+
+    for gang in network.map.values() {
+        for trigger in gang.triggers.iter().filter(|t| !t.deleted) {
+            let obj = &objects[trigger.obj].0;
+            let is_color_trigger = matches!(obj.params.get(&1), Some(ObjParam::Number(n)) if *n as u16 == obj_ids::COLOR);
+            for (prop, param) in obj.params.iter() {
+                if let ObjParam::Color(color) = param {
+                    if is_color_trigger && *prop == obj_props::TARGET_COLOR {
+                        unseen.insert(color.id);
+                    } else {
+                        seen.insert(color.id);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut unseen = unseen
+        .into_iter()
+        .filter(|id| matches!(id, Id::Arbitrary(_)) && !seen.contains(id))
+        .collect::<Vec<_>>();
+    unseen.sort();
+
+    let swaps = match unseen.first() {
+        Some(first) => unseen[1..].iter().map(|id| (*id, *first)).collect(),
+        None => return,
+    };
+
+    replace_ids(
+        objects,
+        |param| match param {
+            ObjParam::Color(color) => Some(&mut color.id),
+            _ => None,
+        },
+        &swaps,
+    );
+}
+
+fn activates_instantly(obj_id: u16, params: &AHashMap<u16, ObjParam>) -> bool {
+    match obj_id {
+        obj_ids::INSTANT_COUNT => {
+            matches!(
+                params.get(&obj_props::ACTIVATE_GROUP),
+                Some(ObjParam::Bool(true))
+            )
+        }
+        obj_ids::SPAWN => match params.get(&obj_props::SPAWN_DELAY) {
+            None => true,
+            Some(ObjParam::Number(d)) => *d == 0.0,
+            _ => false,
+        },
+        obj_ids::ITEM_COMPARE | obj_ids::RANDOM | obj_ids::ADVANCED_RANDOM | obj_ids::SEQUENCE => {
+            true
+        }
+        _ => false,
+    }
+}
+
+// goes through the cascade of a root group, updates the lifetimes of the items it uses
+// and returns those items
+fn trace(
+    root: Group,
+    groups: &AHashMap<Group, Vec<TracedTrigger>>,
+    lifetimes: &mut AHashMap<Id, Lifetime>,
+) -> Option<Vec<Id>> {
+    let mut point = 0;
+    let mut items = Vec::new();
+    let mut seen = AHashSet::new();
+    let mut stack = vec![(root, 0)];
+    let mut running = AHashSet::new();
+
+    while let Some((group, index)) = stack.last().copied() {
+        if index == 0 {
+            running.insert(group);
+        }
+        let trigger = match groups[&group].get(index) {
+            Some(t) => t,
+            None => {
+                running.remove(&group);
+                stack.pop();
+                continue;
+            }
+        };
+        stack.last_mut().unwrap().1 += 1;
+
+        point += 1;
+        if point > MAX_TRACE_LEN {
+            return None;
+        }
+
+        for id in &trigger.items {
+            if seen.insert(*id) {
+                items.push(*id);
+            }
+            let lifetime = match lifetimes.get(id) {
+                None => Lifetime::Cascade(root, point, point),
+                Some(Lifetime::Cascade(r, first, _)) if *r == root => {
+                    Lifetime::Cascade(root, *first, point)
+                }
+                Some(_) => Lifetime::Pinned,
+            };
+            lifetimes.insert(*id, lifetime);
+        }
+
+        // a group that activates itself instantly would never stop, so it's not followed
+        for target in trigger.calls.iter().rev() {
+            if groups.contains_key(target) && !running.contains(target) {
+                stack.push((*target, 0));
+            }
+        }
+    }
+    Some(items)
+}
+
+// a group in the cascade that is being followed
+struct Frame {
+    group: Group,
+    index: usize,
+    // the trigger in the group whose targets are being followed
+    call: Option<Call>,
+}
+
+struct Call {
+    // the targets left, and the values they start with (`None` if the trigger can't activate it)
+    targets: Vec<(Group, Option<Values>)>,
+    // whether the target being followed can be activated
+    possible: bool,
+    values_before: Values,
+    // every range the items can have after the trigger, from the targets followed so far
+    values_after: Option<Values>,
+}
+
+// the ranges the items of a cascade can have once it's done, if the items in `zero` are 0 when it
+// starts (the others could be anything). `None` if the cascade activates a group that is already
+// running, since that isn't followed
+fn final_values(
+    root: Group,
+    groups: &AHashMap<Group, Vec<TracedTrigger>>,
+    items: &[Id],
+    zero: &AHashSet<Id>,
+) -> Option<Values> {
+    let mut values = items
+        .iter()
+        .filter(|id| !zero.contains(id))
+        .map(|id| (*id, ANY))
+        .collect::<Values>();
+    let mut stack = vec![Frame {
+        group: root,
+        index: 0,
+        call: None,
+    }];
+    let mut running = AHashSet::new();
+    running.insert(root);
+
+    while let Some(frame) = stack.last_mut() {
+        match groups[&frame.group].get(frame.index) {
+            Some(trigger) => {
+                frame.index += 1;
+                if let Some((id, amount)) = trigger.adds {
+                    let (lo, hi) = values.get(&id).copied().unwrap_or(ZERO);
+                    let range = match amount {
+                        Some(n) => (lo.saturating_add(n), hi.saturating_add(n)),
+                        None => ANY,
+                    };
+                    values.insert(id, range);
+                }
+                if trigger.calls.is_empty() {
+                    continue;
+                }
+                let refined = |passed| match trigger.condition {
+                    Some((id, comparison)) => {
+                        let range = values.get(&id).copied().unwrap_or(ZERO);
+                        let mut values = values.clone();
+                        values.insert(id, comparison.refine(range, passed)?);
+                        Some(values)
+                    }
+                    None => Some(values.clone()),
+                };
+                let mut values_after = if trigger.always { None } else { refined(false) };
+                let mut targets = Vec::new();
+                for target in trigger.calls.iter().rev() {
+                    if running.contains(target) {
+                        return None;
+                    }
+                    match (groups.contains_key(target), refined(true)) {
+                        (true, start) => targets.push((*target, start)),
+                        (false, Some(start)) => join(&mut values_after, &start),
+                        (false, None) => (),
+                    }
+                }
+                frame.call = Some(Call {
+                    targets,
+                    possible: true,
+                    values_before: values.clone(),
+                    values_after,
+                });
+            }
+            None => {
+                running.remove(&frame.group);
+                stack.pop();
+                match stack.last_mut().and_then(|f| f.call.as_mut()) {
+                    Some(call) if call.possible => join(&mut call.values_after, &values),
+                    _ => (),
+                }
+            }
+        }
+
+        // follows the next target of the trigger in the group on top, or goes on after the trigger
+        if let Some(frame) = stack.last_mut() {
+            if let Some(call) = &mut frame.call {
+                match call.targets.pop() {
+                    Some((target, start)) => {
+                        call.possible = start.is_some();
+                        values = start.unwrap_or_else(|| call.values_before.clone());
+                        running.insert(target);
+                        stack.push(Frame {
+                            group: target,
+                            index: 0,
+                            call: None,
+                        });
+                    }
+                    None => {
+                        let call = frame.call.take().unwrap();
+                        values = call.values_after.unwrap_or(call.values_before);
+                    }
+                }
+            }
+        }
+    }
+    Some(values)
+}
+
+// adds the ranges in `values` to `into`
+fn join(into: &mut Option<Values>, values: &Values) {
+    let into = match into {
+        Some(into) => into,
+        None => {
+            *into = Some(values.clone());
+            return;
+        }
+    };
+    for (id, (lo, hi)) in into.iter_mut() {
+        let (other_lo, other_hi) = values.get(id).copied().unwrap_or(ZERO);
+        *lo = (*lo).min(other_lo);
+        *hi = (*hi).max(other_hi);
+    }
+    for (id, (lo, hi)) in values {
+        into.entry(*id).or_insert(((*lo).min(0), (*hi).max(0)));
+    }
+}
+
+fn replace_ids(
+    objects: &mut Triggerlist,
+    get_id: impl Fn(&mut ObjParam) -> Option<&mut Id>,
+    swaps: &AHashMap<Id, Id>,
+) {
+    if swaps.is_empty() {
+        return;
+    }
+    for fn_id in objects.list.iter_mut() {
+        for (object, _) in fn_id.obj_list.iter_mut() {
+            for param in object.params.values_mut() {
+                if let Some(id) = get_id(param) {
+                    if let Some(to) = swaps.get(id) {
+                        *id = *to;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use compiler::compiler_types::{FunctionId, TriggerOrder};
+    use compiler::leveldata::GdObj;
+
+    use super::*;
+    use crate::optimize::build_network;
+    use crate::test_util::{group, item, trigger_at};
+
+    // adds `amount` to an item
+    fn pickup(order: f64, in_group: u16, id: u16, amount: f64) -> (GdObj, TriggerOrder) {
+        trigger_at(
+            order,
+            &[
+                (1, ObjParam::Number(obj_ids::PICKUP as f64)),
+                (obj_props::GROUPS, group(in_group)),
+                (obj_props::ITEM, item(id)),
+                (obj_props::COUNT, ObjParam::Number(amount)),
+            ],
+        )
+    }
+
+    // activates `target` if the item is larger than 0
+    fn if_positive(order: f64, in_group: u16, id: u16, target: u16) -> (GdObj, TriggerOrder) {
+        trigger_at(
+            order,
+            &[
+                (1, ObjParam::Number(obj_ids::INSTANT_COUNT as f64)),
+                (obj_props::GROUPS, group(in_group)),
+                (obj_props::ITEM, item(id)),
+                (obj_props::COUNT, ObjParam::Number(0.0)),
+                (obj_props::COMPARISON, ObjParam::Number(1.0)),
+                (obj_props::ACTIVATE_GROUP, ObjParam::Bool(true)),
+                (obj_props::TARGET, group(target)),
+            ],
+        )
+    }
+
+    // the item every trigger uses after item coalescing
+    fn coalesce(obj_list: Vec<(GdObj, TriggerOrder)>) -> Vec<Id> {
+        let mut list = vec![FunctionId {
+            parent: None,
+            width: None,
+            obj_list,
+        }];
+        let network = build_network(&list);
+        let reserved = ReservedIds::from_objects(&[], &list);
+        item_coalescing(&network, &mut Triggerlist { list: &mut list }, &reserved);
+        list[0]
+            .obj_list
+            .iter()
+            .map(|(obj, _)| match obj.params.get(&obj_props::ITEM) {
+                Some(ObjParam::Item(item)) => item.id,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shares_emptied_items() {
+        // two cascades that each add 1 to an item, and take it out again
+        let ids = coalesce(vec![
+            pickup(0.0, 1, 1, 1.0),
+            if_positive(1.0, 1, 1, 3),
+            pickup(2.0, 3, 1, -1.0),
+            pickup(0.0, 2, 2, 1.0),
+            if_positive(1.0, 2, 2, 4),
+            pickup(2.0, 4, 2, -1.0),
+        ]);
+        assert!(ids.iter().all(|id| *id == ids[0]));
+    }
+
+    #[test]
+    fn pins_items_that_keep_their_value() {
+        // like two counters in event handlers that run at different times
+        let ids = coalesce(vec![
+            pickup(0.0, 1, 1, 1.0),
+            if_positive(1.0, 1, 1, 3),
+            pickup(2.0, 3, 3, 1.0),
+            pickup(0.0, 2, 2, 1.0),
+            if_positive(1.0, 2, 2, 4),
+            pickup(2.0, 4, 4, 1.0),
+        ]);
+        assert_eq!(ids[0], Id::Arbitrary(1));
+        assert_eq!(ids[3], Id::Arbitrary(2));
+    }
+
+    #[test]
+    fn pins_items_emptied_on_some_paths() {
+        // the item is only taken out again if another item is positive,
+        // which could be anything when the cascade starts
+        let ids = coalesce(vec![
+            pickup(0.0, 1, 1, 1.0),
+            if_positive(1.0, 1, 5, 3),
+            pickup(2.0, 3, 1, -1.0),
+            pickup(0.0, 2, 2, 1.0),
+            if_positive(1.0, 2, 2, 4),
+            pickup(2.0, 4, 2, -1.0),
+            pickup(0.0, 6, 5, 1.0),
+        ]);
+        assert_eq!(ids[0], Id::Arbitrary(1));
+        assert_eq!(ids[3], Id::Arbitrary(2));
+    }
+
+    #[test]
+    fn doesnt_follow_groups_that_are_running() {
+        // the second group activates itself, so its item is pinned,
+        // but the other cascades are still traced and share their item
+        let ids = coalesce(vec![
+            pickup(0.0, 1, 1, 1.0),
+            if_positive(1.0, 1, 1, 3),
+            if_positive(0.0, 3, 1, 3),
+            pickup(1.0, 3, 1, -1.0),
+            pickup(0.0, 2, 2, 1.0),
+            if_positive(1.0, 2, 2, 4),
+            pickup(2.0, 4, 2, -1.0),
+            pickup(0.0, 5, 3, 1.0),
+            if_positive(1.0, 5, 3, 6),
+            pickup(2.0, 6, 3, -1.0),
+        ]);
+        assert_eq!(ids[0], Id::Arbitrary(1));
+        assert_eq!(ids[4], Id::Arbitrary(2));
+        assert_eq!(ids[7], Id::Arbitrary(2));
+    }
+}
@@ -6,8 +6,12 @@ use compiler::{
 use ahash::{AHashMap, AHashSet};
 
 mod dead_code;
+pub mod graph;
+mod group_coalescing;
 mod group_toggling;
+mod id_coalescing;
 pub mod optimize;
+pub mod passes;
 mod spawn_optimisation;
 mod trigger_dedup;
 
@@ -39,12 +43,34 @@ mod obj_ids {
     pub const DISABLE_TRAIL: u16 = 33;
     pub const HIDE: u16 = 1612;
     pub const SHOW: u16 = 1613;
+
+    // added in 2.2
+    pub const RANDOM: u16 = 1912;
+    pub const ADVANCED_RANDOM: u16 = 2068;
+    pub const SEQUENCE: u16 = 3607;
+    pub const TIME: u16 = 3614;
+    pub const TIME_EVENT: u16 = 3615;
+    pub const TIME_CONTROL: u16 = 3617;
+    pub const ITEM_EDIT: u16 = 3619;
+    pub const ITEM_COMPARE: u16 = 3620;
+    pub const ITEM_PERSIST: u16 = 3641;
 }
 
 pub mod obj_props {
     pub const TARGET: u16 = 51;
     pub const GROUPS: u16 = 57;
     pub const ACTIVATE_GROUP: u16 = 56;
+    pub const TARGET_COLOR: u16 = 23;
+    pub const SPAWN_TRIGGERED: u16 = 62;
+    pub const SPAWN_DELAY: u16 = 63;
+    pub const SECOND_TARGET: u16 = 71;
+    pub const ITEM: u16 = 80;
+    pub const COUNT: u16 = 77;
+    pub const COMPARISON: u16 = 88;
+    pub const CHANCES: u16 = 152;
+    pub const SEQUENCE: u16 = 435;
+    pub const SPAWN_ORDERED: u16 = 441;
+    pub const REMAPS: u16 = 442;
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
@@ -71,6 +97,9 @@ pub struct ReservedIds {
     pub object_blocks: AHashSet<Id>,
 
     pub object_items: AHashSet<Id>,
+
+    // groups activated by triggers the optimizer doesn't follow (see `activated_groups`)
+    pub activated_groups: AHashSet<Id>,
 }
 
 impl ReservedIds {
@@ -83,6 +112,8 @@ impl ReservedIds {
             object_blocks: Default::default(),
 
             object_items: Default::default(),
+
+            activated_groups: Default::default(),
         };
         for obj in objects {
             for param in obj.params.values() {
@@ -93,6 +124,9 @@ impl ReservedIds {
                     leveldata::ObjParam::GroupList(g) => {
                         reserved.object_groups.extend(g.iter().map(|g| g.id));
                     }
+                    leveldata::ObjParam::WeightedGroups(g) => {
+                        reserved.object_groups.extend(g.iter().map(|(g, _)| g.id));
+                    }
 
                     leveldata::ObjParam::Color(g) => {
                         reserved.object_colors.insert(g.id);
@@ -112,6 +146,9 @@ impl ReservedIds {
 
         for fn_id in func_ids {
             for (trigger, _) in &fn_id.obj_list {
+                reserved
+                    .activated_groups
+                    .extend(activated_groups(trigger).into_iter().map(|g| g.id));
                 for (prop, param) in trigger.params.iter() {
                     if *prop == 57 {
                         match &param {
@@ -197,6 +234,9 @@ pub fn get_role(obj: &GdObj) -> TriggerRole {
                 })) = obj.params.get(&obj_props::TARGET)
                 {
                     TriggerRole::Output
+                } else if is_remapped_spawn(obj) {
+                    // remaps and ordering would be lost if it was merged with other spawn triggers
+                    TriggerRole::Output
                 } else if hd {
                     TriggerRole::Func
                 } else {
@@ -251,6 +291,8 @@ pub fn get_role(obj: &GdObj) -> TriggerRole {
                     TriggerRole::Output
                 }
             }
+            // this includes the 2.2 triggers: the groups they pick between or wait to activate
+            // are treated like start groups instead (see `activated_groups`)
             _ => TriggerRole::Output,
         }
     } else {
@@ -258,6 +300,82 @@ pub fn get_role(obj: &GdObj) -> TriggerRole {
     }
 }
 
+fn is_remapped_spawn(obj: &GdObj) -> bool {
+    matches!(obj.params.get(&obj_props::REMAPS), Some(ObjParam::GroupList(l)) if !l.is_empty())
+        || matches!(
+            obj.params.get(&obj_props::SPAWN_ORDERED),
+            Some(ObjParam::Bool(true))
+        )
+}
+
+/// The groups a trigger activates that the optimizer doesn't follow:
+/// the targets of 2.2 triggers that choose between groups or wait for a timer,
+/// and the target and remapped groups of spawn triggers with remaps
+pub fn activated_groups(obj: &GdObj) -> Vec<Group> {
+    let obj_id = match obj.params.get(&1) {
+        Some(ObjParam::Number(n)) => *n as u16,
+        _ => return Vec::new(),
+    };
+    let props: &[u16] = match obj_id {
+        obj_ids::ITEM_COMPARE | obj_ids::RANDOM => &[obj_props::TARGET, obj_props::SECOND_TARGET],
+        obj_ids::ADVANCED_RANDOM => &[obj_props::CHANCES],
+        obj_ids::SEQUENCE => &[obj_props::SEQUENCE],
+        obj_ids::TIME | obj_ids::TIME_EVENT => &[obj_props::TARGET],
+        obj_ids::SPAWN if is_remapped_spawn(obj) => &[obj_props::TARGET, obj_props::REMAPS],
+        _ => &[],
+    };
+    let mut groups = Vec::new();
+    for prop in props {
+        match obj.params.get(prop) {
+            Some(ObjParam::Group(g)) => groups.push(*g),
+            Some(ObjParam::GroupList(l)) => groups.extend(l.iter().copied()),
+            Some(ObjParam::WeightedGroups(l)) => groups.extend(l.iter().map(|(g, _)| *g)),
+            _ => (),
+        }
+    }
+    groups
+}
+
 pub const NO_GROUP: Group = Group {
     id: Id::Specific(0),
 };
+
+// objects for the tests of the optimization passes
+#[cfg(test)]
+pub(crate) mod test_util {
+    use compiler::builtins::{Group, Id, Item};
+    use compiler::compiler_types::TriggerOrder;
+    use compiler::leveldata::{GdObj, ObjParam};
+    use parser::ast::ObjectMode;
+
+    pub fn group(id: u16) -> ObjParam {
+        ObjParam::Group(Group {
+            id: Id::Arbitrary(id),
+        })
+    }
+
+    pub fn item(id: u16) -> ObjParam {
+        ObjParam::Item(Item {
+            id: Id::Arbitrary(id),
+        })
+    }
+
+    pub fn obj(mode: ObjectMode, params: &[(u16, ObjParam)]) -> GdObj {
+        GdObj {
+            func_id: 0,
+            params: params.iter().cloned().collect(),
+            mode,
+            unique_id: 0,
+            source: Default::default(),
+        }
+    }
+
+    pub fn trigger(params: &[(u16, ObjParam)]) -> (GdObj, TriggerOrder) {
+        trigger_at(0.0, params)
+    }
+
+    // a trigger with the order `order` in its function
+    pub fn trigger_at(order: f64, params: &[(u16, ObjParam)]) -> (GdObj, TriggerOrder) {
+        (obj(ObjectMode::Trigger, params), TriggerOrder(order))
+    }
+}
@@ -2,10 +2,11 @@ use compiler::builtins::{Group, Id};
 use compiler::compiler_types::{FunctionId, TriggerOrder};
 use parser::ast::ObjectMode;
 
+use crate::passes::{OptOptions, OptStats, Pass, PassManager};
 use crate::{
-    dead_code, get_role, group_toggling, obj_ids, obj_props, spawn_optimisation, trigger_dedup,
-    ObjPtr, ReservedIds, Swaps, Trigger, TriggerGang, TriggerNetwork, TriggerRole, Triggerlist,
-    NO_GROUP,
+    dead_code, get_role, group_coalescing, group_toggling, id_coalescing, obj_ids, obj_props,
+    spawn_optimisation, trigger_dedup, ObjPtr, ReservedIds, Swaps, Trigger, TriggerGang,
+    TriggerNetwork, TriggerRole, Triggerlist, NO_GROUP,
 };
 
 //mod icalgebra;
@@ -15,47 +16,15 @@ use ahash::AHashMap;
 
 pub fn optimize(
     mut obj_in: Vec<FunctionId>,
+    level_objects: &mut [GdObj],
     mut closed_group: u16,
     mut reserved: ReservedIds,
-) -> Vec<FunctionId> {
-    let mut network = TriggerNetwork::default();
+    options: &OptOptions,
+) -> (Vec<FunctionId>, OptStats) {
+    let mut network = build_network(&obj_in);
 
     let toggle_groups = get_toggle_groups(&obj_in);
 
-    // sort all triggers by their group
-    for (f, fnid) in obj_in.iter().enumerate() {
-        for (o, (obj, _)) in fnid.obj_list.iter().enumerate() {
-            //if let Some(ObjParam::Number(id)) = obj.params.get(&1) {
-            // let mut hd = false;
-            // if let Some(ObjParam::Bool(hd_val)) = obj.params.get(&103) {
-            //     hd = *hd_val;
-            // }
-            let trigger = Trigger {
-                obj: ObjPtr(f, o),
-                role: get_role(obj),
-                deleted: false,
-            };
-            if let Some(ObjParam::Group(group)) = obj.params.get(&obj_props::GROUPS) {
-                match network.map.get_mut(&group) {
-                    Some(l) => (*l).triggers.push(trigger),
-                    None => {
-                        network.map.insert(*group, TriggerGang::new(vec![trigger]));
-                    }
-                }
-            } else {
-                match network.map.get_mut(&NO_GROUP) {
-                    Some(l) => (*l).triggers.push(trigger),
-                    None => {
-                        network
-                            .map
-                            .insert(NO_GROUP, TriggerGang::new(vec![trigger]));
-                    }
-                }
-            }
-            //}
-        }
-    }
-
     let mut objects = Triggerlist { list: &mut obj_in };
 
     //optimize
@@ -67,39 +36,87 @@ pub fn optimize(
     // this somewhere else if i want to add an option to not have optimization
     //network = fix_read_write_order(&mut objects, &network, &mut closed_group);
 
-    // round 1
-    //dbg!(&objects.list);
-
-    for _ in 0..10 {
-        clean_network(&mut network, &objects, true);
-
-        dead_code::dead_code_optimization(&mut network, &mut objects, &reserved);
-
-        clean_network(&mut network, &objects, false);
-        //dbg!(&objects.list);
-
-        spawn_optimisation::spawn_optimisation(
-            &mut network,
-            &mut objects,
-            &reserved,
-            &toggle_groups,
-        );
+    clean_network(&mut network, &objects, false);
 
-        clean_network(&mut network, &objects, false);
-        //dbg!(&objects.list);
+    let mut passes = PassManager::new(options, &network, &objects);
+
+    // repeat until the passes stop changing anything
+    loop {
+        let changed = passes.round(&mut network, &mut objects, |passes, network, objects| {
+            passes.run(Pass::DeadCode, network, objects, |network, objects| {
+                clean_network(network, objects, true);
+                dead_code::dead_code_optimization(network, objects, &reserved);
+            });
+
+            passes.run(
+                Pass::SpawnOptimisation,
+                network,
+                objects,
+                |network, objects| {
+                    spawn_optimisation::spawn_optimisation(
+                        network,
+                        objects,
+                        &reserved,
+                        &toggle_groups,
+                    );
+                },
+            );
 
-        update_reserved(&mut network, &mut objects, &mut reserved);
+            update_reserved(network, objects, &mut reserved);
+        });
+        if !changed {
+            break;
+        }
     }
 
-    clean_network(&mut network, &objects, false);
+    passes.run(
+        Pass::ItemCoalescing,
+        &mut network,
+        &mut objects,
+        |network, objects| {
+            id_coalescing::item_coalescing(network, objects, &reserved);
+        },
+    );
 
-    trigger_dedup::dedup_triggers(&mut network, &mut objects, &reserved);
+    passes.run(
+        Pass::ColorCoalescing,
+        &mut network,
+        &mut objects,
+        |network, objects| {
+            id_coalescing::color_coalescing(network, objects, &reserved);
+        },
+    );
 
-    clean_network(&mut network, &objects, false);
+    passes.run(
+        Pass::TriggerDedup,
+        &mut network,
+        &mut objects,
+        |network, objects| {
+            trigger_dedup::dedup_triggers(network, objects, &reserved);
+        },
+    );
+
+    passes.run(
+        Pass::GroupToggling,
+        &mut network,
+        &mut objects,
+        |network, objects| {
+            group_toggling::group_toggling(network, objects, &reserved, &mut closed_group);
+        },
+    );
 
-    group_toggling::group_toggling(&mut network, &mut objects, &reserved, &mut closed_group);
+    passes.run(
+        Pass::GroupCoalescing,
+        &mut network,
+        &mut objects,
+        |network, objects| {
+            group_coalescing::group_coalescing(network, objects, level_objects, options.target);
+        },
+    );
     //dbg!(&network);
 
+    let stats = passes.finish(&network, &objects);
+
     let zero_group = Group {
         id: Id::Specific(0),
     };
@@ -132,11 +149,52 @@ pub fn optimize(
         }
     }
 
-    rebuild(&network, &obj_in)
+    (rebuild(&network, &obj_in), stats)
+}
+
+// sort all triggers by their group
+pub(crate) fn build_network(obj_in: &[FunctionId]) -> TriggerNetwork {
+    let mut network = TriggerNetwork::default();
+
+    for (f, fnid) in obj_in.iter().enumerate() {
+        for (o, (obj, _)) in fnid.obj_list.iter().enumerate() {
+            //if let Some(ObjParam::Number(id)) = obj.params.get(&1) {
+            // let mut hd = false;
+            // if let Some(ObjParam::Bool(hd_val)) = obj.params.get(&103) {
+            //     hd = *hd_val;
+            // }
+            let trigger = Trigger {
+                obj: ObjPtr(f, o),
+                role: get_role(obj),
+                deleted: false,
+            };
+            if let Some(ObjParam::Group(group)) = obj.params.get(&obj_props::GROUPS) {
+                match network.map.get_mut(&group) {
+                    Some(l) => (*l).triggers.push(trigger),
+                    None => {
+                        network.map.insert(*group, TriggerGang::new(vec![trigger]));
+                    }
+                }
+            } else {
+                match network.map.get_mut(&NO_GROUP) {
+                    Some(l) => (*l).triggers.push(trigger),
+                    None => {
+                        network
+                            .map
+                            .insert(NO_GROUP, TriggerGang::new(vec![trigger]));
+                    }
+                }
+            }
+            //}
+        }
+    }
+    network
 }
 
 pub fn is_start_group(g: Group, reserved: &ReservedIds) -> bool {
-    matches!(g.id, Id::Specific(_)) || reserved.object_groups.contains(&g.id)
+    matches!(g.id, Id::Specific(_))
+        || reserved.object_groups.contains(&g.id)
+        || reserved.activated_groups.contains(&g.id)
 }
 
 #[derive(Default)]
@@ -335,6 +393,13 @@ pub fn replace_groups(table: Swaps, objects: &mut Triggerlist) {
                             }
                         }
                     }
+                    ObjParam::WeightedGroups(list) => {
+                        for (g, _) in list {
+                            if let Some(to) = map.get(g) {
+                                *g = to.1;
+                            }
+                        }
+                    }
                     _ => (),
                 }
             }
@@ -408,6 +473,7 @@ pub fn create_spawn_trigger(
         func_id: trigger.obj.0,
         mode: ObjectMode::Trigger,
         unique_id: objects[trigger.obj].0.unique_id,
+        source: objects[trigger.obj].0.source,
     };
 
     (*objects.list)[trigger.obj.0]
@@ -0,0 +1,509 @@
+// the optimization passes, which of them run at each opt level,
+// and the statistics of what each of them removed
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use ahash::AHashSet;
+use compiler::builtins::{Group, Id};
+use compiler::leveldata::ObjParam;
+use compiler::target::Target;
+
+use crate::optimize::clean_network;
+use crate::{TriggerNetwork, Triggerlist};
+
+pub const MAX_LEVEL: u8 = 3;
+pub const DEFAULT_LEVEL: u8 = MAX_LEVEL;
+
+// the passes are run again until they stop changing the triggers, but never more than this
+const MAX_ROUNDS: usize = 100;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Pass {
+    // removes triggers that are unreachable or don't lead to an output
+    DeadCode,
+    // merges chains of spawn triggers
+    SpawnOptimisation,
+    // gives item IDs that are never in use at the same time the same ID
+    ItemCoalescing,
+    // gives color channels that are never seen the same ID
+    ColorCoalescing,
+    // removes triggers that do exactly the same thing as another trigger
+    TriggerDedup,
+    // replaces spawn triggers with toggles where that uses fewer objects
+    GroupToggling,
+    // gives groups that are never used by the same triggers the same ID
+    GroupCoalescing,
+}
+
+impl Pass {
+    // in the order they run
+    pub const ALL: [Pass; 7] = [
+        Pass::DeadCode,
+        Pass::SpawnOptimisation,
+        Pass::ItemCoalescing,
+        Pass::ColorCoalescing,
+        Pass::TriggerDedup,
+        Pass::GroupToggling,
+        Pass::GroupCoalescing,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::DeadCode => "dead_code",
+            Pass::SpawnOptimisation => "spawn_optimisation",
+            Pass::ItemCoalescing => "item_coalescing",
+            Pass::ColorCoalescing => "color_coalescing",
+            Pass::TriggerDedup => "trigger_dedup",
+            Pass::GroupToggling => "group_toggling",
+            Pass::GroupCoalescing => "group_coalescing",
+        }
+    }
+
+    // the lowest opt level the pass runs at
+    fn level(self) -> u8 {
+        match self {
+            Pass::DeadCode => 1,
+            Pass::SpawnOptimisation | Pass::TriggerDedup => 2,
+            Pass::ItemCoalescing
+            | Pass::ColorCoalescing
+            | Pass::GroupToggling
+            | Pass::GroupCoalescing => 3,
+        }
+    }
+
+    // whether what the pass assumes about the game holds for the target
+    fn supports(self, target: Target) -> bool {
+        match self {
+            // intraframe grouping relies on the order 2.1 runs the triggers of a group in,
+            // which 2.2 only keeps for spawn triggers with spawn ordered on
+            Pass::GroupToggling => target == Target::Gd21,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for Pass {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Pass::ALL
+            .into_iter()
+            .find(|p| p.name() == s)
+            .ok_or_else(|| format!("Unknown optimization pass: {}", s))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OptOptions {
+    // 0 runs no passes, 1 only removes dead code, 2 also merges spawn triggers
+    // and duplicates, and 3 also shares item IDs, color channels and groups and uses group toggling
+    pub level: u8,
+    pub disabled: AHashSet<Pass>,
+    // only runs this many passes, to find which pass run breaks a level
+    pub pass_limit: Option<usize>,
+    pub target: Target,
+}
+
+impl Default for OptOptions {
+    fn default() -> Self {
+        OptOptions {
+            level: DEFAULT_LEVEL,
+            disabled: AHashSet::new(),
+            pass_limit: None,
+            target: Target::default(),
+        }
+    }
+}
+
+impl OptOptions {
+    pub fn enabled(&self, pass: Pass) -> bool {
+        pass.level() <= self.level && pass.supports(self.target) && !self.disabled.contains(&pass)
+    }
+}
+
+// how many of each thing the triggers use
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Usage {
+    pub objects: usize,
+    pub groups: usize,
+    pub items: usize,
+    pub colors: usize,
+}
+
+impl Usage {
+    fn removed_since(self, before: Usage) -> [i64; 4] {
+        [
+            before.objects as i64 - self.objects as i64,
+            before.groups as i64 - self.groups as i64,
+            before.items as i64 - self.items as i64,
+            before.colors as i64 - self.colors as i64,
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PassRun {
+    pub pass: Pass,
+    pub round: usize,
+    pub before: Usage,
+    pub after: Usage,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct OptStats {
+    pub rounds: usize,
+    pub runs: Vec<PassRun>,
+    pub before: Usage,
+    pub after: Usage,
+    // the passes that would have run after the pass limit
+    pub skipped: usize,
+}
+
+impl fmt::Display for OptStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<20} {:>5} {:>8} {:>8} {:>8} {:>8}",
+            "pass (removed)", "runs", "objects", "groups", "items", "colors"
+        )?;
+        for pass in Pass::ALL {
+            let runs = self.runs.iter().filter(|r| r.pass == pass);
+            let count = runs.clone().count();
+            if count == 0 {
+                continue;
+            }
+            let removed = runs.fold([0; 4], |mut total, r| {
+                for (t, n) in total.iter_mut().zip(r.after.removed_since(r.before)) {
+                    *t += n;
+                }
+                total
+            });
+            writeln!(
+                f,
+                "{:<20} {:>5} {:>8} {:>8} {:>8} {:>8}",
+                pass, count, removed[0], removed[1], removed[2], removed[3]
+            )?;
+        }
+        write!(
+            f,
+            "{} -> {} objects, {} -> {} groups, {} -> {} items, {} -> {} colors in {} round{}",
+            self.before.objects,
+            self.after.objects,
+            self.before.groups,
+            self.after.groups,
+            self.before.items,
+            self.after.items,
+            self.before.colors,
+            self.after.colors,
+            self.rounds,
+            if self.rounds == 1 { "" } else { "s" }
+        )?;
+        if self.skipped > 0 {
+            let last = self.runs.last();
+            write!(
+                f,
+                "\nstopped after pass run {}{}, skipping {} more",
+                self.runs.len(),
+                last.map(|r| format!(" ({} in round {})", r.pass, r.round))
+                    .unwrap_or_default(),
+                self.skipped
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct PassManager<'a> {
+    pub options: &'a OptOptions,
+    pub stats: OptStats,
+}
+
+impl<'a> PassManager<'a> {
+    pub fn new(options: &'a OptOptions, network: &TriggerNetwork, objects: &Triggerlist) -> Self {
+        PassManager {
+            options,
+            stats: OptStats {
+                before: measure(network, objects),
+                ..Default::default()
+            },
+        }
+    }
+
+    // runs the pass if it's enabled and within the pass limit, and cleans the network after it
+    pub fn run(
+        &mut self,
+        pass: Pass,
+        network: &mut TriggerNetwork,
+        objects: &mut Triggerlist,
+        f: impl FnOnce(&mut TriggerNetwork, &mut Triggerlist),
+    ) {
+        if !self.options.enabled(pass) {
+            return;
+        }
+        if matches!(self.options.pass_limit, Some(limit) if self.stats.runs.len() >= limit) {
+            self.stats.skipped += 1;
+            return;
+        }
+        let before = measure(network, objects);
+        f(network, objects);
+        clean_network(network, objects, false);
+        let after = measure(network, objects);
+
+        self.stats.runs.push(PassRun {
+            pass,
+            round: self.stats.rounds,
+            before,
+            after,
+        });
+    }
+
+    // runs a round of the repeating passes, and returns whether they changed anything
+    pub fn round(
+        &mut self,
+        network: &mut TriggerNetwork,
+        objects: &mut Triggerlist,
+        f: impl FnOnce(&mut Self, &mut TriggerNetwork, &mut Triggerlist),
+    ) -> bool {
+        if self.stats.rounds >= MAX_ROUNDS {
+            return false;
+        }
+        self.stats.rounds += 1;
+        let runs = self.stats.runs.len();
+        let before = fingerprint(network, objects);
+        f(self, network, objects);
+        self.stats.runs.len() > runs && fingerprint(network, objects) != before
+    }
+
+    pub fn finish(mut self, network: &TriggerNetwork, objects: &Triggerlist) -> OptStats {
+        self.stats.after = measure(network, objects);
+        self.stats
+    }
+}
+
+// the number of triggers left, and the number of IDs they use
+fn measure(network: &TriggerNetwork, objects: &Triggerlist) -> Usage {
+    let mut count = 0;
+    let mut groups = AHashSet::<Group>::new();
+    let mut items = AHashSet::<Id>::new();
+    let mut colors = AHashSet::<Id>::new();
+    for gang in network.map.values() {
+        for trigger in gang.triggers.iter().filter(|t| !t.deleted) {
+            count += 1;
+            for param in objects[trigger.obj].0.params.values() {
+                match param {
+                    ObjParam::Group(g) => {
+                        groups.insert(*g);
+                    }
+                    ObjParam::GroupList(list) => groups.extend(list.iter().copied()),
+                    ObjParam::WeightedGroups(list) => groups.extend(list.iter().map(|(g, _)| *g)),
+                    ObjParam::Item(i) => {
+                        items.insert(i.id);
+                    }
+                    ObjParam::Color(c) => {
+                        colors.insert(c.id);
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+    Usage {
+        objects: count,
+        groups: groups.len(),
+        items: items.len(),
+        colors: colors.len(),
+    }
+}
+
+// a hash of all the triggers left, which doesn't depend on their order
+fn fingerprint(network: &TriggerNetwork, objects: &Triggerlist) -> (usize, u64) {
+    let mut count = 0;
+    let mut sum = 0u64;
+    for gang in network.map.values() {
+        for trigger in gang.triggers.iter().filter(|t| !t.deleted) {
+            let mut params = objects[trigger.obj].0.params.iter().collect::<Vec<_>>();
+            params.sort_by_key(|(k, _)| **k);
+            let mut hasher = DefaultHasher::new();
+            params.hash(&mut hasher);
+            count += 1;
+            sum = sum.wrapping_add(hasher.finish());
+        }
+    }
+    (count, sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use compiler::builtins::Item;
+    use compiler::compiler_types::{FunctionId, TriggerOrder};
+    use compiler::leveldata::{GdObj, ObjParam};
+
+    use super::*;
+    use crate::optimize::{build_network, optimize};
+    use crate::test_util::{group, trigger};
+    use crate::{dead_code, obj_ids, obj_props, ReservedIds};
+
+    fn pickup(in_group: u16, item: u16) -> (GdObj, TriggerOrder) {
+        trigger(&[
+            (1, ObjParam::Number(obj_ids::PICKUP as f64)),
+            (obj_props::GROUPS, group(in_group)),
+            (obj_props::ITEM, ObjParam::Item(Item::new(item))),
+        ])
+    }
+
+    // a spawn trigger at the start of the level that spawns a pickup trigger,
+    // and a pickup trigger in a group nothing spawns
+    fn network() -> Vec<FunctionId> {
+        vec![FunctionId {
+            parent: None,
+            width: None,
+            obj_list: vec![
+                trigger(&[
+                    (1, ObjParam::Number(obj_ids::SPAWN as f64)),
+                    (obj_props::TARGET, group(1)),
+                ]),
+                pickup(1, 1),
+                pickup(2, 2),
+            ],
+        }]
+    }
+
+    fn remove_dead_code(network: &mut TriggerNetwork, objects: &mut Triggerlist) {
+        clean_network(network, objects, true);
+        dead_code::dead_code_optimization(network, objects, &ReservedIds::from_objects(&[], &[]));
+    }
+
+    #[test]
+    fn measures_passes() {
+        let mut list = network();
+        let mut network = build_network(&list);
+        let mut objects = Triggerlist { list: &mut list };
+        let options = OptOptions::default();
+
+        let mut passes = PassManager::new(&options, &network, &objects);
+        passes.run(Pass::DeadCode, &mut network, &mut objects, remove_dead_code);
+        let stats = passes.finish(&network, &objects);
+
+        assert_eq!(stats.runs.len(), 1);
+        assert_eq!(stats.runs[0].pass, Pass::DeadCode);
+        assert_eq!((stats.before.objects, stats.before.items), (3, 2));
+        assert_eq!((stats.after.objects, stats.after.items), (2, 1));
+        assert_eq!(stats.after.groups, 1);
+    }
+
+    #[test]
+    fn skips_passes_that_arent_enabled() {
+        let mut list = network();
+        let mut network = build_network(&list);
+        let mut objects = Triggerlist { list: &mut list };
+        let mut ran = Vec::new();
+        let mut run_all = |options: &OptOptions, ran: &mut Vec<Pass>| {
+            let mut passes = PassManager::new(options, &network, &objects);
+            for pass in Pass::ALL {
+                passes.run(pass, &mut network, &mut objects, |_, _| ran.push(pass));
+            }
+        };
+
+        run_all(
+            &OptOptions {
+                level: 0,
+                ..Default::default()
+            },
+            &mut ran,
+        );
+        assert!(ran.is_empty());
+
+        run_all(
+            &OptOptions {
+                level: 2,
+                disabled: [Pass::SpawnOptimisation].into_iter().collect(),
+                ..Default::default()
+            },
+            &mut ran,
+        );
+        assert_eq!(ran, [Pass::DeadCode, Pass::TriggerDedup]);
+
+        ran.clear();
+        run_all(
+            &OptOptions {
+                target: Target::Gd22,
+                ..Default::default()
+            },
+            &mut ran,
+        );
+        assert!(!ran.contains(&Pass::GroupToggling));
+        assert!(ran.contains(&Pass::ColorCoalescing));
+    }
+
+    #[test]
+    fn stops_at_pass_limit() {
+        let mut list = network();
+        let mut network = build_network(&list);
+        let mut objects = Triggerlist { list: &mut list };
+        let options = OptOptions {
+            pass_limit: Some(1),
+            ..Default::default()
+        };
+
+        let mut passes = PassManager::new(&options, &network, &objects);
+        let mut ran = 0;
+        for pass in [Pass::DeadCode, Pass::SpawnOptimisation, Pass::TriggerDedup] {
+            passes.run(pass, &mut network, &mut objects, |_, _| ran += 1);
+        }
+        let stats = passes.finish(&network, &objects);
+
+        assert_eq!(ran, 1);
+        assert_eq!(stats.runs.len(), 1);
+        assert_eq!(stats.skipped, 2);
+        assert!(stats
+            .to_string()
+            .ends_with("stopped after pass run 1 (dead_code in round 0), skipping 2 more"));
+
+        // with no passes, the triggers are left as they are
+        let (optimized, stats) = optimize(
+            self::network(),
+            &mut [],
+            0,
+            ReservedIds::from_objects(&[], &[]),
+            &OptOptions {
+                pass_limit: Some(0),
+                ..Default::default()
+            },
+        );
+        assert!(stats.runs.is_empty());
+        assert_eq!(optimized.iter().map(|f| f.obj_list.len()).sum::<usize>(), 3);
+    }
+
+    #[test]
+    fn rounds_stop_when_nothing_changes() {
+        let mut list = network();
+        let mut network = build_network(&list);
+        let mut objects = Triggerlist { list: &mut list };
+        let options = OptOptions::default();
+
+        let mut passes = PassManager::new(&options, &network, &objects);
+        let round =
+            |passes: &mut PassManager, network: &mut TriggerNetwork, objects: &mut Triggerlist| {
+                passes.round(network, objects, |passes, network, objects| {
+                    passes.run(Pass::DeadCode, network, objects, remove_dead_code);
+                })
+            };
+        assert!(round(&mut passes, &mut network, &mut objects));
+        assert!(!round(&mut passes, &mut network, &mut objects));
+        let stats = passes.finish(&network, &objects);
+
+        assert_eq!(stats.rounds, 2);
+        assert_eq!(
+            stats.runs.iter().map(|r| r.round).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(stats.after.objects, 2);
+    }
+}
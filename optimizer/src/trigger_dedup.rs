@@ -59,6 +59,17 @@ pub(crate) fn param_identifier(param: &ObjParam) -> String {
             out.pop();
             out
         }
+        ObjParam::WeightedGroups(list) => list
+            .iter()
+            .map(|(g, n)| {
+                format!(
+                    "{}.{}",
+                    param_identifier(&ObjParam::Group(*g)),
+                    param_identifier(&ObjParam::Number(*n))
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("."),
         ObjParam::Epsilon => "0.050".to_string(),
     };
     str
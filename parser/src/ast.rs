@@ -3,6 +3,7 @@
 use ahash::AHashSet;
 
 use internment::LocalIntern;
+use serde::{Deserialize, Serialize};
 
 use crate::fmt::SpwnFmt;
 use shared::FileRange;
@@ -10,36 +11,58 @@ use shared::ImportType;
 use shared::StoredValue;
 
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum DictDef {
     Def((LocalIntern<String>, Expression)),
     Extract(Expression),
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum ArrayPrefix {
     Collect,
     Spread,
     // future-proofing
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct ArrayDef {
     pub value: Expression,
     pub operator: Option<ArrayPrefix>,
 }
 
-//pub type Comment = (Option<String>, Option<String>);
+/// A comment or an empty line between statements
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Trivia {
+    Comment(String),
+    BlankLine,
+}
+
+/// Comments attached to a statement. These are only filled in when
+/// parsing with trivia (for the formatter), otherwise they are empty.
+#[derive(Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub struct Comments {
+    /// comments and blank lines on the lines before the statement
+    pub before: Vec<Trivia>,
+    /// comment on the same line, after the statement
+    pub trailing: Option<String>,
+    /// comments after the last statement of a block, before the closing bracket
+    pub after: Vec<Trivia>,
+    /// the original text of the statement, if it contains comments or syntax
+    /// that the formatter can't reproduce (for example comments inside a dictionary)
+    pub verbatim: Option<String>,
+    /// where the statement is in the source, including its attribute
+    pub span: FileRange,
+}
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Statement {
     pub body: StatementBody,
     pub arrow: bool, /*context changing */
     pub pos: FileRange,
-    //pub comment: Comment,
+    pub comments: Comments,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum StatementBody {
     //Definition(Definition),
     Call(Call),
@@ -63,13 +86,13 @@ pub enum StatementBody {
 
 // TODO: implement this in parser and compiler
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Definition {
     pub symbol: Variable,
     pub value: Option<Expression>,
     pub mutable: bool,
 }
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct ValueLiteral {
     pub body: ValueBody,
     //pub comment: Comment,
@@ -84,12 +107,13 @@ impl ValueLiteral {
     }
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum ValueBody {
     Id(Id),
     Number(f64),
     CmpStmt(CompoundStatement),
-    Dictionary(Vec<DictDef>),
+    /// the members, and the blank lines before them (only when parsing with trivia)
+    Dictionary(Vec<DictDef>, Vec<Comments>),
     Symbol(LocalIntern<String>),
     Bool(bool),
     Expression(Expression),
@@ -108,7 +132,7 @@ pub enum ValueBody {
     Null,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MacroPattern {
     pub args: Vec<Expression>,
     pub ret: Expression,
@@ -127,32 +151,34 @@ impl ValueBody {
     }
 }
 
-#[derive(Clone, PartialEq, Debug, Copy, Hash)]
+#[derive(Clone, PartialEq, Debug, Copy, Hash, Serialize, Deserialize)]
 pub enum ObjectMode {
     Object,
     Trigger,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct ObjectLiteral {
     pub props: Vec<(Expression, Expression)>,
     pub mode: ObjectMode,
+    /// the blank lines before each prop (only when parsing with trivia)
+    pub comments: Vec<Comments>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct StrInner {
     pub inner: String,
     pub flags: Option<StringFlags>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum StringFlags {
     Base64,
     Raw,
     Unindent,
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum Operator {
     Or,
     And,
@@ -190,7 +216,7 @@ pub enum Operator {
     Swap,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum UnaryOperator {
     Not,
     Minus,
@@ -206,7 +232,7 @@ pub enum UnaryOperator {
     InPattern,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum IdClass {
     Group,
     Color,
@@ -214,7 +240,7 @@ pub enum IdClass {
     Block,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Attribute {
     pub tags: Vec<(String, Vec<Argument>)>,
 }
@@ -254,16 +280,18 @@ impl Attribute {
             if args.is_empty() {
                 None
             } else {
-                if testable && args.len() > 1 {
-                    if let Some(s) = args[1].symbol {
-                        if s.as_ref() == "run_test" {
-                            if let &ValueBody::Bool(b) = &args[1].value.values[0].value.body {
-                                if !b {
-                                    return None;
+                if testable {
+                    for arg in &args[1..] {
+                        match arg.symbol.as_ref().map(|s| s.as_str()) {
+                            Some("run_test") => {
+                                if let &ValueBody::Bool(b) = &arg.value.values[0].value.body {
+                                    if !b {
+                                        return None;
+                                    }
                                 }
                             }
-                        } else {
-                            panic!("expected run_test as second argument");
+                            Some("target") => (),
+                            _ => panic!("expected run_test or target after the example"),
                         }
                     }
                 }
@@ -276,6 +304,18 @@ impl Attribute {
             None
         }
     }
+
+    /// The Geometry Dash version the example has to be built for, like
+    /// `example("...", target = "gd2.2")` for examples of triggers that were added in 2.2
+    pub fn get_example_target(&self) -> Option<String> {
+        self.get("example")?
+            .iter()
+            .find(|arg| arg.symbol.is_some_and(|s| s.as_ref() == "target"))
+            .and_then(|arg| match &arg.value.values[0].value.body {
+                ValueBody::Str(s) => Some(s.inner.clone()),
+                _ => None,
+            })
+    }
 }
 
 impl Default for Attribute {
@@ -298,7 +338,7 @@ pub trait CountSymbols {
     }
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum Path {
     Member(LocalIntern<String>),
     Associated(LocalIntern<String>),
@@ -310,14 +350,14 @@ pub enum Path {
     Decrement,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Argument {
     pub symbol: Option<LocalIntern<String>>,
     pub value: Expression,
     pub pos: FileRange,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Slice {
     pub left: Option<Expression>,
     pub right: Option<Expression>,
@@ -344,25 +384,25 @@ impl Argument {
     }
 }
 
-/*#[derive(Clone, PartialEq, Debug)]
+/*#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Event {
     pub symbol: String,
     pub args: Vec<Expression>,
     pub func: Variable,
 }*/
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Call {
     pub function: Variable,
 }
 
-/*#[derive(Clone, PartialEq, Debug)]
+/*#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Native {
     pub function: Variable,
     pub args: Vec<Argument>,
 }*/
 //     name     def value     props     type ind.     location in file     is reference
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum ArgType {
     Ref,
     Mut,
@@ -376,53 +416,55 @@ pub type ArgDef = (
     FileRange,
     ArgType,
 );
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Macro {
     pub args: Vec<ArgDef>,
     pub body: CompoundStatement,
     pub properties: Attribute,
     pub arg_pos: FileRange,
     pub ret_type: Option<Expression>,
+    /// written as `(args) => expr`
+    pub shorthand: bool,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct For {
     pub symbol: Expression,
     pub array: Expression,
     pub body: Vec<Statement>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct While {
     pub condition: Expression,
     pub body: Vec<Statement>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum CaseType {
     //Value(Expression),
     Pattern(Expression),
     Default,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Case {
     pub typ: CaseType,
     pub body: Expression,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Match {
     pub value: Expression,
     pub cases: Vec<Case>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Error {
     pub message: Expression,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Variable {
     pub operator: Option<UnaryOperator>,
     pub value: ValueLiteral,
@@ -445,13 +487,13 @@ impl Variable {
     }
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Expression {
     pub values: Vec<Variable>,
     pub operators: Vec<Operator>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Ternary {
     pub condition: Expression,
     pub if_expr: Expression,
@@ -459,7 +501,7 @@ pub struct Ternary {
     pub is_pattern: bool,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Comprehension {
     pub symbol: LocalIntern<String>,
     pub iterator: Expression,
@@ -486,25 +528,27 @@ impl Expression {
     }
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct CompoundStatement {
     pub statements: Vec<Statement>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Implementation {
     pub symbol: Variable,
     pub members: Vec<DictDef>,
+    /// comments attached to each member (only when parsing with trivia)
+    pub comments: Vec<Comments>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct If {
     pub condition: Expression,
     pub if_body: Vec<Statement>,
     pub else_body: Option<Vec<Statement>>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Id {
     pub number: u16,
     pub unspecified: bool,
@@ -1,6 +1,10 @@
 // tools for automatically formatting spwn files
 
 use crate::ast::*;
+use crate::parser::parse_spwn_with_trivia;
+
+use errors::SyntaxError;
+use shared::{ImportType, SpwnSource};
 
 pub trait SpwnFmt {
     fn fmt(&self, ind: Indent) -> String;
@@ -8,30 +12,167 @@ pub trait SpwnFmt {
 
 type Indent = u16;
 
-fn tabs(mut num: Indent) -> String {
-    let mut out = String::new();
-    while num > 4 {
-        out += "\t";
-        num -= 4;
+fn tabs(num: Indent) -> String {
+    " ".repeat(num as usize)
+}
+
+/// Formats a whole file. Comments and blank lines are kept, and every top level
+/// statement is checked to parse to the same thing after formatting. Statements
+/// where that isn't the case are left the way they were written. Files with
+/// `\r\n` line endings keep them.
+pub fn format(
+    source: &str,
+    file: SpwnSource,
+    builtins: &[&'static str],
+) -> Result<String, SyntaxError> {
+    let formatted = format_lines(source.replace("\r\n", "\n"), file, builtins)?;
+    Ok(if source.contains("\r\n") {
+        formatted.replace('\n', "\r\n")
+    } else {
+        formatted
+    })
+}
+
+// formats a file with `\n` line endings
+fn format_lines(
+    source: String,
+    file: SpwnSource,
+    builtins: &[&'static str],
+) -> Result<String, SyntaxError> {
+    let (statements, notes) = parse_spwn_with_trivia(source.clone(), file.clone(), builtins)?;
+
+    if statements.is_empty() {
+        // nothing but comments (and maybe a file tag)
+        let trimmed = source.trim();
+        return Ok(if trimmed.is_empty() {
+            String::new()
+        } else {
+            format!("{}\n", trimmed)
+        });
+    }
+
+    let mut out = trivia(&notes.tag_comments, 0);
+    if !notes.tag.tags.is_empty() {
+        out += &attribute(&notes.tag, 0);
+        out += "\n";
+    }
+
+    for statement in &statements {
+        let formatted = statement.fmt(0);
+        let checked = if same_statement(statement, &formatted, &file, builtins) {
+            formatted
+        } else {
+            let mut original = statement.clone();
+            original.comments.verbatim =
+                Some(source[statement.comments.span.0..statement.comments.span.1].to_string());
+            original.fmt(0)
+        };
+
+        out += &trivia(&statement.comments.before, 0);
+        out += &checked;
+        out += &trivia(&statement.comments.after, 0);
     }
 
-    for _ in 0..num {
-        out += " ";
+    // last line of defense, this should never fail
+    match parse_spwn_with_trivia(out.clone(), file.clone(), builtins) {
+        Ok((new_statements, new_notes))
+            if normalized(&new_statements) == normalized(&statements)
+                && new_notes.tag == notes.tag =>
+        {
+            Ok(out)
+        }
+        _ => Err(SyntaxError::SyntaxError {
+            message: "The formatter could not format this file without changing it".to_string(),
+            pos: (0, 0),
+            file,
+        }),
+    }
+}
+
+// checks that the formatted text of a statement parses back to the same statement
+fn same_statement(
+    statement: &Statement,
+    formatted: &str,
+    file: &SpwnSource,
+    builtins: &[&'static str],
+) -> bool {
+    // the empty tag makes sure an attribute on the statement isn't read as the file tag
+    let code = format!("#[]\n{}", formatted);
+    match parse_spwn_with_trivia(code, file.clone(), builtins) {
+        Ok((new, _)) if new.len() == 1 => {
+            let mut expected = statement.clone();
+            expected.comments.before.clear();
+            expected.comments.after.clear();
+            normalized(&new) == normalized(&[expected])
+        }
+        _ => false,
+    }
+}
+
+// debug output without any source positions, which are different after formatting
+fn normalized(statements: &[Statement]) -> String {
+    let debug = format!("{:?}", statements);
+    let mut out = String::with_capacity(debug.len());
+    let mut rest = debug.as_str();
+
+    while let Some(i) = rest.find('(') {
+        out += &rest[..i];
+        rest = &rest[i..];
+        match position_len(rest) {
+            Some(len) => rest = &rest[len..],
+            None => {
+                out.push('(');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out + rest
+}
+
+// length of a `(123, 456)` at the start of the string
+fn position_len(s: &str) -> Option<usize> {
+    let digits = |s: &str| s.chars().take_while(|c| c.is_ascii_digit()).count();
+
+    let a = digits(&s[1..]);
+    let after_a = &s[1 + a..];
+    if a == 0 || !after_a.starts_with(", ") {
+        return None;
+    }
+    let b = digits(&after_a[2..]);
+    if b == 0 || !after_a[2 + b..].starts_with(')') {
+        return None;
     }
+    Some(1 + a + 2 + b + 1)
+}
 
+fn trivia(list: &[Trivia], ind: Indent) -> String {
+    let mut out = String::new();
+    for t in list {
+        match t {
+            Trivia::Comment(text) => out += &format!("{}{}\n", tabs(ind), text),
+            Trivia::BlankLine => out += "\n",
+        }
+    }
     out
 }
 
-pub fn _format(input: Vec<Statement>) -> String {
+fn statements(list: &[Statement], ind: Indent) -> String {
     let mut out = String::new();
-    for s in input {
-        out += &s.fmt(0)
+    for s in list {
+        out += &trivia(&s.comments.before, ind);
+        out += &tabs(ind);
+        out += &s.fmt(ind);
+        out += &trivia(&s.comments.after, ind);
     }
     out
 }
 
-pub fn _format2(input: &ValueBody) -> String {
-    input.fmt(0)
+fn block(list: &[Statement], ind: Indent) -> String {
+    if list.is_empty() {
+        "{}".to_string()
+    } else {
+        format!("{{\n{}{}}}", statements(list, ind + 4), tabs(ind))
+    }
 }
 
 fn element_list(elements: &[impl SpwnFmt], open: char, closing: char, ind: Indent) -> String {
@@ -39,191 +180,200 @@ fn element_list(elements: &[impl SpwnFmt], open: char, closing: char, ind: Inden
         return format!("{}{}", open, closing);
     }
 
-    let mut elem_text = Vec::<String>::new();
-    let mut sum = 0;
+    let elem_text = elements.iter().map(|el| el.fmt(ind)).collect::<Vec<_>>();
+    let sum: usize = elem_text
+        .iter()
+        .map(|text| text.lines().next().unwrap_or("").len())
+        .sum();
 
     let last = elements.len() - 1;
 
-    for (_i, el) in elements.iter().enumerate() {
-        let text = el.fmt(0);
-
-        sum += text.lines().next().unwrap().len();
-
-        elem_text.push(text)
-    }
-
-    let vertical = if elements.len() == 1 {
-        sum > 150
-    } else {
-        elem_text.iter().enumerate().any(|(i, x)| {
-            if i != last {
-                x.len() > 50 || x.contains('\n')
-            } else {
-                sum > 100
-            }
-        })
-    };
+    // a multiline last argument (like a macro) can stay on the line of the call
+    let vertical = elem_text.iter().enumerate().any(|(i, x)| {
+        if i != last {
+            x.len() > 50 || x.contains('\n')
+        } else {
+            (open != '(' && x.contains('\n')) || sum > if last == 0 { 150 } else { 100 }
+        }
+    });
 
     if vertical {
         let mut out = format!("{}\n", open);
 
-        for el in &elem_text {
-            for line in el.lines() {
-                out += &format!("{}{}\n", tabs(ind + 4), line);
-            }
-            out.pop();
-            out += ",\n";
+        for (el, text) in elements.iter().zip(elem_text) {
+            let text = if text.contains('\n') {
+                el.fmt(ind + 4)
+            } else {
+                text
+            };
+            out += &format!("{}{},\n", tabs(ind + 4), text);
         }
-        /*if elements.len() == 1 {
-            out.pop();
-            out.pop();
-            out += "\n";
-        }*/
 
         out + &format!("{}{}", tabs(ind), closing)
     } else {
-        let mut out = format!("{}", open);
-        let last_elem = elem_text.pop().unwrap();
-        let iter = elem_text.iter();
+        format!("{}{}{}", open, elem_text.join(", "), closing)
+    }
+}
 
-        for el in iter {
-            out += &format!("{}, ", el);
+// dicts and objects with blank lines between their members keep them, which puts every member on its own line
+fn grouped_list(members: &[impl SpwnFmt], comments: &[Comments], ind: Indent) -> String {
+    if comments.iter().all(|c| c.before.is_empty()) {
+        return element_list(members, '{', '}', ind);
+    }
+
+    let mut out = "{\n".to_string();
+    for (i, member) in members.iter().enumerate() {
+        if let Some(c) = comments.get(i) {
+            out += &trivia(&c.before, ind + 4);
         }
+        out += &format!("{}{},\n", tabs(ind + 4), member.fmt(ind + 4));
+    }
+    out + &tabs(ind) + "}"
+}
+
+const KEYWORDS: &[&str] = &[
+    "return", "impl", "for", "in", "throw", "if", "else", "switch", "match", "break", "continue",
+    "while", "obj", "trigger", "import", "extract", "null", "let", "self", "sync", "true", "false",
+    "is", "as",
+];
 
-        let mut last_elem_lines = last_elem.lines();
-        out += last_elem_lines.next().unwrap();
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.contains(&name)
+}
 
-        for line in last_elem_lines {
-            out += &format!("\n{}{}", tabs(ind), line);
+fn escape(text: &str, quote: char) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if c == quote => {
+                out.push('\\');
+                out.push(c)
+            }
+            c => out.push(c),
         }
+    }
+    out
+}
 
-        out.push(closing);
-        out
+fn quote_for(text: &str) -> char {
+    if text.contains('"') && !text.contains('\'') {
+        '\''
+    } else {
+        '"'
+    }
+}
+
+fn string_literal(text: &str) -> String {
+    let quote = quote_for(text);
+    format!("{}{}{}", quote, escape(text, quote), quote)
+}
+
+// whether the text of a raw string can be put between these quotes
+fn raw_fits(text: &str, quote: char) -> bool {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == quote || (c == '\\' && chars.next().is_none()) {
+            return false;
+        }
+    }
+    true
+}
+
+impl SpwnFmt for StrInner {
+    fn fmt(&self, ind: Indent) -> String {
+        match self.flags {
+            None => string_literal(&self.inner),
+            Some(StringFlags::Base64) => {
+                let decoded = base64::decode(&self.inner).unwrap_or_default();
+                format!("64{}", string_literal(&String::from_utf8_lossy(&decoded)))
+            }
+            Some(StringFlags::Raw) => {
+                let quote = if raw_fits(&self.inner, '"') { '"' } else { '\'' };
+                format!("r{}{}{}", quote, self.inner, quote)
+            }
+            Some(StringFlags::Unindent) => {
+                let quote = quote_for(&self.inner);
+                let mut out = format!("u{}\n", quote);
+                if !self.inner.is_empty() {
+                    for line in self.inner.split('\n') {
+                        if !line.is_empty() {
+                            out += &tabs(ind + 4);
+                            out += &escape(line, quote);
+                        }
+                        out += "\n";
+                    }
+                }
+                out += &tabs(ind);
+                out.push(quote);
+                out
+            }
+        }
     }
 }
 
 impl SpwnFmt for DictDef {
     fn fmt(&self, ind: Indent) -> String {
         match self {
-            DictDef::Def((name, expr)) => format!("{}{}: {}", tabs(ind), name, expr.fmt(ind)),
-            DictDef::Extract(expr) => format!("{}..{}", tabs(ind), expr.fmt(ind)),
-        }
-    }
-}
-
-// fn trim_start_tabs(string: &str) -> (&str, Indent) {
-//     //https://doc.rust-lang.org/src/core/str/mod.rs.html#4082-4090
-//     let mut ind = 0;
-//     for (i, c) in string.chars().enumerate() {
-//         match c {
-//             '\t' => ind += 4,
-//             ' ' => ind += 1,
-//             _ => return (unsafe { string.get_unchecked(i..string.len()) }, ind),
-//         }
-//     }
-//     ("", ind)
-// }
-
-// fn indent_comment(comment: &str, ind: Indent) -> String {
-//     let mut in_comment = false;
-//     let mut current_off = 0;
-//     let mut out = String::new();
-//     for line in comment.lines() {
-//         let (trimmed, ind_offset) = trim_start_tabs(line);
-//         if !in_comment {
-//             if trimmed.starts_with("//") {
-//                 out += &format!("{}{}\r\n", tabs(ind), trimmed);
-//             } else if trimmed.starts_with("/*") {
-//                 in_comment = true;
-//                 current_off = ind_offset;
-//                 out += &format!("{}{}\r\n", tabs(ind), trimmed);
-//             }
-//         } else {
-//             out += &format!("{}{}\r\n", tabs(ind_offset - current_off), trimmed);
-//         }
-
-//         if line.trim_end().ends_with("*/") {
-//             in_comment = false
-//         }
-//     }
-//     out
-// }
-
-/*#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn test() {
-        println!(
-            "{}",
-            indent_comment(
-                &String::from(
-                    "
-//hello
-                /*
-                a = {
-                    b = 2
-                    c = 3
-                    a = {
-                        b = 2
-                        c = 3
-                        a = {
-                            b = 2
-                            c = 3
-                        }
+            DictDef::Def((name, expr)) => {
+                if let [Variable {
+                    operator: None,
+                    value:
+                        ValueLiteral {
+                            body: ValueBody::Symbol(s),
+                        },
+                    path,
+                    tag,
+                    ..
+                }] = &expr.values[..]
+                {
+                    if s == name && path.is_empty() && tag.tags.is_empty() && is_identifier(s)
+                    {
+                        // shorthand
+                        return s.to_string();
                     }
                 }
-                */
-
-//bruh
-//bruh
-//bruh
-        "
-                ),
-                0
-            )
-        )
+                let key = if is_identifier(name) || name.as_ref() == "type" {
+                    name.to_string()
+                } else {
+                    string_literal(name)
+                };
+                format!("{}: {}", key, expr.fmt(ind))
+            }
+            DictDef::Extract(expr) => format!("..{}", expr.fmt(ind)),
+        }
     }
-}*/
+}
 
 impl SpwnFmt for Statement {
     fn fmt(&self, ind: Indent) -> String {
-        let mut out = String::new();
-        // if let Some(comment) = &self.comment.0 {
-        //     //out += "[stmt pre]";
-
-        //     out += &indent_comment(comment, ind);
-        //     //
-        //     if !comment.ends_with('\n') {
-        //         out += "\n";
-        //     }
-        //     out += &tabs(ind);
-        // }
-        out += &if self.arrow {
-            format!("-> {}\n", self.body.fmt(ind))
+        let mut out = if let Some(text) = &self.comments.verbatim {
+            text.clone()
+        } else if self.arrow {
+            format!("-> {}", self.body.fmt(ind))
         } else {
-            format!("{}\n", self.body.fmt(ind))
+            self.body.fmt(ind)
         };
-        // if let Some(comment) = &self.comment.1 {
-        //     if !comment.starts_with('\n') {
-        //         out += "\n";
-        //     }
-
-        //     //out += &tabs(ind);
-        //     //out += "[stmt post]";
-        //     out += &indent_comment(comment, ind);
-        // }
-        out
+        if let Some(comment) = &self.comments.trailing {
+            out += " ";
+            out += comment;
+        }
+        out + "\n"
     }
 }
 
 impl SpwnFmt for StatementBody {
     fn fmt(&self, ind: Indent) -> String {
         match self {
-            //StatementBody::Definition(def) => format!("{}", def.fmt(ind)),
             StatementBody::Call(call) => call.fmt(ind),
             StatementBody::Expr(x) => x.fmt(ind),
-            StatementBody::TypeDef { name, .. } => format!("type {}", name),
+            StatementBody::TypeDef { name, attr } => format!("{}type @{}", attr.fmt(ind), name),
             StatementBody::Return(x) => match x {
                 Some(expr) => format!("return {}", expr.fmt(ind)),
                 None => "return".to_string(),
@@ -232,7 +382,7 @@ impl SpwnFmt for StatementBody {
             StatementBody::Impl(x) => x.fmt(ind),
             StatementBody::If(x) => x.fmt(ind),
             StatementBody::For(x) => x.fmt(ind),
-            StatementBody::While(_) => "While loop lol".to_string(),
+            StatementBody::While(x) => x.fmt(ind),
             StatementBody::Error(x) => x.fmt(ind),
             StatementBody::Extract(x) => format!("extract {}", x.fmt(ind)),
             StatementBody::Break => String::from("break"),
@@ -258,56 +408,87 @@ impl SpwnFmt for ArrayDef {
     }
 }
 
+impl SpwnFmt for Case {
+    fn fmt(&self, ind: Indent) -> String {
+        match &self.typ {
+            CaseType::Pattern(p) => format!("{}: {}", p.fmt(ind), self.body.fmt(ind)),
+            CaseType::Default => format!("else: {}", self.body.fmt(ind)),
+        }
+    }
+}
+
 impl SpwnFmt for ValueBody {
     fn fmt(&self, ind: Indent) -> String {
         use ValueBody::*;
         match self {
             Id(x) => x.fmt(ind),
             Number(x) => format!("{}", x),
-            CmpStmt(x) => format!("!{{\n{}\n{}}}", x.fmt(ind + 4), tabs(ind)),
-            Dictionary(x) => element_list(x, '{', '}', ind),
+            CmpStmt(x) => format!("!{}", x.fmt(ind)),
+            Dictionary(x, comments) => grouped_list(x, comments, ind),
             Array(x) => element_list(x, '[', ']', ind),
             Symbol(x) => x.to_string(),
             Bool(x) => format!("{}", x),
             Expression(x) => format!("({})", x.fmt(ind)),
-            Str(x) => format!("\"{}\"", x.inner),
-            Import(x, f) => format!("import{} {:?}", if *f { "!" } else { "" }, x),
+            Str(x) => x.fmt(ind),
+            Import(x, f) => format!(
+                "import{} {}",
+                if *f { "!" } else { "" },
+                match x {
+                    ImportType::Script(path) => string_literal(&path.to_string_lossy()),
+                    ImportType::Lib(name) => match name.split_once('@') {
+                        Some((name, version))
+                            if !version
+                                .chars()
+                                .all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+                        {
+                            format!("{}@{}", name, string_literal(version))
+                        }
+                        _ => name.clone(),
+                    },
+                }
+            ),
             Obj(x) => {
                 (match x.mode {
-                    ObjectMode::Object => "obj".to_string(),
-                    ObjectMode::Trigger => "trigger".to_string(),
-                }) + &element_list(&x.props, '{', '}', ind)
+                    ObjectMode::Object => "obj ".to_string(),
+                    ObjectMode::Trigger => "trigger ".to_string(),
+                }) + &grouped_list(&x.props, &x.comments, ind)
             }
             Macro(x) => x.fmt(ind),
             Resolved(_) => "<val>".to_string(),
             TypeIndicator(x) => format!("@{}", x),
             Null => "null".to_string(),
             SelfVal => "self".to_string(),
-            Ternary(t) => {
-                if !t.is_pattern {
-                    format!(
-                        "{} if is {} else {}",
-                        t.if_expr.fmt(ind),
-                        t.condition.fmt(ind),
-                        t.else_expr.fmt(ind)
-                    )
-                } else {
-                    format!(
-                        "{} if {} else {}",
-                        t.if_expr.fmt(ind),
-                        t.condition.fmt(ind),
-                        t.else_expr.fmt(ind)
-                    )
-                }
-            }
+            Ternary(t) => format!(
+                "{} if {}{} else {}",
+                t.if_expr.fmt(ind),
+                if t.is_pattern { "is " } else { "" },
+                t.condition.fmt(ind),
+                t.else_expr.fmt(ind)
+            ),
             ListComp(c) => format!(
-                "{} for {} in {}",
+                "[{} for {} in {}{}]",
                 c.body.fmt(ind),
                 c.symbol,
-                c.iterator.fmt(ind)
+                c.iterator.fmt(ind),
+                match &c.condition {
+                    Some(cond) => format!(", if {}", cond.fmt(ind)),
+                    None => String::new(),
+                }
             ),
-            Match(_, _) => todo!(),
-            MacroPattern(_) => todo!(),
+            Match(value, cases) => {
+                let mut out = format!("match {} {{\n", value.fmt(ind));
+                for case in cases {
+                    out += &format!("{}{},\n", tabs(ind + 4), case.fmt(ind + 4));
+                }
+                out + &tabs(ind) + "}"
+            }
+            MacroPattern(p) => {
+                format!(
+                    "{} -> {}",
+                    element_list(&p.args, '(', ')', ind),
+                    p.ret.fmt(ind)
+                )
+            }
         }
     }
 }
@@ -330,12 +511,35 @@ impl SpwnFmt for IdClass {
     }
 }
 
+impl SpwnFmt for Slice {
+    fn fmt(&self, ind: Indent) -> String {
+        let part = |e: &Option<Expression>| e.as_ref().map(|e| e.fmt(ind)).unwrap_or_default();
+        // the step is written between the start and the end
+        match &self.step {
+            Some(_) => format!(
+                "{}:{}:{}",
+                part(&self.left),
+                part(&self.step),
+                part(&self.right)
+            ),
+            None => format!("{}:{}", part(&self.left), part(&self.right)),
+        }
+    }
+}
+
 impl SpwnFmt for Path {
     fn fmt(&self, ind: Indent) -> String {
         match self {
             Path::Member(def) => format!(".{}", def),
             Path::Associated(def) => format!("::{}", def),
-            Path::NSlice(_def) => "[its a slice ok]".to_string(),
+            Path::NSlice(slices) => format!(
+                "[{}]",
+                slices
+                    .iter()
+                    .map(|s| s.fmt(ind))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
             Path::Constructor(dict) => format!("::{}", element_list(dict, '{', '}', ind)),
             Path::Index(call) => format!("[{}]", call.fmt(ind)),
             Path::Call(x) => element_list(x, '(', ')', ind),
@@ -364,51 +568,43 @@ impl SpwnFmt for Call {
 impl SpwnFmt for For {
     fn fmt(&self, ind: Indent) -> String {
         format!(
-            "for {} in {} {{\n{}\n{}}}",
+            "for {} in {} {}",
             self.symbol.fmt(ind),
             self.array.fmt(ind),
-            CompoundStatement {
-                statements: self.body.clone()
-            }
-            .fmt(ind + 4),
-            tabs(ind)
+            block(&self.body, ind)
         )
     }
 }
 
-impl SpwnFmt for Variable {
+impl SpwnFmt for While {
     fn fmt(&self, ind: Indent) -> String {
-        let mut out = String::new();
-
-        // if let Some(comment) = &self.comment.0 {
-        //     //out += "[var pre]";
-        //     out += &indent_comment(comment, ind);
+        format!("while {} {}", self.condition.fmt(ind), block(&self.body, ind))
+    }
+}
 
-        //     if comment.ends_with('\n') {
-        //         out += &tabs(ind);
-        //     }
-        // }
+impl SpwnFmt for Variable {
+    fn fmt(&self, ind: Indent) -> String {
+        let mut out = self.tag.fmt(ind);
 
         if let Some(op) = &self.operator {
             out += &op.fmt(ind);
         }
 
-        out += &self.value.fmt(ind);
+        match &self.value.body {
+            // parts of an expression that were grouped by operator precedence,
+            // and not by brackets in the source
+            ValueBody::Expression(e)
+                if self.operator.is_none() && self.path.is_empty() && self.pos == e.get_pos() =>
+            {
+                out += &e.fmt(ind)
+            }
+            body => out += &body.fmt(ind),
+        }
 
         for p in &self.path {
-            out += &p.fmt(ind).to_string();
+            out += &p.fmt(ind);
         }
 
-        // if let Some(comment) = &self.comment.1 {
-        //     //out += "[var post]";
-
-        //     out += &indent_comment(comment, ind);
-
-        //     /*if comment.ends_with("\n") {
-        //         out += &tabs(ind);
-        //     }*/
-        // }
-
         out
     }
 }
@@ -417,7 +613,7 @@ impl SpwnFmt for Expression {
     fn fmt(&self, ind: Indent) -> String {
         let mut out = String::new();
         for (i, op) in self.operators.iter().enumerate() {
-            if let Operator::Range|Operator::InclRange = op {
+            if let Operator::Range | Operator::InclRange = op {
                 out += &format!("{}{}", self.values[i].fmt(ind), (*op).fmt(ind));
             } else {
                 out += &format!("{} {} ", self.values[i].fmt(ind), (*op).fmt(ind));
@@ -432,12 +628,11 @@ impl SpwnFmt for Expression {
 
 impl SpwnFmt for Id {
     fn fmt(&self, ind: Indent) -> String {
-        format!(
-            "{}{}{}",
-            self.number,
-            if self.unspecified { "?" } else { "" },
-            self.class_name.fmt(ind),
-        )
+        if self.unspecified {
+            format!("?{}", self.class_name.fmt(ind))
+        } else {
+            format!("{}{}", self.number, self.class_name.fmt(ind))
+        }
     }
 }
 
@@ -493,7 +688,7 @@ impl SpwnFmt for UnaryOperator {
             UnaryOperator::LessPattern => "<",
             UnaryOperator::MoreOrEqPattern => ">=",
             UnaryOperator::LessOrEqPattern => "<=",
-            UnaryOperator::InPattern => "in",
+            UnaryOperator::InPattern => "in ",
         }
         .to_string()
     }
@@ -516,60 +711,68 @@ impl SpwnFmt for Definition {
 
 impl SpwnFmt for Error {
     fn fmt(&self, ind: Indent) -> String {
-        format!("error {}", self.message.fmt(ind))
+        format!("throw {}", self.message.fmt(ind))
     }
 }
 
-fn trim_newline(s: &mut String) {
-    if s.ends_with('\n') {
-        s.pop();
-        if s.ends_with('\r') {
-            s.pop();
-        }
+impl SpwnFmt for CompoundStatement {
+    fn fmt(&self, ind: Indent) -> String {
+        block(&self.statements, ind)
     }
 }
 
-impl SpwnFmt for CompoundStatement {
+impl SpwnFmt for Implementation {
     fn fmt(&self, ind: Indent) -> String {
-        let mut out = String::new();
-
-        for s in &self.statements {
-            out += &format!("{}{}", tabs(ind), s.fmt(ind));
+        let mut out = format!("impl {} {{", self.symbol.fmt(ind));
+        if self.members.is_empty() {
+            return out + "}";
         }
+        out += "\n";
 
-        trim_newline(&mut out);
+        let no_comments = Comments::default();
+        for (i, member) in self.members.iter().enumerate() {
+            let comments = self.comments.get(i).unwrap_or(&no_comments);
 
-        out
-    }
-}
+            out += &trivia(&comments.before, ind + 4);
+            out += &tabs(ind + 4);
+            match &comments.verbatim {
+                Some(text) => out += text,
+                None => out += &member.fmt(ind + 4),
+            }
+            out += ",";
+            if let Some(comment) = &comments.trailing {
+                out += " ";
+                out += comment;
+            }
+            out += "\n";
+            out += &trivia(&comments.after, ind + 4);
+        }
 
-impl SpwnFmt for Implementation {
-    fn fmt(&self, ind: Indent) -> String {
-        format!("impl {} ", self.symbol.fmt(ind)) + &element_list(&self.members, '{', '}', ind)
+        out + &tabs(ind) + "}"
     }
 }
 
 impl SpwnFmt for If {
     fn fmt(&self, ind: Indent) -> String {
-        let mut out = format!(
-            "if {} {{\n{}\n{}}}",
-            self.condition.fmt(ind),
-            CompoundStatement {
-                statements: self.if_body.clone()
+        let mut out = format!("if {} {}", self.condition.fmt(ind), block(&self.if_body, ind));
+
+        match self.else_body.as_deref() {
+            Some(
+                [Statement {
+                    body: StatementBody::If(else_if),
+                    arrow: false,
+                    comments,
+                    ..
+                }],
+            ) if comments.before.is_empty()
+                && comments.trailing.is_none()
+                && comments.after.is_empty()
+                && comments.verbatim.is_none() =>
+            {
+                out += &format!(" else {}", else_if.fmt(ind))
             }
-            .fmt(ind + 4),
-            tabs(ind)
-        );
-
-        if let Some(body) = &self.else_body {
-            out += &format!(
-                " else {{\n{}\n{}}}",
-                CompoundStatement {
-                    statements: body.clone()
-                }
-                .fmt(ind + 4),
-                tabs(ind)
-            );
+            Some(body) => out += &format!(" else {}", block(body, ind)),
+            None => (),
         }
 
         out
@@ -578,9 +781,14 @@ impl SpwnFmt for If {
 
 impl SpwnFmt for ArgDef {
     fn fmt(&self, ind: Indent) -> String {
-        let (name, value, tag, typ, _, _) = self;
+        let (name, value, tag, typ, _, argtype) = self;
 
         let mut out = tag.fmt(ind);
+        out += match argtype {
+            ArgType::Ref => "&",
+            ArgType::Mut => "let ",
+            ArgType::Const => "",
+        };
         out += name;
         if let Some(expr) = typ {
             out += &format!(": {}", expr.fmt(ind));
@@ -595,33 +803,105 @@ impl SpwnFmt for ArgDef {
 
 impl SpwnFmt for Macro {
     fn fmt(&self, ind: Indent) -> String {
-        let mut out = String::new();
+        // the properties are the same as the tag of the variable, which is printed there
+        let mut out = element_list(&self.args, '(', ')', ind);
 
-        out += &self.properties.fmt(ind);
+        if let Some(ret) = &self.ret_type {
+            out += &format!(" -> {}", ret.fmt(ind));
+        }
 
-        out += &element_list(&self.args, '(', ')', ind);
-        out += &format!(" {{\n{}\n{}}}", &self.body.fmt(ind + 4), tabs(ind));
+        match &self.body.statements[..] {
+            [Statement {
+                body: StatementBody::Return(Some(expr)),
+                ..
+            }] if self.shorthand => out += &format!(" => {}", expr.fmt(ind)),
+            _ => out += &format!(" {}", self.body.fmt(ind)),
+        }
         out
     }
 }
 
 impl SpwnFmt for (String, Vec<Argument>) {
     fn fmt(&self, ind: Indent) -> String {
-        self.0.clone() + &element_list(&self.1, '(', ')', ind)
+        if self.1.is_empty() {
+            self.0.clone()
+        } else {
+            self.0.clone() + &element_list(&self.1, '(', ')', ind)
+        }
     }
 }
 
+fn attribute(attr: &Attribute, ind: Indent) -> String {
+    format!(
+        "#[{}]",
+        attr.tags
+            .iter()
+            .map(|t| t.fmt(ind))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
 impl SpwnFmt for Attribute {
     fn fmt(&self, ind: Indent) -> String {
         if self.tags.is_empty() {
             return String::new();
         }
 
-        let text = String::from("#") + &element_list(&self.tags, '[', ']', ind);
-        if text.len() > 60 {
+        let text = attribute(self, ind);
+        // long attributes go on their own line, unless they already end on a new one
+        if text.len() > 60 && !text.contains('\n') {
             text + "\n" + &tabs(ind)
         } else {
             text + " "
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use internment::LocalIntern;
+
+    fn fmt(code: &str) -> String {
+        let file = SpwnSource::String(LocalIntern::new(code.to_string()));
+        format(code, file, &[]).unwrap()
+    }
+
+    #[test]
+    fn keeps_comments() {
+        let code = "// header\n\n#[no_std]\n\n// the answer\na = 42 // trailing\n\n\n\nb = {x: 1, /* inside */ y: 2}\nif a > 1 {\n    $.print(a)\n    // end of block\n}\n";
+        let formatted = fmt(code);
+        assert_eq!(
+            formatted,
+            "// header\n\n#[no_std]\n\n// the answer\na = 42 // trailing\n\nb = {x: 1, /* inside */ y: 2}\nif a > 1 {\n    $.print(a)\n    // end of block\n}\n"
+        );
+    }
+
+    #[test]
+    fn keeps_blank_lines_in_dicts() {
+        let code = "d = {\n    a: 1,\n\n\n    b: 2,\n    c: 3,\n}\ne = {\n\n    x: 1,\n    y: 2,\n\n}\nt = trigger {\n    A: 1,\n\n    B: 2,\n}\n";
+        let formatted = fmt(code);
+        assert_eq!(
+            formatted,
+            "d = {\n    a: 1,\n\n    b: 2,\n    c: 3,\n}\ne = {x: 1, y: 2}\nt = trigger {\n    A: 1,\n\n    B: 2,\n}\n"
+        );
+        assert_eq!(fmt(&formatted), formatted);
+    }
+
+    #[test]
+    fn idempotent() {
+        let code = "let   x=[1,2,3]\nm = (a, b: @number = 4) {\n  return a+b*(2-1)\n}\n\n\n-> m(1,b=2)\nf = x => x if x is >2 else ?g\nt = match x {\n@number: 1, else: 2\n}\ns = u\"\n    hi\n        there\n\"\n";
+        let once = fmt(code);
+        assert_eq!(fmt(&once), once);
+        assert!(once.contains("return a + b * (2 - 1)"));
+        assert!(once.contains("\n\n-> m(1, b = 2)\n"));
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        let code = "a = 1\r\nb = 2\r\n";
+        assert_eq!(fmt(code), code);
+        assert_eq!(fmt("x=[1,2]\r\n// done\r\n"), "x = [1, 2]\r\n// done\r\n");
+    }
+}
@@ -308,8 +308,11 @@ pub enum Token {
     #[regex(r"[\n\r;]+")]
     StatementSeparator,
 
+    #[regex(r"/\*[^*]*\*(([^/\*][^\*]*)?\*)*/|//[^\n]*")]
+    Comment,
+
     #[error]
-    #[regex(r"[ \t\f]+|/\*[^*]*\*(([^/\*][^\*]*)?\*)*/|//[^\n]*", logos::skip)]
+    #[regex(r"[ \t\f]+", logos::skip)]
     Error,
 }
 
@@ -338,7 +341,7 @@ impl Token {
 
             Return | Implement | For | ErrorStatement | If | Else | Object | Trigger | Import
             | Extract | Null | Type | Let | SelfVal | Break | Continue | Match | While => "keyword",
-            //Comment | MultiCommentStart | MultiCommentEnd => "comment",
+            Comment => "comment",
             StatementSeparator => "statement separator",
             Error => "unknown",
         }
@@ -347,6 +350,8 @@ impl Token {
 
 pub struct ParseNotes {
     pub tag: ast::Attribute,
+    // comments before the file tag (only when parsing with trivia)
+    pub tag_comments: Vec<ast::Trivia>,
     pub file: SpwnSource,
     pub builtins: AHashSet<&'static str>,
 }
@@ -355,6 +360,7 @@ impl ParseNotes {
     pub fn new(path: SpwnSource, builtins: &[&'static str]) -> Self {
         ParseNotes {
             tag: ast::Attribute::new(),
+            tag_comments: Vec::new(),
             file: path,
             builtins: builtins.iter().copied().collect(),
         }
@@ -368,6 +374,17 @@ pub struct Tokens<'a> {
     line_breaks: Vec<u32>,
     //index 0 = element of iter / last element in stack
     index: TokenPos,
+
+    // only used when parsing with trivia:
+    keep_trivia: bool,
+    // comments that haven't been attached to a statement yet
+    comments: Vec<(String, FileRange)>,
+    // positions of syntax the formatter can't reproduce (like decorators)
+    opaque: Vec<usize>,
+}
+
+fn has_blank_line(gap: &str) -> bool {
+    gap.matches('\n').count() >= 2
 }
 
 impl<'a> Tokens<'a> {
@@ -377,12 +394,121 @@ impl<'a> Tokens<'a> {
             stack: Vec::new(),
             line_breaks: vec![0],
             index: 0,
+            keep_trivia: false,
+            comments: Vec::new(),
+            opaque: Vec::new(),
+        }
+    }
+
+    fn mark_opaque(&mut self, pos: usize) {
+        if self.keep_trivia {
+            self.opaque.push(pos);
+        }
+    }
+
+    // moves the collected comments before `end` (and after `anchor`) into a list of trivia
+    fn take_comments_before(&mut self, anchor: usize, end: usize, skip_first_blank: bool) -> Vec<ast::Trivia> {
+        let source = self.iter.source();
+        let mut out = Vec::new();
+        let mut last = anchor;
+        self.comments.retain(|(text, pos)| {
+            if pos.0 < anchor || pos.1 > end {
+                return true;
+            }
+            if has_blank_line(&source[last..pos.0]) && !(skip_first_blank && out.is_empty()) {
+                out.push(ast::Trivia::BlankLine);
+            }
+            out.push(ast::Trivia::Comment(text.clone()));
+            last = pos.1;
+            false
+        });
+        if has_blank_line(&source[last..end]) && !(skip_first_blank && out.is_empty()) {
+            out.push(ast::Trivia::BlankLine);
+        }
+        out
+    }
+
+    // attaches the collected comments to a statement (or impl member) that was just parsed.
+    // `start` is where it begins (including its attribute), and `anchor` is where
+    // the previous one (or the block) ended
+    fn attach_trivia(
+        &mut self,
+        comments: &mut ast::Comments,
+        (start, end): FileRange,
+        anchor: &mut usize,
+        first: bool,
+    ) {
+        if !self.keep_trivia {
+            return;
+        }
+        let source = self.iter.source();
+        let end = end.max(start);
+        comments.span = (start, end);
+
+        comments.before = self.take_comments_before(*anchor, start, first);
+
+        let opaque = self.opaque.len();
+        self.opaque.retain(|p| !(start <= *p && *p < end));
+        let mut inside = opaque != self.opaque.len();
+
+        *anchor = end;
+        let mut trailing = None;
+        self.comments.retain(|(text, pos)| {
+            if pos.0 < start {
+                true
+            } else if pos.0 < end {
+                inside = true;
+                false
+            } else if trailing.is_none() && !source[end..pos.0].contains('\n') {
+                trailing = Some((text.clone(), pos.1));
+                false
+            } else {
+                true
+            }
+        });
+        if let Some((text, trailing_end)) = trailing {
+            comments.trailing = Some(text);
+            *anchor = trailing_end;
+        }
+        if inside {
+            comments.verbatim = Some(source[start..end].to_string());
+        }
+    }
+
+    // a blank line between two dict members, when parsing with trivia
+    fn blank_line_between(&self, anchor: usize, start: usize, first: bool) -> Vec<ast::Trivia> {
+        if self.keep_trivia && !first && has_blank_line(&self.iter.source()[anchor..start.max(anchor)]) {
+            vec![ast::Trivia::BlankLine]
+        } else {
+            Vec::new()
+        }
+    }
+
+    // attaches the comments at the end of a block to its last statement (or impl member)
+    fn attach_block_end(&mut self, last: Option<&mut ast::Comments>, anchor: usize, end: usize) {
+        if !self.keep_trivia {
+            return;
+        }
+        if let Some(last) = last {
+            let mut after = self.take_comments_before(anchor, end, false);
+            if after.last() == Some(&ast::Trivia::BlankLine) {
+                after.pop();
+            }
+            last.after = after;
         }
     }
 
     fn inner_next(&mut self) -> Option<Token> {
         if self.index == 0 {
-            let next_elem = self.iter.next();
+            let mut next_elem = self.iter.next();
+            while next_elem == Some(Token::Comment) {
+                if self.keep_trivia {
+                    let span = self.iter.span();
+                    self.comments
+                        .push((self.iter.slice().trim_end().to_string(), (span.start, span.end)));
+                }
+                next_elem = self.iter.next();
+            }
 
             let slice = self.iter.slice().to_string();
             let range = self.iter.span();
@@ -553,15 +679,35 @@ impl<'a> Tokens<'a> {
 const STATEMENT_SEPARATOR_DESC: &str = "Statement separator (line-break or ';')";
 
 pub fn parse_spwn(
+    unparsed: String,
+    source: SpwnSource,
+    builtin_list: &[&'static str],
+) -> Result<(Vec<ast::Statement>, ParseNotes), SyntaxError> {
+    parse_file(unparsed, source, builtin_list, false)
+}
+
+/// Parses the file like `parse_spwn`, but also attaches the comments and blank lines
+/// to the statements they belong to. This is what the formatter uses.
+pub fn parse_spwn_with_trivia(
+    unparsed: String,
+    source: SpwnSource,
+    builtin_list: &[&'static str],
+) -> Result<(Vec<ast::Statement>, ParseNotes), SyntaxError> {
+    parse_file(unparsed, source, builtin_list, true)
+}
+
+fn parse_file(
     mut unparsed: String,
     source: SpwnSource,
     builtin_list: &[&'static str],
+    keep_trivia: bool,
 ) -> Result<(Vec<ast::Statement>, ParseNotes), SyntaxError> {
     unparsed = unparsed.replace("\r\n", "\n");
 
     let tokens_iter = Token::lexer(&unparsed);
 
     let mut tokens = Tokens::new(tokens_iter);
+    tokens.keep_trivia = keep_trivia;
 
     let mut statements = Vec::<ast::Statement>::new();
 
@@ -579,40 +725,48 @@ pub fn parse_spwn(
     tokens.line_breaks = line_breaks;
 
     let start_tag = check_for_tag(&mut tokens, &mut notes)?;
+    let mut anchor = 0;
+    if !start_tag.tags.is_empty() {
+        anchor = tokens.position().1;
+        notes.tag_comments = tokens.take_comments_before(0, anchor, true);
+    }
     notes.tag = start_tag;
     loop {
         //+ do something if we have tokens. if no more tokens, leave loop
         match tokens.next(false) {
             //oops we just advanced the tokens in an attempt to check if we have any
             Some(_) => {
+                let start = tokens.position().0;
                 tokens.previous_no_ignore(false); //bring tokens back to original
 
                 //+ we are going to parse the tokens
-                let parsed = parse_statement(&mut tokens, &mut notes)?;
-                // if parsed.comment.0 == None && !statements.is_empty() {
-                //     parsed.comment.0 = statements.last().unwrap().comment.1.clone();
-                //     (*statements.last_mut().unwrap()).comment.1 = None;
-                // }
+                let mut parsed = parse_statement(&mut tokens, &mut notes)?;
 
-                statements.push(parsed)
-            }
-            None => break, //+ no more tokens, probably end of file
-        }
+                //+ can't find any more tokens that are valid syntax, checking for line separator
+                let separator = tokens.next(true);
+                let first = statements.is_empty() && notes.tag.tags.is_empty();
+                let span = (start, parsed.pos.1);
+                tokens.attach_trivia(&mut parsed.comments, span, &mut anchor, first);
+                statements.push(parsed);
 
-        //+ can't find any more tokens that are valid syntax, checking for line separator
-        match tokens.next(true) {
-            Some(Token::StatementSeparator) => {}
-            Some(a) => {
-                return Err(SyntaxError::ExpectedErr {
-                    expected: STATEMENT_SEPARATOR_DESC.to_string(),
-                    found: format!("{}: \"{}\"", a.typ(), tokens.slice()),
-                    pos: tokens.position(),
-                    file: notes.file,
-                })
+                match separator {
+                    Some(Token::StatementSeparator) => {}
+                    Some(a) => {
+                        return Err(SyntaxError::ExpectedErr {
+                            expected: STATEMENT_SEPARATOR_DESC.to_string(),
+                            found: format!("{}: \"{}\"", a.typ(), tokens.slice()),
+                            pos: tokens.position(),
+                            file: notes.file,
+                        })
+                    }
+                    None => break,
+                }
             }
-            None => break,
+            None => break, //+ no more tokens, probably end of file
         }
     }
+    let last = statements.last_mut().map(|s| &mut s.comments);
+    tokens.attach_block_end(last, anchor, unparsed.len());
 
     Ok((statements, notes))
 }
@@ -623,20 +777,26 @@ fn parse_cmp_stmt(
 ) -> Result<Vec<ast::Statement>, SyntaxError> {
     let mut statements = Vec::<ast::Statement>::new();
     let opening_bracket = tokens.position();
+    let mut anchor = opening_bracket.1;
     loop {
         match tokens.next(false) {
             Some(Token::ClosingCurlyBracket) => break,
             Some(_) => {
+                let start = tokens.position().0;
                 tokens.previous_no_ignore(false);
 
-                let parsed = parse_statement(tokens, notes)?;
-                // if parsed.comment.0 == None && !statements.is_empty() {
-                //     parsed.comment.0 = statements.last().unwrap().comment.1.clone();
-                //     (*statements.last_mut().unwrap()).comment.1 = None;
-                // }
+                let mut parsed = parse_statement(tokens, notes)?;
+
+                let separator = tokens.next(true);
+                let (span, first) = ((start, parsed.pos.1), statements.is_empty());
+                tokens.attach_trivia(&mut parsed.comments, span, &mut anchor, first);
+                statements.push(parsed); // add to big statement list
 
-                statements.push(parsed) // add to big statement list
-                                        //println!("statement done");
+                match separator {
+                    Some(Token::StatementSeparator) => {}
+                    Some(Token::ClosingCurlyBracket) => break,
+                    a => expected!(STATEMENT_SEPARATOR_DESC.to_string(), tokens, notes, a),
+                }
             }
             None => {
                 return Err(SyntaxError::SyntaxError {
@@ -646,14 +806,9 @@ fn parse_cmp_stmt(
                 })
             }
         }
-
-        match tokens.next(true) {
-            Some(Token::StatementSeparator) => {}
-            Some(Token::ClosingCurlyBracket) => break,
-            a => expected!(STATEMENT_SEPARATOR_DESC.to_string(), tokens, notes, a),
-        }
     }
-    //tokens.next(false, false);
+    let end = tokens.position().0;
+    tokens.attach_block_end(statements.last_mut().map(|s| &mut s.comments), anchor, end);
     Ok(statements)
 }
 
@@ -670,6 +825,28 @@ pub fn parse_statement(
 
     let (start_pos, _) = tokens.position();
 
+    if !attr.tags.is_empty()
+        && matches!(
+            first,
+            Some(
+                Token::Arrow
+                    | Token::Let
+                    | Token::Return
+                    | Token::Break
+                    | Token::Continue
+                    | Token::If
+                    | Token::While
+                    | Token::For
+                    | Token::ErrorStatement
+                    | Token::Implement
+                    | Token::Extract
+            )
+        )
+    {
+        // these statements ignore the attribute, so the formatter can't print it
+        tokens.mark_opaque(start_pos);
+    }
+
     let mut arrow = false;
     let body = match first {
         // ooh what type of token is it
@@ -925,10 +1102,16 @@ pub fn parse_statement(
 
             match tokens.next(false) {
                 // check if it has the brace
-                Some(Token::OpenCurlyBracket) => ast::StatementBody::Impl(ast::Implementation {
-                    symbol,
-                    members: parse_dict(tokens, notes)?, // impl block is basically a dict
-                }),
+                Some(Token::OpenCurlyBracket) => {
+                    let mut comments = Vec::new();
+                    // impl block is basically a dict
+                    let members = parse_dict_members(tokens, notes, &mut comments, true)?;
+                    ast::StatementBody::Impl(ast::Implementation {
+                        symbol,
+                        members,
+                        comments,
+                    })
+                }
 
                 a => {
                     // no brace
@@ -996,7 +1179,7 @@ pub fn parse_statement(
         body,
         arrow,
         pos: (start_pos, end_pos),
-        // comment: (preceding_comment, comment_after),
+        comments: ast::Comments::default(),
     })
 }
 
@@ -1393,16 +1576,24 @@ fn parse_operator(token: &Token) -> Option<ast::Operator> {
     }
 }
 
-fn parse_dict(
+// attaches the blank lines between the members to `comments`, and also the comments
+// if `with_comments` is set (used for impl blocks). Otherwise the comments are left
+// for the statement around the dict
+fn parse_dict_members(
     tokens: &mut Tokens,
     notes: &mut ParseNotes,
+    comments: &mut Vec<ast::Comments>,
+    with_comments: bool,
 ) -> Result<Vec<ast::DictDef>, SyntaxError> {
     let mut defs = Vec::<ast::DictDef>::new();
 
     let mut defined_members = AHashMap::<LocalIntern<String>, FileRange>::default();
+    let mut anchor = tokens.position().1;
 
     loop {
-        match tokens.next(false) {
+        let first = tokens.next(false);
+        let start = tokens.position().0;
+        match first {
             Some(Token::Symbol) | Some(Token::Type) | Some(Token::StringLiteral) => {
                 let symbol = if let Some(Token::StringLiteral) = tokens.current() {
                     let s = str_content(tokens.slice(), tokens, notes)?.0;
@@ -1475,14 +1666,13 @@ fn parse_dict(
                                 file: notes.file.clone(),
                             });
                         }
+                        tokens.previous();
                         defs.push(ast::DictDef::Def((
                             symbol,
                             ast::ValueBody::Symbol(symbol)
                                 .to_variable(tokens.position())
                                 .to_expression(),
                         )));
-                        //tokens.previous();
-                        break;
                     }
                     a => expected!("':'".to_string(), tokens, notes, a),
                 }
@@ -1506,8 +1696,18 @@ fn parse_dict(
                 a
             ),
         };
+        let end = tokens.position().1;
         let next = tokens.next(false);
 
+        let mut member_comments = ast::Comments::default();
+        if with_comments {
+            tokens.attach_trivia(&mut member_comments, (start, end), &mut anchor, defs.len() == 1);
+        } else {
+            member_comments.before = tokens.blank_line_between(anchor, start, defs.len() == 1);
+            anchor = end;
+        }
+        comments.push(member_comments);
+
         if next == Some(Token::ClosingCurlyBracket) {
             break;
         }
@@ -1521,22 +1721,32 @@ fn parse_dict(
             });
         }
     }
+    if with_comments {
+        let end = tokens.position().0;
+        tokens.attach_block_end(comments.last_mut(), anchor, end);
+    }
     Ok(defs)
 }
 
+// also returns the blank lines before each prop, like `parse_dict_members`
+#[allow(clippy::type_complexity)]
 fn parse_object(
     tokens: &mut Tokens,
     notes: &mut ParseNotes,
-) -> Result<Vec<(ast::Expression, ast::Expression)>, SyntaxError> {
+) -> Result<(Vec<(ast::Expression, ast::Expression)>, Vec<ast::Comments>), SyntaxError> {
     let mut defs = Vec::<(ast::Expression, ast::Expression)>::new();
+    let mut comments = Vec::new();
 
     match tokens.next(false) {
         Some(Token::OpenCurlyBracket) => (),
         a => expected!("'{'".to_string(), tokens, notes, a),
     }
+    let mut anchor = tokens.position().1;
 
     loop {
-        if tokens.next(false) == Some(Token::ClosingCurlyBracket) {
+        let first = tokens.next(false);
+        let start = tokens.position().0;
+        if first == Some(Token::ClosingCurlyBracket) {
             break;
         } else {
             tokens.previous();
@@ -1549,6 +1759,11 @@ fn parse_object(
         let val = parse_expr(tokens, notes, true, true, None)?;
 
         defs.push((key, val));
+        comments.push(ast::Comments {
+            before: tokens.blank_line_between(anchor, start, defs.len() == 1),
+            ..Default::default()
+        });
+        anchor = tokens.position().1;
 
         let next = tokens.next(false);
 
@@ -1565,7 +1780,7 @@ fn parse_object(
             });
         }
     }
-    Ok(defs)
+    Ok((defs, comments))
 }
 
 fn parse_args(
@@ -2118,7 +2333,8 @@ fn try_parse_macro(
                 tokens.previous();
                 None
             };
-            let body = match tokens.next(false) {
+            let shorthand = tokens.next(false) == Some(Token::ThickArrow);
+            let body = match tokens.current() {
                 Some(Token::OpenCurlyBracket) => parse_cmp_stmt(tokens, notes)?,
                 Some(Token::ThickArrow) => {
                     let start = tokens.position().0;
@@ -2127,8 +2343,8 @@ fn try_parse_macro(
                     vec![ast::Statement {
                         body: ast::StatementBody::Return(Some(expr)),
                         arrow: false,
-                        //comment: (None, None),
                         pos: (start, end),
+                        comments: ast::Comments::default(),
                     }]
                 }
                 a => expected!("'{'".to_string(), tokens, notes, a),
@@ -2140,6 +2356,7 @@ fn try_parse_macro(
                 properties: properties.clone(),
                 arg_pos: (arg_start, arg_end),
                 ret_type,
+                shorthand,
             });
 
             if let Some(d) = decorator {
@@ -2483,8 +2700,8 @@ fn parse_variable(
                     let macro_body = vec![ast::Statement {
                         body: ast::StatementBody::Return(Some(expr)),
                         arrow: false,
-                        //comment: (None, None),
                         pos: (start.0, end),
+                        comments: ast::Comments::default(),
                     }];
 
                     ast::ValueBody::Macro(ast::Macro {
@@ -2495,6 +2712,7 @@ fn parse_variable(
                         arg_pos: start,
                         properties: properties.clone(),
                         ret_type: None,
+                        shorthand: true,
                     })
                 }
                 _ => {
@@ -2510,6 +2728,7 @@ fn parse_variable(
             let mut potential_macro: Option<ast::ValueBody> = None;
 
             if let Some(Token::OpenSquareBracket) = tokens.next(false) {
+                let decorator_start = tokens.position().0;
                 let mut test_tokens = tokens.clone();
                 if let Ok(mut v) = parse_variable(&mut test_tokens, notes, false, None) {
                     if let Some(Token::ClosingSquareBracket) = test_tokens.next(false) {
@@ -2518,6 +2737,7 @@ fn parse_variable(
                                 Some(Token::OpenBracket) => {
                                     // its a decorator on a macro
                                     *tokens = test_tokens;
+                                    tokens.mark_opaque(decorator_start);
                                     potential_macro = Some(try_parse_macro(
                                         tokens,
                                         notes,
@@ -2536,6 +2756,7 @@ fn parse_variable(
                                     }
 
                                     *tokens = test_tokens;
+                                    tokens.mark_opaque(decorator_start);
 
                                     let trig = ast::ValueBody::CmpStmt(ast::CompoundStatement {
                                         statements: parse_cmp_stmt(tokens, notes)?,
@@ -2768,7 +2989,21 @@ fn parse_variable(
                     ast::ValueBody::Import(ImportType::Script(PathBuf::from(content)), forced)
                 }
                 Some(Token::Symbol) => {
-                    ast::ValueBody::Import(ImportType::Lib(tokens.slice()), forced)
+                    let mut name = tokens.slice();
+                    // `import mylib@1`, `import mylib@latest` or `import mylib@"^1.2"`
+                    if tokens.next(true) == Some(Token::At) {
+                        let version = match tokens.next(false) {
+                            Some(Token::StringLiteral) => {
+                                str_content(tokens.slice(), tokens, notes)?.0
+                            }
+                            Some(Token::Symbol) | Some(Token::Number) => tokens.slice(),
+                            a => expected!("library version".to_string(), tokens, notes, a),
+                        };
+                        name = format!("{}@{}", name, version);
+                    } else {
+                        tokens.previous();
+                    }
+                    ast::ValueBody::Import(ImportType::Lib(name), forced)
                 }
                 a => expected!("literal string".to_string(), tokens, notes, a),
             }
@@ -2805,7 +3040,11 @@ fn parse_variable(
                 }
             }
         }
-        Some(Token::OpenCurlyBracket) => ast::ValueBody::Dictionary(parse_dict(tokens, notes)?),
+        Some(Token::OpenCurlyBracket) => {
+            let mut comments = Vec::new();
+            let members = parse_dict_members(tokens, notes, &mut comments, false)?;
+            ast::ValueBody::Dictionary(members, comments)
+        }
         Some(Token::Exclamation) => {
             // never assume what the next token could be, might lead to issues like !!a} being parsable
             let check = tokens.next(false);
@@ -2818,15 +3057,23 @@ fn parse_variable(
             }
         }
 
-        Some(Token::Object) => ast::ValueBody::Obj(ast::ObjectLiteral {
-            props: parse_object(tokens, notes)?,
-            mode: ast::ObjectMode::Object,
-        }),
+        Some(Token::Object) => {
+            let (props, comments) = parse_object(tokens, notes)?;
+            ast::ValueBody::Obj(ast::ObjectLiteral {
+                props,
+                mode: ast::ObjectMode::Object,
+                comments,
+            })
+        }
 
-        Some(Token::Trigger) => ast::ValueBody::Obj(ast::ObjectLiteral {
-            props: parse_object(tokens, notes)?,
-            mode: ast::ObjectMode::Trigger,
-        }),
+        Some(Token::Trigger) => {
+            let (props, comments) = parse_object(tokens, notes)?;
+            ast::ValueBody::Obj(ast::ObjectLiteral {
+                props,
+                mode: ast::ObjectMode::Trigger,
+                comments,
+            })
+        }
 
         a => expected!("a value".to_string(), tokens, notes, a),
     };
@@ -2921,7 +3168,8 @@ fn parse_variable(
                     path.push(ast::Path::Associated(LocalIntern::new(tokens.slice())))
                 }
                 Some(Token::OpenCurlyBracket) => {
-                    path.push(ast::Path::Constructor(parse_dict(tokens, notes)?))
+                    let members = parse_dict_members(tokens, notes, &mut Vec::new(), false)?;
+                    path.push(ast::Path::Constructor(members))
                 }
                 a => {
                     return Err(SyntaxError::ExpectedErr {
@@ -0,0 +1,115 @@
+// the per-user cache of package repositories and the versions checked out from them,
+// shared by every project so a package is only cloned once
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use git2::build::CheckoutBuilder;
+use git2::{Direction, Oid, Repository};
+use sha2::{Digest, Sha256};
+
+/// Environment variable that overrides the cache directory
+pub const CACHE_ENV: &str = "PCKP_CACHE";
+
+/// The cache directory: `PCKP_CACHE` if it is set, otherwise the user's cache folder
+pub fn cache_dir() -> PathBuf {
+    shared::cache_dir(CACHE_ENV, "pckp")
+}
+
+// `mylib-1a2b3c4d5e6f7a8b` for `https://github.com/someone/mylib`
+fn repo_key(source: &str) -> String {
+    let name = source
+        .trim_end_matches('/')
+        .rsplit(['/', '\\', ':'])
+        .next()
+        .unwrap_or_default();
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    let hash = Sha256::digest(source.as_bytes())
+        .iter()
+        .take(8)
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    format!("{}-{}", name, hash)
+}
+
+/// Opens the cached clone of a repository, after fetching its new commits and tags.
+/// When it can't be fetched (like when offline), the cached clone is used as it is
+pub fn open_repo(source: &str) -> Result<Repository, String> {
+    let path = cache_dir().join("repos").join(repo_key(source));
+    let (repo, cloned) = match Repository::open_bare(&path) {
+        Ok(repo) => (repo, true),
+        Err(_) => {
+            if path.exists() {
+                fs::remove_dir_all(&path).map_err(|e| dir_error(&path, e))?;
+            }
+            fs::create_dir_all(&path).map_err(|e| dir_error(&path, e))?;
+            let repo = Repository::init_bare(&path).map_err(|e| e.message().to_string())?;
+            (repo, false)
+        }
+    };
+
+    match fetch(&repo, source) {
+        Ok(()) => Ok(repo),
+        Err(e) if cloned => {
+            println!(
+                "Unable to fetch {} ({}), using the cached version",
+                source,
+                e.message()
+            );
+            Ok(repo)
+        }
+        Err(e) => {
+            drop(repo);
+            fs::remove_dir_all(&path).ok();
+            Err(e.message().to_string())
+        }
+    }
+}
+
+fn dir_error(path: &Path, e: std::io::Error) -> String {
+    format!("could not write to {}: {}", path.display(), e)
+}
+
+// the branches and tags are mirrored, and HEAD is the default branch of the remote
+fn fetch(repo: &Repository, source: &str) -> Result<(), git2::Error> {
+    let mut remote = repo.remote_anonymous(source)?;
+    remote.connect(Direction::Fetch)?;
+    let default_branch = remote.default_branch()?;
+    remote.fetch(
+        &["+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"],
+        None,
+        None,
+    )?;
+    repo.set_head(default_branch.as_str().unwrap_or("refs/heads/master"))
+}
+
+/// The files of a repository at a commit, which are only checked out the first time
+pub fn checkout_version(repo: &Repository, source: &str, commit: Oid) -> Result<PathBuf, String> {
+    let dir = cache_dir()
+        .join("versions")
+        .join(repo_key(source))
+        .join(commit.to_string());
+    if !dir.exists() {
+        // an interrupted checkout is never used
+        let tmp = dir.with_extension("tmp");
+        if tmp.exists() {
+            fs::remove_dir_all(&tmp).map_err(|e| dir_error(&tmp, e))?;
+        }
+        fs::create_dir_all(&tmp).map_err(|e| dir_error(&tmp, e))?;
+        let tree = repo
+            .find_commit(commit)
+            .and_then(|c| c.tree())
+            .map_err(|e| e.message().to_string())?;
+        repo.checkout_tree(
+            tree.as_object(),
+            Some(
+                CheckoutBuilder::new()
+                    .force()
+                    .update_index(false)
+                    .target_dir(&tmp),
+            ),
+        )
+        .map_err(|e| e.message().to_string())?;
+        fs::rename(&tmp, &dir).map_err(|e| dir_error(&dir, e))?;
+    }
+    Ok(dir)
+}
@@ -8,6 +8,8 @@ use yaml_rust::{YamlLoader, Yaml};
 use path_absolutize::Absolutize;
 
 use crate::package::{Package, Dependency, DependencySource};
+use crate::download::Registry;
+use crate::resolve::Requirement;
 use crate::error::PckpError;
 
 pub const CONFIG_NAME: &str = "pckp.yaml";
@@ -117,6 +119,36 @@ fn check_invalid(n: &str) -> Option<char> {
 
     potential_invalid.first().copied()
 }
+// `name`, `url` or `path` (a folder relative to the config file), and an optional `version`
+fn hash_to_dependency(h: yaml_rust::yaml::Hash, cfg: &Path) -> Result<Dependency, PckpError> {
+    let dmap = YamlMap::from_hash("dependencies", &Yaml::Hash(h), cfg)?;
+
+    let source = match ensure_variant!(dmap, "string" = String, "path"? from "dependencies")? {
+        Some(p) => {
+            let dir = cfg.parent().unwrap().join(p);
+            match fs::canonicalize(&dir) {
+                Ok(dir) if dir.is_dir() => DependencySource::Path(dir),
+                _ => return Err(PckpError::config(
+                    format!("Cannot find the folder '{}' of path dependency", p),
+                    cfg.to_path_buf(),
+                    None
+                ))
+            }
+        },
+        None => ensure_variant!(
+            dmap, 
+            "string" = String, 
+            "name" or "url" from "dependencies",
+            enum DependencySource::Name, DependencySource::Url
+        )?
+    };
+
+    Ok(Dependency {
+        source,
+        version: Requirement::parse(&yaml_to_str(&dmap.get_or_else("version", |_| Ok(Yaml::String("latest".to_string())))?))
+    })
+}
+
 // not on wasm
 #[cfg(not(target_arch = "wasm32"))]
 pub fn config_to_package(cfg: PathBuf) -> Result<Option<Package>, PckpError> {
@@ -212,24 +244,10 @@ pub fn config_to_package(cfg: PathBuf) -> Result<Option<Package>, PckpError> {
                                         Yaml::String(s) => {
                                             Ok(Dependency {
                                                 source: DependencySource::Name(s),
-                                                version: "latest".to_string()
-                                            })
-                                        },
-                                        Yaml::Hash(h) => {
-                                            let dmap = YamlMap::from_hash("dependencies", &Yaml::Hash(h), &cfg)?;
-
-                                            Ok(Dependency {
-
-                                                source: ensure_variant!(
-                                                    dmap, 
-                                                    "string" = String, 
-                                                    "name" or "url" from "dependencies",
-                                                    enum DependencySource::Name, DependencySource::Url
-                                                )?,
-
-                                                version: yaml_to_str(&dmap.get_or_else("version", |_| Ok(Yaml::String("latest".to_string())))?)
+                                                version: Requirement::Latest
                                             })
                                         },
+                                        Yaml::Hash(h) => hash_to_dependency(h, &cfg),
                                         c => Err(
                                             PckpError::config(
                                                 format!("{:?} cannot be parsed as a dependency", c),
@@ -243,24 +261,10 @@ pub fn config_to_package(cfg: PathBuf) -> Result<Option<Package>, PckpError> {
                             Yaml::String(s) => {
                                 vec![Dependency {
                                     source: DependencySource::Name(s),
-                                    version: "latest".to_string()
-                                }]
-                            },
-                            Yaml::Hash(h) => {
-                                let dmap = YamlMap::from_hash("dependencies", &Yaml::Hash(h), &cfg)?;
-
-                                vec![Dependency {
-
-                                    source: ensure_variant!(
-                                        dmap, 
-                                        "string" = String, 
-                                        "name" or "url" from "dependencies",
-                                        enum DependencySource::Name, DependencySource::Url
-                                    )?,
-
-                                    version: yaml_to_str(&dmap.get_or_else("version", |_| Ok(Yaml::String("latest".to_string())))?)
+                                    version: Requirement::Latest
                                 }]
                             },
+                            Yaml::Hash(h) => vec![hash_to_dependency(h, &cfg)?],
                             c => return Err(
                                 PckpError::config(
                                     format!("{:?} cannot be parsed as a dependency", c),
@@ -275,7 +279,10 @@ pub fn config_to_package(cfg: PathBuf) -> Result<Option<Package>, PckpError> {
                  .map(Package::dependency)
                  .collect::<Vec<_>>();
 
-                Ok(Some(Package::local(package_name, version, folders, depends)))
+                let registry = ensure_variant!(ymap, "string" = String, "registry"? from "root")?
+                    .map(|r| Registry::parse(r, cfg.parent().unwrap()));
+
+                Ok(Some(Package::local(package_name, version, folders, depends, registry)))
             },
             Err(_) => {
                 Err(PckpError::config("Could not open configuration file".to_string(), cfg, None))
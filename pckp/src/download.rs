@@ -1,5 +1,8 @@
 use crate::package::DependencySource;
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use crate::error::PckpError;
 use lazy_static::lazy_static;
@@ -8,28 +11,136 @@ use reqwest;
 //TODO: replace with real repo
 pub const MAIN_REPO: &str = "https://raw.githubusercontent.com/camila314/ttest/master/index.txt";
 
+/// Environment variable that overrides the registry of every package
+pub const REGISTRY_ENV: &str = "PCKP_REGISTRY";
+/// Name of the index file in a registry that is a directory
+pub const INDEX_FILE: &str = "index.txt";
+
+/// Where the registry index is read from. The index has a `name|git url` line
+/// for every package that can be depended on by name only
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Registry {
+    Url(String),
+    /// an index file, or a directory with an `index.txt` file
+    Path(PathBuf),
+}
+
+impl Registry {
+    /// Parses a registry location: an http(s) URL, a `file://` URL, or a path
+    /// (relative to `base`, the directory of the config file)
+    pub fn parse(location: &str, base: &Path) -> Registry {
+        if let Some(path) = location.strip_prefix("file://") {
+            Registry::Path(base.join(path))
+        } else if location.contains("://") {
+            Registry::Url(location.to_string())
+        } else {
+            Registry::Path(base.join(location))
+        }
+    }
+
+    /// The registry in `PCKP_REGISTRY` if it is set, otherwise the one in the config file,
+    /// otherwise the main registry
+    pub fn resolve(config: Option<&Registry>) -> Registry {
+        match std::env::var(REGISTRY_ENV) {
+            Ok(env) if !env.is_empty() => {
+                Registry::parse(&env, &std::env::current_dir().unwrap_or_default())
+            }
+            _ => config
+                .cloned()
+                .unwrap_or_else(|| Registry::Url(MAIN_REPO.to_string())),
+        }
+    }
+
+    fn load(&self) -> Result<HashMap<String, String>, String> {
+        match self {
+            Registry::Url(url) => {
+                let text = reqwest::blocking::get(url)
+                    .and_then(|r| r.error_for_status())
+                    .and_then(|r| r.text())
+                    .map_err(|e| e.to_string())?;
+                parse_index(&text, None)
+            }
+            Registry::Path(path) => {
+                let file = if path.is_dir() {
+                    path.join(INDEX_FILE)
+                } else {
+                    path.clone()
+                };
+                let text = fs::read_to_string(&file)
+                    .map_err(|e| format!("could not read {}: {}", file.display(), e))?;
+                parse_index(&text, file.parent())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Registry::Url(url) => write!(f, "{}", url),
+            Registry::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+// `name|url` lines, ignoring blank lines and `#` comments.
+// in local indexes, urls that are relative paths are relative to the index file
+fn parse_index(text: &str, base: Option<&Path>) -> Result<HashMap<String, String>, String> {
+    let mut index = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, url) = match line.split_once('|') {
+            Some((name, url)) if !name.trim().is_empty() && !url.trim().is_empty() => {
+                (name.trim(), url.trim())
+            }
+            _ => return Err(format!("line {} should be `name|url`: {}", i + 1, line)),
+        };
+        let url = match base {
+            Some(base) if !url.contains("://") && Path::new(url).is_relative() => {
+                base.join(url).to_string_lossy().to_string()
+            }
+            _ => url.to_string(),
+        };
+        index.insert(name.to_string(), url);
+    }
+    Ok(index)
+}
+
 lazy_static! {
-    static ref REPO_CACHE: HashMap<String, String> = {
-        reqwest::blocking::get(MAIN_REPO)
-            .unwrap()
-            .text()
-            .unwrap()
-            .replace(' ', "")
-            .split('\n')
-            .map(|x| x.split('|'))
-            .map(|mut x| (x.next().unwrap().to_string(), x.next().unwrap().to_string()))
-            .collect::<HashMap<_, _>>()
-    };
+    static ref REPO_CACHE: Mutex<HashMap<Registry, HashMap<String, String>>> =
+        Mutex::new(HashMap::new());
 }
 
-fn find_in_repo(name: &str) -> Option<&String> {
-    REPO_CACHE.get(name)
+fn find_in_repo(
+    registry: &Registry,
+    name: &str,
+    parent_name: &str,
+) -> Result<Option<String>, PckpError> {
+    let mut cache = REPO_CACHE.lock().unwrap();
+    if !cache.contains_key(registry) {
+        let index = registry.load().map_err(|e| {
+            PckpError::custom_with_note(
+                format!("Unable to read the pckp registry at {} ({})", registry, e),
+                Some(parent_name.to_string()),
+                Some(format!(
+                    "The registry can be set with `registry` in pckp.yaml, or with the {} environment variable",
+                    REGISTRY_ENV
+                )),
+            )
+        })?;
+        cache.insert(registry.clone(), index);
+    }
+    Ok(cache[registry].get(name).cloned())
 }
 
 impl DependencySource {
-    pub fn to_string(&self, parent_name: String) -> Result<String, PckpError> {
+    pub fn to_string(&self, parent_name: String, registry: &Registry) -> Result<String, PckpError> {
         match self {
             DependencySource::Url(a) => Ok(a.to_string()),
+            DependencySource::Path(p) => Ok(p.display().to_string()),
             DependencySource::Name(b) => match b.split('/').count().cmp(&2) {
                 std::cmp::Ordering::Greater => {
                     return Err(PckpError::custom(
@@ -38,10 +149,13 @@ impl DependencySource {
                     ));
                 }
                 std::cmp::Ordering::Equal => Ok("https://github.com/".to_string() + b),
-                _ => match find_in_repo(b) {
-                    Some(x) => Ok(x.to_string()),
+                _ => match find_in_repo(registry, b, &parent_name)? {
+                    Some(x) => Ok(x),
                     None => Err(PckpError::custom(
-                        format!("Unable to locate dependency '{}' in pckp repo", b),
+                        format!(
+                            "Unable to locate dependency '{}' in pckp registry {}",
+                            b, registry
+                        ),
                         Some(parent_name),
                     )),
                 },
@@ -49,3 +163,94 @@ impl DependencySource {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_registries() {
+        let base = Path::new("/project");
+        assert_eq!(
+            Registry::parse("https://example.com/index.txt", base),
+            Registry::Url(String::from("https://example.com/index.txt"))
+        );
+        assert_eq!(
+            Registry::parse("file:///srv/registry", base),
+            Registry::Path(PathBuf::from("/srv/registry"))
+        );
+        assert_eq!(
+            Registry::parse("file://registry/index.txt", base),
+            Registry::Path(PathBuf::from("/project/registry/index.txt"))
+        );
+        assert_eq!(
+            Registry::parse("../registry", base),
+            Registry::Path(PathBuf::from("/project/../registry"))
+        );
+        assert_eq!(
+            Registry::parse("/srv/index.txt", base),
+            Registry::Path(PathBuf::from("/srv/index.txt"))
+        );
+    }
+
+    #[test]
+    fn parse_indexes() {
+        let text = "
+# packages
+  counters | https://github.com/someone/counters  
+
+local|../libs/local
+absolute|/srv/absolute
+";
+        let index = parse_index(text, Some(Path::new("/registry"))).unwrap();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index["counters"], "https://github.com/someone/counters");
+        assert_eq!(index["local"], "/registry/../libs/local");
+        assert_eq!(index["absolute"], "/srv/absolute");
+
+        // indexes downloaded from a url don't have a directory to be relative to
+        let index = parse_index("local|../libs/local", None).unwrap();
+        assert_eq!(index["local"], "../libs/local");
+    }
+
+    #[test]
+    fn malformed_indexes() {
+        for (text, line) in [
+            ("a|https://a\nno separator", "line 2"),
+            ("|https://a", "line 1"),
+            ("a|  ", "line 1"),
+        ] {
+            let err = parse_index(text, None).unwrap_err();
+            assert!(err.starts_with(line), "{}", err);
+        }
+    }
+
+    #[test]
+    fn local_registries() {
+        let dir = std::env::temp_dir().join(format!("pckp-registry-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(INDEX_FILE), "pkg|pkg-repo\n").unwrap();
+        fs::write(dir.join("other.txt"), "other|https://a\n").unwrap();
+
+        // a directory is read through its index.txt
+        let index = Registry::Path(dir.clone()).load().unwrap();
+        assert_eq!(index["pkg"], dir.join("pkg-repo").to_string_lossy());
+        let index = Registry::Path(dir.join("other.txt")).load().unwrap();
+        assert_eq!(index["other"], "https://a");
+
+        let missing = Registry::Path(dir.join("missing.txt")).load().unwrap_err();
+        assert!(missing.starts_with("could not read"));
+
+        // dependencies given by name are looked up in the registry
+        let registry = Registry::Path(dir.clone());
+        let url = DependencySource::Name(String::from("pkg"))
+            .to_string(String::from("parent"), &registry)
+            .unwrap();
+        assert_eq!(url, dir.join("pkg-repo").to_string_lossy());
+        assert!(DependencySource::Name(String::from("unknown"))
+            .to_string(String::from("parent"), &registry)
+            .is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
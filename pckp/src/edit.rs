@@ -0,0 +1,532 @@
+// edits the dependencies in pckp.yaml as text, so the rest of the file keeps its formatting and comments
+use std::fs;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::error::PckpError;
+use crate::package::DependencySource;
+use crate::resolve::Requirement;
+
+const DEPENDENCIES_KEY: &str = "dependencies:";
+
+/// Parses a dependency given on the command line: a name (or `user/repo` on GitHub), a git URL
+/// or `path:` and a folder, optionally followed by `@` and a version requirement, like `mylib@^1.2`
+pub fn parse_dependency(dependency: &str) -> (DependencySource, Requirement) {
+    // `git@github.com:user/repo` doesn't have a version
+    let (source, version) = match dependency.rsplit_once('@') {
+        Some((source, version))
+            if !source.is_empty() && !version.is_empty() && !version.contains(['/', ':']) =>
+        {
+            (source, Requirement::parse(version))
+        }
+        _ => (dependency, Requirement::Latest),
+    };
+    let source = if let Some(path) = source.strip_prefix("path:") {
+        DependencySource::Path(PathBuf::from(path))
+    } else if source.contains("://")
+        || source.starts_with("git@")
+        || source.starts_with('.')
+        || Path::new(source).is_absolute()
+    {
+        DependencySource::Url(source.to_string())
+    } else {
+        DependencySource::Name(source.to_string())
+    };
+    (source, version)
+}
+
+/// Adds a dependency to a config file, or changes its version if it is already there.
+/// The folder of a `path:` dependency is relative to the current folder, and is written relative to the config file
+pub fn add_dependency(
+    cfg: &Path,
+    source: &DependencySource,
+    version: &Requirement,
+) -> Result<(), PckpError> {
+    let source = &match source {
+        DependencySource::Path(path) => {
+            let dir = cfg.parent().unwrap();
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            match (fs::canonicalize(path), fs::canonicalize(dir)) {
+                (Ok(path), Ok(dir)) if path.is_dir() => {
+                    DependencySource::Path(relative_path(&path, &dir))
+                }
+                _ => {
+                    return Err(PckpError::custom(
+                        format!("Cannot find the folder '{}'", path.display()),
+                        None,
+                    ))
+                }
+            }
+        }
+        source => source.clone(),
+    };
+    let mut list = DependencyList::read(cfg)?;
+    let key = list.key();
+    let new = render_item(&list.indent, source, version);
+
+    match list
+        .items
+        .iter()
+        .find(|i| i.source.as_ref() == Some(source))
+    {
+        Some(item) => {
+            let lines = item.lines.clone();
+            list.lines.splice(lines, new);
+        }
+        None => {
+            let end = list.end.max(key + 1);
+            list.lines.splice(end..end, new);
+        }
+    }
+    list.write(cfg)
+}
+
+/// Removes the dependency with this name or URL from a config file
+pub fn remove_dependency(cfg: &Path, name: &str) -> Result<(), PckpError> {
+    let mut list = DependencyList::read(cfg)?;
+    let item = match list
+        .items
+        .iter()
+        .position(|i| i.source.as_ref().is_some_and(|s| matches_name(s, name)))
+    {
+        Some(i) => list.items.remove(i),
+        None => {
+            return Err(PckpError::config(
+                format!("'{}' is not a dependency", name),
+                cfg.to_path_buf(),
+                None,
+            ))
+        }
+    };
+    list.lines.drain(item.lines);
+    // an empty `dependencies:` key isn't a valid config
+    if list.items.is_empty() {
+        if let Some(key) = list.key {
+            list.lines.remove(key);
+        }
+    }
+    list.write(cfg)
+}
+
+// a dependency given by URL can also be removed by the name of its repository
+fn matches_name(source: &DependencySource, name: &str) -> bool {
+    match source {
+        DependencySource::Name(n) => n == name,
+        DependencySource::Url(url) => {
+            let repo = url.trim_end_matches('/').rsplit('/').next().unwrap_or(url);
+            url == name || repo.strip_suffix(".git").unwrap_or(repo) == name
+        }
+        DependencySource::Path(path) => {
+            path == Path::new(name) || path.file_name().is_some_and(|f| f == name)
+        }
+    }
+}
+
+// `../mylib` for `/projects/mylib` in `/projects/mylevel`
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path = path.components().collect::<Vec<_>>();
+    let base = base.components().collect::<Vec<_>>();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    // different drives on windows
+    if common == 0 {
+        return path.iter().collect();
+    }
+    let mut out = base[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .chain(path[common..].iter().copied())
+        .collect::<PathBuf>();
+    if out.as_os_str().is_empty() {
+        out.push(".");
+    }
+    out
+}
+
+struct Item {
+    lines: Range<usize>,
+    // `None` if it couldn't be read
+    source: Option<DependencySource>,
+}
+
+struct DependencyList {
+    lines: Vec<String>,
+    trailing_newline: bool,
+    // line of the `dependencies:` key
+    key: Option<usize>,
+    // indentation of the `-` of every item
+    indent: String,
+    items: Vec<Item>,
+    // line after the last item
+    end: usize,
+}
+
+impl DependencyList {
+    fn read(cfg: &Path) -> Result<DependencyList, PckpError> {
+        let text = fs::read_to_string(cfg).map_err(|_| {
+            PckpError::config(
+                "Could not open configuration file".to_string(),
+                cfg.to_path_buf(),
+                None,
+            )
+        })?;
+        let mut list = DependencyList {
+            lines: text.lines().map(String::from).collect(),
+            trailing_newline: text.ends_with('\n'),
+            key: None,
+            indent: String::from("  "),
+            items: Vec::new(),
+            end: 0,
+        };
+        // only top level keys have no indentation
+        let key = match list
+            .lines
+            .iter()
+            .position(|l| l.starts_with(DEPENDENCIES_KEY))
+        {
+            Some(key) => key,
+            None => return Ok(list),
+        };
+        list.key = Some(key);
+        list.end = key + 1;
+
+        // `dependencies: mylib` and `dependencies: []` are turned into a list
+        let value = list.lines[key][DEPENDENCIES_KEY.len()..]
+            .split(" #")
+            .next()
+            .unwrap()
+            .trim()
+            .to_string();
+        if !value.is_empty() {
+            if value.starts_with(|c| "[{\"'&*!|>".contains(c)) && value != "[]" {
+                return Err(PckpError::config(
+                    "Dependencies can only be edited when they are written as a list with a `-` for every dependency".to_string(),
+                    cfg.to_path_buf(),
+                    None,
+                ));
+            }
+            list.lines[key] = DEPENDENCIES_KEY.to_string();
+            if value != "[]" && value != "~" && value != "null" {
+                list.lines.insert(key + 1, format!("  - {}", value));
+            }
+        }
+
+        let mut starts = Vec::new();
+        for (i, line) in list.lines.iter().enumerate().skip(key + 1) {
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let indented = line.len() != trimmed.len();
+            if !indented && !trimmed.starts_with('-') {
+                break;
+            }
+            let indent = &line[..line.len() - trimmed.len()];
+            if starts.is_empty() {
+                list.indent = indent.to_string();
+            }
+            if trimmed.starts_with('-') && indent == list.indent {
+                starts.push(i);
+            }
+            list.end = i + 1;
+        }
+
+        for (n, start) in starts.iter().enumerate() {
+            // blank lines and comments before the next item aren't part of this one
+            let mut end = starts.get(n + 1).copied().unwrap_or(list.end);
+            while end > start + 1 && {
+                let line = list.lines[end - 1].trim();
+                line.is_empty() || line.starts_with('#')
+            } {
+                end -= 1;
+            }
+            let lines = *start..end;
+            let text = list.lines[lines.clone()]
+                .iter()
+                .map(|l| l.strip_prefix(list.indent.as_str()).unwrap_or(l))
+                .collect::<Vec<_>>()
+                .join("\n");
+            list.items.push(Item {
+                source: read_source(&text),
+                lines,
+            });
+        }
+        Ok(list)
+    }
+
+    // the line of the `dependencies:` key, which is added if there isn't one
+    fn key(&mut self) -> usize {
+        match self.key {
+            Some(key) => key,
+            None => {
+                while self.lines.last().is_some_and(|l| l.trim().is_empty()) {
+                    self.lines.pop();
+                }
+                self.lines.push(DEPENDENCIES_KEY.to_string());
+                self.trailing_newline = true;
+                self.key = Some(self.lines.len() - 1);
+                self.end = self.lines.len();
+                self.lines.len() - 1
+            }
+        }
+    }
+
+    fn write(&self, cfg: &Path) -> Result<(), PckpError> {
+        let mut text = self.lines.join("\n");
+        if self.trailing_newline {
+            text.push('\n');
+        }
+        fs::write(cfg, text).map_err(|e| {
+            PckpError::config(
+                format!("Could not write configuration file: {}", e),
+                cfg.to_path_buf(),
+                None,
+            )
+        })
+    }
+}
+
+fn read_source(item: &str) -> Option<DependencySource> {
+    let yaml = YamlLoader::load_from_str(item).ok()?;
+    match &yaml.first()?[0] {
+        Yaml::String(name) => Some(DependencySource::Name(name.clone())),
+        Yaml::Hash(_) => {
+            let dep = &yaml[0][0];
+            match (
+                dep["name"].as_str(),
+                dep["url"].as_str(),
+                dep["path"].as_str(),
+            ) {
+                (_, _, Some(path)) => Some(DependencySource::Path(PathBuf::from(path))),
+                (Some(name), _, _) => Some(DependencySource::Name(name.to_string())),
+                (_, Some(url), _) => Some(DependencySource::Url(url.to_string())),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn render_item(indent: &str, source: &DependencySource, version: &Requirement) -> Vec<String> {
+    let (key, value) = match source {
+        DependencySource::Name(name) => ("name", name.clone()),
+        DependencySource::Url(url) => ("url", url.clone()),
+        // `/` works on every platform
+        DependencySource::Path(path) => ("path", path.to_string_lossy().replace('\\', "/")),
+    };
+    match (source, version) {
+        (DependencySource::Name(name), Requirement::Latest) => {
+            vec![format!("{}- {}", indent, scalar(name))]
+        }
+        (_, Requirement::Latest) => vec![format!("{}- {}: {}", indent, key, scalar(&value))],
+        _ => vec![
+            format!("{}- {}: {}", indent, key, scalar(&value)),
+            format!("{}  version: {}", indent, scalar(&version.to_string())),
+        ],
+    }
+}
+
+// quotes strings that can't be written as plain YAML, like `>=1, <2`
+fn scalar(s: &str) -> String {
+    let plain = s.starts_with(|c: char| c.is_alphanumeric() || "^~=./".contains(c))
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || "._/^~=-:@+".contains(c))
+        && !s.ends_with(':');
+    if plain {
+        s.to_string()
+    } else {
+        format!("{:?}", s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a project folder with a pckp.yaml, removed when dropped
+    struct Project(PathBuf);
+
+    impl Project {
+        fn new(name: &str, config: &str) -> Project {
+            let dir =
+                std::env::temp_dir().join(format!("pckp-edit-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("pckp.yaml"), config).unwrap();
+            Project(dir)
+        }
+
+        fn cfg(&self) -> PathBuf {
+            self.0.join("pckp.yaml")
+        }
+
+        fn add(&self, dependency: &str) -> Result<String, String> {
+            let (source, version) = parse_dependency(dependency);
+            add_dependency(&self.cfg(), &source, &version).map_err(|e| e.to_string())?;
+            Ok(fs::read_to_string(self.cfg()).unwrap())
+        }
+
+        fn remove(&self, name: &str) -> Result<String, String> {
+            remove_dependency(&self.cfg(), name).map_err(|e| e.to_string())?;
+            Ok(fs::read_to_string(self.cfg()).unwrap())
+        }
+    }
+
+    impl Drop for Project {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn parse_dependencies() {
+        let name = |n: &str| DependencySource::Name(n.to_string());
+        let url = |u: &str| DependencySource::Url(u.to_string());
+        let range = |r: &str| Requirement::Range(semver::VersionReq::parse(r).unwrap());
+
+        assert_eq!(
+            parse_dependency("mylib"),
+            (name("mylib"), Requirement::Latest)
+        );
+        assert_eq!(
+            parse_dependency("mylib@^1.2"),
+            (name("mylib"), range("^1.2"))
+        );
+        assert_eq!(
+            parse_dependency("someone/mylib@nightly"),
+            (
+                name("someone/mylib"),
+                Requirement::Tag("nightly".to_string())
+            )
+        );
+        assert_eq!(
+            parse_dependency("https://github.com/someone/mylib@>=1, <2"),
+            (url("https://github.com/someone/mylib"), range(">=1, <2"))
+        );
+        // the `@` of an ssh URL isn't a version
+        assert_eq!(
+            parse_dependency("git@github.com:someone/mylib"),
+            (url("git@github.com:someone/mylib"), Requirement::Latest)
+        );
+        assert_eq!(
+            parse_dependency("git@github.com:someone/mylib@1.0.0"),
+            (url("git@github.com:someone/mylib"), range("=1.0.0"))
+        );
+        assert_eq!(
+            parse_dependency("path:../mylib@^0.2"),
+            (
+                DependencySource::Path(PathBuf::from("../mylib")),
+                range("^0.2")
+            )
+        );
+        assert_eq!(
+            parse_dependency("./mylib"),
+            (url("./mylib"), Requirement::Latest)
+        );
+        assert_eq!(
+            parse_dependency("mylib@"),
+            (name("mylib@"), Requirement::Latest)
+        );
+    }
+
+    #[test]
+    fn add_dependencies() {
+        let p = Project::new(
+            "add",
+            "name: level\n# the libraries\ndependencies:\n    - counters # trailing\n\n    - url: https://github.com/someone/other\n      version: ^1\nversion: 1.0.0\n",
+        );
+        assert_eq!(
+            p.add("mylib@^1.2").unwrap(),
+            "name: level\n# the libraries\ndependencies:\n    - counters # trailing\n\n    - url: https://github.com/someone/other\n      version: ^1\n    - name: mylib\n      version: ^1.2\nversion: 1.0.0\n"
+        );
+        // a dependency that is already there gets the new version
+        assert_eq!(
+            p.add("https://github.com/someone/other@>=1, <3").unwrap(),
+            "name: level\n# the libraries\ndependencies:\n    - counters # trailing\n\n    - url: https://github.com/someone/other\n      version: \">=1, <3\"\n    - name: mylib\n      version: ^1.2\nversion: 1.0.0\n"
+        );
+        assert_eq!(
+            p.add("counters@1.0.0").unwrap(),
+            "name: level\n# the libraries\ndependencies:\n    - name: counters\n      version: =1.0.0\n\n    - url: https://github.com/someone/other\n      version: \">=1, <3\"\n    - name: mylib\n      version: ^1.2\nversion: 1.0.0\n"
+        );
+    }
+
+    #[test]
+    fn add_to_inline_lists() {
+        let p = Project::new("inline", "name: level\ndependencies: counters # one\n");
+        assert_eq!(
+            p.add("mylib").unwrap(),
+            "name: level\ndependencies:\n  - counters\n  - mylib\n"
+        );
+
+        let p = Project::new("empty", "name: level\ndependencies: []\nversion: 1.0.0");
+        assert_eq!(
+            p.add("mylib").unwrap(),
+            "name: level\ndependencies:\n  - mylib\nversion: 1.0.0"
+        );
+
+        let p = Project::new("missing", "name: level\n\n");
+        assert_eq!(
+            p.add("mylib").unwrap(),
+            "name: level\ndependencies:\n  - mylib\n"
+        );
+
+        let p = Project::new("flow", "name: level\ndependencies: [a, b]\n");
+        assert!(p.add("mylib").unwrap_err().contains("written as a list"));
+    }
+
+    #[test]
+    fn add_path_dependencies() {
+        let p = Project::new("path", "name: level\n");
+        let lib = p.0.join("libs").join("mylib");
+        fs::create_dir_all(&lib).unwrap();
+
+        // written relative to the config file, with `/`
+        let dep = format!("path:{}@^0.2", lib.display());
+        assert_eq!(
+            p.add(&dep).unwrap(),
+            "name: level\ndependencies:\n  - path: libs/mylib\n    version: ^0.2\n"
+        );
+        assert_eq!(
+            relative_path(Path::new("/projects/mylib"), Path::new("/projects/level")),
+            PathBuf::from("../mylib")
+        );
+        assert_eq!(
+            relative_path(Path::new("/projects"), Path::new("/projects")),
+            PathBuf::from(".")
+        );
+
+        let missing = format!("path:{}", p.0.join("missing").display());
+        assert!(p
+            .add(&missing)
+            .unwrap_err()
+            .contains("Cannot find the folder"));
+    }
+
+    #[test]
+    fn remove_dependencies() {
+        let p = Project::new(
+            "remove",
+            "dependencies:\n  # first\n  - counters\n  - url: https://github.com/someone/other.git\n    version: ^1\n  - path: ../libs/local\nname: level\n",
+        );
+        // by the name of the repository
+        assert_eq!(
+            p.remove("other").unwrap(),
+            "dependencies:\n  # first\n  - counters\n  - path: ../libs/local\nname: level\n"
+        );
+        assert!(p
+            .remove("other")
+            .unwrap_err()
+            .contains("'other' is not a dependency"));
+        // by the name of the folder
+        assert_eq!(
+            p.remove("local").unwrap(),
+            "dependencies:\n  # first\n  - counters\nname: level\n"
+        );
+        // the key is removed with the last dependency
+        assert_eq!(p.remove("counters").unwrap(), "  # first\nname: level\n");
+    }
+}
@@ -2,6 +2,7 @@ use std::path::PathBuf;
 
 // replace this with spwn's error system
 
+#[derive(Debug)]
 pub enum PckpError {
     CustomError {
         message: String,
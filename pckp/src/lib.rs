@@ -1,5 +1,9 @@
+pub mod cache;
 pub mod config_file;
 pub mod error;
 pub mod package;
 pub mod version;
-pub mod download;
\ No newline at end of file
+pub mod download;
+pub mod edit;
+pub mod lock;
+pub mod resolve;
\ No newline at end of file
@@ -0,0 +1,273 @@
+// pckp.lock: the exact commit and a hash of the installed files of every dependency,
+// so that installing from the same lock file always gives the same files
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+
+use crate::error::PckpError;
+use shared::{LOCK_FILE_NAME, PACKAGE_DIR};
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct LockedPackage {
+    pub name: String,
+    /// The version in the package's own config file
+    pub version: String,
+    /// Every version requirement on the package (`latest`, a semver requirement or a tag)
+    pub requested: String,
+    /// The git URL the package was cloned from
+    pub source: String,
+    pub commit: String,
+    /// Hash of the installed files, see `hash_dir`
+    pub hash: String,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct LockFile {
+    pub packages: Vec<LockedPackage>,
+}
+
+impl LockFile {
+    pub fn path(mut pckp_dir: PathBuf) -> PathBuf {
+        pckp_dir.push(LOCK_FILE_NAME);
+        pckp_dir
+    }
+
+    /// Reads a lock file, or returns an empty one if it doesn't exist
+    pub fn read(path: &Path) -> Result<LockFile, PckpError> {
+        if !path.exists() {
+            return Ok(LockFile::default());
+        }
+        let err = |message: &str| PckpError::config(message.to_string(), path.to_path_buf(), None);
+
+        let text = fs::read_to_string(path).map_err(|e| err(&e.to_string()))?;
+        let yaml = YamlLoader::load_from_str(&text).map_err(|e| err(&e.to_string()))?;
+        let packages = match yaml.first().map(|y| &y["packages"]) {
+            None | Some(Yaml::BadValue) => return Ok(LockFile::default()),
+            Some(Yaml::Array(packages)) => packages,
+            Some(_) => return Err(err("Expected 'packages' to be a list")),
+        };
+
+        let mut out = Vec::new();
+        for package in packages {
+            let get = |key: &str| match package[key].as_str() {
+                Some(s) => Ok(s.to_string()),
+                None => Err(err(&format!("Expected every package to have a '{}'", key))),
+            };
+            out.push(LockedPackage {
+                name: get("name")?,
+                version: get("version")?,
+                requested: get("requested")?,
+                source: get("source")?,
+                commit: get("commit")?,
+                hash: get("hash")?,
+            });
+        }
+        Ok(LockFile { packages: out })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), PckpError> {
+        let packages = self
+            .packages
+            .iter()
+            .map(|p| {
+                let mut hash = Hash::new();
+                for (key, val) in [
+                    ("name", &p.name),
+                    ("version", &p.version),
+                    ("requested", &p.requested),
+                    ("source", &p.source),
+                    ("commit", &p.commit),
+                    ("hash", &p.hash),
+                ] {
+                    hash.insert(Yaml::String(key.to_string()), Yaml::String(val.clone()));
+                }
+                Yaml::Hash(hash)
+            })
+            .collect();
+        let mut root = Hash::new();
+        root.insert(Yaml::String("packages".to_string()), Yaml::Array(packages));
+
+        let err = |e: &dyn std::fmt::Display| {
+            PckpError::config(
+                format!("Unable to write the lock file: {}", e),
+                path.to_path_buf(),
+                None,
+            )
+        };
+        let mut out = String::from("# Generated by pckp, do not edit by hand\n");
+        YamlEmitter::new(&mut out)
+            .dump(&Yaml::Hash(root))
+            .map_err(|e| err(&format!("{:?}", e)))?;
+        out.push('\n');
+        fs::write(path, out).map_err(|e| err(&e))
+    }
+
+    /// The locked package that was installed from `source` for the version `requested`
+    pub fn find(&self, source: &str, requested: &str) -> Option<&LockedPackage> {
+        self.packages
+            .iter()
+            .find(|p| p.source == source && p.requested == requested)
+    }
+
+    pub fn add(&mut self, package: LockedPackage) {
+        if !self.packages.iter().any(|p| p.source == package.source) {
+            self.packages.push(package);
+            self.packages
+                .sort_by(|a, b| (&a.name, &a.requested).cmp(&(&b.name, &b.requested)));
+        }
+    }
+}
+
+impl LockedPackage {
+    /// Hashes the installed files of the package, and checks that they are the ones that were locked
+    pub fn verify(&self, installed: &Path, parent_name: &str) -> Result<String, PckpError> {
+        let hash = hash_dir(installed)?;
+        if hash != self.hash {
+            return Err(PckpError::custom_with_note(
+                format!(
+                    "The installed files of package {} don't match {} (expected {}, found {})",
+                    self.name, LOCK_FILE_NAME, self.hash, hash
+                ),
+                Some(parent_name.to_string()),
+                Some(format!(
+                    "If the files were changed on purpose, remove the package from {} and {} to install it again",
+                    LOCK_FILE_NAME, PACKAGE_DIR
+                )),
+            ));
+        }
+        Ok(hash)
+    }
+}
+
+/// SHA-256 of the relative paths and contents of every file in `dir`, in sorted order
+pub fn hash_dir(dir: &Path) -> Result<String, PckpError> {
+    let err = |path: &Path, e: std::io::Error| {
+        PckpError::custom(format!("Unable to read {}: {}", path.display(), e), None)
+    };
+    fn collect(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                collect(&path, files)?;
+            } else {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+    let mut files = Vec::new();
+    if dir.exists() {
+        collect(dir, &mut files).map_err(|e| err(dir, e))?;
+    }
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for file in files {
+        let relative = file
+            .strip_prefix(dir)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        hasher.update(relative.as_bytes());
+        hasher.update([0]);
+        hasher.update(fs::read(&file).map_err(|e| err(&file, e))?);
+        hasher.update([0]);
+    }
+    let hash = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    Ok(format!("sha256-{}", hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pckp-lock-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn package(name: &str, requested: &str, hash: &str) -> LockedPackage {
+        LockedPackage {
+            name: name.to_string(),
+            version: String::from("1.0.0"),
+            requested: requested.to_string(),
+            source: format!("https://github.com/someone/{}", name),
+            commit: format!("{:040x}", 1),
+            hash: hash.to_string(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = temp_dir("round-trip");
+        let path = LockFile::path(dir.clone());
+        assert_eq!(LockFile::read(&path).unwrap(), LockFile::default());
+
+        let mut lock = LockFile::default();
+        lock.add(package("b", "^1", "sha256-b"));
+        lock.add(package("a", "latest", "sha256-a"));
+        // a source is only locked once
+        lock.add(package("a", "^2", "sha256-other"));
+        lock.write(&path).unwrap();
+
+        let read = LockFile::read(&path).unwrap();
+        assert_eq!(read, lock);
+        assert_eq!(
+            read.packages
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+        assert_eq!(
+            read.find("https://github.com/someone/b", "^1"),
+            Some(&lock.packages[1])
+        );
+        assert_eq!(read.find("https://github.com/someone/b", "^2"), None);
+
+        fs::write(&path, "packages:\n  - name: a\n").unwrap();
+        assert!(LockFile::read(&path).is_err());
+
+        // the lock file can't be written where a directory is
+        assert!(lock.write(&dir).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hash_mismatch() {
+        let dir = temp_dir("hash");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("lib.spwn"), "return {}").unwrap();
+        fs::write(dir.join("src/util.spwn"), "return 1").unwrap();
+
+        let hash = hash_dir(&dir).unwrap();
+        assert!(hash.starts_with("sha256-"));
+        let locked = package("util", "latest", &hash);
+        assert_eq!(locked.verify(&dir, "root").ok(), Some(hash.clone()));
+
+        // changing, adding or renaming files changes the hash
+        fs::write(dir.join("src/util.spwn"), "return 2").unwrap();
+        let err = locked.verify(&dir, "root").err().unwrap().to_string();
+        assert!(err.contains("The installed files of package util don't match pckp.lock"));
+        fs::write(dir.join("src/util.spwn"), "return 1").unwrap();
+        assert!(locked.verify(&dir, "root").is_ok());
+        fs::rename(dir.join("src/util.spwn"), dir.join("src/other.spwn")).unwrap();
+        assert!(locked.verify(&dir, "root").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+        // a package without files
+        assert_eq!(
+            hash_dir(&dir).unwrap(),
+            format!("sha256-{:x}", Sha256::digest([]))
+        );
+    }
+}
@@ -1,26 +1,28 @@
+use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use git2::Repository;
-
-use crate::config_file::{config_to_package, get_config};
+use crate::download::Registry;
 use crate::error::PckpError;
+use crate::lock::{hash_dir, LockFile, LockedPackage};
+use crate::resolve::{resolve, Requirement, Resolution, Revision};
 use crate::version::{export_version, get_version_file, import_version};
 
 use fs_extra::dir as fs_dir;
-
-pub const PACKAGE_DIR: &str = "pckp_libraries";
+use shared::PACKAGE_DIR;
 
 #[derive(PartialEq, Clone, Debug)]
 pub enum DependencySource {
     Name(String),
     Url(String),
+    /// A package in a local folder
+    Path(PathBuf),
 }
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Dependency {
     pub source: DependencySource,
-    pub version: String,
+    pub version: Requirement,
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -29,6 +31,8 @@ pub struct LocalPackage {
     pub version: String,
     pub paths: Vec<PathBuf>,
     pub dependencies: Vec<Package>,
+    // only used when this is the root package
+    pub registry: Option<Registry>,
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -37,9 +41,23 @@ pub enum PackageType {
     External(Dependency),
 }
 
+/// State shared by everything that is installed for a root package
+pub struct Installation {
+    /// Dependencies are looked up in the registry of the root package
+    pub registry: Registry,
+    /// The lock file of the previous install, which is reproduced
+    pub locked: LockFile,
+    /// Everything installed so far, which is written as the new lock file
+    pub resolved: LockFile,
+    /// The version picked for every package
+    pub resolution: Resolution,
+    /// Sources of the packages installed so far
+    pub installed: HashSet<String>,
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct Package {
-    internal: PackageType,
+    pub(crate) internal: PackageType,
 }
 
 impl Package {
@@ -48,6 +66,7 @@ impl Package {
         version: String,
         paths: Vec<PathBuf>,
         dependencies: Vec<Package>,
+        registry: Option<Registry>,
     ) -> Package {
         Package {
             internal: PackageType::Local(LocalPackage {
@@ -55,6 +74,7 @@ impl Package {
                 version,
                 paths,
                 dependencies,
+                registry,
             }),
         }
     }
@@ -66,13 +86,48 @@ impl Package {
     }
 
     pub fn install_dependencies(&self, path: PathBuf) -> Result<(), PckpError> {
+        let root = self.ensure_local();
+        let lock_path = LockFile::path(path.clone());
+        let registry = Registry::resolve(root.registry.as_ref());
+        let locked = LockFile::read(&lock_path)?;
+        let mut installation = Installation {
+            resolution: resolve(root, &registry, &locked)?,
+            registry,
+            locked,
+            resolved: LockFile::default(),
+            installed: HashSet::new(),
+        };
+        root.install_dependencies(path, &mut installation)?;
+
+        if !installation.resolved.packages.is_empty() || lock_path.exists() {
+            installation.resolved.write(&lock_path)?;
+        }
+        Ok(())
+    }
+
+    /// The resolved dependency graph of a root package, with the version picked for every package.
+    /// Packages that are shown more than once only have their dependencies shown the first time
+    pub fn tree(&self, path: PathBuf) -> Result<String, PckpError> {
+        let root = self.ensure_local();
+        let registry = Registry::resolve(root.registry.as_ref());
+        let locked = LockFile::read(&LockFile::path(path))?;
+        let resolution = resolve(root, &registry, &locked)?;
+
+        let mut out = format!("{} {}\n", root.name, root.version);
+        write_tree(
+            root,
+            "",
+            &registry,
+            &resolution,
+            &mut HashSet::new(),
+            &mut out,
+        )?;
+        Ok(out)
+    }
+
+    fn ensure_local(&self) -> &LocalPackage {
         match &self.internal {
-            PackageType::Local(root) => {
-                for x in &root.dependencies {
-                    x.install(&root.name, path.clone(), false)?;
-                }
-                Ok(())
-            }
+            PackageType::Local(root) => root,
             _ => unreachable!("ensure_local"),
         }
     }
@@ -81,7 +136,7 @@ impl Package {
     fn get_version(&self) -> String {
         match &self.internal {
             PackageType::Local(p) => p.version.clone(),
-            PackageType::External(d) => d.version.clone(),
+            PackageType::External(d) => d.version.to_string(),
         }
     }
     pub fn install(
@@ -89,102 +144,174 @@ impl Package {
         parent_name: &str,
         path: PathBuf,
         ignore_version: bool,
+        installation: &mut Installation,
     ) -> Result<(), PckpError> {
         match &self.internal {
             PackageType::Local(p) => {
-                /*for folder in &p.paths {
-                    // folder guaranteed to exist from config parser
-
-                }*/
-                let mut dest = path.clone();
-                dest.push(PACKAGE_DIR);
-
-                if !dest.exists() {
-                    fs::create_dir(&dest).unwrap();
+                p.install_files(path.clone(), ignore_version, false)?;
+                p.install_dependencies(path, installation)
+            }
+            PackageType::External(d) => {
+                let source_url = d
+                    .source
+                    .to_string(parent_name.to_string(), &installation.registry)?;
+                if !installation.installed.insert(source_url.clone()) {
+                    return Ok(());
                 }
 
-                let version_file = get_version_file(path.clone());
-                let mut version_info = import_version(&version_file);
-
-                if !version_info
-                    .iter()
-                    .any(|(n, v)| n == &p.name && v == &p.version)
-                {
-                    println!("Installing {}", p.name);
-
-                    let new_path = if ignore_version {
-                        p.name.to_string()
-                    } else {
-                        format!("{}@{}", p.name, p.version)
-                    };
-                    dest.push(new_path);
-
-                    for folder in &p.paths {
-                        let mut opts = fs_dir::CopyOptions::new();
-                        opts.content_only = true;
-                        fs_dir::copy(folder, &dest, &opts).unwrap();
-                    }
+                let resolved = match installation.resolution.get(&source_url) {
+                    Some(resolved) => resolved.clone(),
+                    None => unreachable!("every dependency is resolved before installing"),
+                };
+                let p = resolved.package;
 
-                    version_info.push((p.name.clone(), p.version.clone()));
-                    //println!("package {:#?}", p);
-                }
+                // `path:` dependencies are installed again every time, and aren't locked
+                let commit = match resolved.candidate.revision {
+                    Revision::Commit(commit) => commit,
+                    Revision::Path(_) => {
+                        p.install_files(path.clone(), false, true)?;
+                        return p.install_dependencies(path, installation);
+                    }
+                };
 
-                for dep in &p.dependencies {
-                    dep.install(&p.name, path.clone(), false)?;
-                }
+                // packages that aren't locked are installed again, in case they changed
+                let locked = installation.locked.find(&source_url, &resolved.requested);
+                let installed = p.install_files(
+                    path.clone(),
+                    resolved.candidate.tag.is_none(),
+                    locked.is_none(),
+                )?;
+                let hash = match locked {
+                    Some(locked) => locked.verify(&installed, parent_name)?,
+                    None => hash_dir(&installed)?,
+                };
+                installation.resolved.add(LockedPackage {
+                    name: p.name.clone(),
+                    version: p.version.clone(),
+                    requested: resolved.requested,
+                    source: source_url,
+                    commit: commit.to_string(),
+                    hash,
+                });
 
-                export_version(version_info, &version_file);
-                Ok(())
+                p.install_dependencies(path, installation)
             }
-            PackageType::External(d) => {
-                let source_url = d.source.to_string(parent_name.to_string())?;
+        }
+    }
+}
 
-                let tmp_path = PathBuf::from(".pckp_tmp");
+impl LocalPackage {
+    // copies the folders of the package into the package directory, unless this version
+    // is already installed (or `reinstall` is set). returns the folder it is installed in
+    fn install_files(
+        &self,
+        path: PathBuf,
+        ignore_version: bool,
+        reinstall: bool,
+    ) -> Result<PathBuf, PckpError> {
+        let err = |dest: &Path, e: &dyn std::fmt::Display| {
+            PckpError::custom(
+                format!(
+                    "Unable to install package {} to {}. Reason: {}",
+                    self.name,
+                    dest.display(),
+                    e
+                ),
+                None,
+            )
+        };
+        let mut dest = path.clone();
+        dest.push(PACKAGE_DIR);
 
-                if tmp_path.exists() {
-                    fs::remove_dir_all(&tmp_path).unwrap();
-                }
-                let repo = match Repository::clone(&source_url, &tmp_path) {
-                    Ok(repo) => repo,
-                    Err(e) => {
-                        return Err(PckpError::custom(
-                            format!("Unable to clone package '{}'. Reason: {}", source_url, e),
-                            Some(parent_name.to_string()),
-                        ))
-                    }
-                };
+        if !dest.exists() {
+            fs::create_dir(&dest).map_err(|e| err(&dest, &e))?;
+        }
 
-                if d.version != "latest" {
-                    let tag_object =
-                        match repo.revparse_single(&("refs/tags/".to_string() + &d.version)) {
-                            Ok(x) => x,
-                            Err(_) => {
-                                return Err(PckpError::custom(
-                                    format!(
-                                        "Unable to find version {} for package {}",
-                                        d.version, source_url
-                                    ),
-                                    Some(parent_name.to_string()),
-                                ))
-                            }
-                        };
-
-                    repo.checkout_tree(&tag_object, None).unwrap();
-                }
+        let version_file = get_version_file(path);
+        let mut version_info = import_version(&version_file);
 
-                let cfg_dir = get_config(Some(tmp_path));
-                let local_package = if cfg_dir.exists() {
-                    config_to_package(cfg_dir)?.unwrap()
-                } else {
-                    return Err(PckpError::custom(
-                        format!("Package at {} does not have config file", source_url),
-                        Some(parent_name.to_string()),
-                    ));
-                };
+        let new_path = if ignore_version {
+            self.name.to_string()
+        } else {
+            format!("{}@{}", self.name, self.version)
+        };
+        dest.push(new_path);
 
-                local_package.install(parent_name, path, d.version == "latest")
-                //todo!("download and stuff");
+        let recorded = version_info
+            .iter()
+            .any(|(n, v)| n == &self.name && v == &self.version);
+        if !recorded || !dest.exists() || reinstall {
+            println!("Installing {}", self.name);
+
+            if dest.exists() {
+                fs::remove_dir_all(&dest).map_err(|e| err(&dest, &e))?;
+            }
+            for folder in &self.paths {
+                let mut opts = fs_dir::CopyOptions::new();
+                opts.content_only = true;
+                fs_dir::copy(folder, &dest, &opts).map_err(|e| err(&dest, &e))?;
+            }
+
+            if !recorded {
+                version_info.push((self.name.clone(), self.version.clone()));
+                export_version(version_info, &version_file);
             }
         }
+        Ok(dest)
+    }
+
+    fn install_dependencies(
+        &self,
+        path: PathBuf,
+        installation: &mut Installation,
+    ) -> Result<(), PckpError> {
+        for dep in &self.dependencies {
+            dep.install(&self.name, path.clone(), false, installation)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_tree(
+    package: &LocalPackage,
+    prefix: &str,
+    registry: &Registry,
+    resolution: &Resolution,
+    shown: &mut HashSet<String>,
+    out: &mut String,
+) -> Result<(), PckpError> {
+    for (i, dep) in package.dependencies.iter().enumerate() {
+        let d = match &dep.internal {
+            PackageType::External(d) => d,
+            PackageType::Local(_) => unreachable!("dependencies are external"),
+        };
+        let source = d.source.to_string(package.name.clone(), registry)?;
+        let resolved = match resolution.get(&source) {
+            Some(resolved) => resolved,
+            None => unreachable!("every dependency is resolved"),
+        };
+        let last = i == package.dependencies.len() - 1;
+
+        out.push_str(&format!(
+            "{}{} {} {}",
+            prefix,
+            if last { "└──" } else { "├──" },
+            resolved.package.name,
+            resolved.candidate
+        ));
+        if d.version != Requirement::Latest {
+            out.push_str(&format!(" ({})", d.version));
+        }
+        let expand = shown.insert(source);
+        if !expand && !resolved.package.dependencies.is_empty() {
+            out.push_str(" (*)");
+        }
+        out.push('\n');
+
+        if expand {
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            write_tree(&resolved.package, &prefix, registry, resolution, shown, out)?;
+        }
     }
+    Ok(())
 }
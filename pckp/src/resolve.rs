@@ -0,0 +1,549 @@
+// picks one version of every package in the dependency graph, before anything is installed
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+
+use git2::{Oid, Repository};
+use semver::{Comparator, Op, Version, VersionReq};
+
+use crate::cache::{checkout_version, open_repo};
+use crate::config_file::{config_to_package, get_config};
+use crate::download::Registry;
+use crate::error::PckpError;
+use crate::lock::LockFile;
+use crate::package::{DependencySource, LocalPackage, PackageType};
+use shared::LOCK_FILE_NAME;
+
+// a dependency that keeps changing the versions of its dependencies would otherwise never stop
+const MAX_ROUNDS: usize = 100;
+
+/// The `version` of a dependency in pckp.yaml
+#[derive(Clone, PartialEq, Debug)]
+pub enum Requirement {
+    /// `latest`: the newest commit, or any version when other packages ask for one
+    Latest,
+    /// A semver requirement like `^1.2`, `~0.3` or `>=1, <2`, matched against the tags of the repository.
+    /// A full version like `1.4.0` only matches that version
+    Range(VersionReq),
+    /// Any other tag
+    Tag(String),
+}
+
+impl Requirement {
+    pub fn parse(version: &str) -> Requirement {
+        let version = version.trim();
+        if version == "latest" {
+            Requirement::Latest
+        } else if let Ok(v) = Version::parse(version) {
+            // pinned versions stay pinned, instead of meaning `^1.4.0` like in Cargo
+            Requirement::Range(exact(&v))
+        } else if let Ok(req) = VersionReq::parse(version) {
+            Requirement::Range(req)
+        } else {
+            Requirement::Tag(version.to_string())
+        }
+    }
+
+    fn matches(&self, candidate: &Candidate) -> bool {
+        match self {
+            Requirement::Latest => true,
+            Requirement::Range(req) => candidate.version.as_ref().is_some_and(|v| req.matches(v)),
+            Requirement::Tag(tag) => candidate.tag.as_ref() == Some(tag),
+        }
+    }
+}
+
+// a requirement that only matches `version`
+fn exact(version: &Version) -> VersionReq {
+    VersionReq {
+        comparators: vec![Comparator {
+            op: Op::Exact,
+            major: version.major,
+            minor: Some(version.minor),
+            patch: Some(version.patch),
+            pre: version.pre.clone(),
+        }],
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Requirement::Latest => write!(f, "latest"),
+            Requirement::Range(req) => write!(f, "{}", req),
+            Requirement::Tag(tag) => write!(f, "{}", tag),
+        }
+    }
+}
+
+/// Where the files of a candidate come from
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Revision {
+    Commit(Oid),
+    /// A `path:` dependency, which is installed as it is in the folder
+    Path(PathBuf),
+}
+
+/// A version a package can be installed from
+#[derive(Clone, PartialEq, Debug)]
+pub struct Candidate {
+    /// `None` for the newest commit of the default branch.
+    /// For `path:` dependencies, this is the version in their config file
+    pub tag: Option<String>,
+    /// The tag as a semver version (without a leading `v`), if it is one
+    pub version: Option<Version>,
+    pub revision: Revision,
+}
+
+impl Candidate {
+    /// The commit to write in the lock file, if the candidate can be locked
+    pub fn commit(&self) -> Option<Oid> {
+        match self.revision {
+            Revision::Commit(commit) => Some(commit),
+            Revision::Path(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.tag, &self.revision) {
+            (Some(tag), Revision::Path(path)) => write!(f, "{} ({})", tag, path.display()),
+            (Some(tag), _) => write!(f, "{}", tag),
+            (None, Revision::Commit(commit)) => {
+                write!(f, "latest ({:.7})", commit.to_string())
+            }
+            (None, Revision::Path(path)) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// The package picked for a source
+#[derive(Clone, Debug)]
+pub struct ResolvedPackage {
+    /// The package at the picked version, with its folders in the cache (or in its own folder)
+    pub package: LocalPackage,
+    /// Every requirement on the package, as written in the lock file
+    pub requested: String,
+    pub candidate: Candidate,
+}
+
+/// The picked package for every git URL and folder in the dependency graph
+pub struct Resolution {
+    packages: HashMap<String, ResolvedPackage>,
+}
+
+impl Resolution {
+    pub fn get(&self, source: &str) -> Option<&ResolvedPackage> {
+        self.packages.get(source)
+    }
+}
+
+/// Picks the newest version of every package that satisfies everything that depends on it.
+/// Packages in the lock file keep their locked commit as long as their requirements don't change
+pub fn resolve(
+    root: &LocalPackage,
+    registry: &Registry,
+    locked: &LockFile,
+) -> Result<Resolution, PckpError> {
+    let mut resolver = Resolver {
+        locked,
+        paths: HashSet::new(),
+        repos: HashMap::new(),
+        candidates: HashMap::new(),
+        configs: HashMap::new(),
+    };
+    let mut chosen: HashMap<String, ResolvedPackage> = HashMap::new();
+
+    for _ in 0..MAX_ROUNDS {
+        // every requirement on every package, from the root and the packages picked so far
+        let mut requirements: BTreeMap<String, Vec<(String, Requirement)>> = BTreeMap::new();
+        let dependents = std::iter::once((root.name.clone(), root))
+            .chain(
+                chosen
+                    .values()
+                    .map(|c| (format!("{} {}", c.package.name, c.candidate), &c.package)),
+            )
+            .collect::<Vec<_>>();
+        for (dependent, package) in dependents {
+            for dep in &package.dependencies {
+                let d = match &dep.internal {
+                    PackageType::External(d) => d,
+                    PackageType::Local(_) => unreachable!("dependencies are external"),
+                };
+                let source = d.source.to_string(package.name.clone(), registry)?;
+                if let DependencySource::Path(_) = d.source {
+                    resolver.paths.insert(source.clone());
+                }
+                requirements
+                    .entry(source)
+                    .or_default()
+                    .push((dependent.clone(), d.version.clone()));
+            }
+        }
+
+        let mut changed = false;
+        for (source, reqs) in &requirements {
+            let mut requested = reqs.iter().map(|(_, r)| r.to_string()).collect::<Vec<_>>();
+            requested.sort();
+            requested.dedup();
+            let requested = requested.join("; ");
+
+            let candidate = resolver.pick(source, reqs, &requested)?;
+            if let Some(prev) = chosen.get(source) {
+                if prev.candidate == candidate && prev.requested == requested {
+                    continue;
+                }
+            }
+            let package = resolver.config(source, &candidate, &reqs[0].0)?;
+            chosen.insert(
+                source.clone(),
+                ResolvedPackage {
+                    package,
+                    requested,
+                    candidate,
+                },
+            );
+            changed = true;
+        }
+        // packages that nothing depends on anymore
+        let count = chosen.len();
+        chosen.retain(|source, _| requirements.contains_key(source));
+        changed |= chosen.len() != count;
+
+        if !changed {
+            return Ok(Resolution { packages: chosen });
+        }
+    }
+    Err(PckpError::custom(
+        "Unable to resolve the dependencies, the picked versions keep changing".to_string(),
+        Some(root.name.clone()),
+    ))
+}
+
+struct Resolver<'a> {
+    locked: &'a LockFile,
+    // sources of `path:` dependencies
+    paths: HashSet<String>,
+    repos: HashMap<String, Repository>,
+    candidates: HashMap<String, Vec<Candidate>>,
+    configs: HashMap<(String, Revision), LocalPackage>,
+}
+
+impl Resolver<'_> {
+    // lists the newest commit and the tags of a package's repository (or the package in a folder)
+    fn candidates(&mut self, source: &str, dependent: &str) -> Result<&[Candidate], PckpError> {
+        if self.candidates.contains_key(source) {
+            return Ok(&self.candidates[source]);
+        }
+        if self.paths.contains(source) {
+            let mut candidate = Candidate {
+                tag: None,
+                version: None,
+                revision: Revision::Path(PathBuf::from(source)),
+            };
+            let package = self.config(source, &candidate, dependent)?;
+            candidate.version = Version::parse(&package.version).ok();
+            candidate.tag = Some(package.version);
+            self.candidates.insert(source.to_string(), vec![candidate]);
+        } else {
+            let repo = match open_repo(source) {
+                Ok(repo) => repo,
+                Err(e) => {
+                    return Err(PckpError::custom(
+                        format!("Unable to clone package '{}'. Reason: {}", source, e),
+                        Some(dependent.to_string()),
+                    ))
+                }
+            };
+
+            let err = |e: git2::Error| {
+                PckpError::custom(
+                    format!(
+                        "Unable to read the versions of package '{}'. Reason: {}",
+                        source,
+                        e.message()
+                    ),
+                    Some(dependent.to_string()),
+                )
+            };
+            // fails when the repository doesn't have any commits
+            let head = repo
+                .head()
+                .and_then(|h| h.peel_to_commit())
+                .map_err(err)?
+                .id();
+            let mut candidates = vec![Candidate {
+                tag: None,
+                version: None,
+                revision: Revision::Commit(head),
+            }];
+            for tag in repo.tag_names(None).map_err(err)?.iter().flatten() {
+                let commit = repo
+                    .revparse_single(&format!("refs/tags/{}", tag))
+                    .and_then(|t| t.peel_to_commit());
+                if let Ok(commit) = commit {
+                    candidates.push(Candidate {
+                        tag: Some(tag.to_string()),
+                        version: Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok(),
+                        revision: Revision::Commit(commit.id()),
+                    });
+                }
+            }
+            self.repos.insert(source.to_string(), repo);
+            self.candidates.insert(source.to_string(), candidates);
+        }
+        Ok(&self.candidates[source])
+    }
+
+    fn pick(
+        &mut self,
+        source: &str,
+        reqs: &[(String, Requirement)],
+        requested: &str,
+    ) -> Result<Candidate, PckpError> {
+        let dependent = &reqs[0].0;
+        let only_latest = reqs.iter().all(|(_, r)| *r == Requirement::Latest);
+        let locked = self.locked.find(source, requested).cloned();
+        let candidates = self.candidates(source, dependent)?;
+
+        if let Some(locked) = locked {
+            let commit = Oid::from_str(&locked.commit).ok();
+            let candidate = if only_latest {
+                commit
+                    .filter(|c| self.repos[source].find_commit(*c).is_ok())
+                    .map(|commit| Candidate {
+                        tag: None,
+                        version: None,
+                        revision: Revision::Commit(commit),
+                    })
+            } else {
+                candidates
+                    .iter()
+                    .find(|c| c.commit() == commit && reqs.iter().all(|(_, r)| r.matches(c)))
+                    .cloned()
+            };
+            return candidate.ok_or_else(|| {
+                PckpError::custom_with_note(
+                    format!(
+                        "Unable to find commit {} of package {} (from {})",
+                        locked.commit, source, LOCK_FILE_NAME
+                    ),
+                    Some(dependent.clone()),
+                    Some(format!(
+                        "Remove the package from {} to install the version in pckp.yaml again",
+                        LOCK_FILE_NAME
+                    )),
+                )
+            });
+        }
+
+        if only_latest {
+            return Ok(candidates[0].clone());
+        }
+        let picked = candidates
+            .iter()
+            .filter(|c| reqs.iter().all(|(_, r)| r.matches(c)))
+            .max_by(|a, b| a.version.cmp(&b.version));
+        if let Some(picked) = picked {
+            return Ok(picked.clone());
+        }
+
+        let mut available = candidates
+            .iter()
+            .filter(|c| c.tag.is_some())
+            .collect::<Vec<_>>();
+        available.sort_by(|a, b| (&a.version, &a.tag).cmp(&(&b.version, &b.tag)));
+        let available = available
+            .iter()
+            .filter_map(|c| c.tag.clone())
+            .collect::<Vec<_>>();
+        let note = Some(if self.paths.contains(source) {
+            format!(
+                "The package in {} is version {}",
+                source,
+                available.join("")
+            )
+        } else if available.is_empty() {
+            String::from("The package doesn't have any tagged versions")
+        } else {
+            format!("Available versions: {}", available.join(", "))
+        });
+        if reqs.len() == 1 {
+            return Err(PckpError::custom_with_note(
+                format!(
+                    "Unable to find version {} for package {}",
+                    reqs[0].1, source
+                ),
+                Some(dependent.clone()),
+                note,
+            ));
+        }
+        Err(PckpError::custom_with_note(
+            format!(
+                "Unable to find a version of package {} that satisfies everything that depends on it:\n{}",
+                source,
+                reqs.iter()
+                    .map(|(dependent, r)| format!("    {} requires {}", dependent, r))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ),
+            None,
+            note,
+        ))
+    }
+
+    // the package config at a commit
+    fn config(
+        &mut self,
+        source: &str,
+        candidate: &Candidate,
+        dependent: &str,
+    ) -> Result<LocalPackage, PckpError> {
+        let key = (source.to_string(), candidate.revision.clone());
+        if let Some(package) = self.configs.get(&key) {
+            return Ok(package.clone());
+        }
+        let dir = match &candidate.revision {
+            Revision::Commit(commit) => checkout_version(&self.repos[source], source, *commit)
+                .map_err(|e| {
+                    PckpError::custom(
+                        format!(
+                            "Unable to check out version {} of package '{}'. Reason: {}",
+                            candidate, source, e
+                        ),
+                        Some(dependent.to_string()),
+                    )
+                })?,
+            Revision::Path(path) => path.clone(),
+        };
+
+        let cfg = get_config(Some(dir));
+        let package = match config_to_package(cfg)? {
+            Some(package) => match package.internal {
+                PackageType::Local(p) => p,
+                PackageType::External(_) => unreachable!("config_to_package"),
+            },
+            None => {
+                return Err(PckpError::custom(
+                    format!(
+                        "Package at {} does not have config file (version {})",
+                        source, candidate
+                    ),
+                    Some(dependent.to_string()),
+                ))
+            }
+        };
+        self.configs.insert(key, package.clone());
+        Ok(package)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(tag: Option<&str>, n: usize) -> Candidate {
+        Candidate {
+            tag: tag.map(String::from),
+            version: tag.and_then(|t| Version::parse(t.strip_prefix('v').unwrap_or(t)).ok()),
+            revision: Revision::Commit(Oid::from_str(&format!("{:040x}", n)).unwrap()),
+        }
+    }
+
+    // a resolver that already knows the versions of `pkg`, so it never clones anything
+    fn pick(tags: &[&str], reqs: &[(&str, &str)]) -> Result<Candidate, String> {
+        let locked = LockFile::default();
+        let mut candidates = vec![candidate(None, 0)];
+        for (i, tag) in tags.iter().enumerate() {
+            candidates.push(candidate(Some(tag), i + 1));
+        }
+        let mut resolver = Resolver {
+            locked: &locked,
+            paths: HashSet::new(),
+            repos: HashMap::new(),
+            candidates: HashMap::from([(String::from("pkg"), candidates)]),
+            configs: HashMap::new(),
+        };
+        let reqs = reqs
+            .iter()
+            .map(|(dependent, r)| (dependent.to_string(), Requirement::parse(r)))
+            .collect::<Vec<_>>();
+        resolver.pick("pkg", &reqs, "").map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn parse_requirements() {
+        assert_eq!(Requirement::parse(" latest "), Requirement::Latest);
+        for req in ["^1.2", "~0.3", ">=1, <2", "1.4", "=1.4.0"] {
+            assert_eq!(
+                Requirement::parse(req),
+                Requirement::Range(VersionReq::parse(req).unwrap())
+            );
+        }
+        // a full version is pinned
+        for (req, exact) in [("1.4.0", "=1.4.0"), ("2.0.0-beta.1", "=2.0.0-beta.1")] {
+            assert_eq!(
+                Requirement::parse(req),
+                Requirement::Range(VersionReq::parse(exact).unwrap())
+            );
+        }
+        assert_eq!(
+            Requirement::parse("nightly"),
+            Requirement::Tag(String::from("nightly"))
+        );
+    }
+
+    #[test]
+    fn match_requirements() {
+        let head = candidate(None, 0);
+        let v1_2 = candidate(Some("v1.2.5"), 1);
+        let v1_3 = candidate(Some("1.3.0"), 2);
+        let nightly = candidate(Some("nightly"), 3);
+
+        assert!(Requirement::parse("latest").matches(&head));
+        assert!(Requirement::parse("latest").matches(&nightly));
+        assert!(Requirement::parse("^1.2").matches(&v1_2));
+        assert!(Requirement::parse("^1.2").matches(&v1_3));
+        assert!(Requirement::parse("~1.2").matches(&v1_2));
+        assert!(!Requirement::parse("~1.2").matches(&v1_3));
+        assert!(Requirement::parse(">=1.3, <2").matches(&v1_3));
+        assert!(!Requirement::parse(">=1.3, <2").matches(&v1_2));
+        assert!(!Requirement::parse("^1").matches(&head));
+        assert!(Requirement::parse("nightly").matches(&nightly));
+        assert!(!Requirement::parse("nightly").matches(&v1_2));
+    }
+
+    #[test]
+    fn pick_versions() {
+        let tags = ["v0.9.0", "v1.0.0", "v1.4.2", "v2.0.0", "nightly"];
+        let picked = |reqs: &[(&str, &str)]| pick(&tags, reqs).unwrap().to_string();
+
+        // the newest version that satisfies every requirement
+        assert_eq!(picked(&[("a", "^1")]), "v1.4.2");
+        assert_eq!(picked(&[("a", "^1"), ("b", "<1.2")]), "v1.0.0");
+        assert_eq!(picked(&[("a", "latest"), ("b", "~0.9")]), "v0.9.0");
+        assert_eq!(picked(&[("a", "nightly")]), "nightly");
+        // a full version doesn't pick newer compatible versions
+        assert_eq!(picked(&[("a", "1.0.0")]), "v1.0.0");
+        // only `latest` gets the newest commit
+        assert_eq!(picked(&[("a", "latest")]), format!("latest ({:07x})", 0));
+    }
+
+    #[test]
+    fn conflicting_requirements() {
+        let tags = ["v1.0.0", "v2.0.0"];
+        assert_eq!(
+            pick(&tags, &[("a", "^3")]).unwrap_err(),
+            "PckpError in package 'a': Unable to find version ^3 for package pkg\n\
+             Note: Available versions: v1.0.0, v2.0.0"
+        );
+        assert_eq!(
+            pick(&tags, &[("a", "^1"), ("b 0.1.0", "^2")]).unwrap_err(),
+            "PckpError: Unable to find a version of package pkg that satisfies everything that depends on it:\n\
+             \x20   a requires ^1\n\
+             \x20   b 0.1.0 requires ^2\n\
+             Note: Available versions: v1.0.0, v2.0.0"
+        );
+    }
+}
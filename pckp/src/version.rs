@@ -1,4 +1,4 @@
-use crate::package::PACKAGE_DIR;
+use shared::PACKAGE_DIR;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -0,0 +1,93 @@
+// the cache directory is chosen with an environment variable, which is shared by the
+// whole process, so these tests run in their own binary
+
+use std::fs;
+use std::path::Path;
+
+// a git repository with one commit, tagged `tag`
+fn git_package(dir: &Path, config: &str, tag: &str) {
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join("pckp.yaml"), config).unwrap();
+    fs::write(dir.join("lib.spwn"), "return {}").unwrap();
+
+    let repo = git2::Repository::init(dir).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(Path::new("pckp.yaml")).unwrap();
+    index.add_path(Path::new("lib.spwn")).unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let sig = git2::Signature::now("pckp", "pckp@example.com").unwrap();
+    let commit = repo
+        .commit(Some("HEAD"), &sig, &sig, "first", &tree, &[])
+        .unwrap();
+    repo.tag_lightweight(tag, &repo.find_object(commit, None).unwrap(), false)
+        .unwrap();
+}
+
+#[test]
+fn install_path_and_cached_dependencies() {
+    let dir = std::env::temp_dir().join(format!("pckp-install-{}", std::process::id()));
+    let cache = dir.join("cache");
+    std::env::set_var(pckp::cache::CACHE_ENV, &cache);
+
+    let remote = dir.join("remote");
+    git_package(&remote, "name: remote\nversion: 1.0.0\n", "v1.0.0");
+    let local = dir.join("local");
+    fs::create_dir_all(&local).unwrap();
+    fs::write(local.join("pckp.yaml"), "name: local\nversion: 0.2.0\n").unwrap();
+    fs::write(local.join("lib.spwn"), "return {}").unwrap();
+
+    let project = dir.join("project");
+    fs::create_dir_all(&project).unwrap();
+    fs::write(
+        project.join("pckp.yaml"),
+        format!(
+            "name: level\nversion: 1.0.0\ndependencies:\n  - path: ../local\n    version: ^0.2\n  - url: {}\n    version: ^1\n",
+            remote.display()
+        ),
+    )
+    .unwrap();
+    let root =
+        pckp::config_file::config_to_package(pckp::config_file::get_config(Some(project.clone())))
+            .unwrap()
+            .unwrap();
+
+    // path dependencies are resolved to the version in their config file
+    let local = fs::canonicalize(&local).unwrap();
+    assert_eq!(
+        root.tree(project.clone()).unwrap(),
+        format!(
+            "level 1.0.0\n├── local 0.2.0 ({}) (^0.2)\n└── remote v1.0.0 (^1)\n",
+            local.display()
+        )
+    );
+
+    // the first install clones the repository into the cache
+    root.install_dependencies(project.clone()).unwrap();
+    let libraries = project.join(shared::PACKAGE_DIR);
+    assert!(libraries.join("local@0.2.0/lib.spwn").exists());
+    assert!(libraries.join("remote@1.0.0/lib.spwn").exists());
+    assert_eq!(fs::read_dir(cache.join("repos")).unwrap().count(), 1);
+    assert_eq!(fs::read_dir(cache.join("versions")).unwrap().count(), 1);
+    // only packages from git are locked
+    let lock = pckp::lock::LockFile::read(&pckp::lock::LockFile::path(project.clone())).unwrap();
+    assert_eq!(
+        lock.packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>(),
+        ["remote"]
+    );
+
+    // later installs use the cached clone, even when the repository can't be fetched
+    fs::remove_dir_all(&remote).unwrap();
+    fs::remove_dir_all(&libraries).unwrap();
+    root.install_dependencies(project.clone()).unwrap();
+    assert!(libraries.join("remote@1.0.0/lib.spwn").exists());
+
+    // but a package that was never cached can't be installed
+    fs::remove_dir_all(&cache).unwrap();
+    assert!(root.install_dependencies(project).is_err());
+
+    std::env::remove_var(pckp::cache::CACHE_ENV);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,13 +1,15 @@
+use std::env;
 use std::path::PathBuf;
 
 use internment::LocalIntern;
+use serde::{Deserialize, Serialize};
 use slotmap::new_key_type;
 new_key_type! {
     pub struct StoredValue;
 } //index to stored value in globals.stored_values
 pub type FileRange = (usize, usize);
 
-#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Serialize, Deserialize)]
 pub enum ImportType {
     Script(PathBuf),
     Lib(String),
@@ -25,9 +27,34 @@ pub enum BreakType {
     Switch(StoredValue),
     // used for contexts
 }
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub enum SpwnSource {
     File(PathBuf),
     BuiltIn(PathBuf),
     String(LocalIntern<String>),
 }
+
+/// The folder in a project that pckp installs its dependencies in
+pub const PACKAGE_DIR: &str = "pckp_libraries";
+/// The file in a project that has the versions pckp resolved its dependencies to
+pub const LOCK_FILE_NAME: &str = "pckp.lock";
+
+/// A per-user cache directory: the one in the environment variable `env_var` if it is set,
+/// otherwise the `name` folder in the user's cache folder
+pub fn cache_dir(env_var: &str, name: &str) -> PathBuf {
+    if let Some(dir) = env::var_os(env_var).filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    let home = || env::var_os("HOME").map(PathBuf::from);
+    let base = if cfg!(target_os = "windows") {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|h| h.join("Library/Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home().map(|h| h.join(".cache")))
+    };
+    base.unwrap_or_else(env::temp_dir).join(name)
+}
@@ -0,0 +1,578 @@
+//! A headless simulation of Geometry Dash triggers, for testing compiled levels without the game.
+//!
+//! The simulation runs frame by frame at 60 FPS, with the player moving right at normal speed.
+//! It covers spawn, toggle, count, instant count, pickup, move, rotate and stop triggers.
+//! Easing, player-locked or targeted moves, and everything that needs the player's physics
+//! (touch, collision and on death triggers) are not simulated.
+
+use ahash::AHashMap;
+use compiler::builtins::{Group, Id, Item};
+use compiler::leveldata::{GdObj, ObjParam};
+use compiler::parse_levelstring::parse_levelstring;
+use compiler::value::Value;
+use errors::RuntimeError;
+
+pub const FPS: f64 = 60.0;
+// how far the player moves every second at normal speed
+pub const NORMAL_SPEED: f64 = 311.58;
+// zero delay spawns nested deeper than this are delayed by a frame,
+// so spawn loops can't hang the simulation
+const MAX_SPAWN_DEPTH: usize = 1000;
+
+mod obj_ids {
+    pub const MOVE: u16 = 901;
+    pub const ROTATE: u16 = 1346;
+    pub const COUNT: u16 = 1611;
+    pub const TOGGLE: u16 = 1049;
+    pub const SPAWN: u16 = 1268;
+    pub const STOP: u16 = 1616;
+    pub const INSTANT_COUNT: u16 = 1811;
+    pub const PICKUP: u16 = 1817;
+}
+
+mod obj_props {
+    pub const OBJ_ID: u16 = 1;
+    pub const X: u16 = 2;
+    pub const Y: u16 = 3;
+    pub const DURATION: u16 = 10;
+    pub const TOUCH_TRIGGERED: u16 = 11;
+    pub const MOVE_X: u16 = 28;
+    pub const MOVE_Y: u16 = 29;
+    pub const TARGET: u16 = 51;
+    pub const ACTIVATE_GROUP: u16 = 56;
+    pub const GROUPS: u16 = 57;
+    pub const SPAWN_TRIGGERED: u16 = 62;
+    pub const SPAWN_DURATION: u16 = 63;
+    pub const ROTATE_DEGREES: u16 = 68;
+    pub const CENTER: u16 = 71;
+    pub const COUNT: u16 = 77;
+    pub const ITEM: u16 = 80;
+    pub const MULTI_TRIGGER: u16 = 87;
+    pub const COMPARISON: u16 = 88;
+    pub const COUNT_MULTI_ACTIVATE: u16 = 104;
+}
+
+/// Something that happened during the simulation
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    // the triggers in a group were spawned
+    Spawned(Group),
+    // the objects in a group were toggled on or off
+    Toggled {
+        group: Group,
+        enabled: bool,
+    },
+    ItemChanged {
+        item: Item,
+        value: i32,
+    },
+    // a move trigger started moving a group
+    Moved {
+        group: Group,
+        x: f64,
+        y: f64,
+        duration: f64,
+    },
+    // a rotate trigger started rotating a group
+    Rotated {
+        group: Group,
+        degrees: f64,
+        duration: f64,
+    },
+    // a stop trigger stopped the delayed spawns, moves and rotations started by a group
+    Stopped(Group),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub frame: u32,
+    pub time: f64,
+    pub event: Event,
+}
+
+struct SimObject {
+    params: AHashMap<u16, ObjParam>,
+    obj_id: u16,
+    groups: Vec<Id>,
+    start: (f64, f64),
+    pos: (f64, f64),
+    rotation: f64,
+    enabled: bool,
+    // triggers without multi trigger can only be activated once
+    activated: bool,
+}
+
+impl SimObject {
+    fn new(params: AHashMap<u16, ObjParam>) -> Self {
+        let number = |key| match params.get(&key) {
+            Some(ObjParam::Number(n)) => *n,
+            _ => 0.0,
+        };
+        let start = (number(obj_props::X), number(obj_props::Y));
+        let groups = match params.get(&obj_props::GROUPS) {
+            Some(ObjParam::Group(g)) => vec![g.id],
+            Some(ObjParam::GroupList(l)) => l.iter().map(|g| g.id).collect(),
+            _ => Vec::new(),
+        };
+        SimObject {
+            obj_id: number(obj_props::OBJ_ID) as u16,
+            groups,
+            start,
+            pos: start,
+            rotation: 0.0,
+            enabled: true,
+            activated: false,
+            params,
+        }
+    }
+
+    fn number(&self, key: u16) -> f64 {
+        match self.params.get(&key) {
+            Some(ObjParam::Number(n)) => *n,
+            // see the `Display` implementation of `ObjParam`
+            Some(ObjParam::Epsilon) => 0.05,
+            _ => 0.0,
+        }
+    }
+
+    fn flag(&self, key: u16) -> bool {
+        match self.params.get(&key) {
+            Some(ObjParam::Bool(b)) => *b,
+            Some(ObjParam::Number(n)) => *n != 0.0,
+            _ => false,
+        }
+    }
+
+    fn id(&self, key: u16) -> Option<Id> {
+        match self.params.get(&key)? {
+            ObjParam::Group(g) => Some(g.id),
+            ObjParam::Item(i) => Some(i.id),
+            ObjParam::Block(b) => Some(b.id),
+            ObjParam::Color(c) => Some(c.id),
+            ObjParam::Number(n) => Some(Id::Specific(*n as u16)),
+            _ => None,
+        }
+    }
+
+    fn is_trigger(&self) -> bool {
+        matches!(
+            self.obj_id,
+            obj_ids::MOVE
+                | obj_ids::ROTATE
+                | obj_ids::COUNT
+                | obj_ids::TOGGLE
+                | obj_ids::SPAWN
+                | obj_ids::STOP
+                | obj_ids::INSTANT_COUNT
+                | obj_ids::PICKUP
+        )
+    }
+}
+
+struct Pending {
+    time: f64,
+    group: Id,
+    // the trigger that started this
+    source: Option<usize>,
+}
+
+enum TransformKind {
+    Move(f64, f64),
+    Rotate { degrees: f64, center: Option<usize> },
+}
+
+struct Transform {
+    kind: TransformKind,
+    objects: Vec<usize>,
+    duration: f64,
+    remaining: f64,
+    source: Option<usize>,
+}
+
+pub struct Simulator {
+    objects: Vec<SimObject>,
+    // the objects in every group, sorted by their x position
+    groups: AHashMap<Id, Vec<usize>>,
+    items: AHashMap<Id, i32>,
+    // count triggers waiting for their item to reach the count
+    listeners: Vec<usize>,
+    pending: Vec<Pending>,
+    transforms: Vec<Transform>,
+    // triggers that are activated by the player passing them, sorted by their x position
+    player_triggers: Vec<usize>,
+    next_player_trigger: usize,
+    player_x: f64,
+    // how far the player moves every second
+    pub speed: f64,
+    frame: u32,
+    depth: usize,
+    trace: Vec<TraceEntry>,
+}
+
+impl Simulator {
+    /// Simulates the final objects of a compiled level (with their IDs resolved)
+    pub fn new(objects: &[GdObj]) -> Self {
+        Self::from_params(objects.iter().map(|o| o.params.clone()).collect())
+    }
+
+    /// Simulates a full level string, including the header
+    pub fn from_level_string(ls: &str) -> Result<Self, Box<RuntimeError>> {
+        let params = parse_levelstring(ls)
+            .map_err(Box::new)?
+            .into_iter()
+            .filter_map(|obj| match obj {
+                Value::Obj(params, _) => Some(params.into_iter().collect()),
+                _ => None,
+            })
+            .collect();
+        Ok(Self::from_params(params))
+    }
+
+    fn from_params(params: Vec<AHashMap<u16, ObjParam>>) -> Self {
+        let objects = params.into_iter().map(SimObject::new).collect::<Vec<_>>();
+
+        let mut by_x = (0..objects.len()).collect::<Vec<_>>();
+        by_x.sort_by(|a, b| objects[*a].start.0.total_cmp(&objects[*b].start.0));
+
+        let mut groups = AHashMap::<Id, Vec<usize>>::default();
+        for &i in &by_x {
+            for g in &objects[i].groups {
+                groups.entry(*g).or_default().push(i);
+            }
+        }
+
+        let player_triggers = by_x
+            .into_iter()
+            .filter(|i| {
+                let obj = &objects[*i];
+                obj.is_trigger()
+                    && !obj.flag(obj_props::SPAWN_TRIGGERED)
+                    && !obj.flag(obj_props::TOUCH_TRIGGERED)
+            })
+            .collect();
+
+        Simulator {
+            objects,
+            groups,
+            items: AHashMap::default(),
+            listeners: Vec::new(),
+            pending: Vec::new(),
+            transforms: Vec::new(),
+            player_triggers,
+            next_player_trigger: 0,
+            player_x: 0.0,
+            speed: NORMAL_SPEED,
+            frame: 0,
+            depth: 0,
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn time(&self) -> f64 {
+        self.frame as f64 / FPS
+    }
+
+    pub fn player_x(&self) -> f64 {
+        self.player_x
+    }
+
+    /// Everything that happened so far, in order
+    pub fn trace(&self) -> &[TraceEntry] {
+        &self.trace
+    }
+
+    pub fn item(&self, item: Item) -> i32 {
+        self.items.get(&item.id).copied().unwrap_or(0)
+    }
+
+    /// Whether every object in the group is toggled on
+    pub fn group_enabled(&self, group: Group) -> bool {
+        self.group_objects(group.id)
+            .iter()
+            .all(|i| self.objects[*i].enabled)
+    }
+
+    /// How far the first object in the group has moved from where it was placed
+    pub fn group_offset(&self, group: Group) -> (f64, f64) {
+        match self.group_objects(group.id).first() {
+            Some(i) => {
+                let obj = &self.objects[*i];
+                (obj.pos.0 - obj.start.0, obj.pos.1 - obj.start.1)
+            }
+            None => (0.0, 0.0),
+        }
+    }
+
+    /// How many degrees (clockwise) the first object in the group has been rotated
+    pub fn group_rotation(&self, group: Group) -> f64 {
+        match self.group_objects(group.id).first() {
+            Some(i) => self.objects[*i].rotation,
+            None => 0.0,
+        }
+    }
+
+    /// Spawns a group from outside the level, like a trigger without a position would
+    pub fn spawn_group(&mut self, group: Group) {
+        self.spawn(group.id, None);
+    }
+
+    pub fn add_to_item(&mut self, item: Item, amount: i32) {
+        self.set_item(item.id, self.item(item) + amount);
+    }
+
+    /// Simulates a single frame
+    pub fn step(&mut self) {
+        while let Some(&i) = self.player_triggers.get(self.next_player_trigger) {
+            if self.objects[i].start.0 > self.player_x {
+                break;
+            }
+            self.next_player_trigger += 1;
+            self.activate(i);
+        }
+
+        let now = self.time() + 1e-9;
+        while let Some(i) = self.pending.iter().position(|p| p.time <= now) {
+            let pending = self.pending.remove(i);
+            self.spawn(pending.group, pending.source);
+        }
+
+        self.advance_transforms(1.0 / FPS);
+
+        self.frame += 1;
+        self.player_x += self.speed / FPS;
+    }
+
+    /// Simulates (at least) the given number of seconds
+    pub fn run(&mut self, seconds: f64) {
+        let end = self.frame + (seconds * FPS).ceil() as u32;
+        while self.frame < end {
+            self.step();
+        }
+    }
+
+    fn group_objects(&self, group: Id) -> &[usize] {
+        self.groups.get(&group).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    fn record(&mut self, event: Event) {
+        self.trace.push(TraceEntry {
+            frame: self.frame,
+            time: self.time(),
+            event,
+        });
+    }
+
+    fn spawn(&mut self, group: Id, source: Option<usize>) {
+        if self.depth >= MAX_SPAWN_DEPTH {
+            self.pending.push(Pending {
+                time: (self.frame + 1) as f64 / FPS,
+                group,
+                source,
+            });
+            return;
+        }
+
+        self.record(Event::Spawned(Group { id: group }));
+        self.depth += 1;
+        for i in self.group_objects(group).to_vec() {
+            if self.objects[i].is_trigger() && self.objects[i].flag(obj_props::SPAWN_TRIGGERED) {
+                self.activate(i);
+            }
+        }
+        self.depth -= 1;
+    }
+
+    fn activate(&mut self, i: usize) {
+        let obj = &self.objects[i];
+        if !obj.enabled || (obj.activated && !obj.flag(obj_props::MULTI_TRIGGER)) {
+            return;
+        }
+        self.objects[i].activated = true;
+
+        let obj = &self.objects[i];
+        let target = obj.id(obj_props::TARGET);
+        match (obj.obj_id, target) {
+            (obj_ids::SPAWN, Some(target)) => {
+                let delay = obj.number(obj_props::SPAWN_DURATION);
+                if delay > 0.0 {
+                    self.pending.push(Pending {
+                        time: self.time() + delay,
+                        group: target,
+                        source: Some(i),
+                    });
+                } else {
+                    self.spawn(target, Some(i));
+                }
+            }
+            (obj_ids::TOGGLE, Some(target)) => {
+                let enabled = obj.flag(obj_props::ACTIVATE_GROUP);
+                self.toggle(target, enabled);
+            }
+            (obj_ids::PICKUP, _) => {
+                if let Some(item) = obj.id(obj_props::ITEM) {
+                    let amount = obj.number(obj_props::COUNT) as i32;
+                    let value = self.items.get(&item).copied().unwrap_or(0) + amount;
+                    self.set_item(item, value);
+                }
+            }
+            (obj_ids::COUNT, _) if !self.listeners.contains(&i) => self.listeners.push(i),
+            (obj_ids::INSTANT_COUNT, _) => {
+                let value = match obj.id(obj_props::ITEM) {
+                    Some(item) => self.items.get(&item).copied().unwrap_or(0),
+                    None => 0,
+                };
+                let count = obj.number(obj_props::COUNT) as i32;
+                let passed = match obj.number(obj_props::COMPARISON) as u8 {
+                    1 => value > count,
+                    2 => value < count,
+                    _ => value == count,
+                };
+                if passed {
+                    self.fire_count(i);
+                }
+            }
+            (obj_ids::MOVE, Some(target)) => {
+                let (x, y) = (obj.number(obj_props::MOVE_X), obj.number(obj_props::MOVE_Y));
+                let duration = obj.number(obj_props::DURATION);
+                self.record(Event::Moved {
+                    group: Group { id: target },
+                    x,
+                    y,
+                    duration,
+                });
+                self.start_transform(TransformKind::Move(x, y), target, duration, i);
+            }
+            (obj_ids::ROTATE, Some(target)) => {
+                let degrees = obj.number(obj_props::ROTATE_DEGREES);
+                let duration = obj.number(obj_props::DURATION);
+                let center = obj
+                    .id(obj_props::CENTER)
+                    .and_then(|c| self.group_objects(c).first().copied());
+                self.record(Event::Rotated {
+                    group: Group { id: target },
+                    degrees,
+                    duration,
+                });
+                self.start_transform(
+                    TransformKind::Rotate { degrees, center },
+                    target,
+                    duration,
+                    i,
+                );
+            }
+            (obj_ids::STOP, Some(target)) => {
+                let started_by_group = |source: &Option<usize>, objects: &[SimObject]| {
+                    source.is_some_and(|s| objects[s].groups.contains(&target))
+                };
+                let objects = &self.objects;
+                self.pending
+                    .retain(|p| !started_by_group(&p.source, objects));
+                self.transforms
+                    .retain(|t| !started_by_group(&t.source, objects));
+                self.record(Event::Stopped(Group { id: target }));
+            }
+            _ => (),
+        }
+    }
+
+    // a count trigger reached its count
+    fn fire_count(&mut self, i: usize) {
+        let obj = &self.objects[i];
+        if let Some(target) = obj.id(obj_props::TARGET) {
+            if obj.flag(obj_props::ACTIVATE_GROUP) {
+                self.toggle(target, true);
+                self.spawn(target, Some(i));
+            } else {
+                self.toggle(target, false);
+            }
+        }
+    }
+
+    fn toggle(&mut self, group: Id, enabled: bool) {
+        for i in self.group_objects(group).to_vec() {
+            self.objects[i].enabled = enabled;
+        }
+        self.record(Event::Toggled {
+            group: Group { id: group },
+            enabled,
+        });
+    }
+
+    fn set_item(&mut self, item: Id, value: i32) {
+        self.items.insert(item, value);
+        self.record(Event::ItemChanged {
+            item: Item { id: item },
+            value,
+        });
+
+        for i in self.listeners.clone() {
+            let obj = &self.objects[i];
+            if obj.id(obj_props::ITEM) == Some(item)
+                && obj.number(obj_props::COUNT) as i32 == value
+                && obj.enabled
+            {
+                if !obj.flag(obj_props::COUNT_MULTI_ACTIVATE) {
+                    self.listeners.retain(|l| *l != i);
+                }
+                self.fire_count(i);
+            }
+        }
+    }
+
+    fn start_transform(&mut self, kind: TransformKind, target: Id, duration: f64, source: usize) {
+        let transform = Transform {
+            kind,
+            objects: self.group_objects(target).to_vec(),
+            duration,
+            remaining: duration,
+            source: Some(source),
+        };
+        if duration <= 0.0 {
+            self.apply_transform(&transform, 1.0);
+        } else {
+            self.transforms.push(transform);
+        }
+    }
+
+    fn advance_transforms(&mut self, dt: f64) {
+        let mut transforms = std::mem::take(&mut self.transforms);
+        for t in &mut transforms {
+            let step = dt.min(t.remaining);
+            t.remaining -= step;
+            self.apply_transform(t, step / t.duration);
+        }
+        transforms.retain(|t| t.remaining > 0.0);
+        self.transforms = transforms;
+    }
+
+    // applies a fraction of a move or rotation
+    fn apply_transform(&mut self, t: &Transform, fraction: f64) {
+        match t.kind {
+            TransformKind::Move(x, y) => {
+                for i in &t.objects {
+                    let obj = &mut self.objects[*i];
+                    obj.pos.0 += x * fraction;
+                    obj.pos.1 += y * fraction;
+                }
+            }
+            TransformKind::Rotate { degrees, center } => {
+                let angle = degrees * fraction;
+                let center = center.map(|c| self.objects[c].pos);
+                let (sin, cos) = angle.to_radians().sin_cos();
+                for i in &t.objects {
+                    let obj = &mut self.objects[*i];
+                    obj.rotation += angle;
+                    if let Some((cx, cy)) = center {
+                        // clockwise, with y pointing up
+                        let (dx, dy) = (obj.pos.0 - cx, obj.pos.1 - cy);
+                        obj.pos = (cx + dx * cos + dy * sin, cy - dx * sin + dy * cos);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use compiler::builtins::{Group, Item};
+use simulator::{Event, Simulator};
+use std::path::PathBuf;
+
+// compiles a #[no_std] script, and simulates the objects it adds
+fn simulate(code: &str) -> Simulator {
+    let [_, ls] = spwn::run_spwn(code.to_string(), Vec::new(), false).unwrap();
+    // the level string needs a header
+    Simulator::from_level_string(&format!(";{}", ls)).unwrap()
+}
+
+#[test]
+fn spawn_delay() {
+    let mut sim = simulate(
+        "
+#[no_std]
+f = !{
+    $.add(trigger{1: 1817, 80: 1i, 77: 1})
+}
+$.add(trigger{1: 1268, 51: f, 63: 0.5})
+",
+    );
+    sim.run(0.4);
+    assert_eq!(sim.item(Item::new(1)), 0);
+    sim.run(0.2);
+    assert_eq!(sim.item(Item::new(1)), 1);
+}
+
+#[test]
+fn count_and_move() {
+    let mut sim = Simulator::from_level_string(concat!(
+        ";",
+        // when item 1 reaches 2, spawn group 5
+        "1,1611,2,0,80,1,77,2,51,5,56,1;",
+        "1,1817,2,0,80,1,77,1;",
+        "1,1268,2,0,51,3,63,0.5;",
+        "1,1817,2,10,57,3,62,1,87,1,80,1,77,1;",
+        // move group 7 30 units right, over 1 second
+        "1,901,2,20,57,5,62,1,87,1,51,7,28,30,29,0,10,1;",
+        "1,1,2,100,3,100,57,7;",
+    ))
+    .unwrap();
+
+    sim.run(0.4);
+    assert_eq!(sim.item(Item::new(1)), 1);
+    assert_eq!(sim.group_offset(Group::new(7)), (0.0, 0.0));
+
+    sim.run(0.3);
+    assert_eq!(sim.item(Item::new(1)), 2);
+    assert!(sim
+        .trace()
+        .iter()
+        .any(|e| e.event == Event::Spawned(Group::new(5))));
+    let (x, _) = sim.group_offset(Group::new(7));
+    assert!(x > 0.0 && x < 30.0);
+
+    sim.run(1.0);
+    let (x, y) = sim.group_offset(Group::new(7));
+    assert!((x - 30.0).abs() < 1e-6 && y == 0.0);
+}
+
+#[test]
+fn instant_count_and_toggle() {
+    let mut sim = Simulator::from_level_string(concat!(
+        ";",
+        "1,1817,2,0,80,2,77,3;",
+        // if item 2 is larger than 2, spawn group 4
+        "1,1811,2,1,80,2,77,2,88,1,51,4,56,1;",
+        "1,1049,2,10,57,4,62,1,87,1,51,6,56,0;",
+        "1,1,2,50,3,50,57,6;",
+    ))
+    .unwrap();
+
+    assert!(sim.group_enabled(Group::new(6)));
+    sim.run(0.1);
+    assert!(!sim.group_enabled(Group::new(6)));
+}
+
+// arithmetic and loops with the standard library's counters
+#[test]
+fn std_counters() {
+    let code = "
+a = counter(10i)
+b = counter(11i)
+a += 7
+a *= 3
+for_loop(0..4, () {
+    b += 5
+})
+";
+    let [_, ls] = spwn::run_spwn(code.to_string(), vec![PathBuf::from("./")], false).unwrap();
+    let mut sim = Simulator::from_level_string(&format!(";{}", ls)).unwrap();
+    sim.run(5.0);
+    assert_eq!(sim.item(Item::new(10)), 21);
+    assert_eq!(sim.item(Item::new(11)), 20);
+}
+
+// two counters that keep their values between touches, in handlers that run at different times,
+// can't share an item ID
+#[test]
+fn counters_in_event_handlers() {
+    let code = "
+a = counter()
+b = counter()
+on(touch(), !{
+    a += 1
+    if a == 2 {
+        1i.add(1)
+    }
+})
+on(touch_end(), !{
+    b += 1
+    if b == 2 {
+        2i.add(1)
+    }
+})
+";
+    let [_, ls] = spwn::run_spwn(code.to_string(), vec![PathBuf::from("./")], true).unwrap();
+
+    // touch triggers aren't simulated, so their targets are spawned instead
+    let touch_target = |mode: &str| {
+        ls.split(';')
+            .map(|obj| obj.split(',').collect::<Vec<_>>())
+            .find(|props| {
+                let prop = |key: &str| props.chunks(2).find(|kv| kv[0] == key).map(|kv| kv[1]);
+                prop("1") == Some("1595") && prop("82") == Some(mode)
+            })
+            .and_then(|props| props.chunks(2).find(|kv| kv[0] == "51").map(|kv| kv[1]))
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+    let (touch, touch_end) = (Group::new(touch_target("1")), Group::new(touch_target("2")));
+
+    let mut sim = Simulator::from_level_string(&format!(";{}", ls)).unwrap();
+    for _ in 0..2 {
+        sim.spawn_group(touch);
+        sim.run(0.1);
+        sim.spawn_group(touch_end);
+        sim.run(0.1);
+    }
+    assert_eq!(sim.item(Item::new(1)), 1);
+    assert_eq!(sim.item(Item::new(2)), 1);
+}
+
+// the group of the function that moves the block and the group of the block
+// are never used by the same triggers, so they share an ID
+#[test]
+fn shared_groups() {
+    let code = "
+#[no_std]
+block = ?g
+$.add(obj{1: 1, 2: 100, 3: 100, 57: block})
+move_block = !{
+    $.add(trigger{1: 901, 51: block, 28: 30, 29: 0, 10: 0})
+}
+$.add(trigger{1: 1268, 51: move_block, 63: 0.5})
+";
+    let [_, ls] = spwn::run_spwn(code.to_string(), Vec::new(), true).unwrap();
+    assert!(ls.ends_with("1,1,2,100,3,100,57,1.1001,;"));
+
+    // objects are written with a trailing comma
+    let mut sim = Simulator::from_level_string(&format!(";{}", ls.replace(",;", ";"))).unwrap();
+    sim.run(0.4);
+    assert_eq!(sim.group_offset(Group::new(1)), (0.0, 0.0));
+    sim.run(0.2);
+    assert_eq!(sim.group_offset(Group::new(1)), (30.0, 0.0));
+}
@@ -22,6 +22,8 @@ pub use ::parser::fmt;
 pub use ::parser::parser;
 pub use ::parser::parser::parse_spwn;
 
+pub mod testing;
+
 pub use errors;
 pub use errors::compiler_info;
 pub use shared;
@@ -88,8 +90,10 @@ pub fn run_spwn(
         included,
         notes,
         Default::default(),
+        Default::default(),
         "".to_string(),
         &mut std_out,
+        &mut Default::default(),
     ) {
         Ok(a) => a,
         Err(e) => {
@@ -106,15 +110,21 @@ pub fn run_spwn(
     let reserved = optimizer::ReservedIds::from_objects(&compiled.objects, &compiled.func_ids);
 
     if has_stuff && optimize {
-        compiled.func_ids =
-            optimizer::optimize::optimize(compiled.func_ids, compiled.closed_groups, reserved);
+        compiled.func_ids = optimizer::optimize::optimize(
+            compiled.func_ids,
+            &mut compiled.objects,
+            compiled.closed_groups,
+            reserved,
+            &Default::default(),
+        )
+        .0;
     }
 
     let mut objects = leveldata::apply_fn_ids(&compiled.func_ids);
 
     objects.extend(compiled.objects);
 
-    let (new_ls, _) = leveldata::append_objects(objects, &String::new())?;
+    let (new_ls, _) = leveldata::append_objects(objects, &String::new(), Default::default())?;
 
     Ok([String::from_utf8_lossy(&std_out).to_string(), new_ls])
 }
@@ -123,35 +133,20 @@ mod tests;
 
 #[test]
 pub fn run_all_doc_examples() {
-    use shared::ImportType;
+    use ::compiler::target::Target;
     use std::str::FromStr;
 
-    let mut globals_path = std::env::current_dir().unwrap();
-    globals_path.push("temp"); // this folder doesn't actually exist, but it needs to be there because .parent is called in import_module
-    let mut std_out = Vec::<u8>::new();
-
     let permissions = builtins::BuiltinPermissions::new();
 
-    let mut globals = globals::Globals::new(
-        SpwnSource::File(globals_path),
-        permissions.clone(),
-        String::from(""),
-        &mut std_out,
-    );
-    globals.includes.push(PathBuf::from("./"));
-
-    let mut start_context = context::FullContext::new(&globals);
-
-    let info = compiler_info::CompilerInfo::new();
-
-    compiler::import_module(
-        &ImportType::Lib(STD_PATH.to_string()),
-        &mut start_context,
-        &mut globals,
-        info,
-        false,
-    )
-    .unwrap();
+    let mut std_out = Vec::<u8>::new();
+    let (mut globals, mut start_context) = import_std(Target::Gd21, &permissions, &mut std_out);
+    start_context.inner().root_context_ptr = &mut start_context;
+    // the builders of the triggers added in 2.2 throw when building for 2.1,
+    // so examples tagged with `target = "gd2.2"` use the standard library built for 2.2
+    let mut std_out_2_2 = Vec::<u8>::new();
+    let (mut globals_2_2, mut start_context_2_2) =
+        import_std(Target::Gd22, &permissions, &mut std_out_2_2);
+    start_context_2_2.inner().root_context_ptr = &mut start_context_2_2;
 
     let exports = globals.stored_values[start_context.inner().return_value].clone();
 
@@ -162,6 +157,12 @@ pub fn run_all_doc_examples() {
     } else {
         panic!("The standard library must return a dictionary");
     }
+    let exports_2_2 = globals_2_2.stored_values[start_context_2_2.inner().return_value].clone();
+    if let value::Value::Dict(d) = &exports_2_2 {
+        for (a, b, c) in d.iter().map(|(k, v)| (*k, *v, -1)) {
+            start_context_2_2.inner().new_redefinable_variable(a, b, c)
+        }
+    }
 
     let implementations = globals.implementations.clone();
 
@@ -184,55 +185,21 @@ pub fn run_all_doc_examples() {
 
     for (name, code) in builtins::BUILTIN_EXAMPLES {
         if permissions.is_allowed(builtins::Builtin::from_str(*name).unwrap()) {
-            all_tests.push((format!("$.{}", name), code.to_string()));
+            all_tests.push((format!("$.{}", name), code.to_string(), Target::Gd21));
         }
     }
 
     let mut all_failed_tests = Vec::new();
 
-    //let storage = globals.stored_values.clone();
-
-    for (name, code) in all_tests {
+    for (name, code, target) in all_tests {
         //println!("Running test: `\n{}\n`", code);
 
-        let source = SpwnSource::String(LocalIntern::new(code.clone()));
-        let cache = SpwnCache::default();
-
-        let info = compiler_info::CompilerInfo {
-            ..compiler_info::CompilerInfo::from_area(errors::compiler_info::CodeArea {
-                file: LocalIntern::new(source.clone()),
-                pos: (0, 0),
-            })
+        let result = match target {
+            Target::Gd21 => run_doc_example(&code, &mut globals, &start_context),
+            Target::Gd22 => run_doc_example(&code, &mut globals_2_2, &start_context_2_2),
         };
-
-        let (statements, _) = match parse_spwn(code, source.clone(), BUILTIN_NAMES) {
-            Ok(a) => a,
-            Err(e) => {
-                let mut out = Vec::<u8>::new();
-                create_report(ErrorReport::from(e))
-                    .write(cache, &mut out)
-                    .unwrap();
-                all_failed_tests.push((name, String::from_utf8_lossy(&out).to_string()));
-                continue;
-            }
-        };
-
-        //globals.stored_values = storage.clone();
-        globals.objects.clear();
-
-        let mut contexts = start_context.clone();
-        contexts.inner().root_context_ptr = &mut contexts;
-
-        match compiler::compile_scope(&statements, &mut contexts, &mut globals, info.clone()) {
-            Ok(_) => (),
-            Err(e) => {
-                let mut out = Vec::<u8>::new();
-                create_report(ErrorReport::from(e))
-                    .write(cache, &mut out)
-                    .unwrap();
-                all_failed_tests.push((name, String::from_utf8_lossy(&out).to_string()));
-                continue;
-            }
+        if let Err(e) = result {
+            all_failed_tests.push((name, e));
         }
     }
 
@@ -248,17 +215,99 @@ pub fn run_all_doc_examples() {
     }
 }
 
+// the globals and the context with the standard library imported, building for `target`
+// (the root context pointer has to be set again once the context is moved)
+#[cfg(test)]
+fn import_std<'a>(
+    target: ::compiler::target::Target,
+    permissions: &builtins::BuiltinPermissions,
+    std_out: &'a mut Vec<u8>,
+) -> (globals::Globals<'a>, context::FullContext) {
+    use shared::ImportType;
+
+    let mut globals_path = std::env::current_dir().unwrap();
+    globals_path.push("temp"); // this folder doesn't actually exist, but it needs to be there because .parent is called in import_module
+
+    let mut globals = globals::Globals::new(
+        SpwnSource::File(globals_path),
+        permissions.clone(),
+        String::from(""),
+        std_out,
+    );
+    globals.includes.push(PathBuf::from("./"));
+    globals.target = target;
+
+    let mut start_context = context::FullContext::new(&globals);
+    // `new` points the root context at its own local, which is gone once the context is moved
+    start_context.inner().root_context_ptr = &mut start_context;
+
+    let info = compiler_info::CompilerInfo::new();
+
+    compiler::import_module(
+        &ImportType::Lib(STD_PATH.to_string()),
+        &mut start_context,
+        &mut globals,
+        info,
+        false,
+    )
+    .unwrap();
+
+    (globals, start_context)
+}
+
+// compiles an example in a copy of the start context, returning the error report if it fails
+#[cfg(test)]
+fn run_doc_example(
+    code: &str,
+    globals: &mut globals::Globals,
+    start_context: &context::FullContext,
+) -> Result<(), String> {
+    let source = SpwnSource::String(LocalIntern::new(code.to_string()));
+
+    let info = compiler_info::CompilerInfo {
+        ..compiler_info::CompilerInfo::from_area(errors::compiler_info::CodeArea {
+            file: LocalIntern::new(source.clone()),
+            pos: (0, 0),
+        })
+    };
+
+    let report = |e: ErrorReport| {
+        let mut out = Vec::<u8>::new();
+        create_report(e)
+            .write(SpwnCache::default(), &mut out)
+            .unwrap();
+        String::from_utf8_lossy(&out).to_string()
+    };
+
+    let (statements, _) =
+        parse_spwn(code.to_string(), source, BUILTIN_NAMES).map_err(|e| report(e.into()))?;
+
+    globals.objects.clear();
+
+    let mut contexts = start_context.clone();
+    contexts.inner().root_context_ptr = &mut contexts;
+
+    compiler::compile_scope(&statements, &mut contexts, globals, info)
+        .map(|_| ())
+        .map_err(|e| report(e.into()))
+}
+
 #[cfg(test)]
 fn add_tests(
     name: String,
     val: value::Value,
     globals: &globals::Globals,
-    all_tests: &mut Vec<(String, String)>,
+    all_tests: &mut Vec<(String, String, ::compiler::target::Target)>,
 ) {
     match val {
         value::Value::Macro(m) => {
             if let Some(example) = m.tag.get_example(true) {
-                all_tests.push((name, example));
+                let target = m
+                    .tag
+                    .get_example_target()
+                    .map(|t| t.parse().unwrap_or_else(|e| panic!("{}", e)))
+                    .unwrap_or_default();
+                all_tests.push((name, example, target));
             }
         }
         value::Value::Dict(d) => {
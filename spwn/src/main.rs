@@ -1,35 +1,41 @@
 use ariadne::Fmt;
 use clap::arg;
+use clap::Arg;
 use clap::AppSettings;
 use clap::ValueHint;
 //#![feature(arbitrary_enum_discriminant)]
 use ::compiler::builtins;
 use ::compiler::compiler;
-use std::io::Read;
+use ::compiler::globals::ImportCache;
+use levelstring::{BackupKind, SaveFile};
 
 use ::docgen::documentation;
 
 use ::compiler::leveldata;
+use ::compiler::output_cache;
+use ::compiler::target::Target;
 
 use optimizer::optimize;
 
 use ariadne::Cache;
 
 use optimize::optimize;
+use optimizer::passes::{self, OptOptions, Pass};
 
 use ::parser::parser::*;
 use builtins::BuiltinPermissions;
 
 use shared::SpwnSource;
+use spwn::testing::find_spwn_files;
 use spwn::SpwnCache;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use editorlive::editorlive::editor_paste;
 use std::fs;
 
 #[cfg(not(target_arch = "wasm32"))]
-use ::pckp::config_file;
+use ::pckp::{config_file, edit, error::PckpError, lock::LockFile};
 
 const ERROR_EXIT_CODE: i32 = 1;
 
@@ -57,38 +63,94 @@ fn eprint_with_color(text: &str, color: termcolor::Color) {
     stdout.set_color(&ColorSpec::new()).unwrap();
 }
 
+#[derive(Clone)]
 pub struct BuildOptions<'a> {
     permissions: BuiltinPermissions,
     include_paths: Vec<PathBuf>,
     gd_enabled: bool,
-    opti_enabled: bool,
+    target: Target,
+    optimization: OptOptions,
+    opt_stats: bool,
     level_name: Option<String>,
     live_editor: bool,
     save_file: Option<&'a str>,
+    output: Option<(PathBuf, OutputFormat)>,
+    level_info: levelstring::LevelInfo,
+    source_map: Option<PathBuf>,
+    emit_graph: Option<PathBuf>,
+    cache_output: bool,
+}
+
+#[derive(Clone, Copy)]
+enum OutputFormat {
+    // the uncompressed object string
+    Raw,
+    // the level string as GD stores it
+    Compressed,
+    // an exported level file with metadata
+    Gmd,
 }
 
 impl<'a> BuildOptions<'a> {
     fn from(build_cmd: &'a clap::ArgMatches) -> Result<Self, std::io::Error> {
         let mut permissions = BuiltinPermissions::new();
-        let mut include_paths = vec![
-            std::env::current_dir().expect("Cannot access current directory"),
-            std::env::current_exe()?
-                .parent()
-                .expect("Executable must be in a directory")
-                .to_path_buf(),
-        ];
+        let include_paths = include_paths(build_cmd)?;
 
         let gd_enabled =
             !build_cmd.is_present("no-level") && !build_cmd.is_present("console-output");
-        let opti_enabled = !build_cmd.is_present("no-optimize");
+        let target = build_cmd
+            .value_of("target")
+            .map(|t| t.parse().unwrap_or_else(|e| panic!("{}", e)))
+            .unwrap_or_default();
+        let optimization = OptOptions {
+            level: if build_cmd.is_present("no-optimize") {
+                0
+            } else {
+                build_cmd
+                    .value_of("opt-level")
+                    .map(|l| l.parse().unwrap())
+                    .unwrap_or(passes::DEFAULT_LEVEL)
+            },
+            disabled: build_cmd
+                .values_of("disable-pass")
+                .unwrap_or_default()
+                .map(|p| p.parse().unwrap_or_else(|e| panic!("{}", e)))
+                .collect(),
+            pass_limit: build_cmd
+                .value_of("opt-bisect-limit")
+                .map(|n| n.parse().unwrap()),
+            target,
+        };
+        let opt_stats = build_cmd.is_present("opt-stats");
         let level_name = build_cmd.value_of("level-name").map(str::to_string);
         let live_editor = build_cmd.is_present("live-editor");
         let save_file = build_cmd.value_of("save-file");
+        let source_map = build_cmd.value_of("source-map").map(PathBuf::from);
+        let emit_graph = build_cmd.value_of("emit-graph").map(PathBuf::from);
+        let cache_output = !build_cmd.is_present("no-cache");
 
-        build_cmd
-            .values_of("include-path")
-            .unwrap_or_default()
-            .for_each(|val| include_paths.push(val.into()));
+        let output = build_cmd.value_of("output").map(|file| {
+            let file = PathBuf::from(file);
+            let format = match build_cmd.value_of("output-format") {
+                Some("raw") => OutputFormat::Raw,
+                Some("compressed") => OutputFormat::Compressed,
+                Some(_) => OutputFormat::Gmd,
+                None if file.extension().is_some_and(|e| e == "gmd") => OutputFormat::Gmd,
+                None => OutputFormat::Raw,
+            };
+            (file, format)
+        });
+
+        let parse_song = |arg: &str| build_cmd.value_of(arg).map(|id| id.parse().unwrap());
+        let level_info = levelstring::LevelInfo {
+            name: level_name.clone().unwrap_or_default(),
+            description: build_cmd
+                .value_of("description")
+                .unwrap_or_default()
+                .to_string(),
+            official_song: parse_song("official-song"),
+            custom_song: parse_song("song"),
+        };
 
         build_cmd
             .values_of("allow")
@@ -115,66 +177,168 @@ impl<'a> BuildOptions<'a> {
             permissions,
             include_paths,
             gd_enabled,
-            opti_enabled,
+            target,
+            optimization,
+            opt_stats,
             level_name,
             live_editor,
             save_file,
+            output,
+            level_info,
+            source_map,
+            emit_graph,
+            cache_output,
         })
     }
 }
 
+// the current directory, the directory of the executable, and the ones given with --include-path
+fn include_paths(cmd: &clap::ArgMatches) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut include_paths = vec![
+        std::env::current_dir().expect("Cannot access current directory"),
+        std::env::current_exe()?
+            .parent()
+            .expect("Executable must be in a directory")
+            .to_path_buf(),
+    ];
+    cmd.values_of("include-path")
+        .unwrap_or_default()
+        .for_each(|val| include_paths.push(val.into()));
+    Ok(include_paths)
+}
+
+// the arguments `build` and `eval` share
+fn build_args<'a>() -> Vec<Arg<'a>> {
+    vec![
+        arg!(-c --"console-output" "Makes the script print the created level into the console instead of writing it to your save file"),
+        arg!(-l --"no-level" "Only compiles the script, no level creation at all"),
+        arg!(-t --target [TARGET] "Geometry Dash version to build the level for, which decides the ID limits and the available triggers (default: gd2.1)").possible_values(Target::ALL.map(Target::name)),
+        arg!(-o --"no-optimize" "Removes post-optimization of triggers, making the output more readable, while also using a lot more objects and groups"),
+        arg!(-O --"opt-level" [LEVEL] "How much to optimize triggers: 0 (not at all, like --no-optimize), 1 (remove dead code), 2 (also merge spawn triggers and duplicates) or 3 (also share item IDs, color channels and groups and use group toggling) (default: 3)").possible_values(["0", "1", "2", "3"]),
+        arg!(--"disable-pass" "Turns off an optimization pass").takes_value(true).multiple_occurrences(true).possible_values(Pass::ALL.map(Pass::name)),
+        arg!(--"opt-stats" "Shows how many objects and groups each optimization pass removed"),
+        arg!(--"opt-bisect-limit" [N] "Only runs the first N optimization passes, to find the pass that breaks a level").validator(|n| n.parse::<usize>()),
+        arg!(-n --"level-name" [NAME] "Targets a specific level"),
+        arg!(-e --"live-editor" "Instead of writing the level to the save file, the script will use a live editor library if it's installed (Currently works only for MacOS)"),
+        arg!(-s --"save-file" [FILE] "Chooses a specific save file to write to"),
+        arg!(-i --"include-path" "Adds a search path to look for libraries").takes_value(true).multiple_occurrences(true).min_values(0),
+        arg!(-a --allow "Allow the use of a builtin").takes_value(true).multiple_occurrences(true).min_values(0),
+        arg!(-d --deny "Deny the use of a builtin").takes_value(true).multiple_occurrences(true).min_values(0),
+        arg!(--output [FILE] "Writes the level to a file instead of your save file").value_hint(ValueHint::FilePath),
+        arg!(--"output-format" [FORMAT] "Format of the output file (default: gmd for .gmd files, raw otherwise)").possible_values(["raw", "compressed", "gmd"]),
+        arg!(--description [TEXT] "Level description, for .gmd output"),
+        arg!(--song [ID] "Newgrounds ID of the level's custom song, for .gmd output").validator(|s| s.parse::<u32>()),
+        arg!(--"official-song" [INDEX] "Index of the level's official song, for .gmd output").validator(|s| s.parse::<u32>()),
+        arg!(--"source-map" [FILE] "Writes a JSON file that maps the added objects and groups to the code that created them").value_hint(ValueHint::FilePath),
+        arg!(--"emit-graph" [FILE] "Writes the trigger network before and after optimization as a Graphviz (DOT) file").value_hint(ValueHint::FilePath),
+        arg!(--"no-cache" "Builds all imported libraries again instead of using their cached outputs"),
+    ]
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = App::new("SPWN")
-    .global_setting(AppSettings::ArgRequiredElseHelp)
+    .setting(AppSettings::ArgRequiredElseHelp)
     .subcommands(
         [
             App::new("build")
+                .setting(AppSettings::ArgRequiredElseHelp)
                 .about("Runs/builds a given file"
             )
                 .visible_alias("b")
-                .args(&[
-                    arg!(<SCRIPT> "Path to spwn source file").value_hint(ValueHint::AnyPath),
-                    arg!(-c --"console-output" "Makes the script print the created level into the console instead of writing it to your save file"),
-                    arg!(-l --"no-level" "Only compiles the script, no level creation at all"),
-                    arg!(-o --"no-optimize" "Removes post-optimization of triggers, making the output more readable, while also using a lot more objects and groups"),
-                    arg!(-n --"level-name" [NAME] "Targets a specific level"),
-                    arg!(-e --"live-editor" "Instead of writing the level to the save file, the script will use a live editor library if it's installed (Currently works only for MacOS)"),
-                    arg!(-s --"save-file" [FILE] "Chooses a specific save file to write to"),
-                    arg!(-i --"include-path" "Adds a search path to look for libraries").takes_value(true).multiple_occurrences(true).min_values(0),
-                    arg!(-a --allow "Allow the use of a builtin").takes_value(true).multiple_occurrences(true).min_values(0),
-                    arg!(-d --deny "Deny the use of a builtin").takes_value(true).multiple_occurrences(true).min_values(0),
-                ]),
+                .arg(arg!(<SCRIPT> "Path to spwn source file").value_hint(ValueHint::AnyPath))
+                .args(build_args())
+                .arg(arg!(-w --watch "Keeps running, and builds the script again when it or one of the files it imports changes")),
 
             App::new("eval")
+                .setting(AppSettings::ArgRequiredElseHelp)
                 .about("Runs/builds the input given in stdin/the console as SPWN code")
                 .visible_alias("e")
-                .args(&[
-                    arg!(-c --"console-output" "Makes the script print the created level into the console instead of writing it to your save file"),
-                    arg!(-l --"no-level" "Only compiles the script, no level creation at all"),
-                    arg!(-o --"no-optimize" "Removes post-optimization of triggers, making the output more readable, while also using a lot more objects and groups"),
-                    arg!(-n --"level-name" [NAME] "Targets a specific level"),
-                    arg!(-e --"live-editor" "Instead of writing the level to the save file, the script will use a live editor library if it's installed (Currently works only for MacOS)"),
-                    arg!(-s --"save-file" [FILE] "Chooses a specific save file to write to"),
-                    arg!(-i --"include-path" "Adds a search path to look for libraries").takes_value(true).multiple_occurrences(true).min_values(0),
-                    arg!(-a --allow "Allow the use of a builtin").takes_value(true).multiple_occurrences(true).min_values(0),
-                    arg!(-d --deny "Deny the use of a builtin").takes_value(true).multiple_occurrences(true).min_values(0),
-                ]),
+                .args(build_args()),
 
             App::new("doc")
-            .arg(
-                arg!(<LIBRARY> "Library to document")
-            )
-                .about("Generates documentation for a SPWN library, in the form of a markdown file"),
+                .setting(AppSettings::ArgRequiredElseHelp)
+            .args(&[
+                arg!(<LIBRARY> "Library to document"),
+                arg!(--format [FORMAT] "Format of the documentation: markdown (a folder of markdown files), json, or html (a static site) (default: markdown)").possible_values(["markdown", "json", "html"]),
+            ])
+                .about("Generates documentation for a SPWN library, in the form of markdown files, JSON or a static HTML site"),
 
             App::new("new")
+                .setting(AppSettings::ArgRequiredElseHelp)
                 .about("Creates a new SPWN project in the given directory"
             )
                 .args(&[
                     arg!(-l --"lib" "Creates a PCKP-compatible SPWN library"),
                     arg!(<PATH> "Path to create project in").value_hint(ValueHint::AnyPath),
                 ]),
+
+            App::new("fmt")
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .about("Formats SPWN files, keeping comments and blank lines")
+                .args(&[
+                    arg!(--check "Only checks if the files are formatted, without changing them"),
+                    arg!(<PATHS> ... "Files or folders to format").value_hint(ValueHint::AnyPath),
+                ]),
+
+            App::new("levels")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .about("Lists, creates, backs up and restores levels in your save file")
+                .subcommands([
+                    App::new("list")
+                        .about("Lists every level in the save file, with its object count")
+                        .arg(arg!(-s --"save-file" [FILE] "Chooses a specific save file")),
+                    App::new("create")
+                        .setting(AppSettings::ArgRequiredElseHelp)
+                        .about("Adds a new, empty level to the save file")
+                        .args(&[
+                            arg!(<NAME> "Name of the new level"),
+                            arg!(-s --"save-file" [FILE] "Chooses a specific save file"),
+                        ]),
+                    App::new("backup")
+                        .about("Makes a timestamped backup of the save file")
+                        .arg(arg!(-s --"save-file" [FILE] "Chooses a specific save file")),
+                    App::new("restore")
+                        .about("Replaces the save file with a backup (the newest one by default)")
+                        .args(&[
+                            arg!([BACKUP] "Backup file to restore").value_hint(ValueHint::FilePath),
+                            arg!(-s --"save-file" [FILE] "Chooses a specific save file"),
+                        ]),
+                ]),
+
+            App::new("test")
+                .about("Runs the macros tagged with #[test] in a file, or in every .spwn file in a folder")
+                .args(&[
+                    arg!([PATH] "File or folder with tests (default: the current folder)").value_hint(ValueHint::AnyPath),
+                    arg!(-f --filter [NAME] "Only runs the tests with this in their name"),
+                    arg!(-i --"include-path" "Adds a search path to look for libraries").takes_value(true).multiple_occurrences(true).min_values(0),
+                ]),
+
+            App::new("pckp")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .about("Adds, removes, installs and updates the pckp dependencies of a project")
+                .arg(arg!(-p --path [DIR] "Folder with the pckp.yaml (default: the current folder)").value_hint(ValueHint::DirPath).global(true))
+                .subcommands([
+                    App::new("add")
+                        .setting(AppSettings::ArgRequiredElseHelp)
+                        .about("Adds a dependency to pckp.yaml (or changes its version) and installs it")
+                        .arg(arg!(<DEPENDENCY> "Name or git URL of the package, optionally with a version requirement (like mylib@^1.2)")),
+                    App::new("remove")
+                        .setting(AppSettings::ArgRequiredElseHelp)
+                        .about("Removes a dependency from pckp.yaml")
+                        .arg(arg!(<NAME> "Name or git URL of the dependency")),
+                    App::new("install")
+                        .about("Installs the dependencies in pckp.yaml, with the versions in pckp.lock"),
+                    App::new("update")
+                        .about("Updates the dependencies to the newest versions pckp.yaml allows, instead of the versions in pckp.lock")
+                        .arg(arg!([NAME] "Only updates this package")),
+                    App::new("tree")
+                        .about("Shows the dependency graph, with the version picked for every package"),
+                ]),
+
+            App::new("lsp")
+                .about("Starts a language server for editors, communicating over stdin/stdout"),
         ]
-    ).global_setting(clap::AppSettings::ArgRequiredElseHelp).get_matches();
+    ).get_matches();
 
     if let Some(build_cmd) = matches.subcommand_matches("build") {
         let script_path = build_cmd.value_of("SCRIPT").ok_or("unreachable")?;
@@ -210,7 +374,48 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
 
-        build_spwn_source(source, unparsed, options)
+        if build_cmd.is_present("watch") {
+            let mut import_cache = ImportCache::default();
+            let mut unparsed = Ok(unparsed);
+            loop {
+                // editors that save by renaming a new file over the old one leave it
+                // missing for a moment, so read errors don't stop watching either
+                match unparsed {
+                    Ok(unparsed) => match build_spwn_source(
+                        source.clone(),
+                        unparsed,
+                        options.clone(),
+                        &mut import_cache,
+                    ) {
+                        Err(e) if !e.is::<BuildFailed>() => {
+                            eprint_with_color(&format!("Error: {}", e), Color::Red)
+                        }
+                        _ => (),
+                    },
+                    Err(e) => eprint_with_color(
+                        &format!("Error reading {}: {}", script_path, e),
+                        Color::Red,
+                    ),
+                }
+
+                let mut watched = vec![PathBuf::from(script_path)];
+                watched.extend(import_cache.files.iter().cloned());
+                print_with_color(
+                    &format!("\nWatching {} files for changes ...", watched.len()),
+                    Color::Cyan,
+                );
+                wait_for_change(&watched);
+
+                unparsed = fs::read_to_string(script_path);
+            }
+        }
+
+        exit_on_failure(build_spwn_source(
+            source,
+            unparsed,
+            options,
+            &mut ImportCache::default(),
+        ))
     } else if let Some(eval_cmd) = matches.subcommand_matches("eval") {
         use ariadne::Color::{Blue, Red};
         let end_command = ":build";
@@ -231,18 +436,44 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let options = BuildOptions::from(eval_cmd)?;
         let source = SpwnSource::String(internment::LocalIntern::from(unparsed));
 
-        build_spwn_source(source, unparsed.to_string(), options)
+        exit_on_failure(build_spwn_source(
+            source,
+            unparsed.to_string(),
+            options,
+            &mut ImportCache::default(),
+        ))
     } else if let Some(doc_cmd) = matches.subcommand_matches("doc") {
         let lib_path = doc_cmd.value_of("LIBRARY").unwrap();
+        let format = match doc_cmd.value_of("format") {
+            Some("json") => documentation::DocFormat::Json,
+            Some("html") => documentation::DocFormat::Html,
+            _ => documentation::DocFormat::Markdown,
+        };
         if "$" == lib_path {
             // doc builtins
-            let doc = builtins::builtin_docs();
-            fs::write("builtins.md", doc)?;
-            print_with_color("Written to ./builtins.md", Color::Green);
+            match format {
+                documentation::DocFormat::Markdown => {
+                    let doc = builtins::builtin_docs();
+                    fs::write("builtins.md", doc)?;
+                    print_with_color("Written to ./builtins.md", Color::Green);
+                }
+                documentation::DocFormat::Json => {
+                    let doc = format!("{:#}", builtins::builtin_docs_json());
+                    fs::write("builtins.json", doc)?;
+                    print_with_color("Written to ./builtins.json", Color::Green);
+                }
+                documentation::DocFormat::Html => {
+                    eprint_with_color(
+                        "The built-in functions can only be documented as markdown or JSON",
+                        Color::Red,
+                    );
+                    std::process::exit(ERROR_EXIT_CODE);
+                }
+            }
         } else {
             let cache = SpwnCache::default();
 
-            match documentation::document_lib(lib_path) {
+            match documentation::document_lib(lib_path, format) {
                 Ok(_) => (),
                 Err(e) => {
                     create_report(ErrorReport::from(e)).eprint(cache).unwrap();
@@ -313,16 +544,390 @@ folders:
             .unwrap();
         }
 
+        Ok(())
+    } else if let Some(fmt_cmd) = matches.subcommand_matches("fmt") {
+        let check = fmt_cmd.is_present("check");
+
+        let mut files = Vec::new();
+        for path in fmt_cmd.values_of("PATHS").unwrap() {
+            find_spwn_files(PathBuf::from(path), &mut files)?;
+        }
+
+        let mut unformatted = Vec::new();
+        let mut failed = false;
+        for file in files {
+            let unparsed = fs::read_to_string(&file)?;
+            let source = SpwnSource::File(file.clone());
+
+            match ::parser::fmt::format(&unparsed, source, builtins::BUILTIN_NAMES) {
+                Ok(formatted) if formatted != unparsed => {
+                    if check {
+                        unformatted.push(file);
+                    } else {
+                        fs::write(&file, formatted)?;
+                        println!("Formatted {}", file.display());
+                    }
+                }
+                Ok(_) => (),
+                Err(e) => {
+                    create_report(ErrorReport::from(e))
+                        .eprint(SpwnCache::default())
+                        .unwrap();
+                    failed = true;
+                }
+            }
+        }
+
+        if !unformatted.is_empty() {
+            eprint_with_color("These files are not formatted:", Color::Red);
+            for file in unformatted {
+                eprintln!("    {}", file.display());
+            }
+            std::process::exit(ERROR_EXIT_CODE);
+        }
+        if failed {
+            std::process::exit(ERROR_EXIT_CODE);
+        }
+
+        Ok(())
+    } else if let Some(levels_cmd) = matches.subcommand_matches("levels") {
+        let (subcommand, args) = levels_cmd.subcommand().unwrap();
+        if let Err(e) = levels_command(subcommand, args) {
+            eprint_with_color(&e.to_string(), Color::Red);
+            std::process::exit(ERROR_EXIT_CODE);
+        }
+
+        Ok(())
+    } else if let Some(test_cmd) = matches.subcommand_matches("test") {
+        let mut files = Vec::new();
+        find_spwn_files(
+            PathBuf::from(test_cmd.value_of("PATH").unwrap_or(".")),
+            &mut files,
+        )?;
+        let include_paths = include_paths(test_cmd)?;
+        let filter = test_cmd.value_of("filter");
+
+        let mut passed = 0;
+        let mut failures = Vec::new();
+        for file in files {
+            let results = match spwn::testing::run_tests(
+                &file,
+                include_paths.clone(),
+                filter,
+                &mut std::io::stdout(),
+            ) {
+                Ok(results) => results,
+                Err(e) => {
+                    eprint_with_color(&format!("{} failed to compile", file.display()), Color::Red);
+                    failures.push((file.display().to_string(), e));
+                    continue;
+                }
+            };
+
+            for result in results {
+                let name = format!("{}::{}", file.display(), result.name);
+                print!("test {} ... ", name);
+                if result.passed() {
+                    print_with_color("ok", Color::Green);
+                    passed += 1;
+                } else {
+                    print_with_color("FAILED", Color::Red);
+                    failures.push((
+                        name,
+                        result.error.unwrap_or_else(|| {
+                            String::from("The test was expected to fail, but it passed\n")
+                        }),
+                    ));
+                }
+            }
+        }
+
+        if !failures.is_empty() {
+            println!("\nfailures:\n");
+            for (name, error) in &failures {
+                println!("---- {} ----\n{}", name, error);
+            }
+        }
+        let summary = format!("\n{} passed; {} failed", passed, failures.len());
+        if failures.is_empty() {
+            print_with_color(&format!("{}\ntest result: ok", summary), Color::Green);
+            Ok(())
+        } else {
+            print_with_color(&format!("{}\ntest result: FAILED", summary), Color::Red);
+            std::process::exit(ERROR_EXIT_CODE);
+        }
+    } else if let Some(pckp_cmd) = matches.subcommand_matches("pckp") {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (subcommand, args) = pckp_cmd.subcommand().unwrap();
+            if let Err(e) = pckp_command(subcommand, args) {
+                eprint_with_color(&format!("{e}"), Color::Red);
+                std::process::exit(ERROR_EXIT_CODE);
+            }
+        }
+
+        Ok(())
+    } else if matches.subcommand_matches("lsp").is_some() {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Err(e) = lsp::start() {
+            eprint_with_color(&format!("Language server error: {}", e), Color::Red);
+            std::process::exit(ERROR_EXIT_CODE);
+        }
+
         Ok(())
     } else {
         unreachable!()
     }
 }
 
+// blocks until one of the files is modified, created or removed
+fn wait_for_change(files: &[PathBuf]) {
+    let modified = |file: &PathBuf| fs::metadata(file).and_then(|m| m.modified()).ok();
+    let start = files.iter().map(modified).collect::<Vec<_>>();
+
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        if files.iter().map(modified).ne(start.iter().cloned()) {
+            return;
+        }
+    }
+}
+
+/// Returned by `build_spwn_source` when the error has already been printed
+#[derive(Debug)]
+struct BuildFailed;
+
+impl std::fmt::Display for BuildFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Build failed")
+    }
+}
+
+impl std::error::Error for BuildFailed {}
+
+fn exit_on_failure(
+    result: Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match result {
+        Err(e) if e.is::<BuildFailed>() => std::process::exit(ERROR_EXIT_CODE),
+        r => r,
+    }
+}
+
+fn levels_command(
+    subcommand: &str,
+    args: &clap::ArgMatches,
+) -> Result<(), levelstring::SaveFileError> {
+    let path = save_file_path(args.value_of("save-file"));
+
+    match subcommand {
+        "list" => {
+            let save = SaveFile::open(&path)?;
+            for level in save.levels()? {
+                if level.is_initialized() {
+                    println!("{} ({} objects)", level.name, level.object_count()?);
+                } else {
+                    println!("{} (not initialized)", level.name);
+                }
+            }
+        }
+        "create" => {
+            let name = args.value_of("NAME").unwrap();
+            let mut save = SaveFile::open(&path)?;
+            save.create_level(name)?;
+
+            let backup = levelstring::backup_save_file(&path, BackupKind::Automatic)?;
+            println!("Backed up savefile to {}", backup.display());
+            save.write(&path)?;
+            print_with_color(&format!("Created level \"{}\"", name), Color::Green);
+        }
+        "backup" => {
+            let backup = levelstring::backup_save_file(&path, BackupKind::Manual)?;
+            print_with_color(
+                &format!("Backed up savefile to {}", backup.display()),
+                Color::Green,
+            );
+        }
+        "restore" => {
+            let backup = match args.value_of("BACKUP") {
+                Some(b) => PathBuf::from(b),
+                None => match levelstring::list_backups(&path)?.pop() {
+                    Some(b) => b,
+                    None => return Err(levelstring::SaveFileError::NoBackups),
+                },
+            };
+            // read first, because the backup could be pruned by the one made below
+            let bytes = fs::read(&backup)?;
+            // make sure it's actually a save file
+            SaveFile::from_bytes(&bytes)?;
+            // the current save file could be the one the user wants back later
+            if path.exists() {
+                let current = levelstring::backup_save_file(&path, BackupKind::Automatic)?;
+                println!("Backed up savefile to {}", current.display());
+            }
+            fs::write(&path, bytes)?;
+            print_with_color(&format!("Restored {}", backup.display()), Color::Green);
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn pckp_command(subcommand: &str, args: &clap::ArgMatches) -> Result<(), PckpError> {
+    let dir = PathBuf::from(args.value_of("path").unwrap_or("."));
+    let cfg_file = config_file::get_config(Some(dir.clone()));
+    let package = || match config_file::config_to_package(cfg_file.clone())? {
+        Some(p) => Ok(p),
+        None => Err(PckpError::custom(
+            format!(
+                "There is no {} in {}",
+                config_file::CONFIG_NAME,
+                dir.display()
+            ),
+            None,
+        )),
+    };
+
+    // pckp.yaml is only changed if the dependencies can be installed after the change
+    let read_config = || {
+        fs::read_to_string(&cfg_file).map_err(|e| {
+            PckpError::config(
+                format!("Could not open configuration file: {}", e),
+                cfg_file.clone(),
+                None,
+            )
+        })
+    };
+
+    match subcommand {
+        "add" => {
+            package()?;
+            let (source, version) = edit::parse_dependency(args.value_of("DEPENDENCY").unwrap());
+            let original = read_config()?;
+            edit::add_dependency(&cfg_file, &source, &version)?;
+            if let Err(e) = package().and_then(|p| p.install_dependencies(dir)) {
+                return Err(restore(&cfg_file, Some(original), e));
+            }
+            print_with_color(
+                &format!("Added {}", args.value_of("DEPENDENCY").unwrap()),
+                Color::Green,
+            );
+        }
+        "remove" => {
+            package()?;
+            let name = args.value_of("NAME").unwrap();
+            let original = read_config()?;
+            edit::remove_dependency(&cfg_file, name)?;
+            if let Err(e) = package().and_then(|p| p.install_dependencies(dir)) {
+                return Err(restore(&cfg_file, Some(original), e));
+            }
+            print_with_color(&format!("Removed {}", name), Color::Green);
+        }
+        "install" => {
+            package()?.install_dependencies(dir)?;
+            print_with_color("Installed dependencies", Color::Green);
+        }
+        "update" => {
+            let package = package()?;
+            // packages that aren't in the lock file get the newest version again
+            let lock_path = LockFile::path(dir.clone());
+            let mut lock = LockFile::read(&lock_path)?;
+            match args.value_of("NAME") {
+                Some(name) => {
+                    if !lock.packages.iter().any(|p| p.name == name) {
+                        return Err(PckpError::custom(
+                            format!("'{}' is not an installed package", name),
+                            None,
+                        ));
+                    }
+                    lock.packages.retain(|p| p.name != name);
+                }
+                None => lock.packages.clear(),
+            }
+            // the old lock file is put back if the new versions can't be installed
+            let original = fs::read_to_string(&lock_path).ok();
+            lock.write(&lock_path)?;
+            if let Err(e) = package.install_dependencies(dir) {
+                return Err(restore(&lock_path, original, e));
+            }
+            print_with_color("Updated dependencies", Color::Green);
+        }
+        "tree" => print!("{}", package()?.tree(dir)?),
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+// puts back a file that was changed before an install failed (or removes it if it didn't exist),
+// and returns the error to show
+#[cfg(not(target_arch = "wasm32"))]
+fn restore(path: &Path, original: Option<String>, error: PckpError) -> PckpError {
+    let restored = match original {
+        Some(original) => fs::write(path, original),
+        None => match fs::remove_file(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            r => r,
+        },
+    };
+    match restored {
+        Ok(()) => error,
+        Err(e) => PckpError::custom_with_note(
+            format!("Could not restore {}: {}", path.display(), e),
+            None,
+            Some(format!("It was changed before this error:\n{}", error)),
+        ),
+    }
+}
+
+// the save file given with --save-file, or GD's save file
+fn save_file_path(save_file: Option<&str>) -> PathBuf {
+    find_save_file(save_file).expect("Couldn't find the save file, give it with --save-file")
+}
+
+// the save file given with --save-file, or GD's save file if it can be found on this OS
+fn find_save_file(save_file: Option<&str>) -> Option<PathBuf> {
+    if let Some(save_file) = save_file {
+        Some(PathBuf::from(save_file))
+    } else if cfg!(target_os = "windows") {
+        std::env::var("localappdata")
+            .ok()
+            .map(|dir| PathBuf::from(dir).join("GeometryDash/CCLocalLevels.dat"))
+    } else if cfg!(target_os = "macos") {
+        std::env::var("HOME").ok().map(|dir| {
+            PathBuf::from(dir).join("Library/Application Support/GeometryDash/CCLocalLevels.dat")
+        })
+    } else if cfg!(target_os = "linux") {
+        std::env::var("HOME").ok().map(|dir| {
+            PathBuf::from(dir).join(".steam/steam/steamapps/compatdata/322170/pfx/drive_c/users/steamuser/Local Settings/Application Data/GeometryDash/CCLocalLevels.dat")
+        })
+    } else if cfg!(target_os = "android") {
+        Some(PathBuf::from(
+            "/data/data/com.robtopx.geometryjump/CCLocalLevels.dat",
+        ))
+    } else {
+        None
+    }
+}
+
+// the level settings of exported levels: the ones of the level in the save file
+// if it can be read, otherwise the ones of a new level
+fn export_header(gd_enabled: bool, save_file: Option<&str>, level_name: Option<&str>) -> String {
+    let level_string = match find_save_file(save_file) {
+        Some(path) if gd_enabled => SaveFile::open(path)
+            .and_then(|save| save.level(level_name))
+            .and_then(|level| level.level_string())
+            .unwrap_or_default(),
+        _ => String::new(),
+    };
+    levelstring::level_header(&level_string).to_string()
+}
+
 fn build_spwn_source(
     source: SpwnSource,
     unparsed: String,
     mut options: BuildOptions,
+    import_cache: &mut ImportCache,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut cache = SpwnCache::default();
     match cache.fetch(&source) {
@@ -339,7 +944,7 @@ fn build_spwn_source(
     ) {
         Err(err) => {
             create_report(ErrorReport::from(err)).eprint(cache).unwrap();
-            std::process::exit(ERROR_EXIT_CODE);
+            return Err(Box::new(BuildFailed));
         }
         Ok(p) => p,
     };
@@ -353,74 +958,108 @@ fn build_spwn_source(
             _ => (),
         }
     }
-    let gd_path = if options.gd_enabled {
-        Some(if options.save_file != None {
-            PathBuf::from(options.save_file.expect("what"))
-        } else if cfg!(target_os = "windows") {
-            PathBuf::from(std::env::var("localappdata").expect("No local app data"))
-                .join("GeometryDash/CCLocalLevels.dat")
-        } else if cfg!(target_os = "macos") {
-            PathBuf::from(std::env::var("HOME").expect("No home directory"))
-                .join("Library/Application Support/GeometryDash/CCLocalLevels.dat")
-        } else if cfg!(target_os = "linux") {
-            PathBuf::from(std::env::var("HOME").expect("No home directory"))
-                .join(".steam/steam/steamapps/compatdata/322170/pfx/drive_c/users/steamuser/Local Settings/Application Data/GeometryDash/CCLocalLevels.dat")
-        } else if cfg!(target_os = "android") {
-            PathBuf::from("/data/data/com.robtopx.geometryjump/CCLocalLevels.dat")
-        } else {
-            panic!("Unsupported operating system");
-        })
+    let gd_path = if options.gd_enabled && options.output.is_none() {
+        Some(save_file_path(options.save_file))
     } else {
         None
     };
-    let level_string = if options.gd_enabled {
-        if let Some(gd_path) = &gd_path {
-            print_with_color("Reading savefile...", Color::Cyan);
-            let mut file = fs::File::open(gd_path)?;
-            let mut file_content = Vec::new();
-            file.read_to_end(&mut file_content)
-                .expect("Problem reading savefile");
-            let mut level_string =
-                match levelstring::get_level_string(file_content, options.level_name.as_ref()) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        eprint_with_color(&format!("Error reading level:\n{}", e), Color::Red);
+    let mut save_file = None;
+    let level_string = if let Some(gd_path) = &gd_path {
+        print_with_color("Reading savefile...", Color::Cyan);
+        let save = match SaveFile::open(gd_path) {
+            Ok(s) => s,
+            Err(e) => {
+                eprint_with_color(&format!("Error reading savefile:\n{}", e), Color::Red);
 
-                        std::process::exit(ERROR_EXIT_CODE);
-                    }
-                };
-            if level_string.is_empty() {}
-            leveldata::remove_spwn_objects(&mut level_string);
-            level_string
-        } else {
-            String::new()
+                return Err(Box::new(BuildFailed));
+            }
+        };
+        let level = match save.level(options.level_name.as_deref()) {
+            Ok(l) => l,
+            Err(e) => {
+                eprint_with_color(&format!("Error reading level:\n{}", e), Color::Red);
+
+                return Err(Box::new(BuildFailed));
+            }
+        };
+        if options.level_name.is_none() {
+            print_with_color(
+                &format!(
+                    "No level name given, using the first level (\"{}\")",
+                    level.name
+                ),
+                Color::Yellow,
+            );
         }
+        let mut level_string = if level.is_initialized() {
+            match level.level_string() {
+                Ok(s) => s,
+                Err(e) => {
+                    eprint_with_color(&format!("Error reading level:\n{}", e), Color::Red);
+
+                    return Err(Box::new(BuildFailed));
+                }
+            }
+        } else {
+            levelstring::DEFAULT_LEVEL_STRING.to_string()
+        };
+        leveldata::remove_spwn_objects(&mut level_string);
+        save_file = Some(save);
+        level_string
     } else {
         String::new()
     };
+    import_cache.output_dir = options.cache_output.then(output_cache::cache_dir);
     let mut std_out = std::io::stdout();
     let mut compiled = match compiler::compile_spwn(
         statements,
-        source,
+        source.clone(),
         options.include_paths,
         notes,
         options.permissions,
+        options.target,
         level_string.clone(),
         &mut std_out,
+        import_cache,
     ) {
         Err(err) => {
             create_report(ErrorReport::from(err)).eprint(cache).unwrap();
-            std::process::exit(ERROR_EXIT_CODE);
+            return Err(Box::new(BuildFailed));
         }
         Ok(p) => p,
     };
     if options.gd_enabled {
         let reserved = optimizer::ReservedIds::from_objects(&compiled.objects, &compiled.func_ids);
 
+        let mut graph = optimizer::graph::TriggerGraph::default();
         let has_stuff = compiled.func_ids.iter().any(|x| !x.obj_list.is_empty());
-        if options.opti_enabled && has_stuff {
+        if options.optimization.level > 0 && has_stuff {
+            if options.emit_graph.is_some() {
+                graph.add_stage("before optimization", &compiled.func_ids);
+            }
             print_with_color("Optimizing triggers...", Color::Cyan);
-            compiled.func_ids = optimize(compiled.func_ids, compiled.closed_groups, reserved);
+            let (func_ids, stats) = optimize(
+                compiled.func_ids,
+                &mut compiled.objects,
+                compiled.closed_groups,
+                reserved,
+                &options.optimization,
+            );
+            compiled.func_ids = func_ids;
+            if options.opt_stats {
+                for line in stats.to_string().lines() {
+                    print_with_color(line, Color::White);
+                }
+            }
+        }
+
+        if let Some(file) = &options.emit_graph {
+            graph.add_stage("output", &compiled.func_ids);
+            fs::write(file, graph.to_dot())?;
+            print_with_color(
+                &format!("Trigger graph written to {}", file.display()),
+                Color::White,
+            );
         }
 
         let mut objects = leveldata::apply_fn_ids(&compiled.func_ids);
@@ -429,7 +1068,23 @@ fn build_spwn_source(
 
         print_with_color(&format!("{} objects added", objects.len()), Color::White);
 
-        let (new_ls, used_ids) = leveldata::append_objects(objects, &level_string)?;
+        let used_ids = match leveldata::resolve_ids(&mut objects, &level_string, options.target) {
+            Ok(ids) => ids,
+            Err(e) => {
+                eprint_with_color(&e, Color::Red);
+                return Err(Box::new(BuildFailed));
+            }
+        };
+
+        if let Some(file) = &options.source_map {
+            fs::write(file, leveldata::source_map(&objects))?;
+            print_with_color(
+                &format!("Source map written to {}", file.display()),
+                Color::White,
+            );
+        }
+
+        let new_ls = leveldata::serialize_objects(objects);
 
         print_with_color("\nLevel:", Color::Magenta);
         for (i, len) in used_ids.iter().enumerate() {
@@ -445,27 +1100,63 @@ fn build_spwn_source(
             }
         }
         //println!("level_string: {}", level_string);
-        if options.live_editor {
+        if let Some((file, format)) = options.output {
+            // GD reads the first object of a whole level as its settings
+            let full_level = || {
+                export_header(
+                    options.gd_enabled,
+                    options.save_file,
+                    options.level_name.as_deref(),
+                ) + &new_ls
+            };
+            let content = match format {
+                OutputFormat::Raw => new_ls.clone(),
+                OutputFormat::Compressed => levelstring::compress_level_string(&full_level()),
+                OutputFormat::Gmd => {
+                    let mut info = options.level_info;
+                    if info.name.is_empty() {
+                        info.name = match &source {
+                            SpwnSource::File(path) => path
+                                .file_stem()
+                                .map(|s| s.to_string_lossy().to_string())
+                                .unwrap_or_default(),
+                            _ => String::from("SPWN level"),
+                        };
+                    }
+                    levelstring::level_to_gmd(&full_level(), &info)
+                }
+            };
+            fs::write(&file, content)?;
+            print_with_color(&format!("\nWritten to {}", file.display()), Color::Green);
+        } else if options.live_editor {
             match editor_paste(&new_ls) {
                 Err(e) => {
                     eprint_with_color(&format!("Error pasting into editor:\n{}", e), Color::Red);
 
-                    std::process::exit(ERROR_EXIT_CODE);
+                    return Err(Box::new(BuildFailed));
                 }
                 Ok(_) => {
                     print_with_color("Pasted into the editor!", Color::Green);
                 }
             }
         } else {
-            match gd_path {
-                Some(gd_path) => {
+            match gd_path.zip(save_file) {
+                Some((gd_path, mut save)) => {
                     print_with_color("\nWriting back to savefile...", Color::Cyan);
-                    levelstring::encrypt_level_string(
-                        new_ls,
-                        level_string,
-                        gd_path,
-                        options.level_name,
-                    )?;
+                    let written = save
+                        .set_level_string(options.level_name.as_deref(), &(level_string + &new_ls))
+                        .and_then(|name| {
+                            println!("Writing to level: {}", name);
+                            let backup =
+                                levelstring::backup_save_file(&gd_path, BackupKind::Automatic)?;
+                            println!("Backed up savefile to {}", backup.display());
+                            save.write(&gd_path)
+                        });
+                    if let Err(e) = written {
+                        eprint_with_color(&format!("Error writing savefile:\n{}", e), Color::Red);
+
+                        return Err(Box::new(BuildFailed));
+                    }
 
                     print_with_color(
                         "Written to save. You can now open Geometry Dash again!",
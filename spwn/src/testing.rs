@@ -0,0 +1,309 @@
+// runs the macros tagged with #[test] in a SPWN file (spwn test)
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use ::compiler::builtins::{BuiltinPermissions, BUILTIN_NAMES};
+use ::compiler::compiler::{compile_scope, start_context};
+use ::compiler::globals::Globals;
+use ::parser::ast;
+use ::parser::parser::parse_spwn;
+use errors::compiler_info::{CodeArea, CompilerInfo};
+use errors::{create_report, ErrorReport};
+use internment::LocalIntern;
+use shared::{FileRange, SpwnSource, PACKAGE_DIR};
+
+use crate::SpwnCache;
+
+pub struct TestResult {
+    pub name: String,
+    // whether the test is tagged with #[should_fail]
+    pub should_fail: bool,
+    // the error the test failed with
+    pub error: Option<String>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_some() == self.should_fail
+    }
+}
+
+struct Test {
+    // the variable the macro is defined in, and the dictionary keys leading to it
+    path: Vec<LocalIntern<String>>,
+    should_fail: bool,
+    takes_args: bool,
+    pos: FileRange,
+}
+
+impl Test {
+    fn name(&self) -> String {
+        self.path
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    // `name.key()`, placed at the test macro so errors in the call point to it
+    fn call(&self) -> ast::Statement {
+        let mut path = self.path[1..]
+            .iter()
+            .map(|key| ast::Path::Member(*key))
+            .collect::<Vec<_>>();
+        path.push(ast::Path::Call(Vec::new()));
+
+        let call = ast::Variable {
+            operator: None,
+            value: ast::ValueLiteral {
+                body: ast::ValueBody::Symbol(self.path[0]),
+            },
+            path,
+            pos: self.pos,
+            tag: ast::Attribute::new(),
+        };
+        ast::Statement {
+            body: ast::StatementBody::Expr(call.to_expression()),
+            arrow: false,
+            pos: self.pos,
+            comments: Default::default(),
+        }
+    }
+}
+
+fn report(err: impl Into<ErrorReport>) -> String {
+    let mut out = Vec::<u8>::new();
+    create_report(err.into())
+        .write(SpwnCache::default(), &mut out)
+        .unwrap();
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// Runs every macro tagged with `#[test]` that is defined at the top level of the file
+/// (directly or in a dictionary) and has `filter` in its name.
+/// Each test runs the file and then calls the macro, in its own copy of the start context.
+/// Returns an error report if the file itself fails to compile.
+pub fn run_tests(
+    path: &Path,
+    included: Vec<PathBuf>,
+    filter: Option<&str>,
+    std_out: &mut impl Write,
+) -> Result<Vec<TestResult>, String> {
+    let code = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let source = SpwnSource::File(path.to_path_buf());
+    let (statements, notes) = parse_spwn(code, source.clone(), BUILTIN_NAMES).map_err(report)?;
+
+    let mut tests = Vec::new();
+    for statement in &statements {
+        if let ast::StatementBody::Definition(ast::Definition {
+            symbol,
+            value: Some(value),
+            ..
+        }) = &statement.body
+        {
+            if let (ast::ValueBody::Symbol(name), true) =
+                (&symbol.value.body, symbol.path.is_empty())
+            {
+                find_tests(vec![*name], value, &mut tests);
+            }
+        }
+    }
+    tests.retain(|t| filter.is_none_or(|f| t.name().contains(f)));
+    if tests.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut globals = Globals::new(
+        source.clone(),
+        BuiltinPermissions::new(),
+        String::new(),
+        std_out,
+    );
+    globals.includes = included;
+
+    let info = CompilerInfo::from_area(CodeArea {
+        file: LocalIntern::new(source.clone()),
+        pos: (0, 0),
+    });
+
+    let start = start_context(&source, &notes, &mut globals).map_err(report)?;
+    let run = |statements: &[ast::Statement], globals: &mut Globals| {
+        globals.objects.clear();
+        let mut context = start.clone();
+        context.inner().root_context_ptr = &mut context;
+        compile_scope(statements, &mut context, globals, info.clone()).map_err(report)
+    };
+
+    run(&statements, &mut globals)?;
+
+    let mut results = Vec::new();
+    for test in tests {
+        let error = if test.takes_args {
+            Some(String::from("Test macros can't take arguments\n"))
+        } else {
+            let mut statements = statements.clone();
+            statements.push(test.call());
+            run(&statements, &mut globals).err()
+        };
+
+        results.push(TestResult {
+            name: test.name(),
+            should_fail: test.should_fail,
+            error,
+        });
+    }
+    Ok(results)
+}
+
+fn find_tests(path: Vec<LocalIntern<String>>, value: &ast::Expression, tests: &mut Vec<Test>) {
+    if value.values.len() != 1 || !value.values[0].path.is_empty() {
+        return;
+    }
+    match &value.values[0].value.body {
+        ast::ValueBody::Macro(m) if m.properties.get("test").is_some() => tests.push(Test {
+            path,
+            should_fail: m.properties.get("should_fail").is_some(),
+            takes_args: m.args.iter().any(|arg| arg.1.is_none()),
+            pos: m.arg_pos,
+        }),
+        ast::ValueBody::Dictionary(defs, _) => {
+            for def in defs {
+                if let ast::DictDef::Def((key, value)) = def {
+                    let mut path = path.clone();
+                    path.push(*key);
+                    find_tests(path, value, tests);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Finds the files to format or test. Files given directly are always used, in folders only
+/// .spwn files are, skipping hidden folders and the libraries pckp installed
+pub fn find_spwn_files(path: PathBuf, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(&path)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+
+        for entry in entries {
+            if entry.is_dir() {
+                let name = entry.file_name().unwrap_or_default().to_string_lossy();
+                if !name.starts_with('.') && name != PACKAGE_DIR {
+                    find_spwn_files(entry, out)?;
+                }
+            } else if entry.extension().is_some_and(|e| e == "spwn") {
+                out.push(entry);
+            }
+        }
+    } else {
+        out.push(path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // writes the code to a file and runs its tests
+    fn run_code(code: &str, filter: Option<&str>) -> Result<Vec<TestResult>, String> {
+        static FILES: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let i = FILES.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let dir = std::env::temp_dir().join(format!("spwn-test-{}-{}", std::process::id(), i));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("tests.spwn");
+        fs::write(&file, code).unwrap();
+
+        let results = run_tests(&file, vec![PathBuf::from("./")], filter, &mut Vec::new());
+        fs::remove_dir_all(&dir).unwrap();
+        results
+    }
+
+    // (name, should_fail, passed) of each test
+    fn run(code: &str, filter: Option<&str>) -> Vec<(String, bool, bool)> {
+        run_code(code, filter)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.name.clone(), r.should_fail, r.passed()))
+            .collect()
+    }
+
+    const CODE: &str = r#"
+#[no_std]
+adds = #[test] () {
+    if 1 + 1 != 2 { throw "bad math" }
+}
+breaks = #[test] () {
+    throw "broken"
+}
+tests = {
+    throws: #[test, should_fail] () { throw "expected" },
+    passes: #[test, should_fail] () {},
+    not_a_test: () { throw "never called" },
+}
+"#;
+
+    #[test]
+    fn passing_and_failing() {
+        assert_eq!(
+            run(CODE, None),
+            [
+                (String::from("adds"), false, true),
+                (String::from("breaks"), false, false),
+                (String::from("tests.throws"), true, true),
+                (String::from("tests.passes"), true, false),
+            ]
+        );
+
+        let results = run_code(CODE, Some("breaks")).unwrap();
+        assert!(results[0].error.as_ref().unwrap().contains("broken"));
+    }
+
+    #[test]
+    fn filtered() {
+        assert_eq!(
+            run(CODE, Some("tests.")),
+            [
+                (String::from("tests.throws"), true, true),
+                (String::from("tests.passes"), true, false),
+            ]
+        );
+        assert!(run(CODE, Some("nothing")).is_empty());
+    }
+
+    #[test]
+    fn compile_errors() {
+        let code = "#[no_std]\nt = #[test] () {}\nthrow \"at the top level\"";
+        match run_code(code, None) {
+            Err(e) => assert!(e.contains("at the top level")),
+            Ok(_) => panic!("the file should fail to compile"),
+        }
+    }
+
+    #[test]
+    fn finds_project_files() {
+        let dir = std::env::temp_dir().join(format!("spwn-find-{}", std::process::id()));
+        for folder in ["src", ".git", "pckp_libraries/lib@1.0.0"] {
+            fs::create_dir_all(dir.join(folder)).unwrap();
+            fs::write(dir.join(folder).join("a.spwn"), "").unwrap();
+        }
+        fs::write(dir.join("main.spwn"), "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let mut files = Vec::new();
+        find_spwn_files(dir.clone(), &mut files).unwrap();
+        assert_eq!(files, [dir.join("main.spwn"), dir.join("src/a.spwn")]);
+
+        // files given directly are used even if they are not .spwn files
+        let mut files = Vec::new();
+        find_spwn_files(dir.join("notes.txt"), &mut files).unwrap();
+        assert_eq!(files, [dir.join("notes.txt")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,14 @@
 #![allow(unused_variables)]
 
+use std::fs;
 use std::path::PathBuf;
+use std::rc::Rc;
+
+use ::compiler::builtins::BUILTIN_NAMES;
+use ::compiler::compiler::compile_spwn_quiet;
+use ::compiler::globals::ImportCache;
+use parser::parser::parse_spwn;
+use shared::SpwnSource;
 
 use crate::run_spwn;
 
@@ -118,3 +126,58 @@ true
 true
     "
 }
+
+// `spwn build --watch` keeps the parsed modules between builds,
+// and only parses the files that changed again
+#[test]
+fn watch_reuses_unchanged_imports() {
+    let dir = std::env::temp_dir().join(format!("spwn-watch-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let main = dir.join("main.spwn");
+    fs::write(
+        &main,
+        "#[no_std]\n$.print(import 'a.spwn', import 'b.spwn')",
+    )
+    .unwrap();
+    fs::write(dir.join("a.spwn"), "#[no_std]\nreturn 1").unwrap();
+    fs::write(dir.join("b.spwn"), "#[no_std]\nreturn 2").unwrap();
+
+    let build = |import_cache: &mut ImportCache| {
+        let source = SpwnSource::File(main.clone());
+        let code = fs::read_to_string(&main).unwrap();
+        let (statements, notes) = parse_spwn(code, source.clone(), BUILTIN_NAMES).unwrap();
+        let mut std_out = Vec::<u8>::new();
+        compile_spwn_quiet(
+            statements,
+            source,
+            vec![PathBuf::from("./")],
+            notes,
+            Default::default(),
+            Default::default(),
+            String::new(),
+            &mut std_out,
+            import_cache,
+        )
+        .unwrap_or_else(|e| panic!("build failed: {:?}", e));
+        String::from_utf8(std_out).unwrap()
+    };
+    let parsed = |import_cache: &ImportCache, file: &str| {
+        Rc::clone(&import_cache.parsed[&SpwnSource::File(dir.join(file))].1)
+    };
+
+    let mut import_cache = ImportCache::default();
+    assert_eq!(build(&mut import_cache).trim(), "12");
+    let (a, b) = (
+        parsed(&import_cache, "a.spwn"),
+        parsed(&import_cache, "b.spwn"),
+    );
+
+    fs::write(dir.join("b.spwn"), "#[no_std]\nreturn 3").unwrap();
+    let output = build(&mut import_cache);
+    fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(output.trim(), "13");
+    assert!(Rc::ptr_eq(&a, &parsed(&import_cache, "a.spwn")));
+    assert!(!Rc::ptr_eq(&b, &parsed(&import_cache, "b.spwn")));
+    assert_eq!(import_cache.files.len(), 2);
+}